@@ -6,13 +6,16 @@ mod sdl2_display;
 mod sdl2_audio;
 
 use std::fs::File;
-use std::io::Read;
-use std::time::Duration;
+use std::io::{Read, Write};
+use std::time::{Duration, Instant};
 use std::env;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use nes_rust::Nes;
 use nes_rust::rom::Rom;
+use nes_rust::database::RomDatabase;
+use nes_rust::fds::is_fds_image;
+use nes_rust::patch::{apply_patch, find_sibling_patch};
 use nes_rust::ds::emulator::DsEmulator;
 
 use sdl2_input::Sdl2Input;
@@ -81,8 +84,54 @@ fn run_ds(rom_path: &str, sdl: sdl2::Sdl) -> std::io::Result<()> {
     Ok(())
 }
 
+// Writes battery backed RAM to the .sav file if the game changed it
+fn flush_sram(nes: &mut Nes, path: &Path) -> std::io::Result<()> {
+	if nes.is_sram_dirty() {
+		let mut file = File::create(path)?;
+		file.write_all(&nes.export_sram())?;
+		nes.mark_sram_flushed();
+	}
+	Ok(())
+}
+
+// Prints NSF metadata and the track list
+fn print_nsf_metadata(nes: &Nes) {
+	if let Some(metadata) = nes.nsf_metadata() {
+		println!("{} - {} ({})", metadata.title, metadata.artist, metadata.copyright);
+		for track in 0..metadata.track_count as usize {
+			let name = metadata.track_names.get(track).map(|name| name.as_str()).unwrap_or("");
+			match metadata.track_lengths.get(track) {
+				Some(Some(length)) => println!("{:3}: {} ({}:{:02})", track + 1, name, length / 60000, length / 1000 % 60),
+				_ => println!("{:3}: {}", track + 1, name)
+			};
+		}
+		println!("N: Next track, P: Previous track");
+	}
+}
+
+// Removes "<name> <file>" from the arguments
+fn take_path_option(args: &mut Vec<String>, name: &str) -> std::io::Result<Option<PathBuf>> {
+	match args.iter().position(|arg| arg == name) {
+		Some(index) if index + 1 < args.len() => {
+			let path = PathBuf::from(args.remove(index + 1));
+			args.remove(index);
+			Ok(Some(path))
+		},
+		Some(_) => Err(std::io::Error::new(std::io::ErrorKind::Other, format!("{} needs a file", name))),
+		None => Ok(None)
+	}
+}
+
 fn main() -> std::io::Result<()> {
-	let args: Vec<String> = env::args().collect();
+	// --patch <file> applies an IPS, UPS or BPS patch to the ROM.
+	// --database <nes20db.xml> corrects the header with the game database
+	// instead of the embedded one, which only knows the bundled ROMs.
+	let mut args: Vec<String> = env::args().collect();
+	let patch_path = take_path_option(&mut args, "--patch")?;
+	let database = match take_path_option(&mut args, "--database")? {
+		Some(path) => Some(RomDatabase::parse(&std::fs::read_to_string(&path)?)),
+		None => None
+	};
 
 	if args.len() < 2 {
 		return Ok(());
@@ -98,9 +147,26 @@ fn main() -> std::io::Result<()> {
 	let mut file = File::open(filename)?;
 	let mut contents = vec![];
 	file.read_to_end(&mut contents)?;
-	let rom = match Rom::new(contents) {
-        Some(r) => r,
-        None => return Err(std::io::Error::new(std::io::ErrorKind::Other, "Invalid ROM")),
+	// Without --patch, a .ips, .bps or .ups file next to the ROM is applied
+	if let Some(path) = patch_path.or_else(|| find_sibling_patch(Path::new(filename))) {
+		contents = apply_patch(&contents, &std::fs::read(&path)?)
+			.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, format!("Invalid patch {}: {}", path.display(), e)))?;
+	}
+	// FDS disk images play with the BIOS given as the second argument,
+	// or disksys.rom next to the image
+	let rom = match is_fds_image(&contents) {
+		true => {
+			let bios_path = match args.get(2) {
+				Some(path) => PathBuf::from(path),
+				None => Path::new(filename).with_file_name("disksys.rom")
+			};
+			Rom::new_fds(contents, std::fs::read(&bios_path)?)
+		},
+		false => Rom::new_with_database(contents, database.as_ref().unwrap_or(RomDatabase::embedded()))
+	};
+	let rom = match rom {
+        Ok(r) => r,
+        Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::Other, format!("Invalid ROM: {}", e))),
     };
 	assert_eq!(rom.valid(), true);
 
@@ -111,14 +177,31 @@ fn main() -> std::io::Result<()> {
 	let audio = Box::new(Sdl2Audio::new(audio_subsystem));
 	let mut nes = Nes::new(input, display, audio);
 	nes.set_rom(rom);
+	if let Some(title) = &nes.rom_info().title {
+		println!("{} ({})", title, nes.rom_info().board);
+	}
+	print_nsf_metadata(&nes);
+
+	// Battery backed RAM, or FDS disk writes, is kept next to the ROM as <rom name>.sav
+	let sram_path = Path::new(filename).with_extension("sav");
+	if nes.has_battery_backed_ram() {
+		if let Ok(data) = std::fs::read(&sram_path) {
+			nes.import_sram(&data);
+		}
+	}
 
 	nes.bootup();
+	let mut last_sram_flush = Instant::now();
 	loop {
 		nes.step_frame();
 		if !nes.is_power_on() {
 			break;
 		}
+		if last_sram_flush.elapsed() > Duration::from_secs(2) {
+			flush_sram(&mut nes, &sram_path)?;
+			last_sram_flush = Instant::now();
+		}
 		std::thread::sleep(Duration::from_millis(1));
 	}
-	Ok(())
+	flush_sram(&mut nes, &sram_path)
 }
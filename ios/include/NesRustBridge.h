@@ -11,13 +11,41 @@ extern "C" {
 // Basic NES API (for direct Nes pointer usage)
 void* nes_create(void);
 void nes_destroy(void* nes_ptr);
-void nes_load_rom(void* nes_ptr, const uint8_t* data, size_t len);
+// Returns 1 on success, 0 on failure (see nes_last_error())
+int nes_load_rom(void* nes_ptr, const uint8_t* data, size_t len);
+// Same as nes_load_rom() but applies the IPS, UPS or BPS patch to the ROM first
+int nes_load_rom_with_patch(void* nes_ptr, const uint8_t* data, size_t len, const uint8_t* patch, size_t patch_len);
+// Same as nes_load_rom() but for an FDS disk image and the 8KB BIOS
+int nes_load_fds(void* nes_ptr, const uint8_t* data, size_t len, const uint8_t* bios, size_t bios_len);
+// Inserts the FDS disk side. Negative side ejects the disk.
+void nes_insert_disk(void* nes_ptr, int side);
+// Starts playing the NSF track, 0-based
+void nes_select_track(void* nes_ptr, int track);
 void nes_reset(void* nes_ptr);
+// Solder pad setting of multicarts selecting the game list
+void nes_set_solder_pad(void* nes_ptr, int pad);
 void nes_step_frame(void* nes_ptr);
 void nes_get_pixels(void* nes_ptr, uint8_t* buffer, size_t len);
 void nes_get_audio_samples(void* nes_ptr, float* buffer, size_t len);
 void nes_input(void* nes_ptr, int button_id, int pressed);
 
+// ROM info, header fields corrected with the game database.
+// The string getters copy the string into buffer like snprintf: at most
+// size - 1 bytes followed by '\0' (nothing if buffer is NULL or size is 0).
+// They return the length of the whole string, so a return value >= size
+// means the string was truncated.
+
+// Title in the game database, or of the NSF. Empty if unknown.
+int nes_rom_title(void* nes_ptr, char* buffer, size_t size);
+// Board name. Empty if unknown.
+int nes_rom_board(void* nes_ptr, char* buffer, size_t size);
+// SHA-1 of PRG-ROM + CHR-ROM in uppercase hex. Empty unless the game
+// database has the CRC32.
+int nes_rom_sha1(void* nes_ptr, char* buffer, size_t size);
+uint32_t nes_rom_crc32(void* nes_ptr);
+// 0: NTSC, 1: PAL, 2: Multiple region, 3: Dendy
+int nes_rom_region(void* nes_ptr);
+
 // Save state API (for direct Nes pointer usage)
 int nes_save_state(void* nes_ptr, const char* path);
 int nes_load_state(void* nes_ptr, const char* path);
@@ -39,6 +67,9 @@ int nes_is_ds(EmuState* state);
 int saveEmuState(EmuState* state, const char* path);
 int loadEmuState(EmuState* state, const char* path);
 
+// Reason the last initEmu/nes_load_rom call failed, or NULL
+const char* nes_last_error(void);
+
 // Virtual button input (global, thread-safe)
 void set_virtual_button_state(int button_id, int pressed);
 
@@ -1,5 +1,6 @@
 use register::Register;
 use audio::Audio;
+use rom::Rom;
 use save_state::{ApuState, ApuPulseState, ApuTriangleState, ApuNoiseState, ApuDmcState};
 
 /*
@@ -74,14 +75,14 @@ impl Apu {
 
 	// Expects being called at CPU clock rate
 	#[inline]
-	pub fn step(&mut self, dmc_sample_data: u8) {
+	pub fn step(&mut self, dmc_sample_data: u8, rom: &Rom) {
 		self.cycle += 1;
 
 		// Samping at sample rate timing
 		// @TODO Fix me, more precise timing
 
 		if (self.cycle % self.sample_period) == 0 {
-			self.sample();
+			self.sample(rom);
 		}
 
 		// Timers
@@ -285,9 +286,10 @@ impl Apu {
 		self.dmc.address_counter
 	}
 
-	fn sample(&mut self) {
+	fn sample(&mut self, rom: &Rom) {
 		// Calculates the audio output within the range of 0.0 to 1.0.
 		// Refer to https://wiki.nesdev.com/w/index.php/APU_Mixer
+		// Cartridge expansion audio is added on top of it.
 
 		let pulse1 = self.pulse1.output() as f32;
 		let pulse2 = self.pulse2.output() as f32;
@@ -306,7 +308,7 @@ impl Apu {
 			tnd_out = 159.79 / (1.0 / (triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0) + 100.0);
 		}
 
-		self.audio.push(pulse_out + tnd_out);
+		self.audio.push(pulse_out + tnd_out + rom.audio_output());
 	}
 
 	/// Save APU state
@@ -348,8 +350,10 @@ impl Apu {
  *   - Length counter
  *   - Envelope
  *   - Sweep
+ * Public because some mappers' expansion audio (e.g. MMC5) has
+ * compatible pulse channels.
  */
-struct ApuPulse {
+pub struct ApuPulse {
 	channel: ApuPulseChannel,
 	register0: Register<u8>, // 0x4000, 0x4004
 	register1: Register<u8>, // 0x4001, 0x4005
@@ -378,13 +382,16 @@ static DUTY_TABLE: [u8; 32] = [
   1, 0, 0, 1, 1, 1, 1, 1
 ];
 
-enum ApuPulseChannel {
+pub enum ApuPulseChannel {
 	Channel1,
-	Channel2
+	Channel2,
+	// Expansion audio pulse without sweep unit (e.g. MMC5).
+	// Not muted by the sweep unit at short or long periods.
+	Expansion
 }
 
 impl ApuPulse {
-	fn new(channel: ApuPulseChannel) -> Self {
+	pub fn new(channel: ApuPulseChannel) -> Self {
 		ApuPulse {
 			channel: channel,
 			register0: Register::<u8>::new(),
@@ -404,7 +411,7 @@ impl ApuPulse {
 		}
 	}
 
-	fn store_register(&mut self, address: u16, value: u8) {
+	pub fn store_register(&mut self, address: u16, value: u8) {
 		match address & 0x4003 {
 			0x4000 => self.register0.store(value),
 			0x4001 => {
@@ -435,7 +442,7 @@ impl ApuPulse {
 		};
 	}
 
-	fn set_enable(&mut self, enabled: bool) {
+	pub fn set_enable(&mut self, enabled: bool) {
 		self.enabled = enabled;
 
 		// When the enabled bit is cleared (via $4015), the length counter is forced to 0
@@ -446,7 +453,7 @@ impl ApuPulse {
 	}
 
 	#[inline(always)]
-	fn drive_timer(&mut self) {
+	pub fn drive_timer(&mut self) {
 		if self.timer_counter > 0 {
 			self.timer_counter -= 1;
 		} else {
@@ -461,13 +468,13 @@ impl ApuPulse {
 	}
 
 	#[inline]
-	fn drive_length(&mut self) {
+	pub fn drive_length(&mut self) {
 		if !self.envelope_loop_enabled() && self.length_counter > 0 {
 			self.length_counter -= 1;
 		}
 	}
 
-	fn drive_envelope(&mut self) {
+	pub fn drive_envelope(&mut self) {
 		if self.envelope_start_flag {
 			self.envelope_counter = self.envelope_period();
 			self.envelope_decay_level_counter = 0xF;
@@ -503,7 +510,7 @@ impl ApuPulse {
 				// @TODO: Fix me
 				true => match self.channel {
 					ApuPulseChannel::Channel1 => !change,
-					ApuPulseChannel::Channel2 => !change + 1
+					ApuPulseChannel::Channel2 | ApuPulseChannel::Expansion => !change + 1
 				},
 				false => change
 			};
@@ -517,10 +524,9 @@ impl ApuPulse {
 		}
 	}
 
-	fn output(&self) -> u8 {
+	pub fn output(&self) -> u8 {
 		if self.length_counter == 0 ||
-			self.timer_period < 8 ||
-			self.timer_period > 0x7FF ||
+			self.sweep_muted() ||
 			DUTY_TABLE[(self.duty() * 8 + self.timer_sequence) as usize] == 0 {
 			return 0;
 		}
@@ -532,6 +538,15 @@ impl ApuPulse {
 		}
 	}
 
+	// The sweep unit mutes the channel at periods out of range
+	// even if the sweep is disabled
+	fn sweep_muted(&self) -> bool {
+		match self.channel {
+			ApuPulseChannel::Expansion => false,
+			_ => self.timer_period < 8 || self.timer_period > 0x7FF
+		}
+	}
+
 	fn duty(&self) -> u8 {
 		self.register0.load_bits(6, 2)
 	}
@@ -580,7 +595,11 @@ impl ApuPulse {
 		self.register3.load_bits(3, 5)
 	}
 
-	fn save_state(&self) -> ApuPulseState {
+	pub fn length_counter(&self) -> u8 {
+		self.length_counter
+	}
+
+	pub fn save_state(&self) -> ApuPulseState {
 		ApuPulseState {
 			register0: self.register0.get_data(),
 			register1: self.register1.get_data(),
@@ -599,7 +618,7 @@ impl ApuPulse {
 		}
 	}
 
-	fn load_state(&mut self, state: &ApuPulseState) {
+	pub fn load_state(&mut self, state: &ApuPulseState) {
 		self.register0.set_data(state.register0);
 		self.register1.set_data(state.register1);
 		self.register2.set_data(state.register2);
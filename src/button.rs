@@ -19,6 +19,9 @@ pub enum Button {
     Y,
     L,
     R,
+	// NSF player track selection
+	NextTrack,
+	PreviousTrack,
 }
 
 pub enum Event {
@@ -1,6 +1,6 @@
 use register::Register;
 use memory::Memory;
-use rom::{HEADER_SIZE, Rom};
+use rom::Rom;
 use ppu::Ppu;
 use apu::Apu;
 use button;
@@ -46,12 +46,15 @@ pub struct Cpu {
 	y: Register<u8>,
 	p: CpuStatusRegister,
 
-	// CPU inside RAM
+	// CPU inside 2KB RAM. 0x4020 - 0xFFFF belongs to the cartridge.
 	ram: Memory,
 
 	// manage additional stall cycles eg. DMA or branch success
 	stall_cycles: u16,
 
+	// The last value on the data bus. Unmapped reads return it (open bus).
+	data_bus: u8,
+
 	input: Box<dyn Input>,
 
 	// other devices
@@ -623,14 +626,15 @@ impl Cpu {
 			x: Register::<u8>::new(),
 			y: Register::<u8>::new(),
 			p: CpuStatusRegister::new(),
-			ram: Memory::new(vec![0; 64 * 1024]), // 64KB
+			ram: Memory::new(vec![0; 2 * 1024]), // 2KB
 			stall_cycles: 0,
+			data_bus: 0,
 			input: input,
 			ppu: Ppu::new(display),
 			apu: Apu::new(audio),
 			joypad1: Joypad::new(),
 			joypad2: Joypad::new(),
-			rom: Rom::new(vec![0; HEADER_SIZE]).unwrap() // dummy
+			rom: Rom::empty() // dummy
 		}
 	}
 
@@ -640,6 +644,7 @@ impl Cpu {
 
 	pub fn bootup(&mut self) {
 		self.power_on = true;
+		self.rom.bootup();
 		self.bootup_internal();
 		self.ppu.bootup();
 		self.apu.bootup();
@@ -669,6 +674,35 @@ impl Cpu {
 		self.interrupt(Interrupts::RESET);
 	}
 
+	/// Selects the NSF track and resets to start playing it.
+	/// Tracks out of range are ignored.
+	pub fn select_track(&mut self, track: u8) {
+		let track_count = match self.rom.nsf_metadata() {
+			Some(metadata) => metadata.track_count,
+			None => return
+		};
+		if track < track_count {
+			self.rom.select_track(track);
+			self.reset();
+		}
+	}
+
+	// Wraps around at the last track
+	pub fn next_track(&mut self) {
+		if let Some(track_count) = self.rom.nsf_metadata().map(|metadata| metadata.track_count) {
+			let track = (self.rom.track() + 1) % track_count;
+			self.select_track(track);
+		}
+	}
+
+	// Wraps around at the first track
+	pub fn previous_track(&mut self) {
+		if let Some(track_count) = self.rom.nsf_metadata().map(|metadata| metadata.track_count) {
+			let track = (self.rom.track() + track_count - 1) % track_count;
+			self.select_track(track);
+		}
+	}
+
 	pub fn is_power_on(&self) -> bool {
 		self.power_on
 	}
@@ -686,6 +720,14 @@ impl Cpu {
 		&mut self.apu
 	}
 
+	pub fn get_rom(&self) -> &Rom {
+		&self.rom
+	}
+
+	pub fn get_mut_rom(&mut self) -> &mut Rom {
+		&mut self.rom
+	}
+
 	pub fn get_mut_input(&mut self) -> &mut Box<dyn Input> {
 		&mut self.input
 	}
@@ -711,7 +753,8 @@ impl Cpu {
 				}
 				false => 0
 			};
-			self.apu.step(dmc_sample_data);
+			self.apu.step(dmc_sample_data, &self.rom);
+			self.rom.step();
 		}
 	}
 
@@ -737,6 +780,16 @@ impl Cpu {
 				button::Button::Reset => {
 					self.reset();
 				},
+				button::Button::NextTrack => {
+					if let button::Event::Press = event {
+						self.next_track();
+					}
+				},
+				button::Button::PreviousTrack => {
+					if let button::Event::Press = event {
+						self.previous_track();
+					}
+				},
 				button::Button::Select |
 				button::Button::Start |
 				button::Button::Joypad1A |
@@ -780,6 +833,11 @@ impl Cpu {
 			self.apu.irq_interrupted = false;
 			self.interrupt(Interrupts::IRQ);
 		}
+		// Mapper IRQ is level triggered. Keeps firing while
+		// the mapper asserts it and interrupts are enabled.
+		if self.rom.irq_pending() {
+			self.interrupt(Interrupts::IRQ);
+		}
 
 		let opc = self.fetch();
 		let op = self.decode(opc);
@@ -1259,6 +1317,13 @@ impl Cpu {
 
 	#[inline]
 	pub fn load(&mut self, address: u16) -> u8 {
+		let value = self.load_internal(address);
+		self.data_bus = value;
+		value
+	}
+
+	#[inline]
+	fn load_internal(&mut self, address: u16) -> u8 {
 		// 0x0000 - 0x07FF: 2KB internal RAM
 		// 0x0800 - 0x1FFF: Mirrors of 0x0000 - 0x07FF (repeats every 0x800 bytes)
 
@@ -1270,7 +1335,7 @@ impl Cpu {
 		// 0x2008 - 0x3FFF: Mirrors of 0x2000 - 0x2007 (repeats every 8 bytes)
 
 		if address >= 0x2000 && address < 0x4000 {
-			return self.ppu.load_register(address & 0x2007, &self.rom);
+			return self.ppu.load_register(address & 0x2007, &mut self.rom);
 		}
 
 		if address >= 0x4000 && address < 0x4014 {
@@ -1278,7 +1343,7 @@ impl Cpu {
 		}
 
 		if address == 0x4014 {
-			return self.ppu.load_register(address, &self.rom);
+			return self.ppu.load_register(address, &mut self.rom);
 		}
 
 		if address == 0x4015 {
@@ -1297,15 +1362,19 @@ impl Cpu {
 			return self.apu.load_register(address);
 		}
 
+		// 0x4020 - 0x5FFF: Mapper registers if any
+
 		if address >= 0x4020 && address < 0x6000 {
-			return self.ram.load(address as u32);
+			return match self.rom.load_register(address as u32) {
+				Some(value) => value,
+				None => self.data_bus // Nothing responds, open bus
+			};
 		}
 
-		if address >= 0x6000 && address < 0x8000 {
-			return self.ram.load(address as u32);
-		}
+		// 0x6000 - 0x7FFF: Battery Backed Save or Work RAM in general
+		// 0x8000 - 0xFFFF: ROM
 
-		if address >= 0x8000 {
+		if address >= 0x6000 {
 			return self.rom.load(address as u32);
 		}
 
@@ -1332,6 +1401,8 @@ impl Cpu {
 
 	#[inline]
 	fn store(&mut self, address: u16, value: u8) {
+		self.data_bus = value;
+
 		// 0x0000 - 0x07FF: 2KB internal RAM
 		// 0x0800 - 0x1FFF: Mirrors of 0x0000 - 0x07FF (repeats every 0x800 bytes)
 
@@ -1382,20 +1453,16 @@ impl Cpu {
 		}
 
 		// cartridge space
+		// 0x4020 - 0x5FFF: Mapper registers if any
 
 		if address >= 0x4020 && address < 0x6000 {
-			self.ram.store(address as u32, value);
-		}
-
-		// 0x6000 - 0x7FFF: Battery Backed Save or Work RAM
-
-		if address >= 0x6000 && address < 0x8000 {
-			self.ram.store(address as u32, value);
+			self.rom.store_register(address as u32, value);
 		}
 
+		// 0x6000 - 0x7FFF: Battery Backed Save or Work RAM in general
 		// 0x8000 - 0xFFFF: ROM
 
-		if address >= 0x8000 {
+		if address >= 0x6000 {
 			self.rom.store(address as u32, value);
 		}
 	}
@@ -1745,6 +1812,9 @@ impl Cpu {
 		// Mapper state
 		state.mapper = self.rom.save_mapper_state();
 
+		// Cartridge RAM state
+		state.cartridge = self.rom.save_cartridge_state();
+
 		state
 	}
 
@@ -1772,6 +1842,9 @@ impl Cpu {
 
 		// Mapper state
 		self.rom.load_mapper_state(&state.mapper);
+
+		// Cartridge RAM state
+		self.rom.load_cartridge_state(&state.cartridge);
 	}
 }
 
@@ -1907,3 +1980,41 @@ impl CpuStatusRegister {
 		&")".to_owned()
 	}
 }
+
+#[cfg(test)]
+mod tests_cpu {
+	use super::*;
+	use default_input::DefaultInput;
+	use default_display::DefaultDisplay;
+	use default_audio::DefaultAudio;
+
+	fn nrom_cpu() -> Cpu {
+		let mut v = vec![0; 16];
+		v[0] = 0x4e; // N
+		v[1] = 0x45; // E
+		v[2] = 0x53; // S
+		v[3] = 0x1a; // magic number
+		v[4] = 1;
+		v[5] = 1;
+		v.resize(16 + 0x4000 + 0x2000, 0);
+		let mut cpu = Cpu::new(Box::new(DefaultInput::new()),
+			Box::new(DefaultDisplay::new()), Box::new(DefaultAudio::new()));
+		cpu.set_rom(Rom::new(v).unwrap());
+		cpu
+	}
+
+	#[test]
+	fn unmapped_expansion_area_is_open_bus() {
+		let mut cpu = nrom_cpu();
+		cpu.store(0x0000, 0x5A);
+		cpu.store(0x0001, 0xA5);
+		assert_eq!(cpu.load(0x0000), 0x5A);
+		assert_eq!(cpu.load(0x5000), 0x5A);
+		assert_eq!(cpu.load(0x0001), 0xA5);
+		assert_eq!(cpu.load(0x4020), 0xA5);
+		// Writes to unmapped addresses aren't stored anywhere
+		cpu.store(0x5000, 0x77);
+		assert_eq!(cpu.load(0x0000), 0x5A);
+		assert_eq!(cpu.load(0x5000), 0x5A);
+	}
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+// Embedded game database in the NES 2.0 header database (nes20db.xml) format
+const EMBEDDED_DATABASE: &str = include_str!("nes20db.xml");
+
+lazy_static! {
+	static ref DATABASE: RomDatabase = RomDatabase::parse(EMBEDDED_DATABASE);
+}
+
+/**
+ * Header fields of a game in the database, for the PRG-ROM + CHR-ROM
+ * data dumped right.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct DatabaseEntry {
+	// File name in the comment the database puts before the entry
+	pub title: String,
+	// Of PRG-ROM + CHR-ROM
+	pub crc32: u32,
+	// Uppercase hex. Empty if the database doesn't have it.
+	pub sha1: String,
+	pub mapper_num: u16,
+	pub submapper_num: u8,
+	// "H": Horizontal, "V": Vertical, "4": Four-screen
+	pub mirroring: String,
+	pub battery: bool,
+	pub prg_ram_size: usize,
+	pub prg_nvram_size: usize,
+	pub chr_ram_size: usize,
+	pub chr_nvram_size: usize,
+	// NES 2.0 console type and CPU/PPU timing (region)
+	pub console_type: u8,
+	pub region: u8
+}
+
+/**
+ * Games keyed by PRG-ROM + CHR-ROM CRC32.
+ * Refer to https://forums.nesdev.org/viewtopic.php?t=19940
+ */
+pub struct RomDatabase {
+	entries: HashMap<u32, Vec<DatabaseEntry>>
+}
+
+impl RomDatabase {
+	/// The database embedded in this crate. It only has the ROMs bundled
+	/// in roms/, the full nes20db.xml is to be parsed with parse().
+	pub fn embedded() -> &'static RomDatabase {
+		&DATABASE
+	}
+
+	/// Parses nes20db.xml. Entries without <rom crc32> or <pcb> are skipped.
+	pub fn parse(xml: &str) -> Self {
+		let mut entries: HashMap<u32, Vec<DatabaseEntry>> = HashMap::new();
+		for game in xml.split("<game>").skip(1) {
+			let game = game.split("</game>").next().unwrap_or("");
+			if let Some(entry) = parse_game(game) {
+				entries.entry(entry.crc32).or_default().push(entry);
+			}
+		}
+		RomDatabase {
+			entries: entries
+		}
+	}
+
+	/// Whether any game has the PRG-ROM + CHR-ROM CRC32, to compute
+	/// SHA-1 only when it's needed
+	pub fn contains_crc32(&self, crc32: u32) -> bool {
+		self.entries.contains_key(&crc32)
+	}
+
+	/// Game of the PRG-ROM + CHR-ROM hashes. SHA-1 (uppercase hex)
+	/// tells games having the same CRC32 apart if the database has it.
+	pub fn find(&self, crc32: u32, sha1: &str) -> Option<&DatabaseEntry> {
+		self.entries.get(&crc32)?.iter()
+			.find(|entry| entry.sha1.is_empty() || entry.sha1 == sha1)
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.values().map(|entries| entries.len()).sum()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+}
+
+fn parse_game(game: &str) -> Option<DatabaseEntry> {
+	// <!-- path\to\Title.nes -->
+	let title = game.split("<!--").nth(1)
+		.and_then(|comment| comment.split("-->").next())
+		.map(|path| path.trim().rsplit(['\\', '/']).next().unwrap_or(""))
+		.map(|file| match file.rfind('.') {
+			Some(index) => &file[..index],
+			None => file
+		})
+		.unwrap_or("");
+
+	let rom = find_tag(game, "rom")?;
+	let pcb = find_tag(game, "pcb")?;
+	let size = |name: &str| find_tag(game, name)
+		.and_then(|tag| attribute(tag, "size"))
+		.and_then(|size| size.parse().ok())
+		.unwrap_or(0);
+	let console = find_tag(game, "console").unwrap_or("");
+	let number = |tag: &str, name: &str| attribute(tag, name).and_then(|value| value.parse().ok()).unwrap_or(0);
+
+	Some(DatabaseEntry {
+		title: title.to_string(),
+		crc32: u32::from_str_radix(attribute(rom, "crc32")?, 16).ok()?,
+		sha1: attribute(rom, "sha1").unwrap_or("").to_uppercase(),
+		mapper_num: number(pcb, "mapper") as u16,
+		submapper_num: number(pcb, "submapper") as u8,
+		mirroring: attribute(pcb, "mirroring").unwrap_or("").to_string(),
+		battery: attribute(pcb, "battery") == Some("1"),
+		prg_ram_size: size("prgram"),
+		prg_nvram_size: size("prgnvram"),
+		chr_ram_size: size("chrram"),
+		chr_nvram_size: size("chrnvram"),
+		console_type: number(console, "type") as u8,
+		region: number(console, "region") as u8
+	})
+}
+
+// Attributes of the first <name .../> tag
+fn find_tag<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+	let start = format!("<{} ", name);
+	let index = xml.find(&start)? + start.len();
+	let end = xml[index..].find('>')?;
+	Some(&xml[index..index + end])
+}
+
+fn attribute<'a>(attributes: &'a str, name: &str) -> Option<&'a str> {
+	let start = format!("{}=\"", name);
+	let index = attributes.split_whitespace()
+		.find(|attribute| attribute.starts_with(&start))?;
+	index[start.len()..].split('"').next()
+}
+
+/// Board the mapper number stands for, for displaying.
+/// Empty if unknown.
+pub fn board_name(mapper_num: u16, submapper_num: u8) -> &'static str {
+	match (mapper_num, submapper_num) {
+		(0, _) => "NROM",
+		(1, _) => "MMC1 (SxROM)",
+		(2, _) => "UxROM",
+		(3, _) => "CNROM",
+		(4, 1) => "MMC6 (HKROM)",
+		(4, _) => "MMC3 (TxROM)",
+		(5, _) => "MMC5 (ExROM)",
+		(7, _) => "AxROM",
+		(9, _) => "MMC2 (PxROM)",
+		(10, _) => "MMC4 (FxROM)",
+		(11, _) => "Color Dreams",
+		(13, _) => "CPROM",
+		(19, _) => "Namco 163",
+		(20, _) => "Famicom Disk System",
+		(21, _) | (22, _) | (23, _) | (25, _) => "Konami VRC2 / VRC4",
+		(24, _) | (26, _) => "Konami VRC6",
+		(30, _) => "UNROM 512",
+		(34, 1) => "NINA-001",
+		(34, _) => "BNROM",
+		(59, _) => "D1038 multicart",
+		(66, _) => "GxROM",
+		(69, _) => "Sunsoft FME-7",
+		(71, _) => "Camerica BF909x",
+		(85, _) => "Konami VRC7",
+		(118, _) => "TxSROM",
+		(119, _) => "TQROM",
+		(155, _) => "MMC1A",
+		(225, _) | (255, _) => "72-in-1 multicart",
+		(226, _) => "76-in-1 multicart",
+		(227, _) => "1200-in-1 multicart",
+		(228, _) => "Action 52",
+		_ => ""
+	}
+}
+
+/// SHA-1 in uppercase hex of the pieces of data joined, hashed
+/// without joining them
+pub fn sha1(pieces: &[&[u8]]) -> String {
+	let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+	// Blocks are hashed in place. Only the ones across pieces and
+	// the tail are copied.
+	let mut block = [0u8; 64];
+	let mut filled = 0;
+	let mut length: u64 = 0;
+	for piece in pieces {
+		length += piece.len() as u64;
+		let mut piece = *piece;
+		if filled > 0 {
+			let size = piece.len().min(64 - filled);
+			block[filled..filled + size].copy_from_slice(&piece[..size]);
+			filled += size;
+			piece = &piece[size..];
+			if filled < 64 {
+				continue;
+			}
+			sha1_block(&mut h, &block);
+		}
+		let blocks = piece.chunks_exact(64);
+		let tail = blocks.remainder();
+		for data in blocks {
+			sha1_block(&mut h, data);
+		}
+		block[..tail.len()].copy_from_slice(tail);
+		filled = tail.len();
+	}
+
+	// Appends 0x80, zeros, and the bit length in big endian
+	block[filled] = 0x80;
+	for byte in block[filled + 1..].iter_mut() {
+		*byte = 0;
+	}
+	if filled >= 56 {
+		sha1_block(&mut h, &block);
+		block = [0; 64];
+	}
+	block[56..].copy_from_slice(&(length * 8).to_be_bytes());
+	sha1_block(&mut h, &block);
+
+	h.iter().map(|value| format!("{:08X}", value)).collect()
+}
+
+fn sha1_block(h: &mut [u32; 5], block: &[u8]) {
+	let mut w = [0u32; 80];
+	for i in 0..16 {
+		w[i] = u32::from_be_bytes([block[i * 4], block[i * 4 + 1], block[i * 4 + 2], block[i * 4 + 3]]);
+	}
+	for i in 16..80 {
+		w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+	}
+
+	let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+	for (i, &word) in w.iter().enumerate() {
+		let (f, k) = match i / 20 {
+			0 => ((b & c) | (!b & d), 0x5A827999),
+			1 => (b ^ c ^ d, 0x6ED9EBA1),
+			2 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+			_ => (b ^ c ^ d, 0xCA62C1D6)
+		};
+		let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+		e = d;
+		d = c;
+		c = b.rotate_left(30);
+		b = a;
+		a = temp;
+	}
+	for (value, add) in h.iter_mut().zip([a, b, c, d, e].iter()) {
+		*value = value.wrapping_add(*add);
+	}
+}
+
+#[cfg(test)]
+mod tests_database {
+	use super::*;
+
+	#[test]
+	fn sha1_() {
+		assert_eq!("DA39A3EE5E6B4B0D3255BFEF95601890AFD80709", sha1(&[]));
+		assert_eq!("A9993E364706816ABA3E25717850C26C9CD0D89D", sha1(&[b"abc"]));
+		assert_eq!("84983E441C3BD26EBAAE4AA1F95129E5E54670F1",
+			sha1(&[b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"]));
+		// Pieces split across blocks
+		let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+		let whole = sha1(&[&data]);
+		assert_eq!(whole, sha1(&[&data[..1], &data[1..70], &data[70..128], &data[128..]]));
+		assert_eq!(whole, sha1(&[&data[..60], &[], &data[60..]]));
+		assert_eq!(sha1(&[&data[..120]]), sha1(&[&data[..100], &data[100..120]]));
+	}
+
+	#[test]
+	fn parse() {
+		let database = RomDatabase::parse(r#"<nes20db>
+			<game>
+				<!-- ..\NES\Game (USA).nes -->
+				<prgrom size="131072" crc32="11111111" sha1="AA"/>
+				<rom size="131072" crc32="1234ABCD" sha1="abcd"/>
+				<prgnvram size="8192"/>
+				<chrram size="8192"/>
+				<pcb mapper="1" submapper="5" mirroring="V" battery="1"/>
+				<console type="0" region="1"/>
+			</game>
+			<game>
+				<rom size="16384" crc32="0000FFFF"/>
+			</game>
+		</nes20db>"#);
+		assert_eq!(1, database.len());
+		assert_eq!(None, database.find(0x1234ABCD, "ABCE"));
+		assert_eq!(Some(&DatabaseEntry {
+			title: "Game (USA)".to_string(),
+			crc32: 0x1234ABCD,
+			sha1: "ABCD".to_string(),
+			mapper_num: 1,
+			submapper_num: 5,
+			mirroring: "V".to_string(),
+			battery: true,
+			prg_ram_size: 0,
+			prg_nvram_size: 0x2000,
+			chr_ram_size: 0x2000,
+			chr_nvram_size: 0,
+			console_type: 0,
+			region: 1
+		}), database.find(0x1234ABCD, "ABCD"));
+	}
+
+	#[test]
+	fn embedded() {
+		let database = RomDatabase::embedded();
+		assert!(!database.is_empty());
+		assert_eq!("nestest", database.find(0x158B0388, "4131307F0F69F2A5C54B7D438328C5B2A5ED0820").unwrap().title);
+	}
+}
@@ -0,0 +1,610 @@
+use save_state::{FdsAudioState, FdsEnvelopeState};
+
+// Size of a disk side in .fds images
+pub const FDS_SIDE_SIZE: usize = 65500;
+pub const FDS_BIOS_SIZE: usize = 0x2000;
+
+// Optional fwNES header preceding the disk sides: "FDS" 0x1A, side count
+const FDS_HEADER_SIZE: usize = 16;
+const FDS_HEADER_SIGNATURE: [u8; 4] = [0x46, 0x44, 0x53, 0x1A];
+
+// Disk info block each side starts with: block code 1 and "*NINTENDO-HVC*"
+const DISK_INFO_SIGNATURE: [u8; 15] = [
+	0x01, 0x2A, 0x4E, 0x49, 0x4E, 0x54, 0x45, 0x4E,
+	0x44, 0x4F, 0x2D, 0x48, 0x56, 0x43, 0x2A
+];
+
+// Gaps on the disk in bytes. The drive reads them as 0 until
+// the start mark 0x80 of the next block.
+const LEADING_GAP_SIZE: usize = 28300 / 8;
+const BLOCK_GAP_SIZE: usize = 976 / 8;
+const BLOCK_START_MARK: u8 = 0x80;
+
+// The drive doesn't verify CRCs, dumps don't have them either
+const PLACEHOLDER_CRC: [u8; 2] = [0x4D, 0x62];
+
+/// Whether the data is an FDS disk image, with or without the fwNES header
+pub fn is_fds_image(data: &[u8]) -> bool {
+	let data = strip_header(data);
+	data.len() >= DISK_INFO_SIGNATURE.len() && data[..DISK_INFO_SIGNATURE.len()] == DISK_INFO_SIGNATURE
+}
+
+fn strip_header(data: &[u8]) -> &[u8] {
+	match data.len() >= FDS_HEADER_SIZE && data[..4] == FDS_HEADER_SIGNATURE {
+		true => &data[FDS_HEADER_SIZE..],
+		false => data
+	}
+}
+
+// Length of a block with the block code, including the code.
+// None if it isn't a block code.
+fn block_length(code: u8, file_size: usize) -> Option<usize> {
+	match code {
+		1 => Some(56), // Disk info
+		2 => Some(2), // File amount
+		3 => Some(16), // File header
+		4 => Some(1 + file_size), // File data
+		_ => None
+	}
+}
+
+// File size in a file header block
+fn file_size_of(block: &[u8]) -> usize {
+	block[13] as usize | ((block[14] as usize) << 8)
+}
+
+/**
+ * FDS disk image. Sides are kept as the drive sees them, blocks
+ * preceded by gaps and start marks and followed by CRCs, so that
+ * writes of the BIOS land as on a real disk.
+ * Refer to https://wiki.nesdev.com/w/index.php/FDS_disk_format
+ */
+pub struct FdsDisk {
+	sides: Vec<Vec<u8>>
+}
+
+impl FdsDisk {
+	/// Parses .fds image. None if the data isn't an FDS disk image.
+	pub fn new(data: &[u8]) -> Option<Self> {
+		if !is_fds_image(data) {
+			return None;
+		}
+		let data = strip_header(data);
+		// Some dumps miss the unused tail of the last side
+		let side_count = (data.len() + FDS_SIDE_SIZE - 1) / FDS_SIDE_SIZE;
+		let sides = (0..side_count).map(|i| {
+			let end = std::cmp::min((i + 1) * FDS_SIDE_SIZE, data.len());
+			to_raw_side(&data[i * FDS_SIDE_SIZE..end])
+		}).collect();
+		Some(FdsDisk {
+			sides: sides
+		})
+	}
+
+	pub fn side_count(&self) -> usize {
+		self.sides.len()
+	}
+
+	pub fn side(&self, side: usize) -> &[u8] {
+		&self.sides[side]
+	}
+
+	pub fn side_mut(&mut self, side: usize) -> &mut [u8] {
+		&mut self.sides[side]
+	}
+
+	/// Rebuilds .fds image without the fwNES header
+	pub fn to_image(&self) -> Vec<u8> {
+		let mut image = Vec::with_capacity(self.sides.len() * FDS_SIDE_SIZE);
+		for side in self.sides.iter() {
+			image.extend_from_slice(&from_raw_side(side));
+		}
+		image
+	}
+
+	pub fn save_state(&self) -> Vec<Vec<u8>> {
+		self.sides.clone()
+	}
+
+	pub fn load_state(&mut self, sides: &[Vec<u8>]) {
+		if sides.len() == self.sides.len() {
+			self.sides = sides.to_vec();
+		}
+	}
+}
+
+// Inserts gaps, start marks and CRCs between the blocks of .fds side
+fn to_raw_side(side: &[u8]) -> Vec<u8> {
+	let mut raw = vec![0; LEADING_GAP_SIZE];
+	let mut position = 0;
+	let mut file_size = 0;
+	while position < side.len() {
+		let length = match block_length(side[position], file_size) {
+			Some(length) if position + length <= side.len() => length,
+			_ => break
+		};
+		let block = &side[position..position + length];
+		if block[0] == 3 {
+			file_size = file_size_of(block);
+		}
+		raw.push(BLOCK_START_MARK);
+		raw.extend_from_slice(block);
+		raw.extend_from_slice(&PLACEHOLDER_CRC);
+		raw.resize(raw.len() + BLOCK_GAP_SIZE, 0);
+		position += length;
+	}
+	// Leaves room for the blocks the BIOS appends
+	if raw.len() < LEADING_GAP_SIZE + FDS_SIDE_SIZE {
+		raw.resize(LEADING_GAP_SIZE + FDS_SIDE_SIZE, 0);
+	}
+	raw
+}
+
+// Removes gaps, start marks and CRCs to get .fds side back
+fn from_raw_side(raw: &[u8]) -> Vec<u8> {
+	let mut side = Vec::with_capacity(FDS_SIDE_SIZE);
+	let mut position = 0;
+	let mut file_size = 0;
+	loop {
+		while position < raw.len() && raw[position] != BLOCK_START_MARK {
+			position += 1;
+		}
+		position += 1;
+		if position >= raw.len() {
+			break;
+		}
+		let length = match block_length(raw[position], file_size) {
+			Some(length) if position + length <= raw.len() => length,
+			_ => break
+		};
+		let block = &raw[position..position + length];
+		if block[0] == 3 {
+			file_size = file_size_of(block);
+		}
+		side.extend_from_slice(block);
+		position += length + PLACEHOLDER_CRC.len();
+	}
+	side.resize(FDS_SIDE_SIZE, 0);
+	side
+}
+
+// Master volume of 0x4089 bits 1-0. 2/2, 2/3, 2/4 and 2/5
+const MASTER_VOLUMES: [u32; 4] = [36, 24, 17, 14];
+
+// Modulation counter adjustments of the modulation table entries.
+// The entry 4 resets the counter.
+const MODULATION_ADJUSTMENTS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+
+/**
+ * Volume and modulation envelopes
+ */
+struct FdsEnvelope {
+	speed: u8,
+	gain: u8,
+	disabled: bool,
+	increase: bool,
+	timer: u32
+}
+
+impl FdsEnvelope {
+	fn new() -> Self {
+		FdsEnvelope {
+			speed: 0,
+			gain: 0,
+			disabled: true,
+			increase: false,
+			timer: 0
+		}
+	}
+
+	fn store(&mut self, value: u8, master_speed: u8) {
+		self.disabled = (value & 0x80) != 0;
+		self.increase = (value & 0x40) != 0;
+		self.speed = value & 0x3F;
+		if self.disabled {
+			self.gain = self.speed;
+		}
+		self.reset_timer(master_speed);
+	}
+
+	fn reset_timer(&mut self, master_speed: u8) {
+		self.timer = 8 * (self.speed as u32 + 1) * master_speed as u32;
+	}
+
+	// Expects being called at CPU clock rate
+	fn clock(&mut self, master_speed: u8) {
+		if self.disabled || master_speed == 0 {
+			return;
+		}
+		if self.timer > 1 {
+			self.timer -= 1;
+			return;
+		}
+		self.reset_timer(master_speed);
+		if self.increase && self.gain < 32 {
+			self.gain += 1;
+		} else if !self.increase && self.gain > 0 {
+			self.gain -= 1;
+		}
+	}
+
+	fn save_state(&self) -> FdsEnvelopeState {
+		FdsEnvelopeState {
+			speed: self.speed,
+			gain: self.gain,
+			disabled: self.disabled,
+			increase: self.increase,
+			timer: self.timer
+		}
+	}
+
+	fn load_state(&mut self, state: &FdsEnvelopeState) {
+		self.speed = state.speed;
+		self.gain = state.gain;
+		self.disabled = state.disabled;
+		self.increase = state.increase;
+		self.timer = state.timer;
+	}
+}
+
+/**
+ * FDS expansion audio. A 64-step 6-bit wavetable channel whose
+ * pitch is modulated with another 64-step table.
+ * Refer to https://wiki.nesdev.com/w/index.php/FDS_audio
+ */
+pub struct FdsAudio {
+	wave_table: [u8; 64], // 0x4040 - 0x407F
+	wave_frequency: u16, // 0x4082 - 0x4083
+	wave_halted: bool, // 0x4083 bit 7
+	envelopes_halted: bool, // 0x4083 bit 6
+	wave_accumulator: u32, // Bits 21-16 are the wave position
+	volume: FdsEnvelope, // 0x4080
+	modulation: FdsEnvelope, // 0x4084
+	mod_counter: i8, // 0x4085, 7-bit signed
+	mod_frequency: u16, // 0x4086 - 0x4087
+	mod_halted: bool, // 0x4087 bit 7
+	mod_table: [u8; 64], // 0x4088
+	mod_position: u8,
+	mod_accumulator: u32,
+	wave_write_enabled: bool, // 0x4089 bit 7
+	master_volume: u8, // 0x4089 bits 1-0
+	master_speed: u8, // 0x408A
+	output: u8
+}
+
+impl FdsAudio {
+	pub fn new() -> Self {
+		FdsAudio {
+			wave_table: [0; 64],
+			wave_frequency: 0,
+			wave_halted: true,
+			envelopes_halted: true,
+			wave_accumulator: 0,
+			volume: FdsEnvelope::new(),
+			modulation: FdsEnvelope::new(),
+			mod_counter: 0,
+			mod_frequency: 0,
+			mod_halted: true,
+			mod_table: [0; 64],
+			mod_position: 0,
+			mod_accumulator: 0,
+			wave_write_enabled: false,
+			master_volume: 0,
+			master_speed: 0xE8,
+			output: 0
+		}
+	}
+
+	/**
+	 * 0x4040 - 0x407F: Wavetable
+	 * 0x4090: Volume gain
+	 * 0x4092: Modulation gain
+	 * None if not readable.
+	 */
+	pub fn load(&self, address: u32) -> Option<u8> {
+		match address {
+			0x4040..=0x407F => Some(self.wave_table[(address & 0x3F) as usize] | 0x40),
+			0x4090 => Some(self.volume.gain | 0x40),
+			0x4092 => Some(self.modulation.gain | 0x40),
+			_ => None
+		}
+	}
+
+	pub fn store(&mut self, address: u32, value: u8) {
+		match address {
+			0x4040..=0x407F => {
+				if self.wave_write_enabled {
+					self.wave_table[(address & 0x3F) as usize] = value & 0x3F;
+				}
+			},
+			0x4080 => self.volume.store(value, self.master_speed),
+			0x4082 => {
+				self.wave_frequency = (self.wave_frequency & 0xF00) | value as u16;
+			},
+			0x4083 => {
+				self.wave_frequency = (self.wave_frequency & 0xFF) | ((value as u16 & 0xF) << 8);
+				self.wave_halted = (value & 0x80) != 0;
+				self.envelopes_halted = (value & 0x40) != 0;
+				if self.wave_halted {
+					self.wave_accumulator = 0;
+				}
+				if self.envelopes_halted {
+					self.volume.reset_timer(self.master_speed);
+					self.modulation.reset_timer(self.master_speed);
+				}
+			},
+			0x4084 => self.modulation.store(value, self.master_speed),
+			0x4085 => self.set_mod_counter(value as i32),
+			0x4086 => {
+				self.mod_frequency = (self.mod_frequency & 0xF00) | value as u16;
+			},
+			0x4087 => {
+				self.mod_frequency = (self.mod_frequency & 0xFF) | ((value as u16 & 0xF) << 8);
+				self.mod_halted = (value & 0x80) != 0;
+				if self.mod_halted {
+					self.mod_accumulator = 0;
+				}
+			},
+			0x4088 => {
+				// Writes two entries at once while the modulation is halted
+				if self.mod_halted {
+					self.mod_table[self.mod_position as usize] = value & 0x7;
+					self.mod_table[(self.mod_position as usize + 1) & 0x3F] = value & 0x7;
+					self.mod_position = (self.mod_position + 2) & 0x3F;
+				}
+			},
+			0x4089 => {
+				self.wave_write_enabled = (value & 0x80) != 0;
+				self.master_volume = value & 0x3;
+			},
+			0x408A => {
+				self.master_speed = value;
+			},
+			_ => {}
+		}
+	}
+
+	// Wraps in 7-bit signed range
+	fn set_mod_counter(&mut self, value: i32) {
+		self.mod_counter = (((value + 64) & 0x7F) - 64) as i8;
+	}
+
+	// Pitch offset of the modulation, with the nesdev wiki formula
+	fn modulation_offset(&self) -> i32 {
+		let mut temp = self.mod_counter as i32 * self.modulation.gain as i32;
+		let remainder = temp & 0xF;
+		temp >>= 4;
+		if remainder > 0 && (temp & 0x80) == 0 {
+			temp += match self.mod_counter < 0 {
+				true => -1,
+				false => 2
+			};
+		}
+		if temp >= 192 {
+			temp -= 256;
+		} else if temp < -64 {
+			temp += 256;
+		}
+		temp *= self.wave_frequency as i32;
+		let remainder = temp & 0x3F;
+		temp >>= 6;
+		if remainder >= 32 {
+			temp += 1;
+		}
+		temp
+	}
+
+	// Expects being called at CPU clock rate
+	pub fn clock(&mut self) {
+		if !self.wave_halted && !self.envelopes_halted {
+			self.volume.clock(self.master_speed);
+			self.modulation.clock(self.master_speed);
+		}
+
+		if !self.mod_halted && self.mod_frequency > 0 {
+			self.mod_accumulator += self.mod_frequency as u32;
+			if self.mod_accumulator >= 0x10000 {
+				self.mod_accumulator &= 0xFFFF;
+				let entry = self.mod_table[self.mod_position as usize] as usize;
+				let counter = match entry {
+					4 => 0,
+					_ => self.mod_counter as i32 + MODULATION_ADJUSTMENTS[entry] as i32
+				};
+				self.set_mod_counter(counter);
+				self.mod_position = (self.mod_position + 1) & 0x3F;
+			}
+		}
+
+		// The output holds while the wavetable is writable
+		if self.wave_write_enabled {
+			return;
+		}
+
+		if !self.wave_halted {
+			let pitch = self.wave_frequency as i32 + self.modulation_offset();
+			if pitch > 0 {
+				self.wave_accumulator = (self.wave_accumulator + pitch as u32) & 0x3FFFFF;
+			}
+		}
+
+		let position = (self.wave_accumulator >> 16) as usize;
+		let level = std::cmp::min(self.volume.gain, 32) as u32 * MASTER_VOLUMES[self.master_volume as usize];
+		self.output = (self.wave_table[position] as u32 * level / 1152) as u8;
+	}
+
+	// 0 - 63
+	pub fn output(&self) -> u8 {
+		self.output
+	}
+
+	pub fn save_state(&self) -> FdsAudioState {
+		FdsAudioState {
+			wave_table: self.wave_table.to_vec(),
+			wave_frequency: self.wave_frequency,
+			wave_halted: self.wave_halted,
+			envelopes_halted: self.envelopes_halted,
+			wave_accumulator: self.wave_accumulator,
+			volume: self.volume.save_state(),
+			modulation: self.modulation.save_state(),
+			mod_counter: self.mod_counter,
+			mod_frequency: self.mod_frequency,
+			mod_halted: self.mod_halted,
+			mod_table: self.mod_table.to_vec(),
+			mod_position: self.mod_position,
+			mod_accumulator: self.mod_accumulator,
+			wave_write_enabled: self.wave_write_enabled,
+			master_volume: self.master_volume,
+			master_speed: self.master_speed,
+			output: self.output
+		}
+	}
+
+	/// Err without touching the channel if the tables have wrong lengths
+	pub fn load_state(&mut self, state: &FdsAudioState) -> Result<(), String> {
+		if state.wave_table.len() != self.wave_table.len() ||
+			state.mod_table.len() != self.mod_table.len() {
+			return Err("Invalid FDS audio state".to_owned());
+		}
+		self.wave_table.copy_from_slice(&state.wave_table);
+		self.wave_frequency = state.wave_frequency;
+		self.wave_halted = state.wave_halted;
+		self.envelopes_halted = state.envelopes_halted;
+		self.wave_accumulator = state.wave_accumulator & 0x3FFFFF;
+		self.volume.load_state(&state.volume);
+		self.modulation.load_state(&state.modulation);
+		self.mod_counter = state.mod_counter;
+		self.mod_frequency = state.mod_frequency;
+		self.mod_halted = state.mod_halted;
+		self.mod_table.copy_from_slice(&state.mod_table);
+		self.mod_position = state.mod_position & 0x3F;
+		self.mod_accumulator = state.mod_accumulator;
+		self.wave_write_enabled = state.wave_write_enabled;
+		self.master_volume = state.master_volume & 0x3;
+		self.master_speed = state.master_speed;
+		self.output = state.output;
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests_fds {
+	use super::*;
+
+	// Side with the disk info, file amount and a 3-byte file
+	fn side() -> Vec<u8> {
+		let mut side = DISK_INFO_SIGNATURE.to_vec();
+		side.resize(56, 0);
+		side.extend_from_slice(&[2, 1]);
+		let mut header = vec![3; 16];
+		header[13] = 3;
+		header[14] = 0;
+		side.extend_from_slice(&header);
+		side.extend_from_slice(&[4, 0xAA, 0x80, 0x55]);
+		side.resize(FDS_SIDE_SIZE, 0);
+		side
+	}
+
+	#[test]
+	fn is_fds_image_() {
+		assert!(is_fds_image(&side()));
+		let mut image = FDS_HEADER_SIGNATURE.to_vec();
+		image.push(1);
+		image.resize(FDS_HEADER_SIZE, 0);
+		image.extend_from_slice(&side());
+		assert!(is_fds_image(&image));
+		assert!(!is_fds_image(&[0x4E, 0x45, 0x53, 0x1A]));
+	}
+
+	#[test]
+	fn raw_side() {
+		let mut image = side();
+		image.extend_from_slice(&side());
+		let disk = FdsDisk::new(&image).unwrap();
+		assert_eq!(2, disk.side_count());
+		let raw = disk.side(1);
+		assert_eq!(0, raw[LEADING_GAP_SIZE - 1]);
+		assert_eq!(BLOCK_START_MARK, raw[LEADING_GAP_SIZE]);
+		assert_eq!(1, raw[LEADING_GAP_SIZE + 1]);
+		// Gap, start mark and CRC follow the disk info block
+		let next = LEADING_GAP_SIZE + 1 + 56 + 2 + BLOCK_GAP_SIZE;
+		assert_eq!(BLOCK_START_MARK, raw[next]);
+		assert_eq!(2, raw[next + 1]);
+		assert_eq!(image, disk.to_image());
+	}
+
+	#[test]
+	fn rebuild_written_side() {
+		let mut disk = FdsDisk::new(&side()).unwrap();
+		// The file data block is the last one
+		let position = disk.side(0).iter().rposition(|&b| b == 0x55).unwrap();
+		disk.side_mut(0)[position] = 0x66;
+		let image = disk.to_image();
+		assert_eq!(FDS_SIDE_SIZE, image.len());
+		assert_eq!([4, 0xAA, 0x80, 0x66], image[56 + 2 + 16..56 + 2 + 16 + 4]);
+	}
+
+	#[test]
+	fn wave_output() {
+		let mut audio = FdsAudio::new();
+		audio.store(0x4089, 0x80);
+		for i in 0..64 {
+			audio.store(0x4040 + i, 63);
+		}
+		assert_eq!(Some(63 | 0x40), audio.load(0x4040));
+		audio.store(0x4089, 0x00);
+		// Volume gain 32 without envelope, the highest pitch
+		audio.store(0x4080, 0xA0);
+		audio.store(0x4082, 0xFF);
+		audio.store(0x4083, 0x0F);
+		audio.clock();
+		assert_eq!(63, audio.output());
+		// Wavetable isn't writable while playing
+		audio.store(0x4040, 0);
+		assert_eq!(Some(63 | 0x40), audio.load(0x4040));
+	}
+
+	#[test]
+	fn modulation_counter() {
+		let mut audio = FdsAudio::new();
+		audio.store(0x4087, 0x80);
+		// +4 twice then reset. 32 writes fill the table
+		audio.store(0x4088, 3);
+		audio.store(0x4088, 4);
+		for _ in 0..30 {
+			audio.store(0x4088, 0);
+		}
+		audio.store(0x4085, 0x3E);
+		audio.store(0x4086, 0x00);
+		audio.store(0x4087, 0x08);
+		for _ in 0..0x20 {
+			audio.clock();
+		}
+		// 62 + 4 wraps to -62
+		assert_eq!(-62, audio.mod_counter);
+		for _ in 0..0x20 {
+			audio.clock();
+		}
+		assert_eq!(-58, audio.mod_counter);
+		for _ in 0..0x20 {
+			audio.clock();
+		}
+		assert_eq!(0, audio.mod_counter);
+	}
+
+	#[test]
+	fn load_invalid_audio_state() {
+		let mut audio = FdsAudio::new();
+		audio.store(0x4089, 0x80);
+		audio.store(0x4040, 0x21);
+		let mut state = audio.save_state();
+		state.wave_table.truncate(63);
+		let mut loaded = FdsAudio::new();
+		assert!(loaded.load_state(&state).is_err());
+		assert_eq!(Some(0x40), loaded.load(0x4040));
+		let mut state = audio.save_state();
+		state.mod_table.push(0);
+		assert!(loaded.load_state(&state).is_err());
+		assert!(loaded.load_state(&audio.save_state()).is_ok());
+		assert_eq!(Some(0x21 | 0x40), loaded.load(0x4040));
+	}
+}
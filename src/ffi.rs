@@ -1,6 +1,6 @@
 use std::slice;
 use std::os::raw::{c_int, c_uchar, c_float, c_char};
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::time::Duration;
@@ -12,7 +12,9 @@ use std::path::Path;
 use sdl2::Sdl;
 
 use crate::Nes;
-use crate::rom::Rom;
+use crate::rom::{Rom, TimingMode};
+use crate::fds::is_fds_image;
+use crate::patch::{apply_patch, find_sibling_patch};
 use crate::default_input::DefaultInput;
 use crate::default_display::DefaultDisplay;
 use crate::default_audio::DefaultAudio;
@@ -28,6 +30,26 @@ use crate::ds::emu::input::Keys;
 lazy_static! {
     static ref INPUT_EVENTS: Mutex<VecDeque<(Button, Event)>> = Mutex::new(VecDeque::new());
     static ref TOUCH_EVENT: Mutex<Option<(u16, u16, bool)>> = Mutex::new(None);
+    static ref LAST_ERROR: Mutex<Option<CString>> = Mutex::new(None);
+}
+
+fn set_last_error(message: String) {
+    if let Ok(mut error) = LAST_ERROR.lock() {
+        *error = CString::new(message).ok();
+    }
+}
+
+// Why the last initEmu/nes_load_rom call failed, or null if it didn't.
+// The string is owned by the library and valid until the next failure.
+#[no_mangle]
+pub extern "C" fn nes_last_error() -> *const c_char {
+    match LAST_ERROR.lock() {
+        Ok(error) => match &*error {
+            Some(message) => message.as_ptr(),
+            None => std::ptr::null(),
+        },
+        Err(_) => std::ptr::null(),
+    }
 }
 
 struct IosInput;
@@ -61,6 +83,8 @@ fn map_button(id: c_int) -> Option<Button> {
         11 => Some(Button::Y),
         12 => Some(Button::L),
         13 => Some(Button::R),
+        14 => Some(Button::NextTrack),
+        15 => Some(Button::PreviousTrack),
         _ => None,
     }
 }
@@ -122,7 +146,10 @@ pub extern "C" fn initEmu(rom_path: *const c_char) -> *mut EmuState {
 
     let sdl = match sdl2::init() {
         Ok(s) => s,
-        Err(_) => return std::ptr::null_mut(),
+        Err(e) => {
+            set_last_error(format!("Failed to initialize SDL: {}", e));
+            return std::ptr::null_mut();
+        }
     };
     
     // Configure SDL hints for iOS
@@ -131,7 +158,10 @@ pub extern "C" fn initEmu(rom_path: *const c_char) -> *mut EmuState {
     
     let audio_subsystem = match sdl.audio() {
         Ok(a) => a,
-        Err(_) => return std::ptr::null_mut(),
+        Err(e) => {
+            set_last_error(format!("Failed to initialize audio: {}", e));
+            return std::ptr::null_mut();
+        }
     };
 
     let shared_buffer = Arc::new(Mutex::new([0u8; PIXELS_CAPACITY]));
@@ -149,7 +179,10 @@ pub extern "C" fn initEmu(rom_path: *const c_char) -> *mut EmuState {
         height = 384;
         let emu = match DsEmulator::new(Path::new(filename), None, None, None, &audio_subsystem, Path::new(&save_path)) {
              Ok(e) => e,
-             Err(_) => return std::ptr::null_mut(),
+             Err(e) => {
+                 set_last_error(e);
+                 return std::ptr::null_mut();
+             }
         };
         core = Some(EmuCore::Ds(emu));
     } else {
@@ -158,17 +191,49 @@ pub extern "C" fn initEmu(rom_path: *const c_char) -> *mut EmuState {
 
         let mut file = match File::open(filename) {
             Ok(f) => f,
-            Err(_) => return std::ptr::null_mut(),
+            Err(e) => {
+                set_last_error(format!("Failed to open ROM: {}", e));
+                return std::ptr::null_mut();
+            }
         };
         
         let mut contents = vec![];
-        if file.read_to_end(&mut contents).is_err() {
+        if let Err(e) = file.read_to_end(&mut contents) {
+            set_last_error(format!("Failed to read ROM: {}", e));
             return std::ptr::null_mut();
         }
+
+        // A .ips, .bps or .ups file next to the ROM is applied
+        if let Some(path) = find_sibling_patch(Path::new(filename)) {
+            let patched = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|patch| apply_patch(&contents, &patch).map_err(|e| e.to_string()));
+            contents = match patched {
+                Ok(patched) => patched,
+                Err(e) => {
+                    set_last_error(format!("Failed to apply patch {}: {}", path.display(), e));
+                    return std::ptr::null_mut();
+                }
+            };
+        }
         
-        let rom = match Rom::new(contents) {
-            Some(r) => r,
-            None => return std::ptr::null_mut(),
+        // FDS disk images need disksys.rom next to them
+        let rom = match is_fds_image(&contents) {
+            true => match std::fs::read(Path::new(filename).with_file_name("disksys.rom")) {
+                Ok(bios) => Rom::new_fds(contents, bios),
+                Err(e) => {
+                    set_last_error(format!("Failed to read FDS BIOS disksys.rom: {}", e));
+                    return std::ptr::null_mut();
+                }
+            },
+            false => Rom::new(contents)
+        };
+        let rom = match rom {
+            Ok(r) => r,
+            Err(e) => {
+                set_last_error(e.to_string());
+                return std::ptr::null_mut();
+            }
         };
 
         let input = Box::new(IosInput::new());
@@ -176,6 +241,11 @@ pub extern "C" fn initEmu(rom_path: *const c_char) -> *mut EmuState {
         let audio = Box::new(Sdl2Audio::new(audio_subsystem));
         let mut nes = Nes::new(input, display, audio);
         nes.set_rom(rom);
+        if nes.has_battery_backed_ram() {
+            if let Ok(data) = std::fs::read(&save_path) {
+                nes.import_sram(&data);
+            }
+        }
         nes.bootup();
         core = Some(EmuCore::Nes(nes));
     }
@@ -196,6 +266,18 @@ pub extern "C" fn initEmu(rom_path: *const c_char) -> *mut EmuState {
     state
 }
 
+// Writes battery backed RAM to the save file if the game changed it
+fn flush_nes_sram(nes: &mut Nes, save_path: &str) {
+    if !nes.is_sram_dirty() {
+        return;
+    }
+    if let Ok(mut file) = File::create(save_path) {
+        if file.write_all(&nes.export_sram()).is_ok() {
+            nes.mark_sram_flushed();
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn runEmuLoop(state_ptr: *mut EmuState) {
     if state_ptr.is_null() { return; }
@@ -215,8 +297,15 @@ pub extern "C" fn runEmuLoop(state_ptr: *mut EmuState) {
                 EmuCore::Nes(nes) => {
                     nes.step_frame();
                     if !nes.is_power_on() {
+                        flush_nes_sram(nes, &state.save_path);
                         break;
                     }
+
+                    // Periodic save persistence
+                    if last_save_check.elapsed() > Duration::from_secs(2) {
+                        flush_nes_sram(nes, &state.save_path);
+                        last_save_check = std::time::Instant::now();
+                    }
                 },
                 EmuCore::Ds(ds) => {
                     // ... input processing ...
@@ -394,13 +483,128 @@ pub extern "C" fn nes_destroy(nes_ptr: *mut Nes) {
     }
 }
 
+// Returns 1 on success. On failure returns 0 and nes_last_error() tells why.
 #[no_mangle]
-pub extern "C" fn nes_load_rom(nes_ptr: *mut Nes, data: *const c_uchar, len: usize) {
+pub extern "C" fn nes_load_rom(nes_ptr: *mut Nes, data: *const c_uchar, len: usize) -> c_int {
     let nes = unsafe { &mut *nes_ptr };
     let data_slice = unsafe { slice::from_raw_parts(data, len) };
-    if let Some(rom) = Rom::new(data_slice.to_vec()) {
-        nes.set_rom(rom);
-        nes.bootup();
+    match Rom::new(data_slice.to_vec()) {
+        Ok(rom) => {
+            nes.set_rom(rom);
+            nes.bootup();
+            1
+        },
+        Err(e) => {
+            set_last_error(e.to_string());
+            0
+        }
+    }
+}
+
+// Same as nes_load_rom() but applies the IPS, UPS or BPS patch to the ROM first
+#[no_mangle]
+pub extern "C" fn nes_load_rom_with_patch(nes_ptr: *mut Nes, data: *const c_uchar, len: usize,
+    patch: *const c_uchar, patch_len: usize) -> c_int {
+    let data_slice = unsafe { slice::from_raw_parts(data, len) };
+    let patch_slice = unsafe { slice::from_raw_parts(patch, patch_len) };
+    match apply_patch(data_slice, patch_slice) {
+        Ok(patched) => nes_load_rom(nes_ptr, patched.as_ptr(), patched.len()),
+        Err(e) => {
+            set_last_error(e.to_string());
+            0
+        }
+    }
+}
+
+// Same as nes_load_rom() but for an FDS disk image and the 8KB BIOS
+#[no_mangle]
+pub extern "C" fn nes_load_fds(nes_ptr: *mut Nes, data: *const c_uchar, len: usize,
+    bios: *const c_uchar, bios_len: usize) -> c_int {
+    let nes = unsafe { &mut *nes_ptr };
+    let data_slice = unsafe { slice::from_raw_parts(data, len) };
+    let bios_slice = unsafe { slice::from_raw_parts(bios, bios_len) };
+    match Rom::new_fds(data_slice.to_vec(), bios_slice.to_vec()) {
+        Ok(rom) => {
+            nes.set_rom(rom);
+            nes.bootup();
+            1
+        },
+        Err(e) => {
+            set_last_error(e.to_string());
+            0
+        }
+    }
+}
+
+// Inserts the FDS disk side. Negative side ejects the disk.
+#[no_mangle]
+pub extern "C" fn nes_insert_disk(nes_ptr: *mut Nes, side: c_int) {
+    let nes = unsafe { &mut *nes_ptr };
+    match side < 0 {
+        true => nes.eject_disk(),
+        false => nes.insert_disk(side as usize)
+    };
+}
+
+// Copies the string into the buffer like snprintf, truncated and
+// null-terminated. Returns the length of the whole string.
+fn copy_string(value: &str, buffer: *mut c_char, size: usize) -> c_int {
+    let bytes = value.as_bytes();
+    if !buffer.is_null() && size > 0 {
+        let length = bytes.len().min(size - 1);
+        let buffer_slice = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, size) };
+        buffer_slice[..length].copy_from_slice(&bytes[..length]);
+        buffer_slice[length] = 0;
+    }
+    bytes.len() as c_int
+}
+
+// Title of the ROM in the game database, or of the NSF. Empty if unknown.
+#[no_mangle]
+pub extern "C" fn nes_rom_title(nes_ptr: *mut Nes, buffer: *mut c_char, size: usize) -> c_int {
+    let nes = unsafe { &*nes_ptr };
+    copy_string(nes.rom_info().title.as_deref().unwrap_or(""), buffer, size)
+}
+
+// Board name of the ROM. Empty if unknown.
+#[no_mangle]
+pub extern "C" fn nes_rom_board(nes_ptr: *mut Nes, buffer: *mut c_char, size: usize) -> c_int {
+    let nes = unsafe { &*nes_ptr };
+    copy_string(&nes.rom_info().board, buffer, size)
+}
+
+// SHA-1 of the ROM in uppercase hex. Empty unless the game database
+// has the CRC32.
+#[no_mangle]
+pub extern "C" fn nes_rom_sha1(nes_ptr: *mut Nes, buffer: *mut c_char, size: usize) -> c_int {
+    let nes = unsafe { &*nes_ptr };
+    copy_string(nes.rom_info().sha1.as_deref().unwrap_or(""), buffer, size)
+}
+
+#[no_mangle]
+pub extern "C" fn nes_rom_crc32(nes_ptr: *mut Nes) -> u32 {
+    let nes = unsafe { &*nes_ptr };
+    nes.rom_info().crc32
+}
+
+// 0: NTSC, 1: PAL, 2: Multiple region, 3: Dendy
+#[no_mangle]
+pub extern "C" fn nes_rom_region(nes_ptr: *mut Nes) -> c_int {
+    let nes = unsafe { &*nes_ptr };
+    match nes.rom_info().region {
+        TimingMode::Ntsc => 0,
+        TimingMode::Pal => 1,
+        TimingMode::MultiRegion => 2,
+        TimingMode::Dendy => 3
+    }
+}
+
+// Starts playing the NSF track, 0-based
+#[no_mangle]
+pub extern "C" fn nes_select_track(nes_ptr: *mut Nes, track: c_int) {
+    let nes = unsafe { &mut *nes_ptr };
+    if track >= 0 && track <= 0xFF {
+        nes.select_track(track as u8);
     }
 }
 
@@ -410,6 +614,12 @@ pub extern "C" fn nes_reset(nes_ptr: *mut Nes) {
     nes.reset();
 }
 
+#[no_mangle]
+pub extern "C" fn nes_set_solder_pad(nes_ptr: *mut Nes, pad: c_int) {
+    let nes = unsafe { &mut *nes_ptr };
+    nes.set_solder_pad(pad as u8);
+}
+
 #[no_mangle]
 pub extern "C" fn nes_step_frame(nes_ptr: *mut Nes) {
     let nes = unsafe { &mut *nes_ptr };
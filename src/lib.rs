@@ -11,6 +11,12 @@ pub mod apu;
 pub mod rom;
 pub mod memory;
 pub mod mapper;
+pub mod opll;
+pub mod fds;
+pub mod nsf;
+pub mod unif;
+pub mod patch;
+pub mod database;
 pub mod button;
 pub mod joypad;
 pub mod input;
@@ -112,4 +118,91 @@ impl Nes {
 	pub fn is_power_on(&self) -> bool {
 		self.cpu.is_power_on()
 	}
+
+	/// Whether the cartridge has battery backed RAM (SRAM) worth persisting
+	pub fn has_battery_backed_ram(&self) -> bool {
+		self.cpu.get_rom().has_battery_backed_ram()
+	}
+
+	/// Export battery backed RAM, e.g. to write a .sav file.
+	/// Empty if the cartridge has no battery.
+	pub fn export_sram(&self) -> Vec<u8> {
+		self.cpu.get_rom().export_sram()
+	}
+
+	/// Import battery backed RAM, e.g. read from a .sav file.
+	/// Call after set_rom() and before bootup().
+	pub fn import_sram(&mut self, data: &[u8]) {
+		self.cpu.get_mut_rom().import_sram(data);
+	}
+
+	/// Whether battery backed RAM has changed since the last
+	/// import_sram() or mark_sram_flushed()
+	pub fn is_sram_dirty(&self) -> bool {
+		self.cpu.get_rom().is_sram_dirty()
+	}
+
+	/// Marks battery backed RAM as persisted
+	pub fn mark_sram_flushed(&mut self) {
+		self.cpu.get_mut_rom().mark_sram_flushed();
+	}
+
+	/// Disk side count of the FDS disk. 0 for cartridges.
+	pub fn disk_side_count(&self) -> usize {
+		self.cpu.get_rom().disk_side_count()
+	}
+
+	/// Inserted FDS disk side. None while ejected and for cartridges.
+	pub fn disk_side(&self) -> Option<usize> {
+		self.cpu.get_rom().disk_side()
+	}
+
+	/// Inserts the FDS disk side. Eject the disk for a while (a second
+	/// or so) before switching sides, games wait for the disk to go.
+	pub fn insert_disk(&mut self, side: usize) {
+		self.cpu.get_mut_rom().insert_disk(Some(side));
+	}
+
+	/// Ejects the FDS disk
+	pub fn eject_disk(&mut self) {
+		self.cpu.get_mut_rom().insert_disk(None);
+	}
+
+	/// Title, region, board and hashes of the ROM. Header fields are
+	/// corrected with the embedded game database if it has the ROM.
+	pub fn rom_info(&self) -> &rom::RomInfo {
+		self.cpu.get_rom().info()
+	}
+
+	/// NSF metadata (title, artist, track names and lengths).
+	/// None for cartridges.
+	pub fn nsf_metadata(&self) -> Option<&nsf::NsfMetadata> {
+		self.cpu.get_rom().nsf_metadata()
+	}
+
+	/// Playing NSF track, 0-based
+	pub fn track(&self) -> u8 {
+		self.cpu.get_rom().track()
+	}
+
+	/// Starts playing the NSF track, 0-based. Ignored if out of range.
+	pub fn select_track(&mut self, track: u8) {
+		self.cpu.select_track(track);
+	}
+
+	/// Starts playing the next NSF track, wrapping around at the last
+	pub fn next_track(&mut self) {
+		self.cpu.next_track();
+	}
+
+	/// Starts playing the previous NSF track, wrapping around at the first
+	pub fn previous_track(&mut self) {
+		self.cpu.previous_track();
+	}
+
+	/// Sets the solder pad setting of multicart boards whose menus
+	/// read it to choose the game list. Ignored by other boards.
+	pub fn set_solder_pad(&mut self, pad: u8) {
+		self.cpu.get_mut_rom().set_solder_pad(pad);
+	}
 }
\ No newline at end of file
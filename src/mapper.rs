@@ -1,18 +1,104 @@
 pub struct MapperFactory;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use rom::Mirrorings;
 use rom::RomHeader;
+use rom::RomFormat;
 use register::Register;
-use save_state::MapperState;
+use save_state::{MapperState, VrcIrqState, Vrc6PulseState, Vrc6SawtoothState, Sunsoft5BState};
+use serde::{Serialize, Deserialize};
+use apu::{ApuPulse, ApuPulseChannel};
+use opll::Opll;
+use fds::{FdsDisk, FdsAudio};
+use nsf::{Nsf, NsfMetadata, NSF_VRC6, NSF_VRC7, NSF_FDS, NSF_MMC5, NSF_N163, NSF_SUNSOFT_5B};
+
+/**
+ * Constructs a mapper for a ROM header. Registered with
+ * MapperFactory::register() to add mappers outside of this crate.
+ */
+pub type MapperConstructor = Arc<dyn Fn(&RomHeader) -> Box<dyn Mapper> + Send + Sync>;
+
+lazy_static! {
+	// Keyed by mapper number and submapper number. None submapper
+	// number matches any submapper.
+	static ref REGISTERED_MAPPERS: Mutex<HashMap<(u16, Option<u8>), MapperConstructor>> =
+		Mutex::new(HashMap::new());
+}
 
 impl MapperFactory {
+	/**
+	 * Registers a mapper constructor for the mapper number and the submapper
+	 * number, or any submapper if None. Registered constructors take precedence
+	 * over the built-in mappers, and the one for the exact submapper over the
+	 * one for any. Registering again for the same numbers replaces it.
+	 */
+	pub fn register<F>(mapper_num: u16, submapper_num: Option<u8>, constructor: F)
+		where F: Fn(&RomHeader) -> Box<dyn Mapper> + Send + Sync + 'static {
+		REGISTERED_MAPPERS.lock().unwrap()
+			.insert((mapper_num, submapper_num), Arc::new(constructor));
+	}
+
+	// Returns whether a constructor was registered for the numbers
+	pub fn unregister(mapper_num: u16, submapper_num: Option<u8>) -> bool {
+		REGISTERED_MAPPERS.lock().unwrap()
+			.remove(&(mapper_num, submapper_num)).is_some()
+	}
+
 	pub fn create(header: &RomHeader) -> Option<Box<dyn Mapper>> {
+		// Constructor is called after unlocking so that it can register mappers
+		let constructor = {
+			let mappers = REGISTERED_MAPPERS.lock().unwrap();
+			mappers.get(&(header.mapper_num(), Some(header.submapper_num())))
+				.or_else(|| mappers.get(&(header.mapper_num(), None)))
+				.cloned()
+		};
+		match constructor {
+			Some(constructor) => Some(constructor(header)),
+			None => Self::create_built_in(header)
+		}
+	}
+
+	fn create_built_in(header: &RomHeader) -> Option<Box<dyn Mapper>> {
 		match header.mapper_num() {
 			0 => Some(Box::new(NRomMapper::new(header))),
-			1 => Some(Box::new(MMC1Mapper::new(header))),
+			1 | 155 => Some(Box::new(MMC1Mapper::new(header))),
 			2 => Some(Box::new(UNRomMapper::new(header))),
 			3 => Some(Box::new(CNRomMapper::new())),
-			4 => Some(Box::new(MMC3Mapper::new(header))),
+			4 => Some(Box::new(MMC3Mapper::new(header, match header.submapper_num() {
+				1 => MMC3Variant::MMC6,
+				_ => MMC3Variant::MMC3
+			}))),
+			5 => Some(Box::new(MMC5Mapper::new())),
+			7 => Some(Box::new(AxRomMapper::new())),
+			9 => Some(Box::new(MMC2Mapper::new(header, false))),
+			10 => Some(Box::new(MMC2Mapper::new(header, true))),
+			11 => Some(Box::new(ColorDreamsMapper::new())),
+			13 => Some(Box::new(CPRomMapper::new())),
+			19 => Some(Box::new(Namco163Mapper::new(header))),
+			21 | 22 | 23 | 25 => Some(Box::new(VRC4Mapper::new(header))),
+			24 => Some(Box::new(VRC6Mapper::new(header, false))),
+			26 => Some(Box::new(VRC6Mapper::new(header, true))),
+			30 => Some(Box::new(UNRom512Mapper::new(header))),
+			// Submapper 1 is NINA-001 and 2 is BNROM. Only NINA-001 has CHR-ROM over 8KB
+			34 => match header.submapper_num() {
+				1 => Some(Box::new(Nina001Mapper::new())),
+				2 => Some(Box::new(BNRomMapper::new())),
+				_ => match header.chr_rom_size() > 0x2000 {
+					true => Some(Box::new(Nina001Mapper::new())),
+					false => Some(Box::new(BNRomMapper::new()))
+				}
+			},
+			59 => Some(Box::new(D1038Mapper::new())),
+			66 => Some(Box::new(GxRomMapper::new())),
             69 => Some(Box::new(SunsoftMapper::new(header))),
+			71 => Some(Box::new(CamericaMapper::new(header))),
+			85 => Some(Box::new(VRC7Mapper::new(header))),
+			118 => Some(Box::new(MMC3Mapper::new(header, MMC3Variant::TxSRom))),
+			119 => Some(Box::new(MMC3Mapper::new(header, MMC3Variant::TQRom))),
+			225 | 255 => Some(Box::new(Multicart225Mapper::new())),
+			226 => Some(Box::new(Multicart226Mapper::new())),
+			227 => Some(Box::new(Multicart227Mapper::new())),
+			228 => Some(Box::new(Action52Mapper::new())),
 			_ => None
 		}
 	}
@@ -22,7 +108,8 @@ pub trait Mapper {
 	// Maps 0x8000 - 0xFFFF to the program rom address
 	fn map(&self, address: u32) -> u32;
 
-	// Maps 0x0000 - 0x1FFF to the character rom address
+	// Maps 0x0000 - 0x1FFF to the character rom address,
+	// or character ram address if the cartridge has no character rom
 	fn map_for_chr_rom(&self, address: u32) -> u32;
 
 	// Writes control register inside in general
@@ -32,14 +119,196 @@ pub trait Mapper {
 
 	fn mirroring_type(&self) -> Mirrorings;
 
-	// @TODO: MMC3Mapper specific. Should this method be here?
+	// Called at the end of each rendering scanline. Returns true to fire IRQ.
+	// @TODO: Only Sunsoft uses it. Should this method be here?
 	fn drive_irq_counter(&mut self) -> bool;
 
-	// Save mapper state
-	fn save_state(&self) -> MapperState;
+	// Saves mapper state as an opaque blob stored in save states
+	fn save_state(&self) -> Vec<u8>;
+
+	// Loads mapper state from a blob made by .save_state()
+	fn load_state(&mut self, data: &[u8]);
+
+	// Called after PPU fetches pattern table data for rendering.
+	// Nothing to do in general.
+	fn notify_ppu_read(&mut self, _address: u32) {
+	}
+
+	// Called when line A12 of PPU address bus rises, with how many
+	// PPU cycles it has been low to filter out short pulses.
+	fn notify_ppu_a12_rise(&mut self, _low_cycles: u32) {
+	}
+
+	// Maps 0x6000 - 0xFFFF to the cartridge memory.
+	// PRG-RAM in 0x6000 - 0x7FFF and program rom with .map() in general.
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		match address < 0x8000 {
+			true => CpuMapping::ProgramRam(address - 0x6000),
+			false => CpuMapping::ProgramRom(self.map(address))
+		}
+	}
+
+	// Whether CPU can write PRG-RAM at the address (0x6000 - 0xFFFF) now
+	fn prg_ram_writable(&self, _address: u32) -> bool {
+		true
+	}
+
+	// Whether the pattern table address (0x0000 - 0x1FFF) is mapped
+	// to CHR-RAM on the boards having both CHR-ROM and CHR-RAM
+	fn is_chr_ram(&self, _address: u32) -> bool {
+		false
+	}
+
+	// Reads a register in 0x4020 - 0x5FFF.
+	// None if the mapper has no register at the address.
+	fn load_register(&mut self, _address: u32) -> Option<u8> {
+		None
+	}
+
+	// Writes a register in 0x4020 - 0x7FFF. Writes to 0x6000 - 0x7FFF
+	// reach PRG-RAM as well. Returns false if the mapper has no register
+	// at the address.
+	fn store_register(&mut self, _address: u32, _value: u8) -> bool {
+		false
+	}
+
+	// Sets the solder pad (or DIP switch) setting of multicart boards
+	// whose menus read it. Nothing to do in general.
+	fn set_solder_pad(&mut self, _value: u8) {
+	}
+
+	// Disk side count of FDS. 0 for cartridges.
+	fn disk_side_count(&self) -> usize {
+		0
+	}
+
+	// Inserted disk side of FDS. None while ejected and for cartridges.
+	fn disk_side(&self) -> Option<usize> {
+		None
+	}
+
+	// Inserts the disk side of FDS, or ejects the disk with None
+	fn insert_disk(&mut self, _side: Option<usize>) {
+	}
+
+	// FDS disk image with the writes, in .fds format. None for cartridges.
+	fn export_disk(&self) -> Option<Vec<u8>> {
+		None
+	}
+
+	// Restores FDS disk writes exported with .export_disk()
+	fn import_disk(&mut self, _data: &[u8]) {
+	}
+
+	// Whether FDS disk has been written since the last import or flush
+	fn is_disk_dirty(&self) -> bool {
+		false
+	}
+
+	fn mark_disk_flushed(&mut self) {
+	}
+
+	// NSF metadata. None for cartridges.
+	fn nsf_metadata(&self) -> Option<&NsfMetadata> {
+		None
+	}
+
+	// Playing NSF track, 0-based
+	fn track(&self) -> u8 {
+		0
+	}
+
+	// Selects the NSF track and gets the player back to the power-on
+	// state. The CPU needs a reset to start playing it.
+	fn select_track(&mut self, _track: u8) {
+	}
+
+	// Whether writes to 0x8000 - 0xFFFF conflict with PRG-ROM on the data bus
+	fn has_bus_conflicts(&self) -> bool {
+		false
+	}
+
+	// Called every CPU cycle for mapper timers and expansion audio
+	fn step(&mut self) {
+	}
+
+	// Whether mapper asserts IRQ. Unlike .drive_irq_counter()
+	// it's level triggered, kept until the mapper acknowledges it.
+	fn irq_pending(&self) -> bool {
+		false
+	}
+
+	// Expansion audio output mixed with APU output
+	fn audio_output(&self) -> f32 {
+		0.0
+	}
+
+	// Maps a 1KB nametable slot (0: 0x2000, 1: 0x2400, 2: 0x2800, 3: 0x2C00).
+	// None lets the slot follow .mirroring_type()
+	fn name_table_source(&self, _slot: u8) -> Option<NameTableSource> {
+		None
+	}
+
+	// Accesses NameTableSource::Mapper slots
+	fn load_name_table(&self, _address: u32) -> u8 {
+		0
+	}
+
+	fn store_name_table(&mut self, _address: u32, _value: u8) {
+	}
+
+	// Called when PPU fetches a nametable or attribute table byte
+	// for rendering. Returning Some replaces the fetched data.
+	fn fetch_name_table(&mut self, _address: u32) -> Option<u8> {
+		None
+	}
+
+	fn fetch_attribute_table(&mut self, _address: u32) -> Option<u8> {
+		None
+	}
+
+	// Called when PPU moves to the next rendering phase in a scanline
+	fn notify_ppu_phase(&mut self, _phase: PpuPhase) {
+	}
+}
+
+/**
+ * Cartridge memory a CPU address in 0x6000 - 0xFFFF is mapped to
+ */
+pub enum CpuMapping {
+	ProgramRom(u32),
+	ProgramRam(u32),
+	// Mapper drives the data bus, e.g. with a solder pad setting
+	Value(u8),
+	// Nothing responds. Loads 0.
+	Unmapped
+}
+
+/**
+ * Memory a nametable slot is mapped to
+ */
+pub enum NameTableSource {
+	// Page of PPU VRAM
+	Vram(u8),
+	// Mapper holds the data, accessed via .load_name_table()/.store_name_table()
+	Mapper,
+	// 1KB of CHR memory starting at the address in the CHR memory
+	ChrRom(u32),
+	// 1KB of the cartridge VRAM for four-screen starting at the address
+	CartridgeVram(u32)
+}
 
-	// Load mapper state
-	fn load_state(&mut self, state: &MapperState);
+/**
+ * PPU rendering phases in a scanline notified to mappers
+ */
+#[derive(Clone, Copy)]
+pub enum PpuPhase {
+	// Cycle 321: starts fetching background tiles of the scanline
+	Background(u16),
+	// Cycle 257: fetches sprite patterns. true if 8x16 sprites
+	Sprites(bool),
+	// Rendering is disabled or in vblank
+	Idle
 }
 
 pub struct NRomMapper {
@@ -95,14 +364,14 @@ impl Mapper for NRomMapper {
 		false
 	}
 
-	fn save_state(&self) -> MapperState {
+	fn save_state(&self) -> Vec<u8> {
 		MapperState::NRom {
 			program_bank_num: self.program_bank_num,
-		}
+		}.to_bytes()
 	}
 
-	fn load_state(&mut self, state: &MapperState) {
-		if let MapperState::NRom { program_bank_num } = state {
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::NRom { program_bank_num } = &MapperState::from_bytes(data) {
 			self.program_bank_num = *program_bank_num;
 		}
 	}
@@ -110,6 +379,9 @@ impl Mapper for NRomMapper {
 
 pub struct MMC1Mapper {
 	program_bank_num: u8,
+	// Mapper 155. MMC1A can't disable PRG-RAM.
+	mmc1a: bool,
+	prg_ram_size: usize,
 	control_register: Register<u8>,
 	chr_bank0_register: Register<u8>,
 	chr_bank1_register: Register<u8>,
@@ -124,6 +396,8 @@ impl MMC1Mapper {
 		control_register.store(0x0C);
 		MMC1Mapper {
 			program_bank_num: header.prg_rom_bank_num(),
+			mmc1a: header.mapper_num() == 155,
+			prg_ram_size: header.prg_ram_size() + header.prg_nvram_size(),
 			control_register: control_register,
 			chr_bank0_register: Register::<u8>::new(),
 			chr_bank1_register: Register::<u8>::new(),
@@ -132,6 +406,17 @@ impl MMC1Mapper {
 			register_write_count: 0
 		}
 	}
+
+	// SOROM (16KB) and SXROM (32KB) select 8KB PRG-RAM bank
+	// with CHR bank 0 register bit 3 and bits 3-2 respectively
+	fn prg_ram_bank(&self) -> u32 {
+		let value = self.chr_bank0_register.load() as u32;
+		match self.prg_ram_size {
+			0x4000 => (value >> 3) & 1,
+			0x8000 => (value >> 2) & 3,
+			_ => 0
+		}
+	}
 }
 
 impl Mapper for MMC1Mapper {
@@ -223,7 +508,7 @@ impl Mapper for MMC1Mapper {
 		false
 	}
 
-	fn save_state(&self) -> MapperState {
+	fn save_state(&self) -> Vec<u8> {
 		MapperState::MMC1 {
 			program_bank_num: self.program_bank_num,
 			control_register: self.control_register.get_data(),
@@ -232,10 +517,10 @@ impl Mapper for MMC1Mapper {
 			prg_bank_register: self.prg_bank_register.get_data(),
 			latch: self.latch.get_data(),
 			register_write_count: self.register_write_count,
-		}
+		}.to_bytes()
 	}
 
-	fn load_state(&mut self, state: &MapperState) {
+	fn load_state(&mut self, data: &[u8]) {
 		if let MapperState::MMC1 {
 			program_bank_num,
 			control_register,
@@ -244,7 +529,7 @@ impl Mapper for MMC1Mapper {
 			prg_bank_register,
 			latch,
 			register_write_count,
-		} = state {
+		} = &MapperState::from_bytes(data) {
 			self.program_bank_num = *program_bank_num;
 			self.control_register.set_data(*control_register);
 			self.chr_bank0_register.set_data(*chr_bank0_register);
@@ -254,6 +539,17 @@ impl Mapper for MMC1Mapper {
 			self.register_write_count = *register_write_count;
 		}
 	}
+
+	// PRG bank register bit 4 disables PRG-RAM except on MMC1A
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		if address >= 0x8000 {
+			return CpuMapping::ProgramRom(self.map(address));
+		}
+		match !self.mmc1a && self.prg_bank_register.is_bit_set(4) {
+			true => CpuMapping::Unmapped,
+			false => CpuMapping::ProgramRam(self.prg_ram_bank() * 0x2000 + (address & 0x1FFF))
+		}
+	}
 }
 
 struct UNRomMapper {
@@ -300,15 +596,15 @@ impl Mapper for UNRomMapper {
 		false
 	}
 
-	fn save_state(&self) -> MapperState {
+	fn save_state(&self) -> Vec<u8> {
 		MapperState::UNRom {
 			program_bank_num: self.program_bank_num,
 			register: self.register.get_data(),
-		}
+		}.to_bytes()
 	}
 
-	fn load_state(&mut self, state: &MapperState) {
-		if let MapperState::UNRom { program_bank_num, register } = state {
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::UNRom { program_bank_num, register } = &MapperState::from_bytes(data) {
 			self.program_bank_num = *program_bank_num;
 			self.register.set_data(*register);
 		}
@@ -352,480 +648,6061 @@ impl Mapper for CNRomMapper {
 		false
 	}
 
-	fn save_state(&self) -> MapperState {
+	fn save_state(&self) -> Vec<u8> {
 		MapperState::CNRom {
 			register: self.register.get_data(),
-		}
+		}.to_bytes()
 	}
 
-	fn load_state(&mut self, state: &MapperState) {
-		if let MapperState::CNRom { register } = state {
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::CNRom { register } = &MapperState::from_bytes(data) {
 			self.register.set_data(*register);
 		}
 	}
 }
 
-struct MMC3Mapper {
-	program_bank_num: u8,
-	character_bank_num: u8,
-	register0: Register<u8>,
-	register1: Register<u8>,
-	register2: Register<u8>,
-	register3: Register<u8>,
-	register4: Register<u8>,
-	register5: Register<u8>,
-	register6: Register<u8>,
-	register7: Register<u8>,
-	program_register0: Register<u8>,
-	program_register1: Register<u8>,
-	character_register0: Register<u8>,
-	character_register1: Register<u8>,
-	character_register2: Register<u8>,
-	character_register3: Register<u8>,
-	character_register4: Register<u8>,
-	character_register5: Register<u8>,
-	irq_counter: u8,
-	irq_counter_reload: bool,
-	irq_enabled: bool
+/**
+ * AxROM. 32KB PRG-ROM switching and single-screen nametable selection.
+ * Refer to https://wiki.nesdev.com/w/index.php/AxROM
+ */
+struct AxRomMapper {
+	register: Register<u8>
 }
 
-impl MMC3Mapper {
-	fn new(header: &RomHeader) -> Self {
-		MMC3Mapper {
-			program_bank_num: header.prg_rom_bank_num(),
-			character_bank_num: header.chr_rom_bank_num(),
-			register0: Register::<u8>::new(),
-			register1: Register::<u8>::new(),
-			register2: Register::<u8>::new(),
-			register3: Register::<u8>::new(),
-			register4: Register::<u8>::new(),
-			register5: Register::<u8>::new(),
-			register6: Register::<u8>::new(),
-			register7: Register::<u8>::new(),
-			program_register0: Register::<u8>::new(),
-			program_register1: Register::<u8>::new(),
-			character_register0: Register::<u8>::new(),
-			character_register1: Register::<u8>::new(),
-			character_register2: Register::<u8>::new(),
-			character_register3: Register::<u8>::new(),
-			character_register4: Register::<u8>::new(),
-			character_register5: Register::<u8>::new(),
-			irq_counter: 0,
-			irq_counter_reload: false,
-			irq_enabled: true
+impl AxRomMapper {
+	fn new() -> Self {
+		AxRomMapper {
+			register: Register::<u8>::new()
 		}
 	}
 }
 
-impl Mapper for MMC3Mapper {
+impl Mapper for AxRomMapper {
+	/**
+	 * 0x8000 - 0xFFFF: Switchable 32KB bank, register bits 2-0
+	 */
 	fn map(&self, address: u32) -> u32 {
-		let bank = match address {
-			0x8000..=0x9FFF => match self.register0.is_bit_set(6) {
-				true => self.program_bank_num * 2 - 2,
-				false => self.program_register0.load()
-			},
-			0xA000..=0xBFFF => self.program_register1.load(),
-			0xC000..=0xDFFF => match self.register0.is_bit_set(6) {
-				true => self.program_register0.load(),
-				false => self.program_bank_num * 2 - 2
-			},
-			_ => self.program_bank_num * 2 - 1
-		};
-		// I couldn't in the spec but it seems that
-		// we need to wrap 2k bank with 4k program_bank_num
-		((bank as u32) % ((self.program_bank_num as u32) * 2)) * 0x2000 + (address & 0x1FFF)
+		self.register.load_bits(0, 3) as u32 * 0x8000 + (address & 0x7FFF)
 	}
 
 	fn map_for_chr_rom(&self, address: u32) -> u32 {
-		let bank = match self.register0.is_bit_set(7) {
-			true => match address & 0x1FFF {
-				0x0000..=0x03FF => self.character_register2.load(),
-				0x0400..=0x07FF => self.character_register3.load(),
-				0x0800..=0x0BFF => self.character_register4.load(),
-				0x0C00..=0x0FFF => self.character_register5.load(),
-				0x1000..=0x13FF => self.character_register0.load() & 0xFE,
-				0x1400..=0x17FF => self.character_register0.load() | 1,
-				0x1800..=0x1BFF => self.character_register1.load() & 0xFE,
-				_ => self.character_register1.load() | 1
-			},
-			false => match address & 0x1FFF {
-				0x0000..=0x03FF => self.character_register0.load() & 0xFE,
-				0x0400..=0x07FF => self.character_register0.load() | 1,
-				0x0800..=0x0BFF => self.character_register1.load() & 0xFE,
-				0x0C00..=0x0FFF => self.character_register1.load() | 1,
-				0x1000..=0x13FF => self.character_register2.load(),
-				0x1400..=0x17FF => self.character_register3.load(),
-				0x1800..=0x1BFF => self.character_register4.load(),
-				_ => self.character_register5.load()
-			}
-		};
-		// I couldn't in the spec but it seems that
-		// we need to wrap 0.4k bank with 4k character_bank_num
-		((bank as u32) % ((self.character_bank_num as u32) * 8)) * 0x400 + (address & 0x3FF)
+		address
 	}
 
-	fn store(&mut self, address: u32, value: u8) {
-		match address {
-			0x8000..=0x9FFF => match (address & 1) == 0 {
-				true => self.register0.store(value),
-				false => {
-					self.register1.store(value);
-					match self.register0.load_bits(0, 3) {
-						0 => self.character_register0.store(value & 0xFE),
-						1 => self.character_register1.store(value & 0xFE),
-						2 => self.character_register2.store(value),
-						3 => self.character_register3.store(value),
-						4 => self.character_register4.store(value),
-						5 => self.character_register5.store(value),
-						6 => self.program_register0.store(value & 0x3F),
-						_ => self.program_register1.store(value & 0x3F)
-					};
-				}
-			},
-			0xA000..=0xBFFF => match (address & 1) == 0 {
-				true => self.register2.store(value),
-				false => self.register3.store(value)
-			},
-			0xC000..=0xDFFF => {
-				match (address & 1) == 0 {
-					true => self.register4.store(value),
-					false => self.register5.store(value)
-				};
-				self.irq_counter_reload = true;
-			},
-			_ => match (address & 1) == 0 {
-				true => {
-					self.register6.store(value);
-					self.irq_enabled = false;
-				},
-				false => {
-					self.register7.store(value);
-					self.irq_enabled = true;
-				}
-			}
-		};
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
 	}
 
 	fn has_mirroring_type(&self) -> bool {
 		true
 	}
 
+	// Register bit 4 selects the nametable
 	fn mirroring_type(&self) -> Mirrorings {
-		match self.register2.is_bit_set(0) {
-			true => Mirrorings::Horizontal,
-			false => Mirrorings::Vertical
+		match self.register.is_bit_set(4) {
+			true => Mirrorings::OneScreenHigh,
+			false => Mirrorings::OneScreenLow
 		}
 	}
 
 	fn drive_irq_counter(&mut self) -> bool {
-		match self.irq_counter_reload {
-			true => {
-				self.irq_counter = self.register4.load();
-				self.irq_counter_reload = false;
-				false
-			},
-			false => match self.irq_enabled {
-				true => match self.irq_counter > 0 {
-					true => {
-						self.irq_counter -= 1;
-						match self.irq_counter == 0 {
-							true => {
-								self.irq_counter_reload = true;
-								true
-							}
-							false => false
-						}
-					},
-					false => false
-				},
-				false => false
-			}
-		}
+		false
 	}
 
-	fn save_state(&self) -> MapperState {
-		MapperState::MMC3 {
-			program_bank_num: self.program_bank_num,
-			character_bank_num: self.character_bank_num,
-			register0: self.register0.get_data(),
-			register1: self.register1.get_data(),
-			register2: self.register2.get_data(),
-			register3: self.register3.get_data(),
-			register4: self.register4.get_data(),
-			register5: self.register5.get_data(),
-			register6: self.register6.get_data(),
-			register7: self.register7.get_data(),
-			program_register0: self.program_register0.get_data(),
-			program_register1: self.program_register1.get_data(),
-			character_register0: self.character_register0.get_data(),
-			character_register1: self.character_register1.get_data(),
-			character_register2: self.character_register2.get_data(),
-			character_register3: self.character_register3.get_data(),
-			character_register4: self.character_register4.get_data(),
-			character_register5: self.character_register5.get_data(),
-			irq_counter: self.irq_counter,
-			irq_counter_reload: self.irq_counter_reload,
-			irq_enabled: self.irq_enabled,
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::AxRom {
+			register: self.register.get_data(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::AxRom { register } = &MapperState::from_bytes(data) {
+			self.register.set_data(*register);
 		}
 	}
+}
 
-	fn load_state(&mut self, state: &MapperState) {
-		if let MapperState::MMC3 {
-			program_bank_num,
-			character_bank_num,
-			register0,
-			register1,
-			register2,
-			register3,
-			register4,
-			register5,
-			register6,
-			register7,
-			program_register0,
-			program_register1,
-			character_register0,
-			character_register1,
-			character_register2,
-			character_register3,
-			character_register4,
-			character_register5,
-			irq_counter,
-			irq_counter_reload,
-			irq_enabled,
-		} = state {
+/**
+ * UNROM-512. UNROM with up to 512KB PRG-ROM, 32KB banked CHR-RAM and
+ * optional one-screen mirroring control. Self-flashing is not supported.
+ * Refer to https://wiki.nesdev.com/w/index.php/UNROM_512
+ */
+struct UNRom512Mapper {
+	program_bank_num: u8,
+	// Header four-screen bit set and vertical bit clear
+	one_screen_mirroring: bool,
+	register: Register<u8>
+}
+
+impl UNRom512Mapper {
+	fn new(header: &RomHeader) -> Self {
+		let one_screen_mirroring = match header.mirroring_type() {
+			Mirrorings::FourScreen => !header.vertical_mirroring_flag(),
+			_ => false
+		};
+		UNRom512Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			one_screen_mirroring: one_screen_mirroring,
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for UNRom512Mapper {
+	/**
+	 * 0x8000 - 0xBFFF: Switchable 16KB bank, register bits 4-0
+	 * 0xC000 - 0xFFFF: Fixed to the last 16KB bank
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address < 0xC000 {
+			true => self.register.load_bits(0, 5),
+			false => self.program_bank_num - 1
+		} as u32;
+		0x4000 * bank + (address & 0x3FFF)
+	}
+
+	/**
+	 * 0x0000 - 0x1FFF: Switchable 8KB CHR-RAM bank, register bits 6-5
+	 */
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.register.load_bits(5, 2) as u32 * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		self.one_screen_mirroring
+	}
+
+	// The header four-screen bit means one-screen mirroring control
+	// instead of the cartridge VRAM if the vertical bit is clear
+	fn name_table_source(&self, _slot: u8) -> Option<NameTableSource> {
+		match self.one_screen_mirroring {
+			true => Some(NameTableSource::Vram(self.register.load_bit(7))),
+			false => None
+		}
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		match self.register.is_bit_set(7) {
+			true => Mirrorings::OneScreenHigh,
+			false => Mirrorings::OneScreenLow
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::UNRom512 {
+			program_bank_num: self.program_bank_num,
+			register: self.register.get_data(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::UNRom512 { program_bank_num, register } = &MapperState::from_bytes(data) {
 			self.program_bank_num = *program_bank_num;
-			self.character_bank_num = *character_bank_num;
-			self.register0.set_data(*register0);
-			self.register1.set_data(*register1);
-			self.register2.set_data(*register2);
-			self.register3.set_data(*register3);
-			self.register4.set_data(*register4);
-			self.register5.set_data(*register5);
-			self.register6.set_data(*register6);
-			self.register7.set_data(*register7);
-			self.program_register0.set_data(*program_register0);
-			self.program_register1.set_data(*program_register1);
-			self.character_register0.set_data(*character_register0);
-			self.character_register1.set_data(*character_register1);
-			self.character_register2.set_data(*character_register2);
-			self.character_register3.set_data(*character_register3);
-			self.character_register4.set_data(*character_register4);
-			self.character_register5.set_data(*character_register5);
-			self.irq_counter = *irq_counter;
-			self.irq_counter_reload = *irq_counter_reload;
-			self.irq_enabled = *irq_enabled;
+			self.register.set_data(*register);
+		}
+	}
+}
+
+/**
+ * GxROM (mapper 66). 32KB PRG-ROM and 8KB CHR-ROM switching.
+ * Refer to https://wiki.nesdev.com/w/index.php/GxROM
+ */
+struct GxRomMapper {
+	register: Register<u8>
+}
+
+impl GxRomMapper {
+	fn new() -> Self {
+		GxRomMapper {
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for GxRomMapper {
+	// Register bits 5-4
+	fn map(&self, address: u32) -> u32 {
+		self.register.load_bits(4, 2) as u32 * 0x8000 + (address & 0x7FFF)
+	}
+
+	// Register bits 1-0
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.register.load_bits(0, 2) as u32 * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::SingleScreen // dummy
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::GxRom {
+			register: self.register.get_data(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::GxRom { register } = &MapperState::from_bytes(data) {
+			self.register.set_data(*register);
+		}
+	}
+
+	fn has_bus_conflicts(&self) -> bool {
+		true
+	}
+}
+
+/**
+ * Color Dreams (mapper 11). 32KB PRG-ROM and 8KB CHR-ROM switching.
+ * Refer to https://wiki.nesdev.com/w/index.php/Color_Dreams
+ */
+struct ColorDreamsMapper {
+	register: Register<u8>
+}
+
+impl ColorDreamsMapper {
+	fn new() -> Self {
+		ColorDreamsMapper {
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for ColorDreamsMapper {
+	// Register bits 1-0
+	fn map(&self, address: u32) -> u32 {
+		self.register.load_bits(0, 2) as u32 * 0x8000 + (address & 0x7FFF)
+	}
+
+	// Register bits 7-4
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.register.load_bits(4, 4) as u32 * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::SingleScreen // dummy
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::ColorDreams {
+			register: self.register.get_data(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::ColorDreams { register } = &MapperState::from_bytes(data) {
+			self.register.set_data(*register);
+		}
+	}
+
+	fn has_bus_conflicts(&self) -> bool {
+		true
+	}
+}
+
+/**
+ * BNROM (mapper 34). 32KB PRG-ROM switching with 8KB CHR-RAM.
+ * Register has all the bits for oversize boards.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_034
+ */
+struct BNRomMapper {
+	register: Register<u8>
+}
+
+impl BNRomMapper {
+	fn new() -> Self {
+		BNRomMapper {
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for BNRomMapper {
+	fn map(&self, address: u32) -> u32 {
+		self.register.load() as u32 * 0x8000 + (address & 0x7FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		address
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::SingleScreen // dummy
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::BNRom {
+			register: self.register.get_data(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::BNRom { register } = &MapperState::from_bytes(data) {
+			self.register.set_data(*register);
+		}
+	}
+
+	fn has_bus_conflicts(&self) -> bool {
+		true
+	}
+}
+
+/**
+ * NINA-001 (mapper 34). 32KB PRG-ROM and two 4KB CHR-ROM banks
+ * switched with the registers at the end of PRG-RAM.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_034
+ */
+struct Nina001Mapper {
+	program_register: u8, // 0x7FFD
+	character_registers: [u8; 2] // 0x7FFE, 0x7FFF
+}
+
+impl Nina001Mapper {
+	fn new() -> Self {
+		Nina001Mapper {
+			program_register: 0,
+			character_registers: [0; 2]
+		}
+	}
+}
+
+impl Mapper for Nina001Mapper {
+	fn map(&self, address: u32) -> u32 {
+		(self.program_register & 0x1) as u32 * 0x8000 + (address & 0x7FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let bank = self.character_registers[(address >> 12) as usize & 1] & 0xF;
+		bank as u32 * 0x1000 + (address & 0xFFF)
+	}
+
+	// No registers in 0x8000 - 0xFFFF
+	fn store(&mut self, _address: u32, _value: u8) {
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::SingleScreen // dummy
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Nina001 {
+			program_register: self.program_register,
+			character_registers: self.character_registers,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Nina001 { program_register, character_registers } = &MapperState::from_bytes(data) {
+			self.program_register = *program_register;
+			self.character_registers = *character_registers;
 		}
 	}
+
+	fn store_register(&mut self, address: u32, value: u8) -> bool {
+		match address {
+			0x7FFD => self.program_register = value,
+			0x7FFE => self.character_registers[0] = value,
+			0x7FFF => self.character_registers[1] = value,
+			_ => return false
+		};
+		true
+	}
+}
+
+/**
+ * Camerica/Codemasters (mapper 71). UNROM like 16KB PRG-ROM switching
+ * without bus conflicts. Fire Hawk controls one-screen mirroring with
+ * 0x9000 - 0x9FFF, the other games keep the header mirroring.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_071
+ */
+struct CamericaMapper {
+	program_bank_num: u8,
+	register: u8, // 0xC000 - 0xFFFF
+	mirroring: Option<u8> // 0x9000 - 0x9FFF, None until written
+}
+
+impl CamericaMapper {
+	fn new(header: &RomHeader) -> Self {
+		CamericaMapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			register: 0,
+			mirroring: None
+		}
+	}
+}
+
+impl Mapper for CamericaMapper {
+	/**
+	 * 0x8000 - 0xBFFF: Switchable 16KB bank
+	 * 0xC000 - 0xFFFF: Fixed to the last 16KB bank
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address < 0xC000 {
+			true => self.register as u32,
+			false => (self.program_bank_num as u32).saturating_sub(1)
+		};
+		bank * 0x4000 + (address & 0x3FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		address
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		match address {
+			0x9000..=0x9FFF => self.mirroring = Some(value & 0x10),
+			0xC000..=0xFFFF => self.register = value & 0xF,
+			_ => {}
+		};
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		self.mirroring.is_some()
+	}
+
+	// Bit 4 selects the nametable
+	fn mirroring_type(&self) -> Mirrorings {
+		match self.mirroring {
+			Some(0) | None => Mirrorings::OneScreenLow,
+			_ => Mirrorings::OneScreenHigh
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Camerica {
+			register: self.register,
+			mirroring: self.mirroring,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Camerica { register, mirroring } = &MapperState::from_bytes(data) {
+			self.register = *register;
+			self.mirroring = *mirroring;
+		}
+	}
+}
+
+/**
+ * CPROM (mapper 13). 16KB CHR-RAM with the fixed 4KB bank at 0x0000
+ * and a switchable 4KB bank at 0x1000.
+ * Refer to https://wiki.nesdev.com/w/index.php/CPROM
+ */
+struct CPRomMapper {
+	register: Register<u8>
+}
+
+impl CPRomMapper {
+	fn new() -> Self {
+		CPRomMapper {
+			register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for CPRomMapper {
+	fn map(&self, address: u32) -> u32 {
+		address & 0x7FFF
+	}
+
+	// Register bits 1-0
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		match address < 0x1000 {
+			true => address,
+			false => self.register.load_bits(0, 2) as u32 * 0x1000 + (address & 0xFFF)
+		}
+	}
+
+	fn store(&mut self, _address: u32, value: u8) {
+		self.register.store(value);
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::SingleScreen // dummy
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::CPRom {
+			register: self.register.get_data(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::CPRom { register } = &MapperState::from_bytes(data) {
+			self.register.set_data(*register);
+		}
+	}
+
+	fn has_bus_conflicts(&self) -> bool {
+		true
+	}
+}
+
+/**
+ * BMC-D1038 (mapper 59). NROM multicart switched with the address
+ * written to 0x8000 - 0xFFFF. With address bit 8 set, reads from
+ * 0x8000 - 0xFFFF return the solder pad setting which the menu
+ * selects the game list with.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_059
+ */
+struct D1038Mapper {
+	latch: u16, // Address written to 0x8000 - 0xFFFF
+	solder_pad: u8
+}
+
+impl D1038Mapper {
+	fn new() -> Self {
+		D1038Mapper {
+			latch: 0,
+			solder_pad: 0
+		}
+	}
+}
+
+impl Mapper for D1038Mapper {
+	/**
+	 * Latch bit 7 set: 16KB bank bits 6-4 mirrored in 0x8000 - 0xFFFF
+	 * Otherwise: 32KB bank bits 6-5
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let latch = self.latch as u32;
+		match (latch & 0x80) != 0 {
+			true => ((latch >> 4) & 0x7) * 0x4000 + (address & 0x3FFF),
+			false => ((latch >> 5) & 0x3) * 0x8000 + (address & 0x7FFF)
+		}
+	}
+
+	// Latch bits 2-0
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		(self.latch as u32 & 0x7) * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, address: u32, _value: u8) {
+		self.latch = address as u16;
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// Latch bit 3
+	fn mirroring_type(&self) -> Mirrorings {
+		match (self.latch & 0x8) != 0 {
+			true => Mirrorings::Horizontal,
+			false => Mirrorings::Vertical
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::D1038 {
+			latch: self.latch,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::D1038 { latch } = &MapperState::from_bytes(data) {
+			self.latch = *latch;
+		}
+	}
+
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		match address {
+			0x6000..=0x7FFF => CpuMapping::ProgramRam(address - 0x6000),
+			_ => match (self.latch & 0x100) != 0 {
+				true => CpuMapping::Value(self.solder_pad),
+				false => CpuMapping::ProgramRom(self.map(address))
+			}
+		}
+	}
+
+	fn set_solder_pad(&mut self, value: u8) {
+		self.solder_pad = value;
+	}
+}
+
+/**
+ * Multicart (mapper 225 and 255) switched with the address written to
+ * 0x8000 - 0xFFFF. Four 4-bit RAM cells at 0x5800 - 0x5FFF are used by
+ * the menus.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_225
+ */
+struct Multicart225Mapper {
+	latch: u16, // Address written to 0x8000 - 0xFFFF
+	ram: [u8; 4]
+}
+
+impl Multicart225Mapper {
+	fn new() -> Self {
+		Multicart225Mapper {
+			latch: 0,
+			ram: [0; 4]
+		}
+	}
+
+	// Latch bit 14 is the highest bit of PRG and CHR banks
+	fn high_bank_bit(&self) -> u32 {
+		(self.latch as u32 >> 8) & 0x40
+	}
+}
+
+impl Mapper for Multicart225Mapper {
+	/**
+	 * Latch bits 11-6 and the high bit select 16KB bank.
+	 * Latch bit 12 set: the bank is mirrored in 0x8000 - 0xFFFF
+	 * Otherwise: 32KB bank ignoring the lowest bit
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let bank = ((self.latch as u32 >> 6) & 0x3F) | self.high_bank_bit();
+		match (self.latch & 0x1000) != 0 {
+			true => bank * 0x4000 + (address & 0x3FFF),
+			false => (bank & 0x7E) * 0x4000 + (address & 0x7FFF)
+		}
+	}
+
+	// Latch bits 5-0 and the high bit
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		((self.latch as u32 & 0x3F) | self.high_bank_bit()) * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, address: u32, _value: u8) {
+		self.latch = address as u16;
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// Latch bit 13
+	fn mirroring_type(&self) -> Mirrorings {
+		match (self.latch & 0x2000) != 0 {
+			true => Mirrorings::Horizontal,
+			false => Mirrorings::Vertical
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Multicart225 {
+			latch: self.latch,
+			ram: self.ram,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Multicart225 { latch, ram } = &MapperState::from_bytes(data) {
+			self.latch = *latch;
+			self.ram = *ram;
+		}
+	}
+
+	fn load_register(&mut self, address: u32) -> Option<u8> {
+		match address {
+			0x5800..=0x5FFF => Some(self.ram[address as usize & 3]),
+			_ => None
+		}
+	}
+
+	fn store_register(&mut self, address: u32, value: u8) -> bool {
+		match address {
+			0x5800..=0x5FFF => {
+				self.ram[address as usize & 3] = value & 0xF;
+				true
+			},
+			_ => false
+		}
+	}
+}
+
+/**
+ * Multicart (mapper 226) with 8KB CHR-RAM and two registers
+ * at even and odd addresses in 0x8000 - 0xFFFF.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_226
+ */
+struct Multicart226Mapper {
+	registers: [u8; 2]
+}
+
+impl Multicart226Mapper {
+	fn new() -> Self {
+		Multicart226Mapper {
+			registers: [0; 2]
+		}
+	}
+}
+
+impl Mapper for Multicart226Mapper {
+	/**
+	 * 16KB bank: register 0 bits 4-0, register 0 bit 7 as bit 5
+	 * and register 1 bit 0 as bit 6.
+	 * Register 0 bit 5 set: the bank is mirrored in 0x8000 - 0xFFFF
+	 * Otherwise: 32KB bank ignoring the lowest bit
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let register0 = self.registers[0] as u32;
+		let bank = (register0 & 0x1F) | ((register0 & 0x80) >> 2) |
+			((self.registers[1] as u32 & 1) << 6);
+		match (register0 & 0x20) != 0 {
+			true => bank * 0x4000 + (address & 0x3FFF),
+			false => (bank & 0x7E) * 0x4000 + (address & 0x7FFF)
+		}
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		address
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		self.registers[address as usize & 1] = value;
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// Register 0 bit 6
+	fn mirroring_type(&self) -> Mirrorings {
+		match (self.registers[0] & 0x40) != 0 {
+			true => Mirrorings::Vertical,
+			false => Mirrorings::Horizontal
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Multicart226 {
+			registers: self.registers,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Multicart226 { registers } = &MapperState::from_bytes(data) {
+			self.registers = *registers;
+		}
+	}
+}
+
+/**
+ * Multicart (mapper 227) with 8KB CHR-RAM switched with the address
+ * written to 0x8000 - 0xFFFF. Runs NROM and UNROM games.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_227
+ */
+struct Multicart227Mapper {
+	latch: u16 // Address written to 0x8000 - 0xFFFF
+}
+
+impl Multicart227Mapper {
+	fn new() -> Self {
+		Multicart227Mapper {
+			latch: 0
+		}
+	}
+}
+
+impl Mapper for Multicart227Mapper {
+	/**
+	 * 16KB bank: latch bits 6-2 and bit 8 as bit 5.
+	 * Latch bit 0 (S) selects 32KB banks ignoring the lowest bit.
+	 * Latch bit 7 set (NROM): the banks are mapped as is
+	 * Otherwise (UNROM): 0xC000 - 0xFFFF is fixed to the first bank of
+	 * the 128KB block, or the last one with latch bit 9 (L) set
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let latch = self.latch as u32;
+		let bank = ((latch >> 2) & 0x1F) | ((latch & 0x100) >> 3);
+		let lower_bank = match (latch & 0x1) != 0 {
+			true => bank & 0x3E,
+			false => bank
+		};
+		let bank = match (latch & 0x80) != 0 {
+			true => match (latch & 0x1) != 0 {
+				true => lower_bank | ((address >> 14) & 1),
+				false => bank
+			},
+			false => match address < 0xC000 {
+				true => lower_bank,
+				false => match (latch & 0x200) != 0 {
+					true => bank | 0x7,
+					false => bank & 0x38
+				}
+			}
+		};
+		bank * 0x4000 + (address & 0x3FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		address
+	}
+
+	fn store(&mut self, address: u32, _value: u8) {
+		self.latch = address as u16;
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// Latch bit 1
+	fn mirroring_type(&self) -> Mirrorings {
+		match (self.latch & 0x2) != 0 {
+			true => Mirrorings::Horizontal,
+			false => Mirrorings::Vertical
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Multicart227 {
+			latch: self.latch,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Multicart227 { latch } = &MapperState::from_bytes(data) {
+			self.latch = *latch;
+		}
+	}
+}
+
+/**
+ * Action 52 and Cheetahmen II (mapper 228). Three 512KB PRG-ROM chips
+ * selected with the address written to 0x8000 - 0xFFFF. Four 4-bit
+ * RAM cells are mirrored in 0x4020 - 0x5FFF.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_228
+ */
+struct Action52Mapper {
+	latch: u16, // Address written to 0x8000 - 0xFFFF
+	data: u8, // Data written to 0x8000 - 0xFFFF
+	ram: [u8; 4]
+}
+
+impl Action52Mapper {
+	fn new() -> Self {
+		Action52Mapper {
+			latch: 0,
+			data: 0,
+			ram: [0; 4]
+		}
+	}
+}
+
+impl Mapper for Action52Mapper {
+	/**
+	 * Latch bits 12-11 select the chip. The chip 2 doesn't exist and
+	 * the chip 3 follows the chip 1 in the image. Latch bits 10-6
+	 * select 16KB bank in the chip.
+	 * Latch bit 5 set: the bank is mirrored in 0x8000 - 0xFFFF
+	 * Otherwise: 32KB bank ignoring the lowest bit
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let latch = self.latch as u32;
+		let chip = match (latch >> 11) & 0x3 {
+			3 => 2,
+			chip => chip
+		};
+		let bank = ((latch >> 6) & 0x1F) | (chip << 5);
+		match (latch & 0x20) != 0 {
+			true => bank * 0x4000 + (address & 0x3FFF),
+			false => (bank & 0x7E) * 0x4000 + (address & 0x7FFF)
+		}
+	}
+
+	// Latch bits 3-0 and data bits 1-0
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let bank = ((self.latch as u32 & 0xF) << 2) | (self.data as u32 & 0x3);
+		bank * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		self.latch = address as u16;
+		self.data = value;
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// Latch bit 13
+	fn mirroring_type(&self) -> Mirrorings {
+		match (self.latch & 0x2000) != 0 {
+			true => Mirrorings::Horizontal,
+			false => Mirrorings::Vertical
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Action52 {
+			latch: self.latch,
+			data: self.data,
+			ram: self.ram,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Action52 { latch, data, ram } = &MapperState::from_bytes(data) {
+			self.latch = *latch;
+			self.data = *data;
+			self.ram = *ram;
+		}
+	}
+
+	fn load_register(&mut self, address: u32) -> Option<u8> {
+		Some(self.ram[address as usize & 3])
+	}
+
+	fn store_register(&mut self, address: u32, value: u8) -> bool {
+		match address < 0x6000 {
+			true => {
+				self.ram[address as usize & 3] = value & 0xF;
+				true
+			},
+			false => false
+		}
+	}
+}
+
+/**
+ * MMC2 (PxROM) and MMC4 (FxROM). CHR banks are switched by latches which
+ * flip when PPU fetches tile $FD or $FE from the pattern tables.
+ * MMC2 has a switchable 8KB PRG-ROM bank while MMC4 has a 16KB one.
+ * Refer to https://wiki.nesdev.com/w/index.php/MMC2
+ * and https://wiki.nesdev.com/w/index.php/MMC4
+ */
+struct MMC2Mapper {
+	// true for MMC4
+	mmc4: bool,
+	program_bank_num: u8,
+	program_register: Register<u8>,
+	// 0xB000, 0xC000, 0xD000, 0xE000
+	character_registers: [Register<u8>; 4],
+	// true if latch is $FE, false if $FD
+	latch0: bool,
+	latch1: bool,
+	mirroring_register: Register<u8>
+}
+
+impl MMC2Mapper {
+	fn new(header: &RomHeader, mmc4: bool) -> Self {
+		MMC2Mapper {
+			mmc4: mmc4,
+			program_bank_num: header.prg_rom_bank_num(),
+			program_register: Register::<u8>::new(),
+			character_registers: [
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new()
+			],
+			latch0: true,
+			latch1: true,
+			mirroring_register: Register::<u8>::new()
+		}
+	}
+}
+
+impl Mapper for MMC2Mapper {
+	/**
+	 * MMC2
+	 * 0x8000 - 0x9FFF: Switchable 8KB bank
+	 * 0xA000 - 0xFFFF: Fixed to the last three 8KB banks
+	 *
+	 * MMC4
+	 * 0x8000 - 0xBFFF: Switchable 16KB bank
+	 * 0xC000 - 0xFFFF: Fixed to the last 16KB bank
+	 */
+	fn map(&self, address: u32) -> u32 {
+		if self.mmc4 {
+			let bank = match address < 0xC000 {
+				true => self.program_register.load_bits(0, 4) as u32,
+				false => self.program_bank_num as u32 - 1
+			};
+			return 0x4000 * bank + (address & 0x3FFF);
+		}
+		// program_bank_num is the number of 16KB banks
+		let bank = match address < 0xA000 {
+			true => self.program_register.load_bits(0, 4) as u32,
+			false => (self.program_bank_num as u32 * 2).saturating_sub(4) + ((address - 0x8000) >> 13)
+		};
+		0x2000 * bank + (address & 0x1FFF)
+	}
+
+	/**
+	 * 0x0000 - 0x0FFF: 4KB bank selected by 0xB000 (latch 0 $FD) or 0xC000 (latch 0 $FE)
+	 * 0x1000 - 0x1FFF: 4KB bank selected by 0xD000 (latch 1 $FD) or 0xE000 (latch 1 $FE)
+	 */
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let index = match address < 0x1000 {
+			true => match self.latch0 {
+				true => 1,
+				false => 0
+			},
+			false => match self.latch1 {
+				true => 3,
+				false => 2
+			}
+		};
+		self.character_registers[index].load_bits(0, 5) as u32 * 0x1000 + (address & 0xFFF)
+	}
+
+	/**
+	 * 0xA000 - 0xAFFF: PRG ROM bank select
+	 * 0xB000 - 0xEFFF: CHR ROM bank selects
+	 * 0xF000 - 0xFFFF: Mirroring
+	 */
+	fn store(&mut self, address: u32, value: u8) {
+		match address & 0xF000 {
+			0xA000 => self.program_register.store(value),
+			0xB000 => self.character_registers[0].store(value),
+			0xC000 => self.character_registers[1].store(value),
+			0xD000 => self.character_registers[2].store(value),
+			0xE000 => self.character_registers[3].store(value),
+			0xF000 => self.mirroring_register.store(value),
+			_ => {}
+		};
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		match self.mirroring_register.is_bit_set(0) {
+			true => Mirrorings::Horizontal,
+			false => Mirrorings::Vertical
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::MMC2 {
+			program_register: self.program_register.get_data(),
+			character_registers: [
+				self.character_registers[0].get_data(),
+				self.character_registers[1].get_data(),
+				self.character_registers[2].get_data(),
+				self.character_registers[3].get_data()
+			],
+			latch0: self.latch0,
+			latch1: self.latch1,
+			mirroring_register: self.mirroring_register.get_data(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::MMC2 { program_register, character_registers, latch0, latch1, mirroring_register } = &MapperState::from_bytes(data) {
+			self.program_register.set_data(*program_register);
+			for i in 0..4 {
+				self.character_registers[i].set_data(character_registers[i]);
+			}
+			self.latch0 = *latch0;
+			self.latch1 = *latch1;
+			self.mirroring_register.set_data(*mirroring_register);
+		}
+	}
+
+	/**
+	 * Latch 0 flips on 0x0FD8 / 0x0FE8 (MMC4: 0x0FD8-0x0FDF / 0x0FE8-0x0FEF).
+	 * Latch 1 flips on 0x1FD8-0x1FDF / 0x1FE8-0x1FEF.
+	 * The switch takes effect from the next fetch.
+	 */
+	fn notify_ppu_read(&mut self, address: u32) {
+		let address = address & 0x1FFF;
+		if address < 0x1000 && !self.mmc4 {
+			match address {
+				0x0FD8 => self.latch0 = false,
+				0x0FE8 => self.latch0 = true,
+				_ => {}
+			};
+			return;
+		}
+		match address & 0x1FF8 {
+			0x0FD8 => self.latch0 = false,
+			0x0FE8 => self.latch0 = true,
+			0x1FD8 => self.latch1 = false,
+			0x1FE8 => self.latch1 = true,
+			_ => {}
+		};
+	}
+}
+
+/**
+ * Boards built on MMC3 which differ from MMC3 in some points
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum MMC3Variant {
+	MMC3,
+	// Mapper 4 submapper 1. 1KB internal PRG-RAM at 0x7000 - 0x7FFF
+	// with the read/write protection for each 512B half.
+	// Refer to https://wiki.nesdev.com/w/index.php/MMC6
+	MMC6,
+	// Mapper 118. CHR register bit 7 selects the nametable
+	// instead of the mirroring register.
+	// Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_118
+	TxSRom,
+	// Mapper 119. CHR register bit 6 selects 8KB CHR-RAM over CHR-ROM.
+	// Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_119
+	TQRom
+}
+
+/**
+ * MMC3 IRQ counter behavior differs among the chip revisions.
+ * Refer to https://wiki.nesdev.com/w/index.php/MMC3#IRQ_Specifics
+ */
+#[derive(Clone, Copy, PartialEq)]
+enum MMC3IrqRevision {
+	// Mapper 4 submapper 4. Fires IRQ only when the counter
+	// becomes 0 by decrementing or by reloading after 0xC001 write.
+	RevA,
+	// Fires IRQ whenever the counter is 0 after clocked,
+	// then the reload value 0 fires IRQ every scanline.
+	RevB
+}
+
+// MMC3 counts A12 rising edges only after A12 stays low
+// for a while, about three CPU cycles.
+const MMC3_A12_FILTER_CYCLES: u32 = 10;
+
+struct MMC3Mapper {
+	variant: MMC3Variant,
+	irq_revision: MMC3IrqRevision,
+	// Whether 0xA001 enables and write-protects PRG-RAM
+	prg_ram_protect: bool,
+	program_bank_num: u8,
+	character_bank_num: u8,
+	register0: Register<u8>,
+	register1: Register<u8>,
+	register2: Register<u8>,
+	register3: Register<u8>,
+	register4: Register<u8>,
+	register5: Register<u8>,
+	register6: Register<u8>,
+	register7: Register<u8>,
+	program_register0: Register<u8>,
+	program_register1: Register<u8>,
+	character_register0: Register<u8>,
+	character_register1: Register<u8>,
+	character_register2: Register<u8>,
+	character_register3: Register<u8>,
+	character_register4: Register<u8>,
+	character_register5: Register<u8>,
+	irq_counter: u8,
+	irq_counter_reload: bool,
+	irq_enabled: bool,
+	irq_pending: bool
+}
+
+impl MMC3Mapper {
+	fn new(header: &RomHeader, variant: MMC3Variant) -> Self {
+		MMC3Mapper {
+			variant: variant,
+			irq_revision: match header.mapper_num() == 4 && header.submapper_num() == 4 {
+				true => MMC3IrqRevision::RevA,
+				false => MMC3IrqRevision::RevB
+			},
+			prg_ram_protect: variant != MMC3Variant::MMC6 && header.format() == RomFormat::Nes20,
+			program_bank_num: header.prg_rom_bank_num(),
+			character_bank_num: std::cmp::max(header.chr_bank_num(), 1),
+			register0: Register::<u8>::new(),
+			register1: Register::<u8>::new(),
+			register2: Register::<u8>::new(),
+			register3: Register::<u8>::new(),
+			register4: Register::<u8>::new(),
+			register5: Register::<u8>::new(),
+			register6: Register::<u8>::new(),
+			register7: Register::<u8>::new(),
+			program_register0: Register::<u8>::new(),
+			program_register1: Register::<u8>::new(),
+			character_register0: Register::<u8>::new(),
+			character_register1: Register::<u8>::new(),
+			character_register2: Register::<u8>::new(),
+			character_register3: Register::<u8>::new(),
+			character_register4: Register::<u8>::new(),
+			character_register5: Register::<u8>::new(),
+			irq_counter: 0,
+			irq_counter_reload: false,
+			irq_enabled: true,
+			irq_pending: false
+		}
+	}
+
+	// Clocked on the filtered PPU A12 rising edges
+	fn clock_irq_counter(&mut self) {
+		let counter = self.irq_counter;
+		match counter == 0 || self.irq_counter_reload {
+			true => self.irq_counter = self.register4.load(),
+			false => self.irq_counter -= 1
+		};
+		let fires = match self.irq_revision {
+			MMC3IrqRevision::RevA => (counter > 0 || self.irq_counter_reload) && self.irq_counter == 0,
+			MMC3IrqRevision::RevB => self.irq_counter == 0
+		};
+		if fires && self.irq_enabled {
+			self.irq_pending = true;
+		}
+		self.irq_counter_reload = false;
+	}
+
+	// 1KB CHR bank for the pattern table address
+	fn character_bank(&self, address: u32) -> u8 {
+		match self.register0.is_bit_set(7) {
+			true => match address & 0x1FFF {
+				0x0000..=0x03FF => self.character_register2.load(),
+				0x0400..=0x07FF => self.character_register3.load(),
+				0x0800..=0x0BFF => self.character_register4.load(),
+				0x0C00..=0x0FFF => self.character_register5.load(),
+				0x1000..=0x13FF => self.character_register0.load() & 0xFE,
+				0x1400..=0x17FF => self.character_register0.load() | 1,
+				0x1800..=0x1BFF => self.character_register1.load() & 0xFE,
+				_ => self.character_register1.load() | 1
+			},
+			false => match address & 0x1FFF {
+				0x0000..=0x03FF => self.character_register0.load() & 0xFE,
+				0x0400..=0x07FF => self.character_register0.load() | 1,
+				0x0800..=0x0BFF => self.character_register1.load() & 0xFE,
+				0x0C00..=0x0FFF => self.character_register1.load() | 1,
+				0x1000..=0x13FF => self.character_register2.load(),
+				0x1400..=0x17FF => self.character_register3.load(),
+				0x1800..=0x1BFF => self.character_register4.load(),
+				_ => self.character_register5.load()
+			}
+		}
+	}
+
+	// MMC6 0xA001 read enable bits, 7 for 0x7200 - 0x73FF, 5 for 0x7000 - 0x71FF
+	fn mmc6_half_readable(&self, address: u32) -> bool {
+		match (address & 0x200) != 0 {
+			true => self.register3.is_bit_set(7),
+			false => self.register3.is_bit_set(5)
+		}
+	}
+}
+
+impl Mapper for MMC3Mapper {
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address {
+			0x8000..=0x9FFF => match self.register0.is_bit_set(6) {
+				true => self.program_bank_num * 2 - 2,
+				false => self.program_register0.load()
+			},
+			0xA000..=0xBFFF => self.program_register1.load(),
+			0xC000..=0xDFFF => match self.register0.is_bit_set(6) {
+				true => self.program_register0.load(),
+				false => self.program_bank_num * 2 - 2
+			},
+			_ => self.program_bank_num * 2 - 1
+		};
+		// I couldn't in the spec but it seems that
+		// we need to wrap 2k bank with 4k program_bank_num
+		((bank as u32) % ((self.program_bank_num as u32) * 2)) * 0x2000 + (address & 0x1FFF)
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let bank = self.character_bank(address);
+		// TQROM CHR-RAM is 8KB
+		if self.is_chr_ram(address) {
+			return ((bank & 0x7) as u32) * 0x400 + (address & 0x3FF);
+		}
+		// I couldn't in the spec but it seems that
+		// we need to wrap 0.4k bank with 4k character_bank_num
+		((bank as u32) % ((self.character_bank_num as u32) * 8)) * 0x400 + (address & 0x3FF)
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		match address {
+			0x8000..=0x9FFF => match (address & 1) == 0 {
+				true => self.register0.store(value),
+				false => {
+					self.register1.store(value);
+					match self.register0.load_bits(0, 3) {
+						0 => self.character_register0.store(value & 0xFE),
+						1 => self.character_register1.store(value & 0xFE),
+						2 => self.character_register2.store(value),
+						3 => self.character_register3.store(value),
+						4 => self.character_register4.store(value),
+						5 => self.character_register5.store(value),
+						6 => self.program_register0.store(value & 0x3F),
+						_ => self.program_register1.store(value & 0x3F)
+					};
+				}
+			},
+			0xA000..=0xBFFF => match (address & 1) == 0 {
+				true => self.register2.store(value),
+				false => self.register3.store(value)
+			},
+			0xC000..=0xDFFF => match (address & 1) == 0 {
+				true => self.register4.store(value),
+				false => {
+					self.register5.store(value);
+					self.irq_counter = 0;
+					self.irq_counter_reload = true;
+				}
+			},
+			_ => match (address & 1) == 0 {
+				true => {
+					self.register6.store(value);
+					self.irq_enabled = false;
+					self.irq_pending = false;
+				},
+				false => {
+					self.register7.store(value);
+					self.irq_enabled = true;
+				}
+			}
+		};
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		match self.register2.is_bit_set(0) {
+			true => Mirrorings::Horizontal,
+			false => Mirrorings::Vertical
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn notify_ppu_a12_rise(&mut self, low_cycles: u32) {
+		if low_cycles >= MMC3_A12_FILTER_CYCLES {
+			self.clock_irq_counter();
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq_pending
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::MMC3 {
+			program_bank_num: self.program_bank_num,
+			character_bank_num: self.character_bank_num,
+			register0: self.register0.get_data(),
+			register1: self.register1.get_data(),
+			register2: self.register2.get_data(),
+			register3: self.register3.get_data(),
+			register4: self.register4.get_data(),
+			register5: self.register5.get_data(),
+			register6: self.register6.get_data(),
+			register7: self.register7.get_data(),
+			program_register0: self.program_register0.get_data(),
+			program_register1: self.program_register1.get_data(),
+			character_register0: self.character_register0.get_data(),
+			character_register1: self.character_register1.get_data(),
+			character_register2: self.character_register2.get_data(),
+			character_register3: self.character_register3.get_data(),
+			character_register4: self.character_register4.get_data(),
+			character_register5: self.character_register5.get_data(),
+			irq_counter: self.irq_counter,
+			irq_counter_reload: self.irq_counter_reload,
+			irq_enabled: self.irq_enabled,
+			irq_pending: self.irq_pending,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::MMC3 {
+			program_bank_num,
+			character_bank_num,
+			register0,
+			register1,
+			register2,
+			register3,
+			register4,
+			register5,
+			register6,
+			register7,
+			program_register0,
+			program_register1,
+			character_register0,
+			character_register1,
+			character_register2,
+			character_register3,
+			character_register4,
+			character_register5,
+			irq_counter,
+			irq_counter_reload,
+			irq_enabled,
+			irq_pending,
+		} = &MapperState::from_bytes(data) {
+			self.program_bank_num = *program_bank_num;
+			self.character_bank_num = *character_bank_num;
+			self.register0.set_data(*register0);
+			self.register1.set_data(*register1);
+			self.register2.set_data(*register2);
+			self.register3.set_data(*register3);
+			self.register4.set_data(*register4);
+			self.register5.set_data(*register5);
+			self.register6.set_data(*register6);
+			self.register7.set_data(*register7);
+			self.program_register0.set_data(*program_register0);
+			self.program_register1.set_data(*program_register1);
+			self.character_register0.set_data(*character_register0);
+			self.character_register1.set_data(*character_register1);
+			self.character_register2.set_data(*character_register2);
+			self.character_register3.set_data(*character_register3);
+			self.character_register4.set_data(*character_register4);
+			self.character_register5.set_data(*character_register5);
+			self.irq_counter = *irq_counter;
+			self.irq_counter_reload = *irq_counter_reload;
+			self.irq_enabled = *irq_enabled;
+			self.irq_pending = *irq_pending;
+		}
+	}
+
+	/**
+	 * TxSROM connects CHR A17 to CIRAM A10. Bit 7 of the CHR bank for
+	 * 0x0000 - 0x0FFF selects the page of the matching nametable slot.
+	 */
+	fn name_table_source(&self, slot: u8) -> Option<NameTableSource> {
+		match self.variant {
+			MMC3Variant::TxSRom => {
+				let bank = self.character_bank(slot as u32 * 0x400);
+				Some(NameTableSource::Vram(bank >> 7))
+			},
+			_ => None
+		}
+	}
+
+	/**
+	 * MMC6 has no PRG-RAM in 0x6000 - 0x6FFF. 0x8000 bit 5 enables
+	 * the internal 1KB PRG-RAM mirrored in 0x7000 - 0x7FFF.
+	 * A half disabled for reading reads 0 and can't be written.
+	 * MMC3 0xA001 bit 7 enables PRG-RAM, only for NES 2.0 images because
+	 * MMC6 games in iNES images would write there to control MMC6 RAM.
+	 */
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		if address >= 0x8000 {
+			return CpuMapping::ProgramRom(self.map(address));
+		}
+		match self.variant {
+			MMC3Variant::MMC6 => match address >= 0x7000 && self.register0.is_bit_set(5) &&
+				self.mmc6_half_readable(address) {
+				true => CpuMapping::ProgramRam(address & 0x3FF),
+				false => CpuMapping::Unmapped
+			},
+			_ => match self.prg_ram_protect && !self.register3.is_bit_set(7) {
+				true => CpuMapping::Unmapped,
+				false => CpuMapping::ProgramRam(address - 0x6000)
+			}
+		}
+	}
+
+	// MMC6 0xA001 write enable bits, 6 for 0x7200 - 0x73FF, 4 for 0x7000 - 0x71FF.
+	// MMC3 0xA001 bit 6 write-protects PRG-RAM.
+	fn prg_ram_writable(&self, address: u32) -> bool {
+		match self.variant {
+			MMC3Variant::MMC6 => match (address & 0x200) != 0 {
+				true => self.register3.is_bit_set(6),
+				false => self.register3.is_bit_set(4)
+			},
+			_ => !self.prg_ram_protect || !self.register3.is_bit_set(6)
+		}
+	}
+
+	// TQROM maps CHR banks with bit 6 set to CHR-RAM
+	fn is_chr_ram(&self, address: u32) -> bool {
+		self.variant == MMC3Variant::TQRom && (self.character_bank(address) & 0x40) != 0
+	}
+}
+
+/**
+ * MMC5 (ExROM). Several PRG/CHR banking modes, 1KB ExRAM usable as
+ * nametable, extended attributes or work RAM, vertical split screen,
+ * scanline IRQ, 8x8 multiplier and expansion audio consisting of
+ * two pulse channels and a raw PCM channel.
+ * PCM read mode and its IRQ are not supported.
+ * Refer to https://wiki.nesdev.com/w/index.php/MMC5
+ */
+struct MMC5Mapper {
+	program_mode: u8, // 0x5100
+	character_mode: u8, // 0x5101
+	prg_ram_protect1: u8, // 0x5102
+	prg_ram_protect2: u8, // 0x5103
+	exram_mode: u8, // 0x5104
+	name_table_mapping: u8, // 0x5105
+	fill_tile: u8, // 0x5106
+	fill_attribute: u8, // 0x5107
+	program_registers: [u8; 5], // 0x5113 - 0x5117
+	character_registers: [u16; 12], // 0x5120 - 0x512B, including upper bits
+	character_upper_bits: u8, // 0x5130
+	// true if 0x5128 - 0x512B was written after 0x5120 - 0x5127
+	character_set_b_written: bool,
+	split_control: u8, // 0x5200
+	split_scroll: u8, // 0x5201
+	split_bank: u8, // 0x5202
+	irq_target: u8, // 0x5203
+	irq_enabled: bool, // 0x5204
+	irq_pending: bool,
+	irq_counter: u8,
+	in_frame: bool,
+	multiplicand: u8, // 0x5205
+	multiplier: u8, // 0x5206
+	exram: Vec<u8>, // 0x5C00 - 0x5FFF
+
+	// Tracks PPU rendering via .notify_ppu_phase() and nametable fetches
+	scanline: u16,
+	tile: u8,
+	fetching_sprites: bool,
+	large_sprites: bool,
+	split_tile: bool,
+	extended_attribute: u8,
+
+	pulse1: ApuPulse, // 0x5000 - 0x5003
+	pulse2: ApuPulse, // 0x5004 - 0x5007
+	pcm: u8, // 0x5011
+	audio_cycle: u32
+}
+
+impl MMC5Mapper {
+	fn new() -> Self {
+		MMC5Mapper {
+			program_mode: 3,
+			character_mode: 0,
+			prg_ram_protect1: 0,
+			prg_ram_protect2: 0,
+			exram_mode: 0,
+			name_table_mapping: 0,
+			fill_tile: 0,
+			fill_attribute: 0,
+			// 0x5117 seems to be 0xFF at power on
+			program_registers: [0, 0, 0, 0, 0xFF],
+			character_registers: [0; 12],
+			character_upper_bits: 0,
+			character_set_b_written: false,
+			split_control: 0,
+			split_scroll: 0,
+			split_bank: 0,
+			irq_target: 0,
+			irq_enabled: false,
+			irq_pending: false,
+			irq_counter: 0,
+			in_frame: false,
+			multiplicand: 0xFF,
+			multiplier: 0xFF,
+			exram: vec![0; 0x400],
+			scanline: 0,
+			tile: 0,
+			fetching_sprites: false,
+			large_sprites: false,
+			split_tile: false,
+			extended_attribute: 0,
+			pulse1: ApuPulse::new(ApuPulseChannel::Expansion),
+			pulse2: ApuPulse::new(ApuPulseChannel::Expansion),
+			pcm: 0,
+			audio_cycle: 0
+		}
+	}
+
+	/**
+	 * Returns the index of program_registers and the bank size for 0x8000 - 0xFFFF
+	 *
+	 * Mode 0: 32KB (0x5117)
+	 * Mode 1: 16KB (0x5115), 16KB (0x5117)
+	 * Mode 2: 16KB (0x5115), 8KB (0x5116), 8KB (0x5117)
+	 * Mode 3: 8KB (0x5114), 8KB (0x5115), 8KB (0x5116), 8KB (0x5117)
+	 */
+	fn program_bank(&self, address: u32) -> (usize, u32) {
+		match self.program_mode {
+			0 => (4, 0x8000),
+			1 => match address < 0xC000 {
+				true => (2, 0x4000),
+				false => (4, 0x4000)
+			},
+			2 => match address {
+				0x8000..=0xBFFF => (2, 0x4000),
+				0xC000..=0xDFFF => (3, 0x2000),
+				_ => (4, 0x2000)
+			},
+			_ => (1 + ((address - 0x8000) >> 13) as usize, 0x2000)
+		}
+	}
+
+	/**
+	 * Set A (0x5120 - 0x5127) covers 0x0000 - 0x1FFF while
+	 * set B (0x5128 - 0x512B) covers 0x0000 - 0x0FFF mirrored to 0x1000 - 0x1FFF.
+	 *
+	 * Mode 0: 8KB (0x5127 / 0x512B)
+	 * Mode 1: 4KB (0x5123, 0x5127 / 0x512B)
+	 * Mode 2: 2KB (0x5121, 0x5123, 0x5125, 0x5127 / 0x5129, 0x512B)
+	 * Mode 3: 1KB (0x5120 - 0x5127 / 0x5128 - 0x512B)
+	 */
+	fn character_bank_address(&self, address: u32, set_b: bool) -> u32 {
+		let (index, size) = match self.character_mode {
+			0 => (7, 0x2000),
+			1 => ((address >> 12) * 4 + 3, 0x1000),
+			2 => ((address >> 11) * 2 + 1, 0x800),
+			_ => (address >> 10, 0x400)
+		};
+		let index = match set_b {
+			true => 8 + (index & 3),
+			false => index
+		} as usize;
+		self.character_registers[index] as u32 * size + (address & (size - 1))
+	}
+
+	fn in_split_region(&self, tile: u8) -> bool {
+		// Split mode works only when ExRAM is used as nametable
+		if (self.split_control & 0x80) == 0 || self.exram_mode > 1 {
+			return false;
+		}
+		let threshold = self.split_control & 0x1F;
+		match (self.split_control & 0x40) != 0 {
+			true => tile >= threshold, // right side
+			false => tile < threshold // left side
+		}
+	}
+
+	// Vertical scroll in the split region
+	fn split_y(&self) -> u16 {
+		let y = self.split_scroll as u16 + self.scanline;
+		match self.split_scroll < 240 {
+			true => y % 240,
+			false => y % 256
+		}
+	}
+
+	fn name_table_mapping_of(&self, slot: u32) -> u8 {
+		(self.name_table_mapping >> (slot * 2)) & 3
+	}
+}
+
+impl Mapper for MMC5Mapper {
+	fn map(&self, address: u32) -> u32 {
+		match self.map_for_cpu(address) {
+			CpuMapping::ProgramRom(address_in_rom) => address_in_rom,
+			_ => 0
+		}
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		if self.in_frame && !self.fetching_sprites {
+			if self.split_tile {
+				// Replaces fine y scroll with the split region's
+				let fine_y = (self.split_y() & 7) as u32;
+				return self.split_bank as u32 * 0x1000 + (address & 0xFF8) + fine_y;
+			}
+			if self.exram_mode == 1 {
+				// Extended attribute mode selects 4KB bank for each tile
+				let bank = (self.extended_attribute & 0x3F) as u32 |
+					((self.character_upper_bits as u32) << 6);
+				return bank * 0x1000 + (address & 0xFFF);
+			}
+		}
+		// With 8x16 sprites, set A is for sprites and set B is for background.
+		// Otherwise the last written set is used.
+		let set_b = match self.in_frame && self.large_sprites {
+			true => !self.fetching_sprites,
+			false => self.character_set_b_written
+		};
+		self.character_bank_address(address, set_b)
+	}
+
+	// Registers are in 0x5000 - 0x5FFF, see .store_register()
+	fn store(&mut self, _address: u32, _value: u8) {
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	// Unused, see .name_table_source()
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::Vertical
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::MMC5 {
+			program_mode: self.program_mode,
+			character_mode: self.character_mode,
+			prg_ram_protect1: self.prg_ram_protect1,
+			prg_ram_protect2: self.prg_ram_protect2,
+			exram_mode: self.exram_mode,
+			name_table_mapping: self.name_table_mapping,
+			fill_tile: self.fill_tile,
+			fill_attribute: self.fill_attribute,
+			program_registers: self.program_registers,
+			character_registers: self.character_registers,
+			character_upper_bits: self.character_upper_bits,
+			character_set_b_written: self.character_set_b_written,
+			split_control: self.split_control,
+			split_scroll: self.split_scroll,
+			split_bank: self.split_bank,
+			irq_target: self.irq_target,
+			irq_enabled: self.irq_enabled,
+			irq_pending: self.irq_pending,
+			irq_counter: self.irq_counter,
+			in_frame: self.in_frame,
+			multiplicand: self.multiplicand,
+			multiplier: self.multiplier,
+			exram: self.exram.clone(),
+			pulse1: self.pulse1.save_state(),
+			pulse2: self.pulse2.save_state(),
+			pcm: self.pcm,
+			audio_cycle: self.audio_cycle,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::MMC5 {
+			program_mode, character_mode, prg_ram_protect1, prg_ram_protect2,
+			exram_mode, name_table_mapping, fill_tile, fill_attribute,
+			program_registers, character_registers, character_upper_bits,
+			character_set_b_written, split_control, split_scroll, split_bank,
+			irq_target, irq_enabled, irq_pending, irq_counter, in_frame,
+			multiplicand, multiplier, exram, pulse1, pulse2, pcm, audio_cycle
+		} = &MapperState::from_bytes(data) {
+			self.program_mode = *program_mode;
+			self.character_mode = *character_mode;
+			self.prg_ram_protect1 = *prg_ram_protect1;
+			self.prg_ram_protect2 = *prg_ram_protect2;
+			self.exram_mode = *exram_mode;
+			self.name_table_mapping = *name_table_mapping;
+			self.fill_tile = *fill_tile;
+			self.fill_attribute = *fill_attribute;
+			self.program_registers = *program_registers;
+			self.character_registers = *character_registers;
+			self.character_upper_bits = *character_upper_bits;
+			self.character_set_b_written = *character_set_b_written;
+			self.split_control = *split_control;
+			self.split_scroll = *split_scroll;
+			self.split_bank = *split_bank;
+			self.irq_target = *irq_target;
+			self.irq_enabled = *irq_enabled;
+			self.irq_pending = *irq_pending;
+			self.irq_counter = *irq_counter;
+			self.in_frame = *in_frame;
+			self.multiplicand = *multiplicand;
+			self.multiplier = *multiplier;
+			self.exram = exram.clone();
+			self.pulse1.load_state(pulse1);
+			self.pulse2.load_state(pulse2);
+			self.pcm = *pcm;
+			self.audio_cycle = *audio_cycle;
+		}
+	}
+
+	/**
+	 * 0x6000 - 0x7FFF: 8KB PRG-RAM bank (0x5113)
+	 * 0x8000 - 0xFFFF: See .program_bank(). Bit 7 of the bank register
+	 *                  selects ROM(1) or RAM(0) except for 0x5117 which is always ROM.
+	 */
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		if address < 0x8000 {
+			let bank = (self.program_registers[0] & 0x0F) as u32;
+			return CpuMapping::ProgramRam(bank * 0x2000 + (address & 0x1FFF));
+		}
+		let (index, size) = self.program_bank(address);
+		let value = self.program_registers[index];
+		let is_rom = index == 4 || (value & 0x80) != 0;
+		let bank = (value & match is_rom {
+			true => 0x7F,
+			false => 0x0F
+		}) as u32 & !(size / 0x2000 - 1);
+		let address_in_bank = bank * 0x2000 + (address & (size - 1));
+		match is_rom {
+			true => CpuMapping::ProgramRom(address_in_bank),
+			false => CpuMapping::ProgramRam(address_in_bank)
+		}
+	}
+
+	// Writable only if 0x5102 is 0x02 and 0x5103 is 0x01
+	fn prg_ram_writable(&self, _address: u32) -> bool {
+		(self.prg_ram_protect1 & 0x3) == 0x2 && (self.prg_ram_protect2 & 0x3) == 0x1
+	}
+
+	fn load_register(&mut self, address: u32) -> Option<u8> {
+		match address {
+			0x5015 => {
+				let mut value = 0;
+				if self.pulse1.length_counter() > 0 {
+					value |= 0x01;
+				}
+				if self.pulse2.length_counter() > 0 {
+					value |= 0x02;
+				}
+				Some(value)
+			},
+			0x5204 => {
+				// Reading acknowledges the IRQ
+				let value = ((self.irq_pending as u8) << 7) | ((self.in_frame as u8) << 6);
+				self.irq_pending = false;
+				Some(value)
+			},
+			0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+			0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+			// ExRAM is readable in mode 2 and 3
+			0x5C00..=0x5FFF => Some(match self.exram_mode >= 2 {
+				true => self.exram[(address & 0x3FF) as usize],
+				false => 0
+			}),
+			_ => None
+		}
+	}
+
+	fn store_register(&mut self, address: u32, value: u8) -> bool {
+		match address {
+			0x5000..=0x5003 => self.pulse1.store_register(address as u16, value),
+			0x5004..=0x5007 => self.pulse2.store_register(address as u16, value),
+			0x5011 => {
+				// Writing 0 has no effect in PCM write mode
+				if value != 0 {
+					self.pcm = value;
+				}
+			},
+			0x5015 => {
+				self.pulse1.set_enable((value & 0x1) == 0x1);
+				self.pulse2.set_enable((value & 0x2) == 0x2);
+			},
+			0x5100 => self.program_mode = value & 0x3,
+			0x5101 => self.character_mode = value & 0x3,
+			0x5102 => self.prg_ram_protect1 = value,
+			0x5103 => self.prg_ram_protect2 = value,
+			0x5104 => self.exram_mode = value & 0x3,
+			0x5105 => self.name_table_mapping = value,
+			0x5106 => self.fill_tile = value,
+			0x5107 => self.fill_attribute = value & 0x3,
+			0x5113..=0x5117 => self.program_registers[(address - 0x5113) as usize] = value,
+			0x5120..=0x512B => {
+				self.character_registers[(address - 0x5120) as usize] =
+					value as u16 | ((self.character_upper_bits as u16) << 8);
+				self.character_set_b_written = address >= 0x5128;
+			},
+			0x5130 => self.character_upper_bits = value & 0x3,
+			0x5200 => self.split_control = value,
+			0x5201 => self.split_scroll = value,
+			0x5202 => self.split_bank = value,
+			0x5203 => self.irq_target = value,
+			0x5204 => self.irq_enabled = (value & 0x80) != 0,
+			0x5205 => self.multiplicand = value,
+			0x5206 => self.multiplier = value,
+			0x5C00..=0x5FFF => {
+				// In mode 0 and 1, writing while PPU isn't rendering stores 0
+				match self.exram_mode {
+					0 | 1 => self.exram[(address & 0x3FF) as usize] = match self.in_frame {
+						true => value,
+						false => 0
+					},
+					2 => self.exram[(address & 0x3FF) as usize] = value,
+					_ => {}
+				};
+			},
+			_ => {}
+		};
+		address >= 0x5000 && address < 0x6000
+	}
+
+	fn step(&mut self) {
+		self.audio_cycle = self.audio_cycle.wrapping_add(1);
+
+		if (self.audio_cycle % 2) == 0 {
+			self.pulse1.drive_timer();
+			self.pulse2.drive_timer();
+		}
+
+		// Envelopes and length counters are clocked by 240Hz fixed timer
+		if (self.audio_cycle % 7457) == 0 {
+			self.pulse1.drive_envelope();
+			self.pulse1.drive_length();
+			self.pulse2.drive_envelope();
+			self.pulse2.drive_length();
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq_enabled && self.irq_pending
+	}
+
+	// Mixed in the same way as APU pulse and DMC channels
+	fn audio_output(&self) -> f32 {
+		let pulse = (self.pulse1.output() + self.pulse2.output()) as f32;
+		let pcm = self.pcm as f32 / 2.0;
+		let mut output = 0.0;
+		if pulse != 0.0 {
+			output += 95.88 / ((8128.0 / pulse) + 100.0);
+		}
+		if pcm != 0.0 {
+			output += 159.79 / (1.0 / (pcm / 22638.0) + 100.0);
+		}
+		output
+	}
+
+	/**
+	 * 0x5105 selects each slot
+	 * 0: VRAM page 0
+	 * 1: VRAM page 1
+	 * 2: ExRAM
+	 * 3: Fill mode
+	 */
+	fn name_table_source(&self, slot: u8) -> Option<NameTableSource> {
+		Some(match self.name_table_mapping_of(slot as u32) {
+			0 => NameTableSource::Vram(0),
+			1 => NameTableSource::Vram(1),
+			_ => NameTableSource::Mapper
+		})
+	}
+
+	fn load_name_table(&self, address: u32) -> u8 {
+		match self.name_table_mapping_of((address >> 10) & 3) {
+			2 => match self.exram_mode <= 1 {
+				true => self.exram[(address & 0x3FF) as usize],
+				false => 0
+			},
+			_ => match (address & 0x3FF) >= 0x3C0 {
+				true => self.fill_attribute * 0x55,
+				false => self.fill_tile
+			}
+		}
+	}
+
+	fn store_name_table(&mut self, address: u32, value: u8) {
+		if self.name_table_mapping_of((address >> 10) & 3) == 2 && self.exram_mode <= 1 {
+			self.exram[(address & 0x3FF) as usize] = value;
+		}
+	}
+
+	fn fetch_name_table(&mut self, address: u32) -> Option<u8> {
+		if !self.in_frame {
+			return None;
+		}
+		let tile = self.tile;
+		self.tile = self.tile.wrapping_add(1);
+		self.split_tile = self.in_split_region(tile);
+		if self.split_tile {
+			let row = (self.split_y() >> 3) as usize;
+			return Some(self.exram[(row * 32 + (tile & 0x1F) as usize) & 0x3FF]);
+		}
+		if self.exram_mode == 1 {
+			self.extended_attribute = self.exram[(address & 0x3FF) as usize];
+		}
+		None
+	}
+
+	// Attribute bytes returned here have the same palette in all four quadrants
+	fn fetch_attribute_table(&mut self, _address: u32) -> Option<u8> {
+		if !self.in_frame {
+			return None;
+		}
+		if self.split_tile {
+			let row = (self.split_y() >> 3) as usize;
+			let column = (self.tile.wrapping_sub(1) & 0x1F) as usize;
+			let byte = self.exram[0x3C0 + (row >> 2) * 8 + (column >> 2)];
+			let shift = ((row & 2) << 1) | (column & 2);
+			return Some(((byte >> shift) & 0x3) * 0x55);
+		}
+		if self.exram_mode == 1 {
+			return Some((self.extended_attribute >> 6) * 0x55);
+		}
+		None
+	}
+
+	fn notify_ppu_phase(&mut self, phase: PpuPhase) {
+		match phase {
+			PpuPhase::Background(scanline) => {
+				if !self.in_frame || scanline == 0 {
+					self.in_frame = true;
+					self.irq_counter = 0;
+				} else {
+					self.irq_counter = self.irq_counter.wrapping_add(1);
+					if self.irq_counter == self.irq_target {
+						self.irq_pending = true;
+					}
+				}
+				self.scanline = scanline;
+				self.tile = 0;
+				self.fetching_sprites = false;
+				self.split_tile = false;
+			},
+			PpuPhase::Sprites(large) => {
+				self.fetching_sprites = true;
+				self.large_sprites = large;
+			},
+			PpuPhase::Idle => {
+				if self.in_frame {
+					self.irq_pending = false;
+				}
+				self.in_frame = false;
+				self.fetching_sprites = false;
+				self.split_tile = false;
+			}
+		};
+	}
+}
+
+/**
+ * IRQ counter shared by Konami VRC4, VRC6 and VRC7.
+ * Counts CPU cycles, or scanlines with a 341/3 prescaler.
+ * Refer to https://wiki.nesdev.com/w/index.php/VRC_IRQ
+ */
+struct VrcIrq {
+	latch: u8,
+	enabled: bool,
+	enabled_after_acknowledge: bool,
+	cycle_mode: bool,
+	counter: u8,
+	prescaler: i16,
+	pending: bool
+}
+
+impl VrcIrq {
+	fn new() -> Self {
+		VrcIrq {
+			latch: 0,
+			enabled: false,
+			enabled_after_acknowledge: false,
+			cycle_mode: false,
+			counter: 0,
+			prescaler: 341,
+			pending: false
+		}
+	}
+
+	fn store_latch(&mut self, value: u8) {
+		self.latch = value;
+	}
+
+	/**
+	 * bit 0: Enable after acknowledgement
+	 * bit 1: Enable
+	 * bit 2: Cycle mode(1) or scanline mode(0)
+	 */
+	fn store_control(&mut self, value: u8) {
+		self.enabled_after_acknowledge = (value & 0x1) != 0;
+		self.enabled = (value & 0x2) != 0;
+		self.cycle_mode = (value & 0x4) != 0;
+		if self.enabled {
+			self.counter = self.latch;
+			self.prescaler = 341;
+		}
+		self.pending = false;
+	}
+
+	fn acknowledge(&mut self) {
+		self.pending = false;
+		self.enabled = self.enabled_after_acknowledge;
+	}
+
+	// Expects being called at CPU clock rate
+	fn step(&mut self) {
+		if !self.enabled {
+			return;
+		}
+		if self.cycle_mode {
+			self.clock_counter();
+			return;
+		}
+		self.prescaler -= 3;
+		if self.prescaler <= 0 {
+			self.prescaler += 341;
+			self.clock_counter();
+		}
+	}
+
+	fn clock_counter(&mut self) {
+		if self.counter == 0xFF {
+			self.counter = self.latch;
+			self.pending = true;
+		} else {
+			self.counter += 1;
+		}
+	}
+
+	fn save_state(&self) -> VrcIrqState {
+		VrcIrqState {
+			latch: self.latch,
+			enabled: self.enabled,
+			enabled_after_acknowledge: self.enabled_after_acknowledge,
+			cycle_mode: self.cycle_mode,
+			counter: self.counter,
+			prescaler: self.prescaler,
+			pending: self.pending,
+		}
+	}
+
+	fn load_state(&mut self, state: &VrcIrqState) {
+		self.latch = state.latch;
+		self.enabled = state.enabled;
+		self.enabled_after_acknowledge = state.enabled_after_acknowledge;
+		self.cycle_mode = state.cycle_mode;
+		self.counter = state.counter;
+		self.prescaler = state.prescaler;
+		self.pending = state.pending;
+	}
+}
+
+/**
+ * VRC6 pulse channel. 16-step duty cycle with 4-bit volume.
+ */
+struct Vrc6Pulse {
+	register: u8, // 0x9000, 0xA000
+	period: u16, // 0x9001-0x9002, 0xA001-0xA002
+	enabled: bool,
+	timer_counter: u16,
+	step: u8
+}
+
+impl Vrc6Pulse {
+	fn new() -> Self {
+		Vrc6Pulse {
+			register: 0,
+			period: 0,
+			enabled: false,
+			timer_counter: 0,
+			step: 15
+		}
+	}
+
+	fn store_register(&mut self, index: u32, value: u8) {
+		match index {
+			0 => self.register = value,
+			1 => self.period = (self.period & 0xF00) | value as u16,
+			_ => {
+				self.period = (self.period & 0xFF) | (((value & 0xF) as u16) << 8);
+				self.enabled = (value & 0x80) != 0;
+				// Disabling resets the duty cycle
+				if !self.enabled {
+					self.step = 15;
+				}
+			}
+		};
+	}
+
+	fn drive_timer(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+		if self.timer_counter > 0 {
+			self.timer_counter -= 1;
+		} else {
+			self.timer_counter = self.period >> shift;
+			self.step = self.step.wrapping_sub(1) & 0xF;
+		}
+	}
+
+	fn output(&self) -> u8 {
+		let volume = self.register & 0xF;
+		let duty = (self.register >> 4) & 0x7;
+		// bit 7 of the register ignores the duty
+		match self.enabled && ((self.register & 0x80) != 0 || self.step <= duty) {
+			true => volume,
+			false => 0
+		}
+	}
+
+	fn save_state(&self) -> Vrc6PulseState {
+		Vrc6PulseState {
+			register: self.register,
+			period: self.period,
+			enabled: self.enabled,
+			timer_counter: self.timer_counter,
+			step: self.step,
+		}
+	}
+
+	fn load_state(&mut self, state: &Vrc6PulseState) {
+		self.register = state.register;
+		self.period = state.period;
+		self.enabled = state.enabled;
+		self.timer_counter = state.timer_counter;
+		self.step = state.step;
+	}
+}
+
+/**
+ * VRC6 sawtooth channel. The accumulator is added the rate
+ * every other clock and reset at the 14th clock.
+ */
+struct Vrc6Sawtooth {
+	rate: u8, // 0xB000
+	period: u16, // 0xB001-0xB002
+	enabled: bool,
+	timer_counter: u16,
+	step: u8,
+	accumulator: u8
+}
+
+impl Vrc6Sawtooth {
+	fn new() -> Self {
+		Vrc6Sawtooth {
+			rate: 0,
+			period: 0,
+			enabled: false,
+			timer_counter: 0,
+			step: 0,
+			accumulator: 0
+		}
+	}
+
+	fn store_register(&mut self, index: u32, value: u8) {
+		match index {
+			0 => self.rate = value & 0x3F,
+			1 => self.period = (self.period & 0xF00) | value as u16,
+			_ => {
+				self.period = (self.period & 0xFF) | (((value & 0xF) as u16) << 8);
+				self.enabled = (value & 0x80) != 0;
+				if !self.enabled {
+					self.step = 0;
+					self.accumulator = 0;
+				}
+			}
+		};
+	}
+
+	fn drive_timer(&mut self, shift: u8) {
+		if !self.enabled {
+			return;
+		}
+		if self.timer_counter > 0 {
+			self.timer_counter -= 1;
+			return;
+		}
+		self.timer_counter = self.period >> shift;
+		self.step += 1;
+		if self.step == 14 {
+			self.step = 0;
+			self.accumulator = 0;
+		} else if (self.step & 1) == 0 {
+			self.accumulator = self.accumulator.wrapping_add(self.rate);
+		}
+	}
+
+	// 5-bit output, the high bits of the accumulator
+	fn output(&self) -> u8 {
+		self.accumulator >> 3
+	}
+
+	fn save_state(&self) -> Vrc6SawtoothState {
+		Vrc6SawtoothState {
+			rate: self.rate,
+			period: self.period,
+			enabled: self.enabled,
+			timer_counter: self.timer_counter,
+			step: self.step,
+			accumulator: self.accumulator,
+		}
+	}
+
+	fn load_state(&mut self, state: &Vrc6SawtoothState) {
+		self.rate = state.rate;
+		self.period = state.period;
+		self.enabled = state.enabled;
+		self.timer_counter = state.timer_counter;
+		self.step = state.step;
+		self.accumulator = state.accumulator;
+	}
+}
+
+/**
+ * Konami VRC6. VRC6a (mapper 24) and VRC6b (mapper 26) differ only in
+ * that the address lines A0 and A1 are swapped.
+ * Nametables from CHR-ROM ($B003 bit 4) are not supported.
+ * Refer to https://wiki.nesdev.com/w/index.php/VRC6
+ */
+struct VRC6Mapper {
+	// true for VRC6b
+	swap_address_lines: bool,
+	program_bank_num: u8,
+	program_registers: [u8; 2], // 0x8000, 0xC000
+	control: u8, // 0xB003
+	character_registers: [u8; 8], // 0xD000 - 0xE003
+	frequency_control: u8, // 0x9003
+	irq: VrcIrq, // 0xF000 - 0xF002
+	pulse1: Vrc6Pulse, // 0x9000 - 0x9002
+	pulse2: Vrc6Pulse, // 0xA000 - 0xA002
+	sawtooth: Vrc6Sawtooth // 0xB000 - 0xB002
+}
+
+impl VRC6Mapper {
+	fn new(header: &RomHeader, swap_address_lines: bool) -> Self {
+		VRC6Mapper {
+			swap_address_lines: swap_address_lines,
+			program_bank_num: header.prg_rom_bank_num(),
+			program_registers: [0; 2],
+			control: 0,
+			character_registers: [0; 8],
+			frequency_control: 0,
+			irq: VrcIrq::new(),
+			pulse1: Vrc6Pulse::new(),
+			pulse2: Vrc6Pulse::new(),
+			sawtooth: Vrc6Sawtooth::new()
+		}
+	}
+
+	// Register index 0-3 selected by A0 and A1
+	fn register_index(&self, address: u32) -> u32 {
+		match self.swap_address_lines {
+			true => ((address & 1) << 1) | ((address >> 1) & 1),
+			false => address & 3
+		}
+	}
+
+	// Timer clocks are divided by 16 or 256 with 0x9003
+	fn frequency_shift(&self) -> u8 {
+		match self.frequency_control {
+			v if (v & 0x4) != 0 => 8,
+			v if (v & 0x2) != 0 => 4,
+			_ => 0
+		}
+	}
+}
+
+impl Mapper for VRC6Mapper {
+	/**
+	 * 0x8000 - 0xBFFF: Switchable 16KB bank
+	 * 0xC000 - 0xDFFF: Switchable 8KB bank
+	 * 0xE000 - 0xFFFF: Fixed to the last 8KB bank
+	 */
+	fn map(&self, address: u32) -> u32 {
+		match address {
+			0x8000..=0xBFFF => (self.program_registers[0] & 0xF) as u32 * 0x4000 + (address & 0x3FFF),
+			0xC000..=0xDFFF => (self.program_registers[1] & 0x1F) as u32 * 0x2000 + (address & 0x1FFF),
+			_ => (self.program_bank_num as u32 * 2).saturating_sub(1) * 0x2000 + (address & 0x1FFF)
+		}
+	}
+
+	/**
+	 * 0xB003 bits 0-1 select the banking mode
+	 * 0: 1KB banks R0-R7
+	 * 1: 2KB banks R0-R3
+	 * 2, 3: 1KB banks R0-R3 for 0x0000 - 0x0FFF, 2KB banks R4-R5 for 0x1000 - 0x1FFF
+	 * With 0xB003 bit 5 set, 2KB banks ignore the lowest bit of the register
+	 * and use PPU A10 instead.
+	 */
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let slot = (address >> 10) as usize & 7;
+		let two_kb = |register: u8| match (self.control & 0x20) != 0 {
+			true => (register & 0xFE) | (slot & 1) as u8,
+			false => register
+		};
+		let bank = match self.control & 0x3 {
+			0 => self.character_registers[slot],
+			1 => two_kb(self.character_registers[slot >> 1]),
+			_ => match slot < 4 {
+				true => self.character_registers[slot],
+				false => two_kb(self.character_registers[4 + ((slot >> 1) & 1)])
+			}
+		};
+		bank as u32 * 0x400 + (address & 0x3FF)
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		let index = self.register_index(address);
+		match address & 0xF000 {
+			0x8000 => self.program_registers[0] = value,
+			0x9000 => match index {
+				3 => self.frequency_control = value,
+				_ => self.pulse1.store_register(index, value)
+			},
+			0xA000 => if index < 3 {
+				self.pulse2.store_register(index, value);
+			},
+			0xB000 => match index {
+				3 => self.control = value,
+				_ => self.sawtooth.store_register(index, value)
+			},
+			0xC000 => self.program_registers[1] = value,
+			0xD000 => self.character_registers[index as usize] = value,
+			0xE000 => self.character_registers[4 + index as usize] = value,
+			0xF000 => match index {
+				0 => self.irq.store_latch(value),
+				1 => self.irq.store_control(value),
+				2 => self.irq.acknowledge(),
+				_ => {}
+			},
+			_ => {}
+		};
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// 0xB003 bits 2-3
+	fn mirroring_type(&self) -> Mirrorings {
+		match (self.control >> 2) & 0x3 {
+			0 => Mirrorings::Vertical,
+			1 => Mirrorings::Horizontal,
+			2 => Mirrorings::OneScreenLow,
+			_ => Mirrorings::OneScreenHigh
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::VRC6 {
+			program_registers: self.program_registers,
+			control: self.control,
+			character_registers: self.character_registers,
+			frequency_control: self.frequency_control,
+			irq: self.irq.save_state(),
+			pulse1: self.pulse1.save_state(),
+			pulse2: self.pulse2.save_state(),
+			sawtooth: self.sawtooth.save_state(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::VRC6 { program_registers, control, character_registers,
+			frequency_control, irq, pulse1, pulse2, sawtooth } = &MapperState::from_bytes(data) {
+			self.program_registers = *program_registers;
+			self.control = *control;
+			self.character_registers = *character_registers;
+			self.frequency_control = *frequency_control;
+			self.irq.load_state(irq);
+			self.pulse1.load_state(pulse1);
+			self.pulse2.load_state(pulse2);
+			self.sawtooth.load_state(sawtooth);
+		}
+	}
+
+	// 0xB003 bit 7 enables PRG-RAM
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		match address < 0x8000 {
+			true => match (self.control & 0x80) != 0 {
+				true => CpuMapping::ProgramRam(address - 0x6000),
+				false => CpuMapping::Unmapped
+			},
+			false => CpuMapping::ProgramRom(self.map(address))
+		}
+	}
+
+	fn step(&mut self) {
+		self.irq.step();
+
+		// 0x9003 bit 0 halts the audio
+		if (self.frequency_control & 0x1) == 0 {
+			let shift = self.frequency_shift();
+			self.pulse1.drive_timer(shift);
+			self.pulse2.drive_timer(shift);
+			self.sawtooth.drive_timer(shift);
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq.pending
+	}
+
+	// 6-bit linear output. A pulse at full volume is about
+	// as loud as an APU pulse channel at full volume.
+	fn audio_output(&self) -> f32 {
+		let sum = self.pulse1.output() + self.pulse2.output() + self.sawtooth.output();
+		sum as f32 * 0.00996
+	}
+}
+
+/**
+ * Konami VRC2/VRC4 board variants. They differ mainly in which CPU
+ * address lines are wired to the register select inputs A0 and A1.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum VrcWiring {
+	Vrc2a, // Mapper 22: A1, A0. CHR banks ignore the lowest bit
+	Vrc2b, // Mapper 23 submapper 3: A0, A1
+	Vrc2c, // Mapper 25 submapper 3: A1, A0
+	Vrc4a, // Mapper 21 submapper 1: A1, A2
+	Vrc4b, // Mapper 25 submapper 1: A1, A0
+	Vrc4c, // Mapper 21 submapper 2: A6, A7
+	Vrc4d, // Mapper 25 submapper 2: A3, A2
+	Vrc4e, // Mapper 23 submapper 2: A2, A3
+	Vrc4f, // Mapper 23 submapper 1: A0, A1
+	// Unknown variants (iNES header or submapper 0).
+	// Respond to the address lines of both VRC4 variants of the mapper.
+	Mapper21,
+	Mapper23,
+	Mapper25
+}
+
+impl VrcWiring {
+	pub fn from_header(header: &RomHeader) -> Self {
+		match (header.mapper_num(), header.submapper_num()) {
+			(21, 1) => VrcWiring::Vrc4a,
+			(21, 2) => VrcWiring::Vrc4c,
+			(21, _) => VrcWiring::Mapper21,
+			(23, 1) => VrcWiring::Vrc4f,
+			(23, 2) => VrcWiring::Vrc4e,
+			(23, 3) => VrcWiring::Vrc2b,
+			(23, _) => VrcWiring::Mapper23,
+			(25, 1) => VrcWiring::Vrc4b,
+			(25, 2) => VrcWiring::Vrc4d,
+			(25, 3) => VrcWiring::Vrc2c,
+			(25, _) => VrcWiring::Mapper25,
+			_ => VrcWiring::Vrc2a
+		}
+	}
+
+	// CPU address line masks connected to A0 and A1
+	fn address_lines(&self) -> (u32, u32) {
+		match self {
+			VrcWiring::Vrc2a => (0x02, 0x01),
+			VrcWiring::Vrc2b => (0x01, 0x02),
+			VrcWiring::Vrc2c => (0x02, 0x01),
+			VrcWiring::Vrc4a => (0x02, 0x04),
+			VrcWiring::Vrc4b => (0x02, 0x01),
+			VrcWiring::Vrc4c => (0x40, 0x80),
+			VrcWiring::Vrc4d => (0x08, 0x04),
+			VrcWiring::Vrc4e => (0x04, 0x08),
+			VrcWiring::Vrc4f => (0x01, 0x02),
+			VrcWiring::Mapper21 => (0x42, 0x84),
+			VrcWiring::Mapper23 => (0x05, 0x0A),
+			VrcWiring::Mapper25 => (0x0A, 0x05)
+		}
+	}
+
+	fn is_vrc2(&self) -> bool {
+		match self {
+			VrcWiring::Vrc2a | VrcWiring::Vrc2b | VrcWiring::Vrc2c => true,
+			_ => false
+		}
+	}
+}
+
+/**
+ * Konami VRC2 and VRC4 (mappers 21, 22, 23 and 25). VRC2 is a subset of
+ * VRC4 without PRG swap mode, one-screen mirroring and IRQ.
+ * VRC2 1-bit latch at 0x6000 and VRC4 PRG-RAM disable are not supported.
+ * Refer to https://wiki.nesdev.com/w/index.php/VRC2_and_VRC4
+ */
+struct VRC4Mapper {
+	wiring: VrcWiring,
+	program_bank_num: u8,
+	program_registers: [u8; 2], // 0x8000, 0xA000
+	mirroring: u8, // 0x9000
+	program_mode: u8, // 0x9002, VRC4 only
+	character_registers: [u16; 8], // 0xB000 - 0xE003, low and high nibbles
+	irq: VrcIrq // 0xF000 - 0xF003, VRC4 only
+}
+
+impl VRC4Mapper {
+	fn new(header: &RomHeader) -> Self {
+		VRC4Mapper {
+			wiring: VrcWiring::from_header(header),
+			program_bank_num: header.prg_rom_bank_num(),
+			program_registers: [0; 2],
+			mirroring: 0,
+			program_mode: 0,
+			character_registers: [0; 8],
+			irq: VrcIrq::new()
+		}
+	}
+
+	// Register index 0-3 selected by the wired address lines
+	fn register_index(&self, address: u32) -> u32 {
+		let (a0, a1) = self.wiring.address_lines();
+		((address & a0) != 0) as u32 | ((((address & a1) != 0) as u32) << 1)
+	}
+}
+
+impl Mapper for VRC4Mapper {
+	/**
+	 * PRG swap mode 0 (0x9002 bit 1 clear)
+	 * 0x8000 - 0x9FFF: Switchable 8KB bank (0x8000)
+	 * 0xA000 - 0xBFFF: Switchable 8KB bank (0xA000)
+	 * 0xC000 - 0xDFFF: Fixed to the second last 8KB bank
+	 * 0xE000 - 0xFFFF: Fixed to the last 8KB bank
+	 *
+	 * PRG swap mode 1 swaps 0x8000 - 0x9FFF and 0xC000 - 0xDFFF
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let bank_num = self.program_bank_num as u32 * 2;
+		let swapped = (self.program_mode & 0x2) != 0;
+		let bank = match address {
+			0x8000..=0x9FFF => match swapped {
+				true => bank_num.saturating_sub(2),
+				false => self.program_registers[0] as u32
+			},
+			0xA000..=0xBFFF => self.program_registers[1] as u32,
+			0xC000..=0xDFFF => match swapped {
+				true => self.program_registers[0] as u32,
+				false => bank_num.saturating_sub(2)
+			},
+			_ => bank_num.saturating_sub(1)
+		};
+		bank * 0x2000 + (address & 0x1FFF)
+	}
+
+	// 1KB banks. VRC2a ignores the lowest bit of the bank number.
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		let bank = self.character_registers[(address >> 10) as usize & 7] as u32;
+		let bank = match self.wiring {
+			VrcWiring::Vrc2a => bank >> 1,
+			_ => bank
+		};
+		bank * 0x400 + (address & 0x3FF)
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		let index = self.register_index(address);
+		let vrc2 = self.wiring.is_vrc2();
+		match address & 0xF000 {
+			0x8000 => self.program_registers[0] = value & 0x1F,
+			0x9000 => match vrc2 {
+				true => self.mirroring = value & 0x1,
+				false => match index {
+					0 | 1 => self.mirroring = value & 0x3,
+					_ => self.program_mode = value
+				}
+			},
+			0xA000 => self.program_registers[1] = value & 0x1F,
+			0xB000..=0xE000 => {
+				// Two registers for a bank, low and high nibbles
+				let bank = (((address >> 12) - 0xB) * 2 + (index >> 1)) as usize;
+				let register = self.character_registers[bank];
+				self.character_registers[bank] = match index & 1 {
+					0 => (register & 0x1F0) | (value & 0xF) as u16,
+					_ => (register & 0xF) | (((value & match vrc2 {
+						true => 0xF,
+						false => 0x1F
+					}) as u16) << 4)
+				};
+			},
+			0xF000 => if !vrc2 {
+				match index {
+					0 => {
+						let latch = (self.irq.latch & 0xF0) | (value & 0xF);
+						self.irq.store_latch(latch);
+					},
+					1 => {
+						let latch = (self.irq.latch & 0xF) | ((value & 0xF) << 4);
+						self.irq.store_latch(latch);
+					},
+					2 => self.irq.store_control(value),
+					_ => self.irq.acknowledge()
+				};
+			},
+			_ => {}
+		};
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		match self.mirroring {
+			0 => Mirrorings::Vertical,
+			1 => Mirrorings::Horizontal,
+			2 => Mirrorings::OneScreenLow,
+			_ => Mirrorings::OneScreenHigh
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::VRC4 {
+			wiring: self.wiring,
+			program_registers: self.program_registers,
+			mirroring: self.mirroring,
+			program_mode: self.program_mode,
+			character_registers: self.character_registers,
+			irq: self.irq.save_state(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::VRC4 { wiring, program_registers, mirroring, program_mode,
+			character_registers, irq } = &MapperState::from_bytes(data) {
+			self.wiring = *wiring;
+			self.program_registers = *program_registers;
+			self.mirroring = *mirroring;
+			self.program_mode = *program_mode;
+			self.character_registers = *character_registers;
+			self.irq.load_state(irq);
+		}
+	}
+
+	fn step(&mut self) {
+		self.irq.step();
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq.pending
+	}
+}
+
+/**
+ * Konami VRC7 (mapper 85) with six-channel FM expansion audio.
+ * VRC7a (submapper 2) selects the second register of a pair with A4,
+ * and VRC7b (submapper 1) with A3. Both lines are checked otherwise.
+ * Refer to https://wiki.nesdev.com/w/index.php/VRC7
+ */
+struct VRC7Mapper {
+	register_line: u32,
+	program_bank_num: u8,
+	program_registers: [u8; 3], // 0x8000, 0x8010, 0x9000
+	character_registers: [u8; 8], // 0xA000 - 0xD010
+	control: u8, // 0xE000
+	irq: VrcIrq, // 0xE010 - 0xF010
+	audio: Opll, // 0x9010, 0x9030
+	audio_cycle: u8
+}
+
+impl VRC7Mapper {
+	fn new(header: &RomHeader) -> Self {
+		VRC7Mapper {
+			register_line: match header.submapper_num() {
+				1 => 0x08,
+				2 => 0x10,
+				_ => 0x18
+			},
+			program_bank_num: header.prg_rom_bank_num(),
+			program_registers: [0; 3],
+			character_registers: [0; 8],
+			control: 0,
+			irq: VrcIrq::new(),
+			audio: Opll::new(),
+			audio_cycle: 0
+		}
+	}
+}
+
+impl Mapper for VRC7Mapper {
+	/**
+	 * 0x8000 - 0xDFFF: Three switchable 8KB banks
+	 * 0xE000 - 0xFFFF: Fixed to the last 8KB bank
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address {
+			0x8000..=0x9FFF => self.program_registers[0] as u32,
+			0xA000..=0xBFFF => self.program_registers[1] as u32,
+			0xC000..=0xDFFF => self.program_registers[2] as u32,
+			_ => (self.program_bank_num as u32 * 2).saturating_sub(1)
+		};
+		bank * 0x2000 + (address & 0x1FFF)
+	}
+
+	// 1KB banks
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.character_registers[(address >> 10) as usize & 7] as u32 * 0x400 + (address & 0x3FF)
+	}
+
+	fn store(&mut self, address: u32, value: u8) {
+		let second = (address & self.register_line) != 0;
+		match address & 0xF000 {
+			0x8000 => self.program_registers[second as usize] = value & 0x3F,
+			0x9000 => match address & 0x38 {
+				0x00 => self.program_registers[2] = value & 0x3F,
+				0x10 => self.audio.store_address(value),
+				0x30 => self.audio.store_data(value),
+				_ => {}
+			},
+			0xA000..=0xD000 => {
+				let index = (((address >> 12) - 0xA) * 2) as usize + second as usize;
+				self.character_registers[index] = value;
+			},
+			0xE000 => match second {
+				true => self.irq.store_latch(value),
+				false => self.control = value
+			},
+			0xF000 => match second {
+				true => self.irq.acknowledge(),
+				false => self.irq.store_control(value)
+			},
+			_ => {}
+		};
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// 0xE000 bits 0-1
+	fn mirroring_type(&self) -> Mirrorings {
+		match self.control & 0x3 {
+			0 => Mirrorings::Vertical,
+			1 => Mirrorings::Horizontal,
+			2 => Mirrorings::OneScreenLow,
+			_ => Mirrorings::OneScreenHigh
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::VRC7 {
+			program_registers: self.program_registers,
+			character_registers: self.character_registers,
+			control: self.control,
+			irq: self.irq.save_state(),
+			audio: self.audio.save_state(),
+			audio_cycle: self.audio_cycle,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::VRC7 { program_registers, character_registers, control,
+			irq, audio, audio_cycle } = &MapperState::from_bytes(data) {
+			self.program_registers = *program_registers;
+			self.character_registers = *character_registers;
+			self.control = *control;
+			self.irq.load_state(irq);
+			self.audio.load_state(audio);
+			self.audio_cycle = *audio_cycle;
+		}
+	}
+
+	// 0xE000 bit 6 enables PRG-RAM
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		match address < 0x8000 {
+			true => match (self.control & 0x40) != 0 {
+				true => CpuMapping::ProgramRam(address - 0x6000),
+				false => CpuMapping::Unmapped
+			},
+			false => CpuMapping::ProgramRom(self.map(address))
+		}
+	}
+
+	// FM synthesizer generates a sample every 36 CPU cycles
+	fn step(&mut self) {
+		self.irq.step();
+
+		self.audio_cycle += 1;
+		if self.audio_cycle == 36 {
+			self.audio_cycle = 0;
+			self.audio.clock();
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq.pending
+	}
+
+	// A channel at full volume is about as loud as an APU pulse
+	// channel at full volume. 0xE000 bit 7 silences the audio.
+	fn audio_output(&self) -> f32 {
+		match (self.control & 0x80) != 0 {
+			true => 0.0,
+			false => self.audio.output() as f32 * 0.0000365
+		}
+	}
+}
+
+// 1.5dB steps of the 5-bit level. 0 is silent.
+const SUNSOFT_5B_VOLUMES: [f32; 32] = [
+    0.0000, 0.0056, 0.0067, 0.0079, 0.0094, 0.0112, 0.0133, 0.0158,
+    0.0188, 0.0224, 0.0266, 0.0316, 0.0376, 0.0447, 0.0531, 0.0631,
+    0.0750, 0.0891, 0.1059, 0.1259, 0.1496, 0.1778, 0.2113, 0.2512,
+    0.2985, 0.3548, 0.4217, 0.5012, 0.5957, 0.7079, 0.8414, 1.0000
+];
+
+/**
+ * Sunsoft 5B expansion audio, a variant of the AY-3-8910 PSG.
+ * Three square wave channels with a shared noise and envelope generator.
+ * Refer to https://wiki.nesdev.com/w/index.php/Sunsoft_5B_audio
+ */
+struct Sunsoft5B {
+    address: u8, // 0xC000
+    registers: [u8; 16], // 0xE000
+    cycle: u8,
+    tone_counters: [u16; 3],
+    tone_outputs: [bool; 3],
+    noise_counter: u8,
+    noise_shift: u32,
+    envelope_counter: u16,
+    envelope_step: u8,
+    envelope_attack: bool,
+    envelope_holding: bool
+}
+
+impl Sunsoft5B {
+    fn new() -> Self {
+        Sunsoft5B {
+            address: 0,
+            registers: [0; 16],
+            cycle: 0,
+            tone_counters: [0; 3],
+            tone_outputs: [false; 3],
+            noise_counter: 0,
+            noise_shift: 1,
+            envelope_counter: 0,
+            envelope_step: 0,
+            envelope_attack: false,
+            envelope_holding: false
+        }
+    }
+
+    fn store_address(&mut self, value: u8) {
+        self.address = value & 0x0F;
+    }
+
+    /**
+     * 0x0 - 0x5: Tone periods, 12 bits for each channel
+     * 0x6: Noise period
+     * 0x7: Disables tone (bits 0-2) and noise (bits 3-5) for each channel
+     * 0x8 - 0xA: Volume, bit 4 selects the envelope
+     * 0xB - 0xC: Envelope period
+     * 0xD: Envelope shape, writing restarts the envelope
+     */
+    fn store_data(&mut self, value: u8) {
+        self.registers[self.address as usize] = value;
+        if self.address == 0xD {
+            self.envelope_counter = 0;
+            self.envelope_step = 0;
+            self.envelope_attack = (value & 0x4) != 0;
+            self.envelope_holding = false;
+        }
+    }
+
+    // Expects being called at CPU clock rate. The envelope runs
+    // twice as fast as the tone and noise.
+    fn step(&mut self) {
+        self.cycle = (self.cycle + 1) & 0xF;
+        if (self.cycle & 0x7) == 0 {
+            self.drive_envelope();
+        }
+        if self.cycle == 0 {
+            self.drive_tone();
+            self.drive_noise();
+        }
+    }
+
+    fn drive_tone(&mut self) {
+        for channel in 0..3 {
+            let period = (self.registers[channel * 2] as u16 | ((self.registers[channel * 2 + 1] as u16 & 0xF) << 8)).max(1);
+            self.tone_counters[channel] += 1;
+            if self.tone_counters[channel] >= period {
+                self.tone_counters[channel] = 0;
+                self.tone_outputs[channel] = !self.tone_outputs[channel];
+            }
+        }
+    }
+
+    // 17-bit LFSR
+    fn drive_noise(&mut self) {
+        let period = (self.registers[6] & 0x1F).max(1);
+        self.noise_counter += 1;
+        if self.noise_counter >= period * 2 {
+            self.noise_counter = 0;
+            let feedback = (self.noise_shift ^ (self.noise_shift >> 3)) & 1;
+            self.noise_shift = (self.noise_shift >> 1) | (feedback << 16);
+        }
+    }
+
+    /**
+     * 32 steps per cycle. Shape bits
+     * bit 0: Hold, bit 1: Alternate, bit 2: Attack, bit 3: Continue
+     */
+    fn drive_envelope(&mut self) {
+        if self.envelope_holding {
+            return;
+        }
+        let period = (self.registers[0xB] as u16 | ((self.registers[0xC] as u16) << 8)).max(1);
+        self.envelope_counter += 1;
+        if self.envelope_counter < period {
+            return;
+        }
+        self.envelope_counter = 0;
+        self.envelope_step += 1;
+        if self.envelope_step < 32 {
+            return;
+        }
+        let shape = self.registers[0xD];
+        let alternate = (shape & 0x2) != 0;
+        if (shape & 0x8) == 0 {
+            // Ends at the bottom
+            self.envelope_holding = true;
+            self.envelope_attack = false;
+            self.envelope_step = 31;
+        } else if (shape & 0x1) != 0 {
+            self.envelope_holding = true;
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 31;
+        } else {
+            if alternate {
+                self.envelope_attack = !self.envelope_attack;
+            }
+            self.envelope_step = 0;
+        }
+    }
+
+    fn envelope_level(&self) -> u8 {
+        match self.envelope_attack {
+            true => self.envelope_step,
+            false => 31 - self.envelope_step
+        }
+    }
+
+    // 5-bit level. The 4-bit channel volume steps 3dB and the envelope 1.5dB
+    fn channel_level(&self, channel: usize) -> u8 {
+        let volume = self.registers[8 + channel];
+        if (volume & 0x10) != 0 {
+            return self.envelope_level();
+        }
+        match volume & 0xF {
+            0 => 0,
+            volume => volume * 2 + 1
+        }
+    }
+
+    // Sum of the channels. A channel at full volume outputs 1.0.
+    fn output(&self) -> f32 {
+        let mixer = self.registers[7];
+        let noise = (self.noise_shift & 1) != 0;
+        let mut output = 0.0;
+        for channel in 0..3 {
+            let tone = self.tone_outputs[channel] || (mixer & (1 << channel)) != 0;
+            let noise = noise || (mixer & (8 << channel)) != 0;
+            if tone && noise {
+                output += SUNSOFT_5B_VOLUMES[self.channel_level(channel) as usize];
+            }
+        }
+        output
+    }
+
+    fn save_state(&self) -> Sunsoft5BState {
+        Sunsoft5BState {
+            address: self.address,
+            registers: self.registers,
+            cycle: self.cycle,
+            tone_counters: self.tone_counters,
+            tone_outputs: self.tone_outputs,
+            noise_counter: self.noise_counter,
+            noise_shift: self.noise_shift,
+            envelope_counter: self.envelope_counter,
+            envelope_step: self.envelope_step,
+            envelope_attack: self.envelope_attack,
+            envelope_holding: self.envelope_holding,
+        }
+    }
+
+    fn load_state(&mut self, state: &Sunsoft5BState) {
+        self.address = state.address;
+        self.registers = state.registers;
+        self.cycle = state.cycle;
+        self.tone_counters = state.tone_counters;
+        self.tone_outputs = state.tone_outputs;
+        self.noise_counter = state.noise_counter;
+        self.noise_shift = state.noise_shift;
+        self.envelope_counter = state.envelope_counter;
+        self.envelope_step = state.envelope_step;
+        self.envelope_attack = state.envelope_attack;
+        self.envelope_holding = state.envelope_holding;
+    }
+}
+
+struct SunsoftMapper {
+    command_register: Register<u8>,
+    parameter_register: Register<u8>,
+    chr_banks: [Register<u8>; 8],
+    prg_banks: [Register<u8>; 4],
+    irq_enabled: bool,
+    irq_counter_enabled: bool,
+    irq_counter: u16,
+    mirroring: u8,
+    prg_bank_mask: u32,
+    chr_bank_mask: u32,
+    audio: Sunsoft5B,
+}
+
+impl SunsoftMapper {
+    fn new(header: &RomHeader) -> Self {
+        let prg_bank_num = header.prg_rom_bank_num() as u32 * 2; // 8KB banks
+        let chr_bank_num = header.chr_bank_num() as u32 * 8; // 1KB banks
+
+        let mut m = SunsoftMapper {
+            command_register: Register::<u8>::new(),
+            parameter_register: Register::<u8>::new(),
+            chr_banks: [
+                Register::<u8>::new(), Register::<u8>::new(),
+                Register::<u8>::new(), Register::<u8>::new(),
+                Register::<u8>::new(), Register::<u8>::new(),
+                Register::<u8>::new(), Register::<u8>::new(),
+            ],
+            prg_banks: [
+                Register::<u8>::new(), Register::<u8>::new(),
+                Register::<u8>::new(), Register::<u8>::new(),
+            ],
+            irq_enabled: false,
+            irq_counter_enabled: false,
+            irq_counter: 0,
+            mirroring: 0,
+            prg_bank_mask: prg_bank_num.saturating_sub(1),
+            chr_bank_mask: chr_bank_num.saturating_sub(1),
+            audio: Sunsoft5B::new(),
+        };
+        // Defaults
+        m.prg_banks[3].store((prg_bank_num - 1) as u8); // Fixed last bank
+        m
+    }
+}
+
+impl Mapper for SunsoftMapper {
+    fn map(&self, address: u32) -> u32 {
+        let bank_idx = match address {
+            0x6000..=0x7FFF => 0,
+            0x8000..=0x9FFF => 1,
+            0xA000..=0xBFFF => 2,
+            0xC000..=0xDFFF => 3,
+            0xE000..=0xFFFF => return ((self.prg_bank_mask) * 0x2000) + (address & 0x1FFF),
+            _ => 0,
+        };
+        
+        let bank = self.prg_banks[bank_idx].load() as u32 & self.prg_bank_mask;
+        bank * 0x2000 + (address & 0x1FFF)
+    }
+
+    fn map_for_chr_rom(&self, address: u32) -> u32 {
+        let bank_idx = (address / 0x400) as usize;
+        if bank_idx < 8 {
+            let bank = self.chr_banks[bank_idx].load() as u32 & self.chr_bank_mask;
+            bank * 0x400 + (address & 0x3FF)
+        } else {
+            address
+        }
+    }
+
+    fn store(&mut self, address: u32, value: u8) {
+        match address {
+            0x8000..=0x9FFF => {
+                self.command_register.store(value & 0x0F);
+            }
+            0xA000..=0xBFFF => {
+                self.parameter_register.store(value);
+                let cmd = self.command_register.load();
+                match cmd {
+                    0..=7 => {
+                        self.chr_banks[cmd as usize].store(value);
+                    }
+                    8..=11 => {
+                        self.prg_banks[(cmd - 8) as usize].store(value);
+                    }
+                    12 => {
+                        self.mirroring = value & 0x03;
+                    }
+                    13 => {
+                        self.irq_enabled = (value & 0x80) != 0;
+                        self.irq_counter_enabled = (value & 0x01) != 0;
+                    }
+                    14 => {
+                        self.irq_counter = (self.irq_counter & 0xFF00) | (value as u16);
+                    }
+                    15 => {
+                        self.irq_counter = (self.irq_counter & 0x00FF) | ((value as u16) << 8);
+                    }
+                    _ => {}
+                }
+            }
+            0xC000..=0xDFFF => {
+                self.audio.store_address(value);
+            }
+            0xE000..=0xFFFF => {
+                self.audio.store_data(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn has_mirroring_type(&self) -> bool {
+        true
+    }
+
+    fn mirroring_type(&self) -> Mirrorings {
+        match self.mirroring {
+            0 => Mirrorings::Vertical,
+            1 => Mirrorings::Horizontal,
+            2 => Mirrorings::OneScreenLow, // OneScreen 0
+            3 => Mirrorings::OneScreenHigh, // OneScreen 1
+            _ => Mirrorings::Vertical,
+        }
+    }
+
+    fn drive_irq_counter(&mut self) -> bool {
+        if self.irq_counter_enabled {
+            self.irq_counter = self.irq_counter.wrapping_sub(1);
+            if self.irq_counter == 0xFFFF {
+                return self.irq_enabled;
+            }
+        }
+        false
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        MapperState::Sunsoft {
+            command_register: self.command_register.get_data(),
+            parameter_register: self.parameter_register.get_data(),
+            chr_banks: [
+                self.chr_banks[0].get_data(), self.chr_banks[1].get_data(),
+                self.chr_banks[2].get_data(), self.chr_banks[3].get_data(),
+                self.chr_banks[4].get_data(), self.chr_banks[5].get_data(),
+                self.chr_banks[6].get_data(), self.chr_banks[7].get_data(),
+            ],
+            prg_banks: [
+                self.prg_banks[0].get_data(), self.prg_banks[1].get_data(),
+                self.prg_banks[2].get_data(), self.prg_banks[3].get_data(),
+            ],
+            irq_enabled: self.irq_enabled,
+            irq_counter_enabled: self.irq_counter_enabled,
+            irq_counter: self.irq_counter,
+            mirroring: self.mirroring,
+            audio: self.audio.save_state(),
+        }.to_bytes()
+    }
+
+    fn load_state(&mut self, data: &[u8]) {
+        if let MapperState::Sunsoft {
+            command_register,
+            parameter_register,
+            chr_banks,
+            prg_banks,
+            irq_enabled,
+            irq_counter_enabled,
+            irq_counter,
+            mirroring,
+            audio,
+        } = &MapperState::from_bytes(data) {
+            self.command_register.set_data(*command_register);
+            self.parameter_register.set_data(*parameter_register);
+            for (i, val) in chr_banks.iter().enumerate() {
+                self.chr_banks[i].set_data(*val);
+            }
+            for (i, val) in prg_banks.iter().enumerate() {
+                self.prg_banks[i].set_data(*val);
+            }
+            self.irq_enabled = *irq_enabled;
+            self.irq_counter_enabled = *irq_counter_enabled;
+            self.irq_counter = *irq_counter;
+            self.mirroring = *mirroring;
+            self.audio.load_state(audio);
+        }
+    }
+
+    // Bank 0 (command 8) maps 0x6000 - 0x7FFF.
+    // Bit 6 selects PRG-RAM over PRG-ROM and bit 7 enables PRG-RAM.
+    fn map_for_cpu(&self, address: u32) -> CpuMapping {
+        if address >= 0x8000 {
+            return CpuMapping::ProgramRom(self.map(address));
+        }
+        let bank = self.prg_banks[0].load();
+        match bank & 0xC0 {
+            0xC0 => CpuMapping::ProgramRam((bank & 0x3F) as u32 * 0x2000 + (address & 0x1FFF)),
+            0x40 => CpuMapping::Unmapped,
+            _ => CpuMapping::ProgramRom(self.map(address))
+        }
+    }
+
+    fn step(&mut self) {
+        self.audio.step();
+    }
+
+    // A channel at full volume is a bit louder than
+    // an APU pulse channel at full volume.
+    fn audio_output(&self) -> f32 {
+        self.audio.output() * 0.2
+    }
+}
+
+/**
+ * Namco 163 (mapper 19) with wavetable expansion audio.
+ * Pattern tables from the PPU VRAM (CHR bank 0xE0 - 0xFF) are not supported.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES_Mapper_019
+ */
+struct Namco163Mapper {
+	program_bank_num: u8,
+	character_registers: [u8; 8], // 0x8000 - 0xBFFF
+	name_table_registers: [u8; 4], // 0xC000 - 0xDFFF
+	program_registers: [u8; 3], // 0xE000 - 0xF7FF
+	sound_address: u8, // 0xF800
+	sound_ram: Vec<u8>, // 0x4800, 128 bytes
+	irq_counter: u16, // 0x5000, 0x5800
+	irq_enabled: bool,
+	irq_pending: bool,
+	audio_cycle: u8,
+	audio_channel: u8,
+	audio_outputs: [i16; 8]
+}
+
+impl Namco163Mapper {
+	fn new(header: &RomHeader) -> Self {
+		Namco163Mapper {
+			program_bank_num: header.prg_rom_bank_num(),
+			character_registers: [0; 8],
+			name_table_registers: [0; 4],
+			program_registers: [0; 3],
+			sound_address: 0,
+			sound_ram: vec![0; 128],
+			irq_counter: 0,
+			irq_enabled: false,
+			irq_pending: false,
+			audio_cycle: 0,
+			audio_channel: 0,
+			audio_outputs: [0; 8]
+		}
+	}
+
+	// 0x7F bits 4-6
+	fn audio_channel_num(&self) -> u8 {
+		((self.sound_ram[0x7F] >> 4) & 0x7) + 1
+	}
+
+	// Accessing the data port increments the address if 0xF800 bit 7 is set
+	fn increment_sound_address(&mut self) {
+		if (self.sound_address & 0x80) != 0 {
+			self.sound_address = 0x80 | (((self.sound_address & 0x7F) + 1) & 0x7F);
+		}
+	}
+
+	/**
+	 * The chip updates a channel every 15 CPU cycles, starting from
+	 * the channel 8 whose registers are in 0x78 - 0x7F.
+	 * 0x00: Frequency bits 0-7
+	 * 0x01: Phase bits 0-7
+	 * 0x02: Frequency bits 8-15
+	 * 0x03: Phase bits 8-15
+	 * 0x04: Wave length in the upper 6 bits, Frequency bits 16-17
+	 * 0x05: Phase bits 16-23
+	 * 0x06: Wave address in 4-bit samples
+	 * 0x07: Volume in the lower 4 bits
+	 */
+	fn drive_audio(&mut self) {
+		self.audio_cycle += 1;
+		if self.audio_cycle < 15 {
+			return;
+		}
+		self.audio_cycle = 0;
+
+		let channel = self.audio_channel as usize;
+		let base = 0x78 - channel * 8;
+		let ram = &mut self.sound_ram;
+		let frequency = ram[base] as u32 | ((ram[base + 2] as u32) << 8) | ((ram[base + 4] as u32 & 0x3) << 16);
+		let phase = ram[base + 1] as u32 | ((ram[base + 3] as u32) << 8) | ((ram[base + 5] as u32) << 16);
+		let length = 256 - (ram[base + 4] & 0xFC) as u32;
+		let phase = (phase + frequency) % (length << 16);
+		ram[base + 1] = phase as u8;
+		ram[base + 3] = (phase >> 8) as u8;
+		ram[base + 5] = (phase >> 16) as u8;
+
+		let index = (((phase >> 16) + ram[base + 6] as u32) & 0xFF) as usize;
+		let sample = (ram[index >> 1] >> ((index & 1) * 4)) & 0xF;
+		self.audio_outputs[channel] = (sample as i16 - 8) * (ram[base + 7] & 0xF) as i16;
+
+		self.audio_channel = (self.audio_channel + 1) % self.audio_channel_num();
+	}
+}
+
+impl Mapper for Namco163Mapper {
+	/**
+	 * 0x8000 - 0xDFFF: Three switchable 8KB banks
+	 * 0xE000 - 0xFFFF: Fixed to the last 8KB bank
+	 */
+	fn map(&self, address: u32) -> u32 {
+		let bank = match address {
+			0x8000..=0x9FFF => (self.program_registers[0] & 0x3F) as u32,
+			0xA000..=0xBFFF => (self.program_registers[1] & 0x3F) as u32,
+			0xC000..=0xDFFF => (self.program_registers[2] & 0x3F) as u32,
+			_ => (self.program_bank_num as u32 * 2).saturating_sub(1)
+		};
+		bank * 0x2000 + (address & 0x1FFF)
+	}
+
+	// 1KB banks
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		self.character_registers[(address >> 10) as usize & 7] as u32 * 0x400 + (address & 0x3FF)
+	}
+
+	/**
+	 * Registers take 0x800 bytes each
+	 * 0x8000 - 0xBFFF: CHR banks
+	 * 0xC000 - 0xDFFF: Nametable banks
+	 * 0xE000: PRG bank 0, bit 6 disables the audio
+	 * 0xE800: PRG bank 1
+	 * 0xF000: PRG bank 2
+	 * 0xF800: Sound RAM address, bit 7 enables the auto increment
+	 */
+	fn store(&mut self, address: u32, value: u8) {
+		let index = ((address >> 11) & 0x7) as usize;
+		match address {
+			0x8000..=0xBFFF => self.character_registers[index] = value,
+			0xC000..=0xDFFF => self.name_table_registers[index & 0x3] = value,
+			0xE000..=0xF7FF => self.program_registers[index & 0x3] = value,
+			_ => self.sound_address = value
+		};
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::SingleScreen // dummy
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Namco163 {
+			character_registers: self.character_registers,
+			name_table_registers: self.name_table_registers,
+			program_registers: self.program_registers,
+			sound_address: self.sound_address,
+			sound_ram: self.sound_ram.clone(),
+			irq_counter: self.irq_counter,
+			irq_enabled: self.irq_enabled,
+			irq_pending: self.irq_pending,
+			audio_cycle: self.audio_cycle,
+			audio_channel: self.audio_channel,
+			audio_outputs: self.audio_outputs,
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Namco163 { character_registers, name_table_registers, program_registers,
+			sound_address, sound_ram, irq_counter, irq_enabled, irq_pending,
+			audio_cycle, audio_channel, audio_outputs } = &MapperState::from_bytes(data) {
+			self.character_registers = *character_registers;
+			self.name_table_registers = *name_table_registers;
+			self.program_registers = *program_registers;
+			self.sound_address = *sound_address;
+			self.sound_ram = sound_ram.clone();
+			self.irq_counter = *irq_counter;
+			self.irq_enabled = *irq_enabled;
+			self.irq_pending = *irq_pending;
+			self.audio_cycle = *audio_cycle;
+			self.audio_channel = *audio_channel;
+			self.audio_outputs = *audio_outputs;
+		}
+	}
+
+	// 0xF800 upper 4 bits must be 0100 to write PRG-RAM.
+	// Bits 0-3 protect 2KB windows from 0x6000.
+	fn prg_ram_writable(&self, address: u32) -> bool {
+		let window = (address.wrapping_sub(0x6000) >> 11) & 0x3;
+		(self.sound_address & 0xF0) == 0x40 && (self.sound_address & (1 << window)) == 0
+	}
+
+	/**
+	 * 0x4800 - 0x4FFF: Sound RAM data
+	 * 0x5000 - 0x57FF: IRQ counter bits 0-7
+	 * 0x5800 - 0x5FFF: IRQ counter bits 8-14, IRQ enable in bit 7
+	 */
+	fn load_register(&mut self, address: u32) -> Option<u8> {
+		match address {
+			0x4800..=0x4FFF => {
+				let value = self.sound_ram[(self.sound_address & 0x7F) as usize];
+				self.increment_sound_address();
+				Some(value)
+			},
+			0x5000..=0x57FF => Some(self.irq_counter as u8),
+			0x5800..=0x5FFF => Some((self.irq_counter >> 8) as u8 | ((self.irq_enabled as u8) << 7)),
+			_ => None
+		}
+	}
+
+	// Writing the IRQ counter acknowledges IRQ
+	fn store_register(&mut self, address: u32, value: u8) -> bool {
+		match address {
+			0x4800..=0x4FFF => {
+				self.sound_ram[(self.sound_address & 0x7F) as usize] = value;
+				self.increment_sound_address();
+			},
+			0x5000..=0x57FF => {
+				self.irq_counter = (self.irq_counter & 0x7F00) | value as u16;
+				self.irq_pending = false;
+			},
+			0x5800..=0x5FFF => {
+				self.irq_counter = (self.irq_counter & 0xFF) | (((value & 0x7F) as u16) << 8);
+				self.irq_enabled = (value & 0x80) != 0;
+				self.irq_pending = false;
+			},
+			_ => return false
+		};
+		true
+	}
+
+	// IRQ counter counts up CPU cycles and stops at 0x7FFF
+	fn step(&mut self) {
+		if self.irq_enabled && self.irq_counter < 0x7FFF {
+			self.irq_counter += 1;
+			if self.irq_counter == 0x7FFF {
+				self.irq_pending = true;
+			}
+		}
+
+		if (self.program_registers[0] & 0x40) == 0 {
+			self.drive_audio();
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.irq_pending
+	}
+
+	// The chip outputs the channels in turn. They are averaged here.
+	fn audio_output(&self) -> f32 {
+		let channel_num = self.audio_channel_num() as usize;
+		let sum: i16 = self.audio_outputs[..channel_num].iter().sum();
+		sum as f32 / channel_num as f32 * 0.00125
+	}
+
+	/**
+	 * 0xE0 - 0xFF in the nametable bank select PPU VRAM pages,
+	 * otherwise 1KB banks of CHR-ROM.
+	 */
+	fn name_table_source(&self, slot: u8) -> Option<NameTableSource> {
+		Some(match self.name_table_registers[slot as usize] {
+			value if value >= 0xE0 => NameTableSource::Vram(value & 0x1),
+			value => NameTableSource::ChrRom(value as u32 * 0x400)
+		})
+	}
+}
+
+// CPU cycles from the motor start to the first byte, and between bytes
+const FDS_MOTOR_START_DELAY: u32 = 50000;
+const FDS_BYTE_DELAY: u32 = 150;
+
+/**
+ * Famicom Disk System RAM adapter (mapper 20). 32KB PRG-RAM in
+ * 0x6000 - 0xDFFF, the BIOS in 0xE000 - 0xFFFF, 8KB CHR-RAM, a timer
+ * IRQ, a disk drive and wavetable audio. Made by Rom::new_fds() rather
+ * than MapperFactory since it needs a disk image.
+ * Refer to https://wiki.nesdev.com/w/index.php/Family_Computer_Disk_System
+ */
+pub struct FdsMapper {
+	disk: FdsDisk,
+	disk_side: Option<usize>, // None while ejected
+	disk_dirty: bool,
+	disk_registers_enabled: bool, // 0x4023 bit 0
+	sound_registers_enabled: bool, // 0x4023 bit 1
+	timer_reload: u16, // 0x4020 - 0x4021
+	timer_counter: u16,
+	timer_repeat: bool, // 0x4022 bit 0
+	timer_enabled: bool, // 0x4022 bit 1
+	timer_irq: bool,
+	write_data: u8, // 0x4024
+	read_data: u8, // 0x4031
+	control: u8, // 0x4025
+	motor_on: bool,
+	disk_position: usize,
+	drive_delay: u32,
+	end_of_head: bool,
+	scanning: bool,
+	gap_ended: bool,
+	transfer_complete: bool,
+	disk_irq: bool,
+	previous_crc_control: bool,
+	audio: FdsAudio
+}
+
+impl FdsMapper {
+	pub fn new(disk: FdsDisk) -> Self {
+		FdsMapper {
+			disk: disk,
+			disk_side: Some(0),
+			disk_dirty: false,
+			disk_registers_enabled: false,
+			sound_registers_enabled: false,
+			timer_reload: 0,
+			timer_counter: 0,
+			timer_repeat: false,
+			timer_enabled: false,
+			timer_irq: false,
+			write_data: 0,
+			read_data: 0,
+			control: 0,
+			motor_on: false,
+			disk_position: 0,
+			drive_delay: 0,
+			end_of_head: true,
+			scanning: false,
+			gap_ended: false,
+			transfer_complete: false,
+			disk_irq: false,
+			previous_crc_control: false,
+			audio: FdsAudio::new()
+		}
+	}
+
+	fn clock_timer(&mut self) {
+		if !self.timer_enabled {
+			return;
+		}
+		if self.timer_counter > 0 {
+			self.timer_counter -= 1;
+			return;
+		}
+		self.timer_irq = true;
+		self.timer_counter = self.timer_reload;
+		if !self.timer_repeat {
+			self.timer_enabled = false;
+		}
+	}
+
+	/**
+	 * Moves the head a byte every 150 CPU cycles while the motor is on,
+	 * reads or writes it, and fires IRQ if the control register asks.
+	 * The head returns to the start of the side at the end.
+	 */
+	fn clock_drive(&mut self) {
+		let side = match self.disk_side {
+			Some(side) if self.motor_on => side,
+			_ => {
+				self.end_of_head = true;
+				self.scanning = false;
+				return;
+			}
+		};
+
+		// Transfer reset holds the head at the start
+		if (self.control & 0x2) != 0 && !self.scanning {
+			return;
+		}
+
+		if self.end_of_head {
+			self.drive_delay = FDS_MOTOR_START_DELAY;
+			self.end_of_head = false;
+			self.disk_position = 0;
+			self.gap_ended = false;
+			return;
+		}
+
+		if self.drive_delay > 0 {
+			self.drive_delay -= 1;
+			return;
+		}
+
+		self.scanning = true;
+		let read_mode = (self.control & 0x4) != 0;
+		let crc_control = (self.control & 0x10) != 0;
+		let transfer_ready = (self.control & 0x40) != 0;
+		let mut irq = (self.control & 0x80) != 0;
+
+		if read_mode {
+			let data = match self.disk.side(side).get(self.disk_position) {
+				Some(data) => *data,
+				None => {
+					self.motor_on = false;
+					return;
+				}
+			};
+			if !transfer_ready {
+				self.gap_ended = false;
+			} else if data != 0 && !self.gap_ended {
+				// Start mark ends the gap, without IRQ
+				self.gap_ended = true;
+				irq = false;
+			}
+			if self.gap_ended {
+				self.transfer_complete = true;
+				self.read_data = data;
+				if irq {
+					self.disk_irq = true;
+				}
+			}
+		} else {
+			if !crc_control {
+				self.transfer_complete = true;
+				if irq {
+					self.disk_irq = true;
+				}
+			}
+			// CRCs are written as 0 since nothing verifies them
+			let data = match transfer_ready && !crc_control {
+				true => self.write_data,
+				false => 0
+			};
+			match self.disk.side_mut(side).get_mut(self.disk_position) {
+				Some(side_data) if *side_data != data => {
+					*side_data = data;
+					self.disk_dirty = true;
+				},
+				Some(_) => {},
+				None => {
+					self.motor_on = false;
+					return;
+				}
+			};
+			self.gap_ended = false;
+		}
+
+		self.previous_crc_control = crc_control;
+		self.disk_position += 1;
+		match self.disk_position >= self.disk.side(side).len() {
+			true => self.motor_on = false,
+			false => self.drive_delay = FDS_BYTE_DELAY
+		};
+	}
+}
+
+impl Mapper for FdsMapper {
+	fn map(&self, address: u32) -> u32 {
+		address & 0x1FFF
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		address
+	}
+
+	fn store(&mut self, _address: u32, _value: u8) {
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		true
+	}
+
+	// 0x4025 bit 3
+	fn mirroring_type(&self) -> Mirrorings {
+		match (self.control & 0x8) != 0 {
+			true => Mirrorings::Horizontal,
+			false => Mirrorings::Vertical
+		}
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Fds {
+			disk_sides: self.disk.save_state(),
+			disk_side: self.disk_side,
+			disk_registers_enabled: self.disk_registers_enabled,
+			sound_registers_enabled: self.sound_registers_enabled,
+			timer_reload: self.timer_reload,
+			timer_counter: self.timer_counter,
+			timer_repeat: self.timer_repeat,
+			timer_enabled: self.timer_enabled,
+			timer_irq: self.timer_irq,
+			write_data: self.write_data,
+			read_data: self.read_data,
+			control: self.control,
+			motor_on: self.motor_on,
+			disk_position: self.disk_position,
+			drive_delay: self.drive_delay,
+			end_of_head: self.end_of_head,
+			scanning: self.scanning,
+			gap_ended: self.gap_ended,
+			transfer_complete: self.transfer_complete,
+			disk_irq: self.disk_irq,
+			previous_crc_control: self.previous_crc_control,
+			audio: self.audio.save_state(),
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Fds {
+			disk_sides,
+			disk_side,
+			disk_registers_enabled,
+			sound_registers_enabled,
+			timer_reload,
+			timer_counter,
+			timer_repeat,
+			timer_enabled,
+			timer_irq,
+			write_data,
+			read_data,
+			control,
+			motor_on,
+			disk_position,
+			drive_delay,
+			end_of_head,
+			scanning,
+			gap_ended,
+			transfer_complete,
+			disk_irq,
+			previous_crc_control,
+			audio
+		} = &MapperState::from_bytes(data) {
+			if self.audio.load_state(audio).is_err() {
+				return;
+			}
+			self.disk.load_state(disk_sides);
+			self.disk_side = *disk_side;
+			self.disk_registers_enabled = *disk_registers_enabled;
+			self.sound_registers_enabled = *sound_registers_enabled;
+			self.timer_reload = *timer_reload;
+			self.timer_counter = *timer_counter;
+			self.timer_repeat = *timer_repeat;
+			self.timer_enabled = *timer_enabled;
+			self.timer_irq = *timer_irq;
+			self.write_data = *write_data;
+			self.read_data = *read_data;
+			self.control = *control;
+			self.motor_on = *motor_on;
+			self.disk_position = *disk_position;
+			self.drive_delay = *drive_delay;
+			self.end_of_head = *end_of_head;
+			self.scanning = *scanning;
+			self.gap_ended = *gap_ended;
+			self.transfer_complete = *transfer_complete;
+			self.disk_irq = *disk_irq;
+			self.previous_crc_control = *previous_crc_control;
+		}
+	}
+
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		match address < 0xE000 {
+			true => CpuMapping::ProgramRam(address - 0x6000),
+			false => CpuMapping::ProgramRom(self.map(address))
+		}
+	}
+
+	/**
+	 * 0x4030: Status. Reading acknowledges IRQs
+	 * 0x4031: Data read from the disk
+	 * 0x4032: Drive status
+	 * 0x4033: External connector, bit 7 is battery good
+	 * 0x4040 - 0x4092: Audio
+	 */
+	fn load_register(&mut self, address: u32) -> Option<u8> {
+		match address {
+			0x4030..=0x4033 if self.disk_registers_enabled => Some(match address {
+				0x4030 => {
+					let mut value = 0;
+					if self.timer_irq {
+						value |= 0x01;
+					}
+					if self.transfer_complete {
+						value |= 0x02;
+					}
+					if self.end_of_head {
+						value |= 0x40;
+					}
+					self.transfer_complete = false;
+					self.timer_irq = false;
+					self.disk_irq = false;
+					value
+				},
+				0x4031 => {
+					self.transfer_complete = false;
+					self.disk_irq = false;
+					self.read_data
+				},
+				0x4032 => {
+					// Bit 0: Disk missing, bit 1: Not ready, bit 2: Write protected
+					let mut value = 0x40;
+					if self.disk_side.is_none() {
+						value |= 0x05;
+					}
+					if self.disk_side.is_none() || !self.scanning {
+						value |= 0x02;
+					}
+					value
+				},
+				_ => 0x80
+			}),
+			0x4040..=0x4092 if self.sound_registers_enabled => self.audio.load(address),
+			_ => None
+		}
+	}
+
+	/**
+	 * 0x4020 - 0x4021: Timer IRQ reload value
+	 * 0x4022: Timer IRQ control
+	 * 0x4023: Disk and sound register enable
+	 * 0x4024: Data to write to the disk
+	 * 0x4025: Drive control
+	 * 0x4040 - 0x408A: Audio
+	 */
+	fn store_register(&mut self, address: u32, value: u8) -> bool {
+		match address {
+			0x4020 => {
+				self.timer_reload = (self.timer_reload & 0xFF00) | value as u16;
+			},
+			0x4021 => {
+				self.timer_reload = (self.timer_reload & 0xFF) | ((value as u16) << 8);
+			},
+			0x4022 => {
+				if self.disk_registers_enabled {
+					self.timer_repeat = (value & 0x1) != 0;
+					self.timer_enabled = (value & 0x2) != 0;
+					match self.timer_enabled {
+						true => self.timer_counter = self.timer_reload,
+						false => self.timer_irq = false
+					};
+				}
+			},
+			0x4023 => {
+				self.disk_registers_enabled = (value & 0x1) != 0;
+				self.sound_registers_enabled = (value & 0x2) != 0;
+				if !self.disk_registers_enabled {
+					self.timer_enabled = false;
+					self.timer_irq = false;
+					self.disk_irq = false;
+				}
+			},
+			0x4024 => {
+				if self.disk_registers_enabled {
+					self.write_data = value;
+					self.transfer_complete = false;
+					self.disk_irq = false;
+				}
+			},
+			0x4025 => {
+				if self.disk_registers_enabled {
+					self.control = value;
+					self.motor_on = (value & 0x1) != 0;
+					self.disk_irq = false;
+				}
+			},
+			0x4040..=0x408A => {
+				if self.sound_registers_enabled {
+					self.audio.store(address, value);
+				}
+			},
+			_ => return false
+		};
+		true
+	}
+
+	fn step(&mut self) {
+		self.clock_timer();
+		self.clock_drive();
+		self.audio.clock();
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.timer_irq || self.disk_irq
+	}
+
+	fn audio_output(&self) -> f32 {
+		self.audio.output() as f32 * 0.0057
+	}
+
+	fn disk_side_count(&self) -> usize {
+		self.disk.side_count()
+	}
+
+	fn disk_side(&self) -> Option<usize> {
+		self.disk_side
+	}
+
+	fn insert_disk(&mut self, side: Option<usize>) {
+		let side = match side {
+			Some(side) if side >= self.disk.side_count() => None,
+			side => side
+		};
+		// Sides differ in length, the head starts over on the new one
+		if side != self.disk_side {
+			self.disk_position = 0;
+			self.end_of_head = true;
+		}
+		self.disk_side = side;
+	}
+
+	fn export_disk(&self) -> Option<Vec<u8>> {
+		Some(self.disk.to_image())
+	}
+
+	fn import_disk(&mut self, data: &[u8]) {
+		if let Some(disk) = FdsDisk::new(data) {
+			if disk.side_count() == self.disk.side_count() {
+				self.disk = disk;
+				self.disk_dirty = false;
+			}
+		}
+	}
+
+	fn is_disk_dirty(&self) -> bool {
+		self.disk_dirty
+	}
+
+	fn mark_disk_flushed(&mut self) {
+		self.disk_dirty = false;
+	}
+}
+
+// Player code the NSF mapper serves in 0x4100 - 0x41FF. It clears RAM and
+// APU, calls INIT with the track in A and the region in X, and then calls
+// PLAY on every play timer IRQ.
+const NSF_DRIVER: [u8; 0x66] = [
+	// 0x4100: Reset. Stops the play timer and clears RAM
+	0x78, 0xD8, 0xA2, 0xFF, 0x9A, 0xE8, 0x8A, 0x8D, 0xF2, 0x41,
+	0x95, 0x00, 0x9D, 0x00, 0x01, 0x9D, 0x00, 0x02, 0x9D, 0x00, 0x03,
+	0x9D, 0x00, 0x04, 0x9D, 0x00, 0x05, 0x9D, 0x00, 0x06, 0x9D, 0x00, 0x07,
+	0xE8, 0xD0, 0xE6,
+	// 0x4124: Clears 0x4000 - 0x4013 and enables the APU channels
+	0xA2, 0x13, 0x9D, 0x00, 0x40, 0xCA, 0x10, 0xFA,
+	0x8D, 0x15, 0x40, 0xA9, 0x0F, 0x8D, 0x15, 0x40, 0xA9, 0x40, 0x8D, 0x17, 0x40,
+	// 0x4139: Calls INIT, starts the play timer and idles
+	0xAD, 0xF0, 0x41, 0xAE, 0xF1, 0x41, 0x20, 0x60, 0x41,
+	0xA9, 0x01, 0x8D, 0xF2, 0x41, 0x58, 0x4C, 0x48, 0x41,
+	// 0x414B: IRQ. Calls PLAY if the play timer fired
+	0x48, 0x8A, 0x48, 0x98, 0x48, 0xAD, 0xF3, 0x41, 0xF0, 0x03, 0x20, 0x63, 0x41,
+	0x68, 0xA8, 0x68, 0xAA, 0x68, 0x40,
+	// 0x415E: NMI
+	0x40, 0x00,
+	// 0x4160: JMP (INIT), JMP (PLAY)
+	0x6C, 0xF4, 0x41, 0x6C, 0xF6, 0x41
+];
+
+// NMI, RESET and IRQ vectors pointing to the player code
+const NSF_VECTORS: [u8; 6] = [0x5E, 0x41, 0x00, 0x41, 0x4B, 0x41];
+
+/**
+ * NSF player (not a real board). Serves the music data in 4KB banks
+ * switched with 0x5FF8 - 0x5FFF (and 0x5FF6 - 0x5FF7 for FDS), the player
+ * code and a play timer. The expansion audio chips the NSF uses are the
+ * existing mappers receiving only the audio register writes.
+ * Made by Rom::new() for NSF and NSFe data rather than MapperFactory.
+ * Refer to https://wiki.nesdev.com/w/index.php/NSF
+ */
+pub struct NsfMapper {
+	nsf: Nsf,
+	header: RomHeader,
+	track: u8,
+	banks: [u8; 10], // 0x6000 - 0xFFFF
+	// 0x6000 - 0x7FFF. FDS tunes have RAM in the whole 0x6000 - 0xFFFF
+	// with the banks copied into it.
+	ram: Vec<u8>,
+	play_counter: u32,
+	playing: bool,
+	play_irq: bool,
+	fds_audio: Option<FdsAudio>,
+	expansions: Vec<(u8, Box<dyn Mapper>)> // Chip flag and mapper
+}
+
+impl NsfMapper {
+	pub fn new(nsf: Nsf, header: &RomHeader) -> Self {
+		let mut mapper = NsfMapper {
+			track: nsf.metadata.starting_track,
+			banks: nsf.banks,
+			ram: vec![],
+			play_counter: 0,
+			playing: false,
+			play_irq: false,
+			fds_audio: None,
+			expansions: vec![],
+			nsf: nsf,
+			header: header.clone()
+		};
+		mapper.reset();
+		mapper
+	}
+
+	fn has_fds(&self) -> bool {
+		(self.nsf.expansion & NSF_FDS) != 0
+	}
+
+	// Gets the player back to the power-on state for the track
+	fn reset(&mut self) {
+		self.banks = self.nsf.banks;
+		self.ram = vec![0; match self.has_fds() {
+			true => 0xA000,
+			false => 0x2000
+		}];
+		if self.has_fds() {
+			for slot in 0..self.banks.len() {
+				self.copy_bank_to_ram(slot);
+			}
+		}
+		self.play_counter = 0;
+		self.playing = false;
+		self.play_irq = false;
+		self.fds_audio = match self.has_fds() {
+			true => Some(FdsAudio::new()),
+			false => None
+		};
+		self.expansions = self.create_expansions();
+	}
+
+	fn create_expansions(&self) -> Vec<(u8, Box<dyn Mapper>)> {
+		let header = &self.header;
+		let mut expansions: Vec<(u8, Box<dyn Mapper>)> = vec![];
+		if (self.nsf.expansion & NSF_VRC6) != 0 {
+			expansions.push((NSF_VRC6, Box::new(VRC6Mapper::new(header, false))));
+		}
+		if (self.nsf.expansion & NSF_VRC7) != 0 {
+			expansions.push((NSF_VRC7, Box::new(VRC7Mapper::new(header))));
+		}
+		if (self.nsf.expansion & NSF_MMC5) != 0 {
+			// ExRAM is general purpose RAM in NSF
+			let mut mmc5 = MMC5Mapper::new();
+			mmc5.store_register(0x5104, 2);
+			expansions.push((NSF_MMC5, Box::new(mmc5)));
+		}
+		if (self.nsf.expansion & NSF_N163) != 0 {
+			expansions.push((NSF_N163, Box::new(Namco163Mapper::new(header))));
+		}
+		if (self.nsf.expansion & NSF_SUNSOFT_5B) != 0 {
+			expansions.push((NSF_SUNSOFT_5B, Box::new(SunsoftMapper::new(header))));
+		}
+		expansions
+	}
+
+	fn play_period(&self) -> u32 {
+		let speed = match self.nsf.pal_only {
+			true => self.nsf.pal_speed,
+			false => self.nsf.ntsc_speed
+		};
+		// Speeds are in microseconds
+		(speed as u64 * 1789773 / 1000000) as u32
+	}
+
+	fn load_bank(&self, slot: usize, address: u32) -> u8 {
+		let offset = self.banks[slot] as usize * 0x1000 + (address & 0xFFF) as usize;
+		match self.nsf.data.get(offset) {
+			Some(value) => *value,
+			None => 0
+		}
+	}
+
+	fn copy_bank_to_ram(&mut self, slot: usize) {
+		for i in 0..0x1000 {
+			self.ram[slot * 0x1000 + i] = self.load_bank(slot, i as u32);
+		}
+	}
+
+	/**
+	 * 0x41F0: Track
+	 * 0x41F1: Region (0: NTSC, 1: PAL)
+	 * 0x41F3: Whether the play timer fired. Reading acknowledges it
+	 * 0x41F4 - 0x41F7: INIT and PLAY addresses
+	 */
+	fn load_driver(&mut self, address: u32) -> u8 {
+		match address {
+			0x41F0 => self.track,
+			0x41F1 => self.nsf.pal_only as u8,
+			0x41F3 => {
+				let value = self.play_irq as u8;
+				self.play_irq = false;
+				value
+			},
+			0x41F4 => self.nsf.init_address as u8,
+			0x41F5 => (self.nsf.init_address >> 8) as u8,
+			0x41F6 => self.nsf.play_address as u8,
+			0x41F7 => (self.nsf.play_address >> 8) as u8,
+			_ => match NSF_DRIVER.get((address - 0x4100) as usize) {
+				Some(value) => *value,
+				None => 0
+			}
+		}
+	}
+
+	// Expansion chip the address is an audio register (or MMC5 ExRAM) of
+	fn expansion_index(&self, address: u32) -> Option<usize> {
+		self.expansions.iter().position(|&(chip, _)| match chip {
+			NSF_VRC6 => match address {
+				0x9000..=0x9003 | 0xA000..=0xA002 | 0xB000..=0xB002 => true,
+				_ => false
+			},
+			NSF_VRC7 => address == 0x9010 || address == 0x9030,
+			NSF_MMC5 => match address {
+				0x5000..=0x5015 | 0x5205..=0x5206 | 0x5C00..=0x5FF5 => true,
+				_ => false
+			},
+			NSF_N163 => match address {
+				0x4800..=0x4FFF | 0xF800..=0xFFFF => true,
+				_ => false
+			},
+			NSF_SUNSOFT_5B => address == 0xC000 || address == 0xE000,
+			_ => false
+		})
+	}
+}
+
+impl Mapper for NsfMapper {
+	// Unused. .map_for_cpu() serves everything.
+	fn map(&self, address: u32) -> u32 {
+		address - 0x8000
+	}
+
+	fn map_for_chr_rom(&self, address: u32) -> u32 {
+		address
+	}
+
+	/**
+	 * 0x6000 - 0xDFFF: RAM for FDS tunes
+	 * Expansion audio registers
+	 */
+	fn store(&mut self, address: u32, value: u8) {
+		if self.has_fds() && address < 0xE000 {
+			self.ram[(address - 0x6000) as usize] = value;
+		}
+		if let Some(index) = self.expansion_index(address) {
+			self.expansions[index].1.store(address, value);
+		}
+	}
+
+	fn has_mirroring_type(&self) -> bool {
+		false
+	}
+
+	fn mirroring_type(&self) -> Mirrorings {
+		Mirrorings::Horizontal
+	}
+
+	fn drive_irq_counter(&mut self) -> bool {
+		false
+	}
+
+	fn save_state(&self) -> Vec<u8> {
+		MapperState::Nsf {
+			track: self.track,
+			banks: self.banks,
+			ram: self.ram.clone(),
+			play_counter: self.play_counter,
+			playing: self.playing,
+			play_irq: self.play_irq,
+			fds_audio: self.fds_audio.as_ref().map(|audio| audio.save_state()),
+			expansions: self.expansions.iter().map(|&(_, ref mapper)| mapper.save_state()).collect()
+		}.to_bytes()
+	}
+
+	fn load_state(&mut self, data: &[u8]) {
+		if let MapperState::Nsf {
+			track,
+			banks,
+			ram,
+			play_counter,
+			playing,
+			play_irq,
+			fds_audio,
+			expansions
+		} = &MapperState::from_bytes(data) {
+			self.track = *track;
+			self.banks = *banks;
+			self.ram = ram.clone();
+			self.play_counter = *play_counter;
+			self.playing = *playing;
+			self.play_irq = *play_irq;
+			if let (Some(audio), Some(state)) = (self.fds_audio.as_mut(), fds_audio) {
+				audio.load_state(state);
+			}
+			for (&mut (_, ref mut mapper), state) in self.expansions.iter_mut().zip(expansions) {
+				mapper.load_state(state);
+			}
+		}
+	}
+
+	/**
+	 * 0x6000 - 0xFFFF: Banks, or RAM for FDS tunes.
+	 * 0xFFFA - 0xFFFF: Vectors to the player code
+	 */
+	fn map_for_cpu(&self, address: u32) -> CpuMapping {
+		CpuMapping::Value(match address {
+			0xFFFA..=0xFFFF => NSF_VECTORS[(address - 0xFFFA) as usize],
+			_ if self.has_fds() || address < 0x8000 => self.ram[(address - 0x6000) as usize],
+			_ => self.load_bank(((address - 0x6000) >> 12) as usize, address)
+		})
+	}
+
+	/**
+	 * 0x4040 - 0x4092: FDS audio
+	 * 0x4100 - 0x41FF: Player code
+	 * Expansion audio registers
+	 */
+	fn load_register(&mut self, address: u32) -> Option<u8> {
+		match address {
+			0x4040..=0x4092 => match self.fds_audio {
+				Some(ref audio) => audio.load(address),
+				None => None
+			},
+			0x4100..=0x41FF => Some(self.load_driver(address)),
+			_ => match self.expansion_index(address) {
+				Some(index) => self.expansions[index].1.load_register(address),
+				None => None
+			}
+		}
+	}
+
+	/**
+	 * 0x4040 - 0x408A: FDS audio
+	 * 0x41F2: Starts the play timer if non-zero, stops it if zero
+	 * 0x5FF6 - 0x5FFF: 4KB bank of 0x6000 - 0xFFFF
+	 * 0x6000 - 0x7FFF: RAM
+	 * Expansion audio registers
+	 */
+	fn store_register(&mut self, address: u32, value: u8) -> bool {
+		match address {
+			0x4040..=0x408A => match self.fds_audio {
+				Some(ref mut audio) => audio.store(address, value),
+				None => return false
+			},
+			0x41F2 => {
+				self.playing = value != 0;
+				self.play_counter = self.play_period();
+				self.play_irq = false;
+			},
+			0x5FF6..=0x5FFF => {
+				let slot = (address - 0x5FF6) as usize;
+				self.banks[slot] = value;
+				if self.has_fds() {
+					self.copy_bank_to_ram(slot);
+				}
+			},
+			0x6000..=0x7FFF => self.ram[(address - 0x6000) as usize] = value,
+			_ => return match self.expansion_index(address) {
+				Some(index) => self.expansions[index].1.store_register(address, value),
+				None => false
+			}
+		};
+		true
+	}
+
+	fn step(&mut self) {
+		if self.playing {
+			self.play_counter = self.play_counter.saturating_sub(1);
+			if self.play_counter == 0 {
+				self.play_irq = true;
+				self.play_counter = self.play_period();
+			}
+		}
+		if let Some(ref mut audio) = self.fds_audio {
+			audio.clock();
+		}
+		for &mut (_, ref mut mapper) in self.expansions.iter_mut() {
+			mapper.step();
+		}
+	}
+
+	fn irq_pending(&self) -> bool {
+		self.play_irq
+	}
+
+	fn audio_output(&self) -> f32 {
+		let fds_output = match self.fds_audio {
+			Some(ref audio) => audio.output() as f32 * 0.0057,
+			None => 0.0
+		};
+		self.expansions.iter().fold(fds_output, |output, &(_, ref mapper)| output + mapper.audio_output())
+	}
+
+	fn nsf_metadata(&self) -> Option<&NsfMetadata> {
+		Some(&self.nsf.metadata)
+	}
+
+	fn track(&self) -> u8 {
+		self.track
+	}
+
+	fn select_track(&mut self, track: u8) {
+		if track < self.nsf.metadata.track_count {
+			self.track = track;
+			self.reset();
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests_nrom_mapper {
+	use super::*;
+
+	#[test]
+	fn initialize() {
+		NRomMapper{program_bank_num: 1};
+	}
+
+	#[test]
+	fn map_with_program_bank_num_1() {
+		let m = NRomMapper{program_bank_num: 1};
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x3FFF, m.map(0xBFFF));
+		assert_eq!(0x0000, m.map(0xC000));
+		assert_eq!(0x3FFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_with_program_bank_num_2() {
+		let m = NRomMapper{program_bank_num: 2};
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x3FFF, m.map(0xBFFF));
+		assert_eq!(0x4000, m.map(0xC000));
+		assert_eq!(0x7FFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let m = NRomMapper{program_bank_num: 1};
+		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x1FFF, m.map_for_chr_rom(0x1FFF));
+	}
+}
+
+#[cfg(test)]
+mod tests_unrom512_mapper {
+	use super::*;
+
+	fn mapper() -> UNRom512Mapper {
+		UNRom512Mapper {
+			program_bank_num: 32,
+			one_screen_mirroring: true,
+			register: Register::<u8>::new()
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper();
+		m.store(0x8000, 0x03);
+		assert_eq!(0x0C000, m.map(0x8000));
+		assert_eq!(0x7C000, m.map(0xC000));
+		assert_eq!(0x7FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let mut m = mapper();
+		m.store(0x8000, 0x60);
+		assert_eq!(0x6000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x7FFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn one_screen_mirroring() {
+		let mut m = mapper();
+		m.store(0x8000, 0x80);
+		match m.mirroring_type() {
+			Mirrorings::OneScreenHigh => {},
+			_ => panic!("expected one-screen high")
+		};
+		match m.name_table_source(2) {
+			Some(NameTableSource::Vram(1)) => {},
+			_ => panic!("expected VRAM page 1")
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests_axrom_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = AxRomMapper::new();
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x7FFF, m.map(0xFFFF));
+		m.store(0x8000, 0x05);
+		assert_eq!(0x28000, m.map(0x8000));
+		assert_eq!(0x2FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn mirroring_type() {
+		let mut m = AxRomMapper::new();
+		match m.mirroring_type() {
+			Mirrorings::OneScreenLow => {},
+			_ => panic!("expected one-screen low")
+		};
+		m.store(0x8000, 0x10);
+		match m.mirroring_type() {
+			Mirrorings::OneScreenHigh => {},
+			_ => panic!("expected one-screen high")
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests_mmc2_mapper {
+	use super::*;
+
+	fn mapper(mmc4: bool) -> MMC2Mapper {
+		MMC2Mapper {
+			mmc4: mmc4,
+			program_bank_num: 8,
+			program_register: Register::<u8>::new(),
+			character_registers: [
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new(),
+				Register::<u8>::new()
+			],
+			latch0: true,
+			latch1: true,
+			mirroring_register: Register::<u8>::new()
+		}
+	}
+
+	#[test]
+	fn map_mmc2() {
+		let mut m = mapper(false);
+		m.store(0xA000, 0x03);
+		assert_eq!(0x06000, m.map(0x8000));
+		assert_eq!(0x1A000, m.map(0xA000));
+		assert_eq!(0x1C000, m.map(0xC000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_mmc4() {
+		let mut m = mapper(true);
+		m.store(0xA000, 0x03);
+		assert_eq!(0x0C000, m.map(0x8000));
+		assert_eq!(0x1C000, m.map(0xC000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn latch_switches_chr_bank() {
+		let mut m = mapper(false);
+		m.store(0xB000, 0x01);
+		m.store(0xC000, 0x02);
+		m.store(0xD000, 0x03);
+		m.store(0xE000, 0x04);
+		assert_eq!(0x2000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x4000, m.map_for_chr_rom(0x1000));
+		m.notify_ppu_read(0x0FD8);
+		assert_eq!(0x1000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x4000, m.map_for_chr_rom(0x1000));
+		m.notify_ppu_read(0x1FDF);
+		assert_eq!(0x3000, m.map_for_chr_rom(0x1000));
+		m.notify_ppu_read(0x1FE8);
+		assert_eq!(0x4000, m.map_for_chr_rom(0x1000));
+	}
+
+	#[test]
+	fn mmc2_latch0_needs_exact_address() {
+		let mut m = mapper(false);
+		m.notify_ppu_read(0x0FD9);
+		assert!(m.latch0);
+		let mut m = mapper(true);
+		m.notify_ppu_read(0x0FD9);
+		assert!(!m.latch0);
+	}
+
+	#[test]
+	fn mirroring_type() {
+		let mut m = mapper(false);
+		m.store(0xF000, 0x01);
+		match m.mirroring_type() {
+			Mirrorings::Horizontal => {},
+			_ => panic!("expected horizontal")
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests_mmc5_mapper {
+	use super::*;
+
+	fn rom_address(m: &MMC5Mapper, address: u32) -> u32 {
+		match m.map_for_cpu(address) {
+			CpuMapping::ProgramRom(a) => a,
+			_ => panic!("expected program rom")
+		}
+	}
+
+	fn ram_address(m: &MMC5Mapper, address: u32) -> u32 {
+		match m.map_for_cpu(address) {
+			CpuMapping::ProgramRam(a) => a,
+			_ => panic!("expected program ram")
+		}
+	}
+
+	#[test]
+	fn program_banking() {
+		let mut m = MMC5Mapper::new();
+		// Mode 3 with 0x5117 = 0xFF at power on
+		assert_eq!(0xFE000, rom_address(&m, 0xE000));
+		m.store_register(0x5114, 0x81);
+		m.store_register(0x5115, 0x02);
+		assert_eq!(0x02000, rom_address(&m, 0x8000));
+		assert_eq!(0x04000, ram_address(&m, 0xA000));
+		m.store_register(0x5100, 0x01);
+		m.store_register(0x5115, 0x83);
+		m.store_register(0x5117, 0x85);
+		assert_eq!(0x04000, rom_address(&m, 0x8000));
+		assert_eq!(0x0BFFF, rom_address(&m, 0xFFFF));
+		m.store_register(0x5100, 0x00);
+		assert_eq!(0x08000, rom_address(&m, 0x8000));
+		m.store_register(0x5113, 0x01);
+		assert_eq!(0x02000, ram_address(&m, 0x6000));
+	}
+
+	#[test]
+	fn pulse_without_sweep() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5015, 0x01);
+		// Constant volume 0xA, duty 75%, period 4 which mutes APU pulses
+		m.store_register(0x5000, 0xFA);
+		m.store_register(0x5002, 0x04);
+		m.store_register(0x5003, 0x08);
+		let outputs: Vec<u8> = (0..80).map(|_| {
+			m.step();
+			m.pulse1.output()
+		}).collect();
+		assert!(outputs.contains(&0xA));
+		assert!(outputs.contains(&0));
+	}
+
+	#[test]
+	fn prg_ram_protect() {
+		let mut m = MMC5Mapper::new();
+		assert!(!m.prg_ram_writable(0x6000));
+		m.store_register(0x5102, 0x02);
+		m.store_register(0x5103, 0x01);
+		assert!(m.prg_ram_writable(0x6000));
+	}
+
+	#[test]
+	fn character_banking() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5101, 0x03);
+		m.store_register(0x5120, 0x04);
+		m.store_register(0x5127, 0x05);
+		assert_eq!(0x1000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x1400, m.map_for_chr_rom(0x1C00));
+		m.store_register(0x5130, 0x01);
+		m.store_register(0x5128, 0x02);
+		// Set B was written last, mirrored in 0x1000 - 0x1FFF
+		assert_eq!(0x40800, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x40800, m.map_for_chr_rom(0x1000));
+	}
+
+	#[test]
+	fn character_sets_with_8x16_sprites() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5101, 0x01);
+		m.store_register(0x5127, 0x01);
+		m.store_register(0x512B, 0x02);
+		m.notify_ppu_phase(PpuPhase::Background(0));
+		m.notify_ppu_phase(PpuPhase::Sprites(true));
+		assert_eq!(0x1000, m.map_for_chr_rom(0x1000));
+		m.notify_ppu_phase(PpuPhase::Background(1));
+		assert_eq!(0x2000, m.map_for_chr_rom(0x1000));
+	}
+
+	#[test]
+	fn scanline_irq() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5203, 2);
+		m.store_register(0x5204, 0x80);
+		m.notify_ppu_phase(PpuPhase::Background(0));
+		m.notify_ppu_phase(PpuPhase::Background(1));
+		assert!(!m.irq_pending());
+		m.notify_ppu_phase(PpuPhase::Background(2));
+		assert!(m.irq_pending());
+		assert_eq!(Some(0xC0), m.load_register(0x5204));
+		assert!(!m.irq_pending());
+		m.notify_ppu_phase(PpuPhase::Idle);
+		assert_eq!(Some(0x00), m.load_register(0x5204));
+	}
+
+	#[test]
+	fn multiplier() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5205, 200);
+		m.store_register(0x5206, 100);
+		assert_eq!(Some(0x20), m.load_register(0x5205));
+		assert_eq!(Some(0x4E), m.load_register(0x5206));
+	}
+
+	#[test]
+	fn name_tables() {
+		let mut m = MMC5Mapper::new();
+		// Slot 0: VRAM page 1, slot 1: ExRAM, slot 2: fill mode
+		m.store_register(0x5105, 0x39);
+		m.store_register(0x5106, 0x12);
+		m.store_register(0x5107, 0x02);
+		match m.name_table_source(0) {
+			Some(NameTableSource::Vram(1)) => {},
+			_ => panic!("expected vram page 1")
+		};
+		m.store_name_table(0x2405, 0x34);
+		assert_eq!(0x34, m.load_name_table(0x2405));
+		assert_eq!(0x12, m.load_name_table(0x2805));
+		assert_eq!(0xAA, m.load_name_table(0x2BC0));
+	}
+
+	#[test]
+	fn exram_as_work_ram() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5C00, 0x55);
+		assert_eq!(Some(0), m.load_register(0x5C00));
+		m.store_register(0x5104, 0x02);
+		m.store_register(0x5C00, 0x55);
+		assert_eq!(Some(0x55), m.load_register(0x5C00));
+	}
+
+	#[test]
+	fn extended_attribute() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5104, 0x02);
+		m.store_register(0x5C01, 0xC5);
+		m.store_register(0x5104, 0x01);
+		m.notify_ppu_phase(PpuPhase::Background(0));
+		assert_eq!(None, m.fetch_name_table(0x2001));
+		assert_eq!(Some(0xFF), m.fetch_attribute_table(0x23C0));
+		assert_eq!(0x5008, m.map_for_chr_rom(0x0008));
+	}
+
+	#[test]
+	fn vertical_split() {
+		let mut m = MMC5Mapper::new();
+		m.store_register(0x5104, 0x02);
+		m.store_register(0x5C00 + 32 + 1, 0x77);
+		m.store_register(0x5104, 0x00);
+		// Left side of tile 2, scroll 8 lines down
+		m.store_register(0x5200, 0x82);
+		m.store_register(0x5201, 0x08);
+		m.store_register(0x5202, 0x03);
+		m.notify_ppu_phase(PpuPhase::Background(2));
+		assert_eq!(Some(0), m.fetch_name_table(0x2000));
+		assert_eq!(Some(0x77), m.fetch_name_table(0x2001));
+		assert_eq!(0x3772, m.map_for_chr_rom(0x0775));
+		assert_eq!(None, m.fetch_name_table(0x2002));
+	}
+}
+
+#[cfg(test)]
+mod tests_vrc6_mapper {
+	use super::*;
+
+	fn mapper(swap_address_lines: bool) -> VRC6Mapper {
+		VRC6Mapper {
+			swap_address_lines: swap_address_lines,
+			program_bank_num: 16,
+			program_registers: [0; 2],
+			control: 0,
+			character_registers: [0; 8],
+			frequency_control: 0,
+			irq: VrcIrq::new(),
+			pulse1: Vrc6Pulse::new(),
+			pulse2: Vrc6Pulse::new(),
+			sawtooth: Vrc6Sawtooth::new()
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper(false);
+		m.store(0x8000, 0x03);
+		m.store(0xC000, 0x05);
+		assert_eq!(0x0C000, m.map(0x8000));
+		assert_eq!(0x0A000, m.map(0xC000));
+		assert_eq!(0x3E000, m.map(0xE000));
+		assert_eq!(0x3FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn address_lines() {
+		let mut m = mapper(false);
+		m.store(0xD001, 0x11);
+		assert_eq!(0x4400, m.map_for_chr_rom(0x0400));
+		let mut m = mapper(true);
+		m.store(0xD001, 0x11);
+		assert_eq!(0x4400, m.map_for_chr_rom(0x0800));
+	}
+
+	#[test]
+	fn chr_banking_modes() {
+		let mut m = mapper(false);
+		for i in 0..4 {
+			m.store(0xD000 + i, i as u8 + 0x10);
+			m.store(0xE000 + i, i as u8 + 0x20);
+		}
+		assert_eq!(0x8C00, m.map_for_chr_rom(0x1C00));
+		// 2KB banks with PPU A10
+		m.store(0xB003, 0x21);
+		assert_eq!(0x4000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x4400, m.map_for_chr_rom(0x0400));
+		assert_eq!(0x4C00, m.map_for_chr_rom(0x1C00));
+		m.store(0xB003, 0x22);
+		assert_eq!(0x4C00, m.map_for_chr_rom(0x0C00));
+		assert_eq!(0x8400, m.map_for_chr_rom(0x1C00));
+	}
+
+	#[test]
+	fn mirroring_and_prg_ram() {
+		let mut m = mapper(false);
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::Unmapped => {},
+			_ => panic!("expected unmapped")
+		};
+		m.store(0xB003, 0x84);
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::ProgramRam(0) => {},
+			_ => panic!("expected program ram")
+		};
+		match m.mirroring_type() {
+			Mirrorings::Horizontal => {},
+			_ => panic!("expected horizontal")
+		};
+	}
+
+	#[test]
+	fn cycle_irq() {
+		let mut m = mapper(false);
+		m.store(0xF000, 0xFD);
+		m.store(0xF001, 0x07);
+		m.step();
+		m.step();
+		assert!(!m.irq_pending());
+		m.step();
+		assert!(m.irq_pending());
+		m.store(0xF002, 0);
+		assert!(!m.irq_pending());
+		// Still enabled with the bit 0 of the control
+		m.step();
+		m.step();
+		m.step();
+		assert!(m.irq_pending());
+	}
+
+	#[test]
+	fn scanline_irq() {
+		let mut m = mapper(false);
+		m.store(0xF000, 0xFF);
+		m.store(0xF001, 0x02);
+		for _i in 0..113 {
+			m.step();
+		}
+		assert!(!m.irq_pending());
+		m.step();
+		assert!(m.irq_pending());
+	}
+
+	#[test]
+	fn pulse() {
+		let mut m = mapper(false);
+		// Duty 1/16, volume 10
+		m.store(0x9000, 0x0A);
+		m.store(0x9001, 0x00);
+		m.store(0x9002, 0x80);
+		let mut outputs = vec![];
+		for _i in 0..16 {
+			m.step();
+			outputs.push(m.pulse1.output());
+		}
+		assert_eq!(1, outputs.iter().filter(|&&o| o == 10).count());
+		// Ignores duty
+		m.store(0x9000, 0x8A);
+		assert_eq!(10, m.pulse1.output());
+	}
+
+	#[test]
+	fn sawtooth() {
+		let mut m = mapper(false);
+		m.store(0xB000, 0x08);
+		m.store(0xB001, 0x00);
+		m.store(0xB002, 0x80);
+		let mut outputs = vec![];
+		for _i in 0..14 {
+			m.step();
+			outputs.push(m.sawtooth.output());
+		}
+		assert_eq!(vec![0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 0], outputs);
+	}
+}
+
+#[cfg(test)]
+mod tests_vrc4_mapper {
+	use super::*;
+
+	fn mapper(wiring: VrcWiring) -> VRC4Mapper {
+		VRC4Mapper {
+			wiring: wiring,
+			program_bank_num: 16,
+			program_registers: [0; 2],
+			mirroring: 0,
+			program_mode: 0,
+			character_registers: [0; 8],
+			irq: VrcIrq::new()
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper(VrcWiring::Vrc4f);
+		m.store(0x8000, 0x03);
+		m.store(0xA000, 0x04);
+		assert_eq!(0x06000, m.map(0x8000));
+		assert_eq!(0x08000, m.map(0xA000));
+		assert_eq!(0x3C000, m.map(0xC000));
+		assert_eq!(0x3E000, m.map(0xE000));
+		// PRG swap mode
+		m.store(0x9002, 0x02);
+		assert_eq!(0x3C000, m.map(0x8000));
+		assert_eq!(0x06000, m.map(0xC000));
+	}
+
+	#[test]
+	fn wiring() {
+		// 0xB003 selects the high nibble of CHR bank 1
+		for &(wiring, address) in [
+			(VrcWiring::Vrc4a, 0xB006),
+			(VrcWiring::Vrc4b, 0xB003),
+			(VrcWiring::Vrc4c, 0xB0C0),
+			(VrcWiring::Vrc4d, 0xB00C),
+			(VrcWiring::Vrc4e, 0xB00C),
+			(VrcWiring::Vrc4f, 0xB003),
+			(VrcWiring::Mapper21, 0xB0C0),
+			(VrcWiring::Mapper23, 0xB00F),
+			(VrcWiring::Mapper25, 0xB00F)
+		].iter() {
+			let mut m = mapper(wiring);
+			m.store(address, 0x01);
+			assert_eq!(0x4000, m.map_for_chr_rom(0x0400), "{:?}", wiring);
+		}
+	}
+
+	#[test]
+	fn chr_banks() {
+		let mut m = mapper(VrcWiring::Vrc4f);
+		m.store(0xE002, 0x05);
+		m.store(0xE003, 0x12);
+		assert_eq!(0x49400, m.map_for_chr_rom(0x1C00));
+		let mut m = mapper(VrcWiring::Vrc2a);
+		m.store(0xE001, 0x05);
+		m.store(0xE003, 0x12);
+		// VRC2 has 4-bit high nibble and VRC2a drops the lowest bit
+		assert_eq!(0x4800, m.map_for_chr_rom(0x1C00));
+	}
+
+	#[test]
+	fn vrc2_has_no_prg_swap_mode() {
+		let mut m = mapper(VrcWiring::Vrc2b);
+		m.store(0x9002, 0x03);
+		assert_eq!(0x3C000, m.map(0xC000));
+		match m.mirroring_type() {
+			Mirrorings::Horizontal => {},
+			_ => panic!("expected horizontal")
+		};
+	}
+
+	#[test]
+	fn irq() {
+		let mut m = mapper(VrcWiring::Vrc4f);
+		m.store(0xF000, 0x0E);
+		m.store(0xF001, 0x0F);
+		m.store(0xF002, 0x06);
+		m.step();
+		assert!(!m.irq_pending());
+		m.step();
+		assert!(m.irq_pending());
+		m.store(0xF003, 0);
+		assert!(!m.irq_pending());
+		// Disabled after acknowledgement
+		m.step();
+		m.step();
+		assert!(!m.irq_pending());
+	}
+
+	#[test]
+	fn vrc2_has_no_irq() {
+		let mut m = mapper(VrcWiring::Vrc2b);
+		m.store(0xF000, 0x0F);
+		m.store(0xF001, 0x0F);
+		m.store(0xF002, 0x06);
+		m.step();
+		assert!(!m.irq_pending());
+	}
+}
+
+#[cfg(test)]
+mod tests_vrc7_mapper {
+	use super::*;
+
+	fn mapper(register_line: u32) -> VRC7Mapper {
+		VRC7Mapper {
+			register_line: register_line,
+			program_bank_num: 16,
+			program_registers: [0; 3],
+			character_registers: [0; 8],
+			control: 0,
+			irq: VrcIrq::new(),
+			audio: Opll::new(),
+			audio_cycle: 0
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper(0x18);
+		m.store(0x8000, 0x01);
+		m.store(0x8010, 0x02);
+		m.store(0x9000, 0x43);
+		assert_eq!(0x2000, m.map(0x8000));
+		assert_eq!(0x4000, m.map(0xA000));
+		assert_eq!(0x6000, m.map(0xC000));
+		assert_eq!(0x3E000, m.map(0xE000));
+	}
+
+	#[test]
+	fn register_lines() {
+		let mut m = mapper(0x08);
+		m.store(0xA008, 0x05);
+		m.store(0xD010, 0x07);
+		assert_eq!(0x1400, m.map_for_chr_rom(0x0400));
+		assert_eq!(0x1C00, m.map_for_chr_rom(0x1800));
+		let mut m = mapper(0x10);
+		m.store(0xA010, 0x05);
+		m.store(0xD008, 0x07);
+		assert_eq!(0x1400, m.map_for_chr_rom(0x0400));
+		assert_eq!(0x1C00, m.map_for_chr_rom(0x1800));
+	}
+
+	#[test]
+	fn control() {
+		let mut m = mapper(0x18);
+		m.store(0xE000, 0x01);
+		match m.mirroring_type() {
+			Mirrorings::Horizontal => {},
+			_ => panic!("expected horizontal")
+		};
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::Unmapped => {},
+			_ => panic!("PRG-RAM is disabled")
+		};
+		m.store(0xE000, 0x40);
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::ProgramRam(0) => {},
+			_ => panic!("PRG-RAM is enabled")
+		};
+	}
+
+	#[test]
+	fn irq() {
+		let mut m = mapper(0x18);
+		m.store(0xE010, 0xFE);
+		m.store(0xF000, 0x06);
+		m.step();
+		assert!(!m.irq_pending());
+		m.step();
+		assert!(m.irq_pending());
+		m.store(0xF010, 0);
+		assert!(!m.irq_pending());
+	}
+
+	#[test]
+	fn audio() {
+		let mut m = mapper(0x18);
+		// Instrument 1 at full volume on channel 0
+		m.store(0x9010, 0x10);
+		m.store(0x9030, 0x80);
+		m.store(0x9010, 0x30);
+		m.store(0x9030, 0x10);
+		m.store(0x9010, 0x20);
+		m.store(0x9030, 0x18);
+		let mut heard = false;
+		for _ in 0..36 * 256 {
+			m.step();
+			heard |= m.audio_output() != 0.0;
+		}
+		assert!(heard);
+		// Silenced with 0xE000 bit 7
+		m.store(0xE000, 0x80);
+		assert_eq!(0.0, m.audio_output());
+	}
+}
+
+#[cfg(test)]
+mod tests_namco163_mapper {
+	use super::*;
+
+	fn mapper() -> Namco163Mapper {
+		Namco163Mapper {
+			program_bank_num: 16,
+			character_registers: [0; 8],
+			name_table_registers: [0; 4],
+			program_registers: [0; 3],
+			sound_address: 0,
+			sound_ram: vec![0; 128],
+			irq_counter: 0,
+			irq_enabled: false,
+			irq_pending: false,
+			audio_cycle: 0,
+			audio_channel: 0,
+			audio_outputs: [0; 8]
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper();
+		m.store(0xE000, 0x41);
+		m.store(0xE800, 0x02);
+		m.store(0xF000, 0x03);
+		assert_eq!(0x2000, m.map(0x8000));
+		assert_eq!(0x4000, m.map(0xA000));
+		assert_eq!(0x6000, m.map(0xC000));
+		assert_eq!(0x3E000, m.map(0xE000));
+		m.store(0x8800, 0x05);
+		m.store(0xB800, 0x07);
+		assert_eq!(0x1400, m.map_for_chr_rom(0x0400));
+		assert_eq!(0x1C00, m.map_for_chr_rom(0x1C00));
+	}
+
+	#[test]
+	fn name_table_source() {
+		let mut m = mapper();
+		m.store(0xC000, 0xE0);
+		m.store(0xC800, 0xE1);
+		m.store(0xD000, 0x12);
+		match m.name_table_source(0) {
+			Some(NameTableSource::Vram(0)) => {},
+			_ => panic!("expected VRAM page 0")
+		};
+		match m.name_table_source(1) {
+			Some(NameTableSource::Vram(1)) => {},
+			_ => panic!("expected VRAM page 1")
+		};
+		match m.name_table_source(2) {
+			Some(NameTableSource::ChrRom(0x4800)) => {},
+			_ => panic!("expected CHR-ROM")
+		};
+	}
+
+	#[test]
+	fn sound_ram() {
+		let mut m = mapper();
+		m.store(0xF800, 0xFF);
+		assert!(m.store_register(0x4800, 0x12));
+		assert!(m.store_register(0x4800, 0x34));
+		assert_eq!(0x81, m.sound_address);
+		m.store(0xF800, 0x7F);
+		assert_eq!(Some(0x12), m.load_register(0x4800));
+		assert_eq!(Some(0x12), m.load_register(0x4800));
+		assert_eq!(0x34, m.sound_ram[0]);
+		assert_eq!(None, m.load_register(0x4020));
+	}
+
+	#[test]
+	fn irq() {
+		let mut m = mapper();
+		m.store_register(0x5000, 0xFD);
+		m.store_register(0x5800, 0xFF);
+		assert_eq!(Some(0xFF), m.load_register(0x5800));
+		m.step();
+		assert!(!m.irq_pending());
+		m.step();
+		assert!(m.irq_pending());
+		// Stops at 0x7FFF
+		m.step();
+		assert_eq!(Some(0xFF), m.load_register(0x5000));
+		m.store_register(0x5800, 0x7F);
+		assert!(!m.irq_pending());
+	}
+
+	#[test]
+	fn audio() {
+		let mut m = mapper();
+		// Wave at 0x00: 0xF, 0x0, 0xF, 0x0, ...
+		for i in 0..4 {
+			m.sound_ram[i] = 0x0F;
+		}
+		// Channel 8 with wave length 8, volume 15,
+		// stepping a sample per update
+		m.sound_ram[0x7A] = 0x00;
+		m.sound_ram[0x78] = 0x00;
+		m.sound_ram[0x7C] = 0xF8 | 0x1;
+		m.sound_ram[0x7F] = 0x0F;
+		let mut outputs = vec![];
+		for _ in 0..15 * 4 {
+			m.step();
+			if m.audio_cycle == 0 {
+				outputs.push(m.audio_outputs[0]);
+			}
+		}
+		// The phase advances before the sample is read
+		assert_eq!(vec![-8 * 15, 7 * 15, -8 * 15, 7 * 15], outputs);
+		// Disabled with 0xE000 bit 6
+		m.store(0xE000, 0x40);
+		m.step();
+		assert_eq!(0, m.audio_cycle);
+	}
+
+	#[test]
+	fn prg_ram_write_protect() {
+		let mut m = mapper();
+		assert!(!m.prg_ram_writable(0x6000));
+		m.store(0xF800, 0x40);
+		assert!(m.prg_ram_writable(0x6000));
+		assert!(m.prg_ram_writable(0x7FFF));
+		m.store(0xF800, 0x45);
+		assert!(!m.prg_ram_writable(0x6000));
+		assert!(m.prg_ram_writable(0x6800));
+		assert!(!m.prg_ram_writable(0x77FF));
+		assert!(m.prg_ram_writable(0x7800));
+	}
+}
+
+#[cfg(test)]
+mod tests_sunsoft_mapper {
+	use super::*;
+
+	fn mapper() -> SunsoftMapper {
+		let mut m = SunsoftMapper {
+			command_register: Register::<u8>::new(),
+			parameter_register: Register::<u8>::new(),
+			chr_banks: [
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new()
+			],
+			prg_banks: [
+				Register::<u8>::new(), Register::<u8>::new(),
+				Register::<u8>::new(), Register::<u8>::new()
+			],
+			irq_enabled: false,
+			irq_counter_enabled: false,
+			irq_counter: 0,
+			mirroring: 0,
+			prg_bank_mask: 31,
+			chr_bank_mask: 255,
+			audio: Sunsoft5B::new()
+		};
+		m.prg_banks[3].store(31);
+		m
+	}
+
+	fn store_command(m: &mut SunsoftMapper, command: u8, value: u8) {
+		m.store(0x8000, command);
+		m.store(0xA000, value);
+	}
+
+	fn store_audio(m: &mut SunsoftMapper, address: u8, value: u8) {
+		m.store(0xC000, address);
+		m.store(0xE000, value);
+	}
+
+	#[test]
+	fn map_for_cpu() {
+		let mut m = mapper();
+		store_command(&mut m, 8, 0x03);
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::ProgramRom(0x6000) => {},
+			_ => panic!("expected PRG-ROM bank 3")
+		};
+		store_command(&mut m, 8, 0x41);
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::Unmapped => {},
+			_ => panic!("expected disabled PRG-RAM")
+		};
+		store_command(&mut m, 8, 0xC1);
+		match m.map_for_cpu(0x6001) {
+			CpuMapping::ProgramRam(0x2001) => {},
+			_ => panic!("expected PRG-RAM bank 1")
+		};
+		match m.map_for_cpu(0xE000) {
+			CpuMapping::ProgramRom(0x3E000) => {},
+			_ => panic!("expected the last bank")
+		};
+	}
+
+	#[test]
+	fn tone() {
+		let mut m = mapper();
+		// Channel A, period 2, volume 15, noise disabled
+		store_audio(&mut m, 0x0, 0x02);
+		store_audio(&mut m, 0x7, 0x38);
+		store_audio(&mut m, 0x8, 0x0F);
+		let mut outputs = vec![];
+		for _ in 0..4 {
+			for _ in 0..32 {
+				m.step();
+			}
+			outputs.push(m.audio.output());
+		}
+		assert_eq!(vec![1.0, 0.0, 1.0, 0.0], outputs);
+	}
+
+	#[test]
+	fn volume() {
+		let mut m = mapper();
+		// Tone and noise disabled outputs the volume
+		store_audio(&mut m, 0x7, 0x3F);
+		store_audio(&mut m, 0x8, 0x0F);
+		store_audio(&mut m, 0x9, 0x07);
+		assert_eq!(1.0 + SUNSOFT_5B_VOLUMES[15], m.audio.output());
+		store_audio(&mut m, 0x8, 0x00);
+		assert_eq!(SUNSOFT_5B_VOLUMES[15], m.audio.output());
+		assert_eq!(SUNSOFT_5B_VOLUMES[15] * 0.2, m.audio_output());
+	}
+
+	#[test]
+	fn envelope() {
+		let mut m = mapper();
+		store_audio(&mut m, 0x8, 0x10);
+		store_audio(&mut m, 0xB, 0x01);
+		// Attack and hold
+		store_audio(&mut m, 0xD, 0x0D);
+		assert_eq!(0, m.audio.channel_level(0));
+		for _ in 0..8 * 10 {
+			m.step();
+		}
+		assert_eq!(10, m.audio.channel_level(0));
+		for _ in 0..8 * 40 {
+			m.step();
+		}
+		assert_eq!(31, m.audio.channel_level(0));
+		// Decay once
+		store_audio(&mut m, 0xD, 0x00);
+		assert_eq!(31, m.audio.channel_level(0));
+		for _ in 0..8 * 40 {
+			m.step();
+		}
+		assert_eq!(0, m.audio.channel_level(0));
+		// Saw down repeats
+		store_audio(&mut m, 0xD, 0x08);
+		for _ in 0..8 * 33 {
+			m.step();
+		}
+		assert_eq!(30, m.audio.channel_level(0));
+		// Triangle
+		store_audio(&mut m, 0xD, 0x0E);
+		for _ in 0..8 * 33 {
+			m.step();
+		}
+		assert_eq!(30, m.audio.channel_level(0));
+	}
+}
+
+#[cfg(test)]
+mod tests_gxrom_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = GxRomMapper::new();
+		assert_eq!(0x0000, m.map(0x8000));
+		m.store(0x8000, 0x31);
+		assert_eq!(0x18000, m.map(0x8000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let mut m = GxRomMapper::new();
+		m.store(0x8000, 0x32);
+		assert_eq!(0x4000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x5FFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn independent_banks() {
+		let mut m = GxRomMapper::new();
+		m.store(0x8000, 0x20);
+		assert_eq!(0x10000, m.map(0x8000));
+		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
+		m.store(0x8000, 0x03);
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x6000, m.map_for_chr_rom(0x0000));
+	}
+}
+
+#[cfg(test)]
+mod tests_color_dreams_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = ColorDreamsMapper::new();
+		m.store(0x8000, 0x23);
+		assert_eq!(0x18000, m.map(0x8000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let mut m = ColorDreamsMapper::new();
+		m.store(0x8000, 0xF3);
+		assert_eq!(0x1E000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x1FFFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn nibble_split() {
+		let mut m = ColorDreamsMapper::new();
+		m.store(0x8000, 0x10);
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x2000, m.map_for_chr_rom(0x0000));
+		// Bits 3-2 are unused
+		m.store(0x8000, 0x0D);
+		assert_eq!(0x8000, m.map(0x8000));
+		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
+	}
+}
+
+#[cfg(test)]
+mod tests_bnrom_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = BNRomMapper::new();
+		m.store(0x8000, 0x03);
+		assert_eq!(0x18000, m.map(0x8000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+		assert_eq!(0x1FFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn oversize() {
+		let mut m = BNRomMapper::new();
+		assert_eq!(0x0000, m.map(0x8000));
+		m.store(0x8000, 0x10);
+		assert_eq!(0x80000, m.map(0x8000));
+		assert_eq!(0x87FFF, m.map(0xFFFF));
+	}
+}
+
+#[cfg(test)]
+mod tests_nina001_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = Nina001Mapper::new();
+		assert!(m.store_register(0x7FFD, 0x01));
+		assert_eq!(0x8000, m.map(0x8000));
+		// Writes to 0x8000 - 0xFFFF do nothing
+		m.store(0x8000, 0x00);
+		assert_eq!(0xFFFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let mut m = Nina001Mapper::new();
+		m.store_register(0x7FFE, 0x02);
+		m.store_register(0x7FFF, 0x0F);
+		assert_eq!(0x2000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0xFFFF, m.map_for_chr_rom(0x1FFF));
+		assert!(!m.store_register(0x7FFC, 0x00));
+	}
+
+	#[test]
+	fn character_banks() {
+		let mut m = Nina001Mapper::new();
+		m.store_register(0x7FFE, 0x01);
+		m.store_register(0x7FFF, 0x03);
+		assert_eq!(0x1FFF, m.map_for_chr_rom(0x0FFF));
+		assert_eq!(0x3000, m.map_for_chr_rom(0x1000));
+		// Only bit 0 selects the program bank
+		m.store_register(0x7FFD, 0x02);
+		assert_eq!(0x0000, m.map(0x8000));
+	}
+}
+
+#[cfg(test)]
+mod tests_camerica_mapper {
+	use super::*;
+
+	fn mapper() -> CamericaMapper {
+		CamericaMapper {
+			program_bank_num: 8,
+			register: 0,
+			mirroring: None
+		}
+	}
+
+	#[test]
+	fn map() {
+		let mut m = mapper();
+		m.store(0xC000, 0x03);
+		assert_eq!(0xC000, m.map(0x8000));
+		assert_eq!(0x1C000, m.map(0xC000));
+		assert_eq!(0x1FFFF, m.map(0xFFFF));
+		// 0x8000 - 0xBFFF is ignored
+		m.store(0x8000, 0x01);
+		assert_eq!(0xC000, m.map(0x8000));
+	}
+
+	#[test]
+	fn mirroring_type() {
+		let mut m = mapper();
+		assert!(!m.has_mirroring_type());
+		m.store(0x9000, 0x10);
+		assert!(m.has_mirroring_type());
+		match m.mirroring_type() {
+			Mirrorings::OneScreenHigh => {},
+			_ => panic!("expected one-screen high")
+		};
+	}
+
+	#[test]
+	fn fixed_last_bank() {
+		let mut m = mapper();
+		m.store(0xC000, 0x17);
+		assert_eq!(0x1C000, m.map(0x8000));
+		assert_eq!(0x1C000, m.map(0xC000));
+		m.store(0xFFFF, 0x00);
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x1C000, m.map(0xC000));
+		// Mirroring writes don't switch banks
+		m.store(0x9000, 0x10);
+		assert_eq!(0x0000, m.map(0x8000));
+	}
+}
+
+#[cfg(test)]
+mod tests_cprom_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = CPRomMapper::new();
+		m.store(0x8000, 0x03);
+		assert_eq!(0x0000, m.map(0x8000));
+		assert_eq!(0x7FFF, m.map(0xFFFF));
+	}
+
+	#[test]
+	fn map_for_chr_rom() {
+		let mut m = CPRomMapper::new();
+		m.store(0x8000, 0x02);
+		assert_eq!(0x0FFF, m.map_for_chr_rom(0x0FFF));
+		assert_eq!(0x2000, m.map_for_chr_rom(0x1000));
+		assert_eq!(0x2FFF, m.map_for_chr_rom(0x1FFF));
+	}
+
+	#[test]
+	fn fixed_lower_chr_bank() {
+		let mut m = CPRomMapper::new();
+		m.store(0x8000, 0x03);
+		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
+		assert_eq!(0x0FFF, m.map_for_chr_rom(0x0FFF));
+		assert_eq!(0x3000, m.map_for_chr_rom(0x1000));
+		// Bits 7-2 are unused
+		m.store(0x8000, 0x04);
+		assert_eq!(0x0000, m.map_for_chr_rom(0x1000));
+	}
+}
+
+#[cfg(test)]
+mod tests_d1038_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = D1038Mapper::new();
+		m.store(0x8000 | (2 << 5) | 0x5, 0);
+		assert_eq!(0x10000, m.map(0x8000));
+		assert_eq!(0x17FFF, m.map(0xFFFF));
+		assert_eq!(0xA000, m.map_for_chr_rom(0x0000));
+		m.store(0x8000 | 0x80 | (5 << 4), 0);
+		assert_eq!(0x14000, m.map(0x8000));
+		assert_eq!(0x14000, m.map(0xC000));
+	}
+
+	#[test]
+	fn solder_pad() {
+		let mut m = D1038Mapper::new();
+		m.set_solder_pad(2);
+		match m.map_for_cpu(0x8000) {
+			CpuMapping::ProgramRom(_) => {},
+			_ => panic!("expected program rom")
+		};
+		m.store(0x8100, 0);
+		match m.map_for_cpu(0x8000) {
+			CpuMapping::Value(2) => {},
+			_ => panic!("expected solder pad value")
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests_multicart225_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = Multicart225Mapper::new();
+		// 32KB mode ignores the lowest bank bit
+		m.store(0x8000 | (3 << 6) | 0x4, 0);
+		assert_eq!(0x8000, m.map(0x8000));
+		assert_eq!(0xFFFF, m.map(0xFFFF));
+		assert_eq!(0x8000, m.map_for_chr_rom(0x0000));
+		// 16KB mode with the high bit
+		m.store(0xD000 | (3 << 6) | 0x4, 0);
+		assert_eq!(0x10C000, m.map(0x8000));
+		assert_eq!(0x10C000, m.map(0xC000));
+		assert_eq!(0x88000, m.map_for_chr_rom(0x0000));
+	}
+
+	#[test]
+	fn ram() {
+		let mut m = Multicart225Mapper::new();
+		assert_eq!(None, m.load_register(0x5000));
+		assert!(m.store_register(0x5801, 0xFF));
+		assert_eq!(Some(0xF), m.load_register(0x5805));
+	}
+}
+
+#[cfg(test)]
+mod tests_multicart226_mapper {
+	use super::*;
+
+	#[test]
+	fn map() {
+		let mut m = Multicart226Mapper::new();
+		m.store(0x8000, 0x23);
+		m.store(0x8001, 0x01);
+		assert_eq!(0x10C000, m.map(0x8000));
+		assert_eq!(0x10C000, m.map(0xC000));
+		m.store(0x8000, 0x83);
+		assert_eq!(0x188000, m.map(0x8000));
+		assert_eq!(0x18FFFF, m.map(0xFFFF));
+	}
 }
 
+#[cfg(test)]
+mod tests_multicart227_mapper {
+	use super::*;
 
-struct SunsoftMapper {
-    command_register: Register<u8>,
-    parameter_register: Register<u8>,
-    chr_banks: [Register<u8>; 8],
-    prg_banks: [Register<u8>; 4],
-    irq_enabled: bool,
-    irq_counter_enabled: bool,
-    irq_counter: u16,
-    mirroring: u8,
-    prg_bank_mask: u32,
-    chr_bank_mask: u32,
+	#[test]
+	fn map() {
+		let mut m = Multicart227Mapper::new();
+		// UNROM-like, the last bank fixed to the first one of the block
+		m.store(0x8000 | (0x9 << 2), 0);
+		assert_eq!(0x24000, m.map(0x8000));
+		assert_eq!(0x20000, m.map(0xC000));
+		// With L, the last one of the block
+		m.store(0x8200 | (0x9 << 2), 0);
+		assert_eq!(0x3C000, m.map(0xC000));
+		// NROM-256
+		m.store(0x8081 | (0x9 << 2), 0);
+		assert_eq!(0x20000, m.map(0x8000));
+		assert_eq!(0x24000, m.map(0xC000));
+		// NROM-128
+		m.store(0x8080 | (0x9 << 2), 0);
+		assert_eq!(0x24000, m.map(0x8000));
+		assert_eq!(0x24000, m.map(0xC000));
+	}
 }
 
-impl SunsoftMapper {
-    fn new(header: &RomHeader) -> Self {
-        let prg_bank_num = header.prg_rom_bank_num() as u32 * 2; // 8KB banks
-        let chr_bank_num = header.chr_rom_bank_num() as u32 * 8; // 1KB banks
+#[cfg(test)]
+mod tests_action52_mapper {
+	use super::*;
 
-        let mut m = SunsoftMapper {
-            command_register: Register::<u8>::new(),
-            parameter_register: Register::<u8>::new(),
-            chr_banks: [
-                Register::<u8>::new(), Register::<u8>::new(),
-                Register::<u8>::new(), Register::<u8>::new(),
-                Register::<u8>::new(), Register::<u8>::new(),
-                Register::<u8>::new(), Register::<u8>::new(),
-            ],
-            prg_banks: [
-                Register::<u8>::new(), Register::<u8>::new(),
-                Register::<u8>::new(), Register::<u8>::new(),
-            ],
-            irq_enabled: false,
-            irq_counter_enabled: false,
-            irq_counter: 0,
-            mirroring: 0,
-            prg_bank_mask: prg_bank_num.saturating_sub(1),
-            chr_bank_mask: chr_bank_num.saturating_sub(1),
-        };
-        // Defaults
-        m.prg_banks[3].store((prg_bank_num - 1) as u8); // Fixed last bank
-        m
-    }
+	#[test]
+	fn map() {
+		let mut m = Action52Mapper::new();
+		// The chip 3 follows the chip 1
+		m.store(0x8000 | (3 << 11) | 0x20 | (1 << 6) | 0x2, 0x3);
+		assert_eq!(0x104000, m.map(0x8000));
+		assert_eq!(0x104000, m.map(0xC000));
+		assert_eq!(0x16000, m.map_for_chr_rom(0x0000));
+	}
+
+	#[test]
+	fn ram() {
+		let mut m = Action52Mapper::new();
+		assert!(m.store_register(0x4022, 0x35));
+		assert_eq!(Some(0x5), m.load_register(0x5FFE));
+		assert!(!m.store_register(0x6000, 0));
+	}
 }
 
-impl Mapper for SunsoftMapper {
-    fn map(&self, address: u32) -> u32 {
-        let bank_idx = match address {
-            0x6000..=0x7FFF => 0,
-            0x8000..=0x9FFF => 1,
-            0xA000..=0xBFFF => 2,
-            0xC000..=0xDFFF => 3,
-            0xE000..=0xFFFF => return ((self.prg_bank_mask) * 0x2000) + (address & 0x1FFF),
-            _ => 0,
-        };
-        
-        let bank = self.prg_banks[bank_idx].load() as u32 & self.prg_bank_mask;
-        bank * 0x2000 + (address & 0x1FFF)
-    }
+#[cfg(test)]
+mod tests_fds_mapper {
+	use super::*;
+	use fds::FDS_SIDE_SIZE;
 
-    fn map_for_chr_rom(&self, address: u32) -> u32 {
-        let bank_idx = (address / 0x400) as usize;
-        if bank_idx < 8 {
-            let bank = self.chr_banks[bank_idx].load() as u32 & self.chr_bank_mask;
-            bank * 0x400 + (address & 0x3FF)
-        } else {
-            address
-        }
-    }
+	fn disk() -> FdsDisk {
+		let mut side = vec![0x01];
+		side.extend_from_slice(b"*NINTENDO-HVC*");
+		side.resize(56, 0x11);
+		side.extend_from_slice(&[0x02, 0x00]);
+		side.resize(FDS_SIDE_SIZE, 0);
+		let mut image = side.clone();
+		image.extend_from_slice(&side);
+		FdsDisk::new(&image).unwrap()
+	}
 
-    fn store(&mut self, address: u32, value: u8) {
-        match address {
-            0x8000..=0x9FFF => {
-                self.command_register.store(value & 0x0F);
-            }
-            0xA000..=0xBFFF => {
-                self.parameter_register.store(value);
-                let cmd = self.command_register.load();
-                match cmd {
-                    0..=7 => {
-                        self.chr_banks[cmd as usize].store(value);
-                    }
-                    8..=11 => {
-                        self.prg_banks[(cmd - 8) as usize].store(value);
-                    }
-                    12 => {
-                        self.mirroring = value & 0x03;
-                    }
-                    13 => {
-                        self.irq_enabled = (value & 0x80) != 0;
-                        self.irq_counter_enabled = (value & 0x01) != 0;
-                    }
-                    14 => {
-                        self.irq_counter = (self.irq_counter & 0xFF00) | (value as u16);
-                    }
-                    15 => {
-                        self.irq_counter = (self.irq_counter & 0x00FF) | ((value as u16) << 8);
-                    }
-                    _ => {}
-                }
-            }
-            _ => {}
-        }
-    }
+	// The first side has a file filling it up, longer than the second side
+	fn uneven_disk() -> FdsDisk {
+		let mut side = vec![0x01];
+		side.extend_from_slice(b"*NINTENDO-HVC*");
+		side.resize(56, 0x11);
+		side.extend_from_slice(&[0x02, 0x01]);
+		let mut header = vec![0x03; 16];
+		header[13] = 0x91;
+		header[14] = 0xFF;
+		side.extend_from_slice(&header);
+		side.push(0x04);
+		side.resize(FDS_SIDE_SIZE, 0x22);
+		let mut image = side;
+		image.extend_from_slice(&disk().to_image()[..FDS_SIDE_SIZE]);
+		FdsDisk::new(&image).unwrap()
+	}
 
-    fn has_mirroring_type(&self) -> bool {
-        true
-    }
+	#[test]
+	fn timer_irq() {
+		let mut m = FdsMapper::new(disk());
+		m.store_register(0x4023, 0x01);
+		m.store_register(0x4020, 0x02);
+		m.store_register(0x4021, 0x00);
+		m.store_register(0x4022, 0x03);
+		for _ in 0..2 {
+			m.step();
+			assert!(!m.irq_pending());
+		}
+		m.step();
+		assert!(m.irq_pending());
+		assert_eq!(Some(0x01), m.load_register(0x4030).map(|v| v & 0x01));
+		assert!(!m.irq_pending());
+		// Repeats
+		for _ in 0..3 {
+			m.step();
+		}
+		assert!(m.irq_pending());
+	}
 
-    fn mirroring_type(&self) -> Mirrorings {
-        match self.mirroring {
-            0 => Mirrorings::Vertical,
-            1 => Mirrorings::Horizontal,
-            2 => Mirrorings::OneScreenLow, // OneScreen 0
-            3 => Mirrorings::OneScreenHigh, // OneScreen 1
-            _ => Mirrorings::Vertical,
-        }
-    }
+	#[test]
+	fn read_disk() {
+		let mut m = FdsMapper::new(disk());
+		m.store_register(0x4023, 0x01);
+		assert_eq!(Some(0x42), m.load_register(0x4032));
+		// Motor on, read mode, transfer ready and IRQ
+		m.store_register(0x4025, 0xC5);
+		let mut bytes = vec![];
+		for _ in 0..1000000 {
+			m.step();
+			if m.irq_pending() {
+				bytes.push(m.load_register(0x4031).unwrap());
+				if bytes.len() == 16 {
+					break;
+				}
+			}
+		}
+		assert_eq!(Some(0x40), m.load_register(0x4032));
+		// The start mark 0x80 doesn't fire IRQ
+		assert_eq!(0x01, bytes[0]);
+		assert_eq!(b"*NINTENDO-HVC*", &bytes[1..15]);
+	}
 
-    fn drive_irq_counter(&mut self) -> bool {
-        if self.irq_counter_enabled {
-            self.irq_counter = self.irq_counter.wrapping_sub(1);
-            if self.irq_counter == 0xFFFF {
-                return self.irq_enabled;
-            }
-        }
-        false
-    }
+	#[test]
+	fn insert_disk() {
+		let mut m = FdsMapper::new(disk());
+		m.store_register(0x4023, 0x01);
+		assert_eq!(2, m.disk_side_count());
+		assert_eq!(Some(0), m.disk_side());
+		m.insert_disk(None);
+		assert_eq!(Some(0x47), m.load_register(0x4032));
+		m.insert_disk(Some(1));
+		assert_eq!(Some(1), m.disk_side());
+		m.insert_disk(Some(2));
+		assert_eq!(None, m.disk_side());
+	}
 
-    fn save_state(&self) -> MapperState {
-        MapperState::Sunsoft {
-            command_register: self.command_register.get_data(),
-            parameter_register: self.parameter_register.get_data(),
-            chr_banks: [
-                self.chr_banks[0].get_data(), self.chr_banks[1].get_data(),
-                self.chr_banks[2].get_data(), self.chr_banks[3].get_data(),
-                self.chr_banks[4].get_data(), self.chr_banks[5].get_data(),
-                self.chr_banks[6].get_data(), self.chr_banks[7].get_data(),
-            ],
-            prg_banks: [
-                self.prg_banks[0].get_data(), self.prg_banks[1].get_data(),
-                self.prg_banks[2].get_data(), self.prg_banks[3].get_data(),
-            ],
-            irq_enabled: self.irq_enabled,
-            irq_counter_enabled: self.irq_counter_enabled,
-            irq_counter: self.irq_counter,
-            mirroring: self.mirroring,
-        }
-    }
+	#[test]
+	fn switch_sides_while_reading() {
+		let mut m = FdsMapper::new(uneven_disk());
+		assert!(m.disk.side(0).len() > m.disk.side(1).len());
+		m.store_register(0x4023, 0x01);
+		// Motor on, read mode, transfer ready and IRQ
+		m.store_register(0x4025, 0xC5);
+		for _ in 0..(FDS_MOTOR_START_DELAY + FDS_BYTE_DELAY * 2) {
+			m.step();
+		}
+		// The head is past the end of the second side
+		m.disk_position = m.disk.side(1).len() + 100;
+		m.insert_disk(Some(1));
+		let mut byte = None;
+		for _ in 0..1000000 {
+			m.step();
+			if m.irq_pending() {
+				byte = m.load_register(0x4031);
+				break;
+			}
+		}
+		// Reads the disk info block from the start of the second side
+		assert_eq!(Some(0x01), byte);
+	}
 
-    fn load_state(&mut self, state: &MapperState) {
-        if let MapperState::Sunsoft {
-            command_register,
-            parameter_register,
-            chr_banks,
-            prg_banks,
-            irq_enabled,
-            irq_counter_enabled,
-            irq_counter,
-            mirroring,
-        } = state {
-            self.command_register.set_data(*command_register);
-            self.parameter_register.set_data(*parameter_register);
-            for (i, val) in chr_banks.iter().enumerate() {
-                self.chr_banks[i].set_data(*val);
-            }
-            for (i, val) in prg_banks.iter().enumerate() {
-                self.prg_banks[i].set_data(*val);
-            }
-            self.irq_enabled = *irq_enabled;
-            self.irq_counter_enabled = *irq_counter_enabled;
-            self.irq_counter = *irq_counter;
-            self.mirroring = *mirroring;
-        }
-    }
+	#[test]
+	fn write_disk() {
+		let mut m = FdsMapper::new(disk());
+		m.store_register(0x4023, 0x01);
+		// Motor on, write mode, transfer ready
+		m.store_register(0x4024, 0x33);
+		m.store_register(0x4025, 0x41);
+		assert!(!m.is_disk_dirty());
+		for _ in 0..(FDS_MOTOR_START_DELAY + FDS_BYTE_DELAY * 2) {
+			m.step();
+		}
+		assert!(m.is_disk_dirty());
+		assert_eq!(0x33, m.disk.side(0)[0]);
+		m.mark_disk_flushed();
+		assert!(!m.is_disk_dirty());
+	}
+
+	#[test]
+	fn audio_registers() {
+		let mut m = FdsMapper::new(disk());
+		m.store_register(0x4080, 0x9F);
+		assert_eq!(None, m.load_register(0x4090));
+		m.store_register(0x4023, 0x02);
+		m.store_register(0x4080, 0x9F);
+		assert_eq!(Some(0x5F), m.load_register(0x4090));
+	}
 }
 
 #[cfg(test)]
-mod tests_nrom_mapper {
+mod tests_mmc3_mapper {
 	use super::*;
 
+	fn mapper(variant: MMC3Variant) -> MMC3Mapper {
+		MMC3Mapper {
+			variant: variant,
+			irq_revision: MMC3IrqRevision::RevB,
+			prg_ram_protect: false,
+			program_bank_num: 8,
+			character_bank_num: 16,
+			register0: Register::<u8>::new(),
+			register1: Register::<u8>::new(),
+			register2: Register::<u8>::new(),
+			register3: Register::<u8>::new(),
+			register4: Register::<u8>::new(),
+			register5: Register::<u8>::new(),
+			register6: Register::<u8>::new(),
+			register7: Register::<u8>::new(),
+			program_register0: Register::<u8>::new(),
+			program_register1: Register::<u8>::new(),
+			character_register0: Register::<u8>::new(),
+			character_register1: Register::<u8>::new(),
+			character_register2: Register::<u8>::new(),
+			character_register3: Register::<u8>::new(),
+			character_register4: Register::<u8>::new(),
+			character_register5: Register::<u8>::new(),
+			irq_counter: 0,
+			irq_counter_reload: false,
+			irq_enabled: true,
+			irq_pending: false
+		}
+	}
+
+	fn store_bank(m: &mut MMC3Mapper, register: u8, value: u8) {
+		m.store(0x8000, register);
+		m.store(0x8001, value);
+	}
+
 	#[test]
-	fn initialize() {
-		NRomMapper{program_bank_num: 1};
+	fn map() {
+		let mut m = mapper(MMC3Variant::MMC3);
+		store_bank(&mut m, 6, 0x02);
+		store_bank(&mut m, 7, 0x03);
+		assert_eq!(0x04000, m.map(0x8000));
+		assert_eq!(0x06000, m.map(0xA000));
+		assert_eq!(0x1C000, m.map(0xC000));
+		assert_eq!(0x1E000, m.map(0xE000));
+		store_bank(&mut m, 2, 0x05);
+		assert_eq!(0x1400, m.map_for_chr_rom(0x1000));
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::ProgramRam(0) => {},
+			_ => panic!("expected PRG-RAM")
+		};
+		assert!(m.name_table_source(0).is_none());
 	}
 
 	#[test]
-	fn map_with_program_bank_num_1() {
-		let m = NRomMapper{program_bank_num: 1};
-		assert_eq!(0x0000, m.map(0x8000));
-		assert_eq!(0x3FFF, m.map(0xBFFF));
-		assert_eq!(0x0000, m.map(0xC000));
-		assert_eq!(0x3FFF, m.map(0xFFFF));
+	fn mmc6_prg_ram() {
+		let mut m = mapper(MMC3Variant::MMC6);
+		match m.map_for_cpu(0x7000) {
+			CpuMapping::Unmapped => {},
+			_ => panic!("PRG-RAM is disabled")
+		};
+		// Enable PRG-RAM, the lower half is readable and writable
+		m.store(0x8000, 0x20);
+		m.store(0xA001, 0x30);
+		match m.map_for_cpu(0x7C01) {
+			CpuMapping::ProgramRam(0x001) => {},
+			_ => panic!("expected the lower half")
+		};
+		assert!(m.prg_ram_writable(0x7001));
+		match m.map_for_cpu(0x7201) {
+			CpuMapping::Unmapped => {},
+			_ => panic!("the upper half is disabled")
+		};
+		assert!(!m.prg_ram_writable(0x7201));
+		// The upper half is readable only
+		m.store(0xA001, 0xB0);
+		match m.map_for_cpu(0x7201) {
+			CpuMapping::ProgramRam(0x201) => {},
+			_ => panic!("expected the upper half")
+		};
+		assert!(!m.prg_ram_writable(0x7201));
+		match m.map_for_cpu(0x6000) {
+			CpuMapping::Unmapped => {},
+			_ => panic!("MMC6 has no PRG-RAM in 0x6000 - 0x6FFF")
+		};
 	}
 
 	#[test]
-	fn map_with_program_bank_num_2() {
-		let m = NRomMapper{program_bank_num: 2};
-		assert_eq!(0x0000, m.map(0x8000));
-		assert_eq!(0x3FFF, m.map(0xBFFF));
-		assert_eq!(0x4000, m.map(0xC000));
-		assert_eq!(0x7FFF, m.map(0xFFFF));
+	fn txsrom_name_table_source() {
+		let mut m = mapper(MMC3Variant::TxSRom);
+		store_bank(&mut m, 0, 0x80);
+		store_bank(&mut m, 1, 0x00);
+		// The mirroring register is ignored
+		m.store(0xA000, 0x01);
+		let pages: Vec<u8> = (0..4).map(|slot| match m.name_table_source(slot) {
+			Some(NameTableSource::Vram(page)) => page,
+			_ => panic!("expected VRAM")
+		}).collect();
+		assert_eq!(vec![1, 1, 0, 0], pages);
+		// CHR A12 inversion uses the 1KB banks
+		store_bank(&mut m, 0x82, 0x00);
+		store_bank(&mut m, 0x83, 0x80);
+		store_bank(&mut m, 0x84, 0x80);
+		store_bank(&mut m, 0x85, 0x00);
+		let pages: Vec<u8> = (0..4).map(|slot| match m.name_table_source(slot) {
+			Some(NameTableSource::Vram(page)) => page,
+			_ => panic!("expected VRAM")
+		}).collect();
+		assert_eq!(vec![0, 1, 1, 0], pages);
 	}
 
 	#[test]
-	fn map_for_chr_rom() {
-		let m = NRomMapper{program_bank_num: 1};
-		assert_eq!(0x0000, m.map_for_chr_rom(0x0000));
-		assert_eq!(0x1FFF, m.map_for_chr_rom(0x1FFF));
+	fn tqrom_chr_ram() {
+		let mut m = mapper(MMC3Variant::TQRom);
+		store_bank(&mut m, 2, 0x05);
+		store_bank(&mut m, 3, 0x41);
+		assert!(!m.is_chr_ram(0x1000));
+		assert_eq!(0x1400, m.map_for_chr_rom(0x1000));
+		assert!(m.is_chr_ram(0x1400));
+		assert_eq!(0x0400, m.map_for_chr_rom(0x1400));
+		assert!(!mapper(MMC3Variant::MMC3).is_chr_ram(0x1400));
+	}
+
+	#[test]
+	fn irq() {
+		let mut m = mapper(MMC3Variant::MMC3);
+		m.store(0xC000, 2);
+		m.store(0xC001, 0);
+		m.store(0xE001, 0);
+		// Reload, 2 -> 1, 1 -> 0
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(!m.irq_pending());
+		// Short pulses are filtered out
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES - 1);
+		assert!(!m.irq_pending());
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(m.irq_pending());
+		// Kept until 0xE000 write acknowledges
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(m.irq_pending());
+		m.store(0xE000, 0);
+		assert!(!m.irq_pending());
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(!m.irq_pending());
+	}
+
+	#[test]
+	fn irq_revisions() {
+		// Reload value 0 fires IRQ every clock on Rev B
+		let mut m = mapper(MMC3Variant::MMC3);
+		m.store(0xC000, 0);
+		m.store(0xC001, 0);
+		m.store(0xE001, 0);
+		for _i in 0..3 {
+			m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+			assert!(m.irq_pending());
+			m.store(0xE000, 0);
+			m.store(0xE001, 0);
+		}
+
+		// Only once after 0xC001 write on Rev A
+		let mut m = mapper(MMC3Variant::MMC3);
+		m.irq_revision = MMC3IrqRevision::RevA;
+		m.store(0xC000, 0);
+		m.store(0xC001, 0);
+		m.store(0xE001, 0);
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(m.irq_pending());
+		m.store(0xE000, 0);
+		m.store(0xE001, 0);
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(!m.irq_pending());
+
+		// Decrementing to 0 fires IRQ on both
+		m.store(0xC000, 1);
+		m.store(0xC001, 0);
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(!m.irq_pending());
+		m.notify_ppu_a12_rise(MMC3_A12_FILTER_CYCLES);
+		assert!(m.irq_pending());
 	}
 }
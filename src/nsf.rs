@@ -0,0 +1,360 @@
+const NSF_HEADER_SIZE: usize = 0x80;
+const NSF_SIGNATURE: &[u8] = b"NESM\x1A";
+const NSFE_SIGNATURE: &[u8] = b"NSFE";
+
+// Play rates in microseconds the header should have
+const DEFAULT_NTSC_SPEED: u16 = 16639;
+const DEFAULT_PAL_SPEED: u16 = 19997;
+
+// Expansion audio chip flags
+pub const NSF_VRC6: u8 = 0x01;
+pub const NSF_VRC7: u8 = 0x02;
+pub const NSF_FDS: u8 = 0x04;
+pub const NSF_MMC5: u8 = 0x08;
+pub const NSF_N163: u8 = 0x10;
+pub const NSF_SUNSOFT_5B: u8 = 0x20;
+
+/// Whether the data is NSF or NSFe
+pub fn is_nsf(data: &[u8]) -> bool {
+	data.starts_with(NSF_SIGNATURE) || data.starts_with(NSFE_SIGNATURE)
+}
+
+/// NSF metadata. Track names and lengths are only in NSFe.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NsfMetadata {
+	pub title: String,
+	pub artist: String,
+	pub copyright: String,
+	pub track_count: u8,
+	// 0-based
+	pub starting_track: u8,
+	// Empty if the track has no name
+	pub track_names: Vec<String>,
+	// In milliseconds. None if the track has no length.
+	pub track_lengths: Vec<Option<u32>>
+}
+
+/**
+ * NSF and NSFe music rip.
+ * Refer to https://wiki.nesdev.com/w/index.php/NSF
+ * and https://wiki.nesdev.com/w/index.php/NSFe
+ */
+pub struct Nsf {
+	pub metadata: NsfMetadata,
+	pub load_address: u16,
+	pub init_address: u16,
+	pub play_address: u16,
+	// Play rates in microseconds
+	pub ntsc_speed: u16,
+	pub pal_speed: u16,
+	// Whether the tune only supports PAL
+	pub pal_only: bool,
+	pub expansion: u8,
+	// Initial 4KB banks of 0x6000 - 0xFFFF
+	pub banks: [u8; 10],
+	// Data padded so that 4KB banks start at 0x?000
+	pub data: Vec<u8>
+}
+
+impl Nsf {
+	/// Parses NSF or NSFe. Err tells why if it's broken.
+	pub fn new(data: &[u8]) -> Result<Self, &'static str> {
+		match data.starts_with(NSFE_SIGNATURE) {
+			true => parse_nsfe(data),
+			false => parse_nsf(data)
+		}
+	}
+
+	fn build(metadata: NsfMetadata, addresses: [u16; 3], speeds: [u16; 2],
+		region: u8, expansion: u8, banks: [u8; 8], data: &[u8]) -> Result<Self, &'static str> {
+		let load_address = addresses[0];
+		if load_address < 0x6000 || (load_address < 0x8000 && (expansion & NSF_FDS) == 0) {
+			return Err("load address is out of range");
+		}
+
+		let bankswitched = banks.iter().any(|&bank| bank != 0);
+		let mut initial_banks = [0; 10];
+		initial_banks[2..].copy_from_slice(&banks);
+		match bankswitched {
+			// FDS tunes start with the banks of 0xE000 - 0xFFFF in 0x6000 - 0x7FFF too
+			true => {
+				initial_banks[0] = banks[6];
+				initial_banks[1] = banks[7];
+			},
+			// Sequential banks from the load address. 0xFF for the slots
+			// below it, which no data is in.
+			false => {
+				let first_slot = (load_address >> 12) as usize - 6;
+				for (slot, bank) in initial_banks.iter_mut().enumerate() {
+					*bank = match slot >= first_slot {
+						true => (slot - first_slot) as u8,
+						false => 0xFF
+					};
+				}
+			}
+		};
+
+		let mut padded_data = vec![0; (load_address & 0xFFF) as usize];
+		padded_data.extend_from_slice(data);
+
+		Ok(Nsf {
+			metadata: metadata,
+			load_address: load_address,
+			init_address: addresses[1],
+			play_address: addresses[2],
+			ntsc_speed: match speeds[0] {
+				0 => DEFAULT_NTSC_SPEED,
+				speed => speed
+			},
+			pal_speed: match speeds[1] {
+				0 => DEFAULT_PAL_SPEED,
+				speed => speed
+			},
+			pal_only: (region & 0x3) == 0x1,
+			expansion: expansion,
+			banks: initial_banks,
+			data: padded_data
+		})
+	}
+}
+
+fn parse_nsf(data: &[u8]) -> Result<Nsf, &'static str> {
+	if data.len() < NSF_HEADER_SIZE {
+		return Err("header is truncated");
+	}
+
+	let track_count = std::cmp::max(data[0x06], 1);
+	let metadata = NsfMetadata {
+		title: parse_string(&data[0x0E..0x2E]),
+		artist: parse_string(&data[0x2E..0x4E]),
+		copyright: parse_string(&data[0x4E..0x6E]),
+		track_count: track_count,
+		starting_track: std::cmp::min(data[0x07].saturating_sub(1), track_count - 1),
+		track_names: vec![],
+		track_lengths: vec![]
+	};
+
+	let mut banks = [0; 8];
+	banks.copy_from_slice(&data[0x70..0x78]);
+
+	// NSF2 may have metadata after the data
+	let data_length = match data[0x05] >= 2 {
+		true => data[0x7D] as usize | ((data[0x7E] as usize) << 8) | ((data[0x7F] as usize) << 16),
+		false => 0
+	};
+	let body = match data_length {
+		0 => &data[NSF_HEADER_SIZE..],
+		length => &data[NSF_HEADER_SIZE..std::cmp::min(NSF_HEADER_SIZE + length, data.len())]
+	};
+
+	Nsf::build(metadata, [read_u16(data, 0x08), read_u16(data, 0x0A), read_u16(data, 0x0C)],
+		[read_u16(data, 0x6E), read_u16(data, 0x78)], data[0x7A], data[0x7B], banks, body)
+}
+
+// NSFe is a sequence of chunks: length (4 bytes), ID (4 bytes), data
+fn parse_nsfe(data: &[u8]) -> Result<Nsf, &'static str> {
+	let mut metadata = NsfMetadata {
+		track_count: 1,
+		..NsfMetadata::default()
+	};
+	let mut info = None;
+	let mut body: &[u8] = &[];
+	let mut banks = [0; 8];
+	let mut speeds = [0; 2];
+
+	let mut position = NSFE_SIGNATURE.len();
+	loop {
+		if position + 8 > data.len() {
+			return Err("chunk is truncated");
+		}
+		let length = read_u32(data, position) as usize;
+		let mut id = [0; 4];
+		id.copy_from_slice(&data[position + 4..position + 8]);
+		let start = position + 8;
+		if length > data.len() - start {
+			return Err("chunk is truncated");
+		}
+		let chunk = &data[start..start + length];
+		position = start + length;
+
+		match &id {
+			b"INFO" => {
+				if chunk.len() < 8 {
+					return Err("INFO chunk is truncated");
+				}
+				if chunk.len() > 8 {
+					metadata.track_count = std::cmp::max(chunk[8], 1);
+				}
+				if chunk.len() > 9 {
+					metadata.starting_track = std::cmp::min(chunk[9], metadata.track_count - 1);
+				}
+				info = Some(chunk);
+			},
+			b"DATA" => body = chunk,
+			b"BANK" => {
+				let length = std::cmp::min(chunk.len(), 8);
+				banks[..length].copy_from_slice(&chunk[..length]);
+			},
+			b"RATE" => {
+				if chunk.len() >= 2 {
+					speeds[0] = read_u16(chunk, 0);
+				}
+				if chunk.len() >= 4 {
+					speeds[1] = read_u16(chunk, 2);
+				}
+			},
+			b"auth" => {
+				let mut strings = chunk.split(|&b| b == 0).map(parse_string);
+				metadata.title = strings.next().unwrap_or_default();
+				metadata.artist = strings.next().unwrap_or_default();
+				metadata.copyright = strings.next().unwrap_or_default();
+			},
+			b"tlbl" => {
+				let mut names: Vec<&[u8]> = chunk.split(|&b| b == 0).collect();
+				// Drops the empty piece after the last terminator
+				if names.last().map_or(false, |name| name.is_empty()) {
+					names.pop();
+				}
+				metadata.track_names = names.into_iter().map(parse_string).collect();
+			},
+			b"time" => {
+				metadata.track_lengths = chunk.chunks(4).filter(|c| c.len() == 4).map(|c| {
+					match read_u32(c, 0) as i32 {
+						length if length >= 0 => Some(length as u32),
+						_ => None
+					}
+				}).collect();
+			},
+			b"NEND" => break,
+			// Chunks starting with an uppercase letter are mandatory
+			_ if id[0].is_ascii_uppercase() => return Err("unsupported mandatory chunk"),
+			_ => {}
+		};
+	}
+
+	let info = match info {
+		Some(info) => info,
+		None => return Err("INFO chunk is missing")
+	};
+
+	Nsf::build(metadata, [read_u16(info, 0), read_u16(info, 2), read_u16(info, 4)],
+		speeds, info[6], info[7], banks, body)
+}
+
+// NUL terminated or padded string
+fn parse_string(data: &[u8]) -> String {
+	let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+	String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+	data[offset] as u16 | ((data[offset + 1] as u16) << 8)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+	(0..4).fold(0, |value, i| value | ((data[offset + i] as u32) << (i * 8)))
+}
+
+#[cfg(test)]
+mod tests_nsf {
+	use super::*;
+
+	fn nsf_data(load_address: u16, banks: [u8; 8]) -> Vec<u8> {
+		let mut data = vec![0; NSF_HEADER_SIZE];
+		data[..5].copy_from_slice(NSF_SIGNATURE);
+		data[0x05] = 1;
+		data[0x06] = 3;
+		data[0x07] = 2;
+		data[0x08] = load_address as u8;
+		data[0x09] = (load_address >> 8) as u8;
+		data[0x0A..0x0E].copy_from_slice(&[0x00, 0x90, 0x03, 0x90]);
+		data[0x0E..0x13].copy_from_slice(b"Title");
+		data[0x2E..0x34].copy_from_slice(b"Artist");
+		data[0x6E..0x70].copy_from_slice(&[0x1A, 0x41]);
+		data[0x70..0x78].copy_from_slice(&banks);
+		data.extend_from_slice(&[0xEA; 0x10]);
+		data
+	}
+
+	fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+		let mut data = vec![body.len() as u8, 0, 0, 0];
+		data.extend_from_slice(id);
+		data.extend_from_slice(body);
+		data
+	}
+
+	#[test]
+	fn parse_nsf() {
+		let data = nsf_data(0x8123, [0; 8]);
+		assert!(is_nsf(&data));
+		let nsf = Nsf::new(&data).unwrap();
+		assert_eq!("Title", nsf.metadata.title);
+		assert_eq!("Artist", nsf.metadata.artist);
+		assert_eq!("", nsf.metadata.copyright);
+		assert_eq!(3, nsf.metadata.track_count);
+		assert_eq!(1, nsf.metadata.starting_track);
+		assert_eq!(0x9000, nsf.init_address);
+		assert_eq!(0x9003, nsf.play_address);
+		assert_eq!(16666, nsf.ntsc_speed);
+		assert_eq!(DEFAULT_PAL_SPEED, nsf.pal_speed);
+		assert_eq!([0xFF, 0xFF, 0, 1, 2, 3, 4, 5, 6, 7], nsf.banks);
+		assert_eq!(0x123 + 0x10, nsf.data.len());
+		assert_eq!(0xEA, nsf.data[0x123]);
+	}
+
+	#[test]
+	fn bankswitched_nsf() {
+		let nsf = Nsf::new(&nsf_data(0x8000, [0, 1, 2, 3, 4, 5, 6, 7])).unwrap();
+		assert_eq!([6, 7, 0, 1, 2, 3, 4, 5, 6, 7], nsf.banks);
+		assert_eq!(Err("load address is out of range"), Nsf::new(&nsf_data(0x6000, [0; 8])).map(|_| ()));
+		assert_eq!(Err("header is truncated"), Nsf::new(NSF_SIGNATURE).map(|_| ()));
+	}
+
+	#[test]
+	fn parse_nsfe() {
+		let mut data = NSFE_SIGNATURE.to_vec();
+		data.extend_from_slice(&chunk(b"INFO", &[0x00, 0x80, 0x00, 0x90, 0x03, 0x90, 0x00, NSF_VRC6, 2, 1]));
+		data.extend_from_slice(&chunk(b"DATA", &[0xEA; 4]));
+		data.extend_from_slice(&chunk(b"auth", b"Title\0Artist\0Copyright\0Ripper\0"));
+		data.extend_from_slice(&chunk(b"tlbl", b"One\0Two\0"));
+		data.extend_from_slice(&chunk(b"time", &[0xE8, 0x03, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF]));
+		data.extend_from_slice(&chunk(b"text", b"ignored"));
+		data.extend_from_slice(&chunk(b"NEND", &[]));
+		assert!(is_nsf(&data));
+		let nsf = Nsf::new(&data).unwrap();
+		assert_eq!(NsfMetadata {
+			title: "Title".to_string(),
+			artist: "Artist".to_string(),
+			copyright: "Copyright".to_string(),
+			track_count: 2,
+			starting_track: 1,
+			track_names: vec!["One".to_string(), "Two".to_string()],
+			track_lengths: vec![Some(1000), None]
+		}, nsf.metadata);
+		assert_eq!(NSF_VRC6, nsf.expansion);
+		assert_eq!(DEFAULT_NTSC_SPEED, nsf.ntsc_speed);
+		assert_eq!(vec![0xEA; 4], nsf.data);
+	}
+
+	#[test]
+	fn broken_nsfe() {
+		let mut data = NSFE_SIGNATURE.to_vec();
+		data.extend_from_slice(&chunk(b"NEND", &[]));
+		assert_eq!(Err("INFO chunk is missing"), Nsf::new(&data).map(|_| ()));
+		let mut data = NSFE_SIGNATURE.to_vec();
+		data.extend_from_slice(&chunk(b"XTRA", &[]));
+		assert_eq!(Err("unsupported mandatory chunk"), Nsf::new(&data).map(|_| ()));
+		assert_eq!(Err("chunk is truncated"), Nsf::new(NSFE_SIGNATURE).map(|_| ()));
+	}
+
+	#[test]
+	fn unterminated_track_names() {
+		let mut data = NSFE_SIGNATURE.to_vec();
+		data.extend_from_slice(&chunk(b"INFO", &[0x00, 0x80, 0x00, 0x90, 0x03, 0x90, 0x00, 0, 3, 0]));
+		data.extend_from_slice(&chunk(b"DATA", &[0xEA; 4]));
+		data.extend_from_slice(&chunk(b"tlbl", b"One\0\0Three"));
+		data.extend_from_slice(&chunk(b"NEND", &[]));
+		let nsf = Nsf::new(&data).unwrap();
+		assert_eq!(vec!["One".to_string(), "".to_string(), "Three".to_string()], nsf.metadata.track_names);
+	}
+}
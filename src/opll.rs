@@ -0,0 +1,587 @@
+use save_state::{OpllState, OpllSlotState};
+
+/**
+ * Built-in instruments 1-15 of VRC7. Instrument 0 is the user defined one
+ * written to the registers 0x00 - 0x07.
+ * Refer to https://wiki.nesdev.com/w/index.php/VRC7_audio
+ */
+const INSTRUMENTS: [[u8; 8]; 15] = [
+	[0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27], // Buzzy bell
+	[0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12], // Guitar
+	[0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12], // Wurly
+	[0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27], // Flute
+	[0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28], // Clarinet
+	[0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4], // Synth
+	[0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07], // Trumpet
+	[0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17], // Organ
+	[0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], // Bells
+	[0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02], // Vibes
+	[0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12], // Vibraphone
+	[0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], // Tutti
+	[0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02], // Fretless
+	[0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6], // Synth bass
+	[0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06]  // Sweep
+];
+
+// Frequency multipliers doubled, for the multiplier 0 means x0.5
+const MULTIPLIERS: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+// Key scale level of the upper 4 bits of F-Number, in 0.75dB
+const KEY_SCALE_LEVELS: [u8; 16] = [0, 32, 40, 45, 48, 51, 53, 55, 56, 58, 59, 60, 61, 62, 63, 64];
+
+// Envelope increments for the lower 2 bits of the rate
+const ENVELOPE_INCREMENTS: [[u8; 8]; 4] = [
+	[0, 1, 0, 1, 0, 1, 0, 1],
+	[0, 1, 0, 1, 1, 1, 0, 1],
+	[0, 1, 1, 1, 0, 1, 1, 1],
+	[0, 1, 1, 1, 1, 1, 1, 1]
+];
+
+// Vibrato F-Number offsets, scaled by the upper 3 bits of F-Number
+const VIBRATO_OFFSETS: [i32; 8] = [0, 1, 2, 1, 0, -1, -2, -1];
+
+// Slot outputs nothing at the bottom of the envelope
+const ENVELOPE_OFF: u8 = 127;
+
+// Tremolo reaches 13 (4.875dB) at the peak
+const TREMOLO_DEPTH: u32 = 13;
+
+lazy_static! {
+	/**
+	 * Quarter sine wave in -log2 with 8 fractional bits, and 2^x
+	 * with 10 fractional bits. Same as the tables found in the
+	 * YM2413 and YM3812 die.
+	 */
+	static ref LOG_SIN_TABLE: [u16; 256] = {
+		let mut table = [0; 256];
+		for (i, value) in table.iter_mut().enumerate() {
+			let x = ((i as f64 + 0.5) * ::std::f64::consts::PI / 512.0).sin();
+			*value = (-x.log2() * 256.0).round() as u16;
+		}
+		table
+	};
+	static ref EXP_TABLE: [u16; 256] = {
+		let mut table = [0; 256];
+		for (i, value) in table.iter_mut().enumerate() {
+			*value = (((i as f64 / 256.0).exp2() - 1.0) * 1024.0).round() as u16;
+		}
+		table
+	};
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EnvelopeState {
+	Attack,
+	Decay,
+	Sustain,
+	Release
+}
+
+/**
+ * An operator. A channel consists of a modulator and a carrier.
+ */
+#[derive(Clone, Copy)]
+struct OpllSlot {
+	phase: u32, // 20 bits. The upper 10 bits index the sine wave
+	envelope: u8, // 0 (loudest) - 127 (silent), in 0.375dB
+	state: EnvelopeState,
+	outputs: [i32; 2] // The last two outputs, for the modulator feedback
+}
+
+impl OpllSlot {
+	fn new() -> Self {
+		OpllSlot {
+			phase: 0,
+			envelope: ENVELOPE_OFF,
+			state: EnvelopeState::Release,
+			outputs: [0; 2]
+		}
+	}
+
+	fn key_on(&mut self) {
+		self.phase = 0;
+		self.state = EnvelopeState::Attack;
+	}
+
+	fn key_off(&mut self) {
+		self.state = EnvelopeState::Release;
+	}
+
+	/**
+	 * Instrument bytes for the slot
+	 * byte 0: AM, VIB, EG type, KSR, multiplier
+	 * byte 2: Key scale level in the upper 2 bits
+	 * byte 4: Attack rate, decay rate
+	 * byte 6: Sustain level, release rate
+	 */
+	fn drive_phase(&mut self, instrument: &[u8; 8], carrier: bool, fnum: u16, block: u8, vibrato: u8) {
+		let index = carrier as usize;
+		let mut fnum = fnum as i32;
+		if (instrument[index] & 0x40) != 0 {
+			fnum += ((fnum >> 6) * VIBRATO_OFFSETS[vibrato as usize]) >> 1;
+		}
+		let increment = ((fnum as u32) << block) * MULTIPLIERS[(instrument[index] & 0xF) as usize];
+		self.phase = (self.phase + increment) & 0xFFFFF;
+	}
+
+	fn drive_envelope(&mut self, instrument: &[u8; 8], carrier: bool, fnum: u16, block: u8,
+		sustain: bool, counter: u32) {
+		let index = carrier as usize;
+		let sustained_type = (instrument[index] & 0x20) != 0;
+		let rate = match self.state {
+			EnvelopeState::Attack => instrument[4 + index] >> 4,
+			EnvelopeState::Decay => instrument[4 + index] & 0xF,
+			EnvelopeState::Sustain => match sustained_type {
+				true => 0,
+				false => instrument[6 + index] & 0xF
+			},
+			EnvelopeState::Release => match sustain {
+				true => 5,
+				false => match sustained_type {
+					true => instrument[6 + index] & 0xF,
+					false => 7
+				}
+			}
+		};
+		let rate = match rate {
+			0 => 0,
+			_ => {
+				let key_scale = (((block as u32) << 1) | (fnum as u32 >> 8)) >> match (instrument[index] & 0x10) != 0 {
+					true => 0,
+					false => 2
+				};
+				(rate as u32 * 4 + key_scale).min(63)
+			}
+		};
+
+		if self.state == EnvelopeState::Attack && rate >= 60 {
+			self.envelope = 0;
+			self.state = EnvelopeState::Decay;
+			return;
+		}
+
+		let increment = envelope_increment(rate, counter);
+		match self.state {
+			EnvelopeState::Attack => {
+				if increment > 0 {
+					let decrement = ((self.envelope as u32 * increment) >> 3) + 1;
+					self.envelope = (self.envelope as u32).saturating_sub(decrement) as u8;
+				}
+				if self.envelope == 0 {
+					self.state = EnvelopeState::Decay;
+				}
+			},
+			_ => {
+				self.envelope = (self.envelope as u32 + increment).min(ENVELOPE_OFF as u32) as u8;
+				let sustain_level = (instrument[6 + index] >> 4) << 3;
+				if self.state == EnvelopeState::Decay && self.envelope >= sustain_level {
+					self.state = EnvelopeState::Sustain;
+				}
+			}
+		};
+	}
+
+	fn save_state(&self) -> OpllSlotState {
+		OpllSlotState {
+			phase: self.phase,
+			envelope: self.envelope,
+			state: self.state as u8,
+			outputs: self.outputs
+		}
+	}
+
+	fn load_state(&mut self, state: &OpllSlotState) {
+		self.phase = state.phase;
+		self.envelope = state.envelope;
+		self.state = match state.state {
+			0 => EnvelopeState::Attack,
+			1 => EnvelopeState::Decay,
+			2 => EnvelopeState::Sustain,
+			_ => EnvelopeState::Release
+		};
+		self.outputs = state.outputs;
+	}
+}
+
+/**
+ * Envelope generators with the rate step every 2^(12 - rate / 4) samples.
+ * Faster rates step every sample with larger increments.
+ */
+fn envelope_increment(rate: u32, counter: u32) -> u32 {
+	let rate_high = rate >> 2;
+	let increments = &ENVELOPE_INCREMENTS[(rate & 3) as usize];
+	match rate_high {
+		0 => 0,
+		1..=11 => {
+			let shift = 12 - rate_high;
+			match (counter & ((1 << shift) - 1)) != 0 {
+				true => 0,
+				false => increments[((counter >> shift) & 7) as usize] as u32
+			}
+		},
+		_ => (increments[(counter & 7) as usize] as u32) << (rate_high - 12)
+	}
+}
+
+/**
+ * Looks up the sine wave with the 10-bit phase and attenuates it
+ * by attenuation in 0.375dB. Returns a signed 13-bit value.
+ * Rectified wave outputs nothing for the negative half.
+ */
+fn operator_output(phase: u32, attenuation: u32, rectified: bool) -> i32 {
+	let negative = (phase & 0x200) != 0;
+	if negative && rectified {
+		return 0;
+	}
+	let index = match (phase & 0x100) != 0 {
+		true => !phase & 0xFF,
+		false => phase & 0xFF
+	};
+	let level = LOG_SIN_TABLE[index as usize] as u32 + (attenuation << 4);
+	let value = match level >> 8 {
+		shift if shift >= 12 => 0,
+		shift => ((((EXP_TABLE[(level & 0xFF) as usize ^ 0xFF] | 0x400) as u32) << 1) >> shift) as i32
+	};
+	match negative {
+		true => -value,
+		false => value
+	}
+}
+
+/**
+ * Six-channel FM synthesizer derived from Yamaha YM2413 (OPLL),
+ * built into Konami VRC7. Each channel consists of a modulator
+ * and a carrier, and an instrument defines both of them.
+ * Rhythm mode is not available on VRC7.
+ * Refer to https://wiki.nesdev.com/w/index.php/VRC7_audio
+ */
+pub struct Opll {
+	address: u8,
+	custom_instrument: [u8; 8], // 0x00 - 0x07
+	frequencies: [u8; 6], // 0x10 - 0x15, the lower 8 bits of F-Number
+	controls: [u8; 6], // 0x20 - 0x25, sustain, key on, block, F-Number bit 8
+	instruments: [u8; 6], // 0x30 - 0x35, instrument, volume
+	slots: [OpllSlot; 12], // modulator, carrier, modulator, ...
+	counter: u32,
+	tremolo_position: u32,
+	vibrato_position: u32,
+	output: i32
+}
+
+impl Opll {
+	pub fn new() -> Self {
+		Opll {
+			address: 0,
+			custom_instrument: [0; 8],
+			frequencies: [0; 6],
+			controls: [0; 6],
+			instruments: [0; 6],
+			slots: [OpllSlot::new(); 12],
+			counter: 0,
+			tremolo_position: 0,
+			vibrato_position: 0,
+			output: 0
+		}
+	}
+
+	pub fn store_address(&mut self, value: u8) {
+		self.address = value;
+	}
+
+	pub fn store_data(&mut self, value: u8) {
+		let address = self.address as usize;
+		match address {
+			0x00..=0x07 => self.custom_instrument[address] = value,
+			0x10..=0x15 => self.frequencies[address - 0x10] = value,
+			0x20..=0x25 => {
+				let channel = address - 0x20;
+				let key_on = (value & 0x10) != 0;
+				let was_key_on = (self.controls[channel] & 0x10) != 0;
+				if key_on && !was_key_on {
+					self.slots[channel * 2].key_on();
+					self.slots[channel * 2 + 1].key_on();
+				}
+				// Only the carrier is released, the modulator keeps going
+				if !key_on && was_key_on {
+					self.slots[channel * 2 + 1].key_off();
+				}
+				self.controls[channel] = value;
+			},
+			0x30..=0x35 => self.instruments[address - 0x30] = value,
+			_ => {}
+		};
+	}
+
+	/**
+	 * Generates a sample. Expects being called at 49716Hz,
+	 * the 3.58MHz clock divided by 72.
+	 */
+	pub fn clock(&mut self) {
+		let tremolo = match self.tremolo_position < TREMOLO_DEPTH * 8 {
+			true => self.tremolo_position >> 3,
+			false => (TREMOLO_DEPTH * 16 - self.tremolo_position) >> 3
+		};
+		let vibrato = self.vibrato_position as u8;
+
+		let mut output = 0;
+		for channel in 0..6 {
+			output += self.clock_channel(channel, tremolo, vibrato);
+		}
+		self.output = output;
+
+		self.counter = self.counter.wrapping_add(1);
+		if (self.counter & 0x3F) == 0 {
+			self.tremolo_position = (self.tremolo_position + 1) % (TREMOLO_DEPTH * 16);
+		}
+		if (self.counter & 0x3FF) == 0 {
+			self.vibrato_position = (self.vibrato_position + 1) & 7;
+		}
+	}
+
+	/**
+	 * Instrument bytes for the channel
+	 * byte 2: Modulator total level in the lower 6 bits
+	 * byte 3: Carrier key scale level, carrier rectified wave,
+	 *         modulator rectified wave, feedback
+	 */
+	fn clock_channel(&mut self, channel: usize, tremolo: u32, vibrato: u8) -> i32 {
+		let instrument = self.instrument(channel);
+		let control = self.controls[channel];
+		let fnum = self.frequencies[channel] as u16 | ((control as u16 & 0x1) << 8);
+		let block = (control >> 1) & 0x7;
+		let sustain = (control & 0x20) != 0;
+		let key_scale_level = (KEY_SCALE_LEVELS[(fnum >> 5) as usize] as i32 * 2 - (7 - block as i32) * 16).max(0) as u32;
+		let attenuation = |index: usize, envelope: u8, level: u32| {
+			let key_scale_shift = match instrument[2 + index] >> 6 {
+				0 => 8,
+				1 => 2,
+				2 => 1,
+				_ => 0
+			};
+			let tremolo = match (instrument[index] & 0x80) != 0 {
+				true => tremolo,
+				false => 0
+			};
+			(envelope as u32 + level + (key_scale_level >> key_scale_shift) + tremolo).min(127)
+		};
+
+		// Modulator
+		let modulator = self.slots[channel * 2];
+		let feedback = match instrument[3] & 0x7 {
+			0 => 0,
+			feedback => (modulator.outputs[0] + modulator.outputs[1]) >> (9 - feedback)
+		};
+		let phase = ((modulator.phase >> 10) as i32 + feedback) as u32 & 0x3FF;
+		let modulation = match modulator.envelope {
+			ENVELOPE_OFF => 0,
+			envelope => operator_output(phase,
+				attenuation(0, envelope, (instrument[2] as u32 & 0x3F) << 1),
+				(instrument[3] & 0x08) != 0)
+		};
+
+		// Carrier
+		let carrier = self.slots[channel * 2 + 1];
+		let phase = ((carrier.phase >> 10) as i32 + modulation) as u32 & 0x3FF;
+		let output = match carrier.envelope {
+			ENVELOPE_OFF => 0,
+			envelope => operator_output(phase,
+				attenuation(1, envelope, (self.instruments[channel] as u32 & 0xF) << 3),
+				(instrument[3] & 0x10) != 0)
+		};
+
+		let counter = self.counter;
+		{
+			let modulator = &mut self.slots[channel * 2];
+			modulator.outputs = [modulator.outputs[1], modulation];
+			modulator.drive_phase(&instrument, false, fnum, block, vibrato);
+			modulator.drive_envelope(&instrument, false, fnum, block, sustain, counter);
+		}
+		{
+			let carrier = &mut self.slots[channel * 2 + 1];
+			carrier.outputs = [carrier.outputs[1], output];
+			carrier.drive_phase(&instrument, true, fnum, block, vibrato);
+			carrier.drive_envelope(&instrument, true, fnum, block, sustain, counter);
+		}
+		output
+	}
+
+	fn instrument(&self, channel: usize) -> [u8; 8] {
+		match self.instruments[channel] >> 4 {
+			0 => self.custom_instrument,
+			index => INSTRUMENTS[index as usize - 1]
+		}
+	}
+
+	/**
+	 * The sum of the six carriers. A channel ranges
+	 * from -4090 to 4090.
+	 */
+	pub fn output(&self) -> i32 {
+		self.output
+	}
+
+	pub fn save_state(&self) -> OpllState {
+		OpllState {
+			address: self.address,
+			custom_instrument: self.custom_instrument,
+			frequencies: self.frequencies,
+			controls: self.controls,
+			instruments: self.instruments,
+			slots: self.slots.iter().map(|slot| slot.save_state()).collect(),
+			counter: self.counter,
+			tremolo_position: self.tremolo_position,
+			vibrato_position: self.vibrato_position,
+			output: self.output
+		}
+	}
+
+	pub fn load_state(&mut self, state: &OpllState) {
+		self.address = state.address;
+		self.custom_instrument = state.custom_instrument;
+		self.frequencies = state.frequencies;
+		self.controls = state.controls;
+		self.instruments = state.instruments;
+		for (slot, slot_state) in self.slots.iter_mut().zip(state.slots.iter()) {
+			slot.load_state(slot_state);
+		}
+		self.counter = state.counter;
+		self.tremolo_position = state.tremolo_position;
+		self.vibrato_position = state.vibrato_position;
+		self.output = state.output;
+	}
+}
+
+#[cfg(test)]
+mod tests_opll {
+	use super::*;
+
+	// Reference waveform for F-Number 0x100, block 4 and MULTI 1 on both
+	// operators (8 of 1024 phase steps per sample), computed with sin() and
+	// dB attenuation in floating point instead of the log-sin and exp
+	// tables. Modulator is (attenuation in dB, feedback), feedback adds
+	// the last two modulator outputs >> (9 - feedback) to its phase.
+	// Off by the table rounding, up to around 1% of the full scale.
+	fn reference(modulator: Option<(f64, u32)>, carrier_attenuation: f64, length: usize) -> Vec<f64> {
+		let operator = |phase: f64, attenuation: f64| {
+			4096.0 * 10f64.powf(-attenuation / 20.0) * (2.0 * std::f64::consts::PI * phase / 1024.0).sin()
+		};
+		let mut outputs = [0.0, 0.0];
+		(0..=length).map(|sample| {
+			let phase = (sample * 8) as f64;
+			let modulation = match modulator {
+				Some((attenuation, feedback)) => {
+					let feedback = match feedback {
+						0 => 0.0,
+						_ => (outputs[0] + outputs[1]) / (1 << (9 - feedback)) as f64
+					};
+					let output = operator(phase + feedback, attenuation);
+					outputs = [outputs[1], output];
+					output
+				},
+				None => 0.0
+			};
+			operator(phase + modulation, carrier_attenuation)
+		}).skip(1).collect()
+	}
+
+	fn assert_close(expected: Vec<f64>, actual: Vec<i32>, tolerance: f64) {
+		for (i, (expected, actual)) in expected.iter().zip(actual.iter()).enumerate() {
+			assert!((expected - *actual as f64).abs() <= tolerance,
+				"sample {}: expected {:.1}, actual {}", i, expected, actual);
+		}
+	}
+
+	fn store(opll: &mut Opll, address: u8, value: u8) {
+		opll.store_address(address);
+		opll.store_data(value);
+	}
+
+	// F-Number 0x100, block 4 with the custom instrument
+	fn play(opll: &mut Opll, instrument: [u8; 8], volume: u8) {
+		for i in 0..8 {
+			store(opll, i as u8, instrument[i]);
+		}
+		store(opll, 0x10, 0x00);
+		store(opll, 0x30, volume);
+		store(opll, 0x20, 0x19);
+		// The carrier envelope reaches the top after the first sample
+		opll.clock();
+	}
+
+	fn dump(opll: &mut Opll, length: usize) -> Vec<i32> {
+		(0..length).map(|_| {
+			opll.clock();
+			opll.output()
+		}).collect()
+	}
+
+	#[test]
+	fn tables() {
+		assert_eq!(2137, LOG_SIN_TABLE[0]);
+		assert_eq!(0, LOG_SIN_TABLE[255]);
+		assert_eq!(0, EXP_TABLE[0]);
+		assert_eq!(1018, EXP_TABLE[255]);
+	}
+
+	#[test]
+	fn sine() {
+		let mut opll = Opll::new();
+		// Silent modulator, carrier with the instant attack
+		play(&mut opll, [0x21, 0x21, 0x3F, 0x00, 0x00, 0xF0, 0x00, 0x00], 0);
+		assert_close(reference(None, 0.0, 64), dump(&mut opll, 64), 24.0);
+	}
+
+	#[test]
+	fn frequency_modulation() {
+		let mut opll = Opll::new();
+		// Modulator at total level 0x20 (24dB) with feedback 3, volume 2 (6dB)
+		play(&mut opll, [0x21, 0x21, 0x20, 0x03, 0xF0, 0xF0, 0x00, 0x00], 2);
+		assert_close(reference(Some((24.0, 3)), 6.0, 31), dump(&mut opll, 31), 48.0);
+	}
+
+	#[test]
+	fn rectified_wave() {
+		let mut opll = Opll::new();
+		play(&mut opll, [0x21, 0x21, 0x3F, 0x10, 0x00, 0xF0, 0x00, 0x00], 0);
+		let samples = dump(&mut opll, 0x400);
+		assert!(samples.iter().all(|&sample| sample >= 0));
+		assert!(samples.iter().any(|&sample| sample > 4000));
+	}
+
+	#[test]
+	fn release() {
+		let mut opll = Opll::new();
+		// Release rate 15
+		play(&mut opll, [0x21, 0x21, 0x3F, 0x00, 0x00, 0xF0, 0x00, 0x0F], 0);
+		assert!(dump(&mut opll, 64).iter().any(|&sample| sample != 0));
+		store(&mut opll, 0x20, 0x09);
+		dump(&mut opll, 64);
+		assert!(dump(&mut opll, 64).iter().all(|&sample| sample == 0));
+	}
+
+	#[test]
+	fn built_in_instrument() {
+		let mut opll = Opll::new();
+		store(&mut opll, 0x10, 0x00);
+		store(&mut opll, 0x30, 0x00);
+		store(&mut opll, 0x20, 0x19);
+		// Custom instrument with all zero never attacks
+		assert!(dump(&mut opll, 256).iter().all(|&sample| sample == 0));
+		// Instrument 3
+		store(&mut opll, 0x20, 0x00);
+		store(&mut opll, 0x30, 0x30);
+		store(&mut opll, 0x20, 0x19);
+		assert!(dump(&mut opll, 256).iter().any(|&sample| sample != 0));
+	}
+
+	#[test]
+	fn save_state() {
+		let mut opll = Opll::new();
+		play(&mut opll, [0x21, 0x21, 0x20, 0x03, 0xF0, 0xF0, 0x00, 0x00], 2);
+		dump(&mut opll, 10);
+		let state = opll.save_state();
+		let mut restored = Opll::new();
+		restored.load_state(&state);
+		assert_eq!(dump(&mut opll, 10), dump(&mut restored, 10));
+	}
+}
@@ -0,0 +1,439 @@
+use std::fmt;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+const IPS_SIGNATURE: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const UPS_SIGNATURE: &[u8] = b"UPS1";
+const BPS_SIGNATURE: &[u8] = b"BPS1";
+
+// UPS and BPS end with the source, target and patch CRC32s
+const FOOTER_SIZE: usize = 12;
+
+// UPS / BPS targets larger than this are taken as broken patches,
+// far above any NES ROM
+const MAX_TARGET_SIZE: usize = 64 * 1024 * 1024;
+
+// UPS / BPS variable length numbers longer than this don't fit in 64 bits
+const MAX_VARINT_SIZE: usize = 10;
+
+// Extensions of the patch files picked next to ROMs, in the priority order
+const PATCH_EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+
+/// Reasons a patch is rejected by `apply_patch()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PatchError {
+	// Neither IPS, UPS nor BPS
+	UnknownFormat,
+	// Patch ends in the middle of a record or command
+	Truncated,
+	// Record or command reaches out of the data
+	OutOfRange,
+	// UPS / BPS patch made for another ROM
+	SourceMismatch { expected: u32, actual: u32 },
+	// UPS / BPS patch result doesn't match the checksum
+	TargetMismatch { expected: u32, actual: u32 },
+	// UPS / BPS patch itself is corrupted
+	PatchMismatch { expected: u32, actual: u32 }
+}
+
+impl fmt::Display for PatchError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			PatchError::UnknownFormat =>
+				write!(f, "Not an IPS, UPS or BPS patch"),
+			PatchError::Truncated =>
+				write!(f, "Patch is truncated"),
+			PatchError::OutOfRange =>
+				write!(f, "Patch reaches out of the ROM"),
+			PatchError::SourceMismatch { expected, actual } =>
+				write!(f, "Patch is for another ROM (CRC32 {:08X}, expected {:08X})", actual, expected),
+			PatchError::TargetMismatch { expected, actual } =>
+				write!(f, "Patched ROM is broken (CRC32 {:08X}, expected {:08X})", actual, expected),
+			PatchError::PatchMismatch { expected, actual } =>
+				write!(f, "Patch is corrupted (CRC32 {:08X}, expected {:08X})", actual, expected)
+		}
+	}
+}
+
+impl Error for PatchError {}
+
+/**
+ * Applies the IPS, UPS or BPS patch to the ROM data, to be loaded
+ * with `Rom::new()`. UPS and BPS checksums are verified.
+ * Refer to http://fileformats.archiveteam.org/wiki/IPS_(binary_patch_format)
+ * and https://www.romhacking.net/documents/746/ (BPS)
+ */
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+	if patch.starts_with(IPS_SIGNATURE) {
+		apply_ips(rom, patch)
+	} else if patch.starts_with(UPS_SIGNATURE) {
+		apply_ups(rom, patch)
+	} else if patch.starts_with(BPS_SIGNATURE) {
+		apply_bps(rom, patch)
+	} else {
+		Err(PatchError::UnknownFormat)
+	}
+}
+
+/// .ips, .bps or .ups file next to the ROM with the same name, if any
+pub fn find_sibling_patch(rom_path: &Path) -> Option<PathBuf> {
+	PATCH_EXTENSIONS.iter()
+		.map(|extension| rom_path.with_extension(extension))
+		.find(|path| path.is_file())
+}
+
+/// CRC32 (the one of zip and PNG)
+pub fn crc32(data: &[u8]) -> u32 {
+	crc32_update(0, data)
+}
+
+/// CRC32 of the data following the data of the given CRC32,
+/// for hashing data split in pieces without joining them
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+	!data.iter().fold(!crc, |crc, &byte| {
+		(0..8).fold(crc ^ byte as u32, |crc, _| match crc & 1 {
+			1 => (crc >> 1) ^ 0xEDB88320,
+			_ => crc >> 1
+		})
+	})
+}
+
+// Records of 3-byte offset and 2-byte size (big endian) followed by
+// the data, or by 2-byte count and a byte to fill if the size is 0.
+// "EOF" may be followed by 3-byte size to truncate to.
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+	let mut target = rom.to_vec();
+	let mut reader = Reader::new(patch, IPS_SIGNATURE.len());
+	loop {
+		if reader.remaining().starts_with(IPS_EOF) {
+			reader.read_bytes(IPS_EOF.len())?;
+			if let Ok(size) = reader.read_be(3) {
+				target.truncate(size);
+			}
+			return Ok(target);
+		}
+		let offset = reader.read_be(3)?;
+		let (data, size) = match reader.read_be(2)? {
+			0 => {
+				let count = reader.read_be(2)?;
+				(None, count)
+			},
+			size => (Some(reader.read_bytes(size)?), size)
+		};
+		if target.len() < offset + size {
+			target.resize(offset + size, 0);
+		}
+		match data {
+			Some(data) => target[offset..offset + size].copy_from_slice(data),
+			None => {
+				let value = reader.read_bytes(1)?[0];
+				for byte in target[offset..offset + size].iter_mut() {
+					*byte = value;
+				}
+			}
+		};
+	}
+}
+
+// Source and target sizes, then hunks of relative offset and bytes
+// XORed with the source until 0.
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+	let footer = verify_footer(rom, patch)?;
+	let mut reader = Reader::new(&patch[..patch.len() - FOOTER_SIZE], UPS_SIGNATURE.len());
+	let source_size = reader.read_varint()?;
+	let target_size = reader.read_varint()?;
+	if source_size != rom.len() || target_size > MAX_TARGET_SIZE {
+		return Err(PatchError::OutOfRange);
+	}
+
+	let mut target = rom.to_vec();
+	target.resize(target_size, 0);
+	let mut position: usize = 0;
+	while !reader.remaining().is_empty() {
+		position = position.checked_add(reader.read_varint()?).ok_or(PatchError::OutOfRange)?;
+		loop {
+			let value = reader.read_bytes(1)?[0];
+			if value == 0 {
+				position = position.checked_add(1).ok_or(PatchError::OutOfRange)?;
+				break;
+			}
+			if position < target.len() {
+				target[position] ^= value;
+			}
+			position = position.checked_add(1).ok_or(PatchError::OutOfRange)?;
+		}
+	}
+	verify_target(&target, footer)
+}
+
+// Source, target and metadata sizes, then commands copying from
+// the source, the patch or the target made so far.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, PatchError> {
+	let footer = verify_footer(rom, patch)?;
+	let mut reader = Reader::new(&patch[..patch.len() - FOOTER_SIZE], BPS_SIGNATURE.len());
+	let source_size = reader.read_varint()?;
+	let target_size = reader.read_varint()?;
+	let metadata_size = reader.read_varint()?;
+	reader.read_bytes(metadata_size)?;
+	if source_size != rom.len() || target_size > MAX_TARGET_SIZE {
+		return Err(PatchError::OutOfRange);
+	}
+
+	let mut target = vec![];
+	let mut source_offset = 0;
+	let mut target_offset = 0;
+	while !reader.remaining().is_empty() {
+		let command = reader.read_varint()?;
+		let length = (command >> 2) + 1;
+		if target.len().checked_add(length).map_or(true, |end| end > target_size) {
+			return Err(PatchError::OutOfRange);
+		}
+		match command & 3 {
+			// SourceRead
+			0 => {
+				let start = target.len();
+				let data = rom.get(start..start.saturating_add(length)).ok_or(PatchError::OutOfRange)?;
+				target.extend_from_slice(data);
+			},
+			// TargetRead
+			1 => target.extend_from_slice(reader.read_bytes(length)?),
+			// SourceCopy
+			2 => {
+				source_offset = reader.read_offset(source_offset)?;
+				let data = rom.get(source_offset..source_offset.saturating_add(length)).ok_or(PatchError::OutOfRange)?;
+				target.extend_from_slice(data);
+				source_offset += length;
+			},
+			// TargetCopy. It can overlap what it makes, copied byte by byte.
+			_ => {
+				target_offset = reader.read_offset(target_offset)?;
+				for _ in 0..length {
+					let value = *target.get(target_offset).ok_or(PatchError::OutOfRange)?;
+					target.push(value);
+					target_offset += 1;
+				}
+			}
+		};
+	}
+	if target.len() != target_size {
+		return Err(PatchError::OutOfRange);
+	}
+	verify_target(&target, footer)
+}
+
+// Verifies the patch and source checksums. Returns the target checksum.
+fn verify_footer(rom: &[u8], patch: &[u8]) -> Result<u32, PatchError> {
+	if patch.len() < 4 + FOOTER_SIZE {
+		return Err(PatchError::Truncated);
+	}
+	let footer = &patch[patch.len() - FOOTER_SIZE..];
+	let checksum = |offset: usize| (0..4).fold(0, |value, i| value | (footer[offset + i] as u32) << (i * 8));
+
+	let actual = crc32(&patch[..patch.len() - 4]);
+	if actual != checksum(8) {
+		return Err(PatchError::PatchMismatch { expected: checksum(8), actual: actual });
+	}
+	let actual = crc32(rom);
+	if actual != checksum(0) {
+		return Err(PatchError::SourceMismatch { expected: checksum(0), actual: actual });
+	}
+	Ok(checksum(4))
+}
+
+fn verify_target(target: &[u8], expected: u32) -> Result<Vec<u8>, PatchError> {
+	match crc32(target) {
+		actual if actual == expected => Ok(target.to_vec()),
+		actual => Err(PatchError::TargetMismatch { expected: expected, actual: actual })
+	}
+}
+
+struct Reader<'a> {
+	data: &'a [u8],
+	position: usize
+}
+
+impl<'a> Reader<'a> {
+	fn new(data: &'a [u8], position: usize) -> Self {
+		Reader {
+			data: data,
+			position: position
+		}
+	}
+
+	fn remaining(&self) -> &'a [u8] {
+		&self.data[self.position..]
+	}
+
+	fn read_bytes(&mut self, size: usize) -> Result<&'a [u8], PatchError> {
+		match self.data.len() - self.position >= size {
+			true => {
+				self.position += size;
+				Ok(&self.data[self.position - size..self.position])
+			},
+			false => Err(PatchError::Truncated)
+		}
+	}
+
+	fn read_be(&mut self, size: usize) -> Result<usize, PatchError> {
+		Ok(self.read_bytes(size)?.iter().fold(0, |value, &byte| (value << 8) | byte as usize))
+	}
+
+	// UPS / BPS variable length number. Bit 7 ends it.
+	fn read_varint(&mut self) -> Result<usize, PatchError> {
+		let mut value: usize = 0;
+		let mut shift: usize = 1;
+		for _ in 0..MAX_VARINT_SIZE {
+			let byte = self.read_bytes(1)?[0];
+			let digit = ((byte & 0x7F) as usize).checked_mul(shift).ok_or(PatchError::OutOfRange)?;
+			value = value.checked_add(digit).ok_or(PatchError::OutOfRange)?;
+			if (byte & 0x80) != 0 {
+				return Ok(value);
+			}
+			shift = shift.checked_mul(0x80).ok_or(PatchError::OutOfRange)?;
+			value = value.checked_add(shift).ok_or(PatchError::OutOfRange)?;
+		}
+		Err(PatchError::OutOfRange)
+	}
+
+	// BPS relative offset. Bit 0 is the sign.
+	fn read_offset(&mut self, base: usize) -> Result<usize, PatchError> {
+		let data = self.read_varint()?;
+		let result = match (data & 1) != 0 {
+			true => base.checked_sub(data >> 1),
+			false => base.checked_add(data >> 1)
+		};
+		result.ok_or(PatchError::OutOfRange)
+	}
+}
+
+#[cfg(test)]
+mod tests_patch {
+	use super::*;
+
+	fn varint(mut value: usize) -> Vec<u8> {
+		let mut data = vec![];
+		loop {
+			let byte = (value & 0x7F) as u8;
+			value >>= 7;
+			if value == 0 {
+				data.push(byte | 0x80);
+				return data;
+			}
+			data.push(byte);
+			value -= 1;
+		}
+	}
+
+	fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+		patch.extend_from_slice(&crc32(source).to_le_bytes());
+		patch.extend_from_slice(&crc32(target).to_le_bytes());
+		let checksum = crc32(&patch);
+		patch.extend_from_slice(&checksum.to_le_bytes());
+		patch
+	}
+
+	#[test]
+	fn crc32_() {
+		assert_eq!(0, crc32(&[]));
+		assert_eq!(0xCBF43926, crc32(b"123456789"));
+		assert_eq!(0xCBF43926, crc32_update(crc32(b"1234"), b"56789"));
+	}
+
+	#[test]
+	fn ips() {
+		let mut patch = b"PATCH".to_vec();
+		patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+		// RLE beyond the ROM extends it
+		patch.extend_from_slice(&[0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+		patch.extend_from_slice(b"EOF");
+		assert_eq!(Ok(vec![0, 0xAA, 0xBB, 0, 0xCC, 0xCC, 0xCC]), apply_patch(&[0; 4], &patch));
+		// Truncation after EOF
+		patch.extend_from_slice(&[0x00, 0x00, 0x02]);
+		assert_eq!(Ok(vec![0, 0xAA]), apply_patch(&[0; 4], &patch));
+		assert_eq!(Err(PatchError::Truncated), apply_patch(&[0; 4], b"PATCH\x00\x00"));
+		assert_eq!(Err(PatchError::UnknownFormat), apply_patch(&[0; 4], b"NOPE"));
+	}
+
+	#[test]
+	fn ups() {
+		let source = [1, 2, 3, 4];
+		let target = [1, 7, 3, 4, 5];
+		let mut patch = b"UPS1".to_vec();
+		patch.extend_from_slice(&varint(4));
+		patch.extend_from_slice(&varint(5));
+		patch.extend_from_slice(&varint(1));
+		patch.extend_from_slice(&[2 ^ 7, 0x00]);
+		patch.extend_from_slice(&varint(1));
+		patch.extend_from_slice(&[5, 0x00]);
+		let patch = with_footer(patch, &source, &target);
+		assert_eq!(Ok(target.to_vec()), apply_patch(&source, &patch));
+		assert_eq!(Err(PatchError::SourceMismatch { expected: crc32(&source), actual: crc32(&target) }),
+			apply_patch(&target, &patch));
+
+		// Relative offset running past the end of the address space
+		let mut patch = b"UPS1".to_vec();
+		patch.extend_from_slice(&varint(4));
+		patch.extend_from_slice(&varint(5));
+		patch.extend_from_slice(&varint(0));
+		patch.extend_from_slice(&[1, 0x00]);
+		patch.extend_from_slice(&varint(usize::MAX - 1));
+		patch.extend_from_slice(&[0x00]);
+		let patch = with_footer(patch, &source, &target);
+		assert_eq!(Err(PatchError::OutOfRange), apply_patch(&source, &patch));
+
+		// Target too large to allocate
+		let mut patch = b"UPS1".to_vec();
+		patch.extend_from_slice(&varint(4));
+		patch.extend_from_slice(&varint(usize::MAX / 2));
+		let patch = with_footer(patch, &source, &target);
+		assert_eq!(Err(PatchError::OutOfRange), apply_patch(&source, &patch));
+	}
+
+	#[test]
+	fn broken_varint() {
+		// Never ends
+		assert_eq!(Err(PatchError::OutOfRange), Reader::new(&[0x00; 16], 0).read_varint());
+		// Doesn't fit
+		let mut data = vec![0x7F; 9];
+		data.push(0xFF);
+		assert_eq!(Err(PatchError::OutOfRange), Reader::new(&data, 0).read_varint());
+		assert_eq!(Ok(usize::MAX - 1), Reader::new(&varint(usize::MAX - 1), 0).read_varint());
+	}
+
+	#[test]
+	fn bps() {
+		let source = [1, 2, 3, 4];
+		let target = [1, 2, 9, 3, 4, 4, 4, 4];
+		let mut patch = b"BPS1".to_vec();
+		patch.extend_from_slice(&varint(4));
+		patch.extend_from_slice(&varint(8));
+		patch.extend_from_slice(&varint(0));
+		// SourceRead 2, TargetRead 1, SourceCopy 2 from 2, TargetCopy 3 from 4 overlapping
+		patch.extend_from_slice(&varint((1 << 2) | 0));
+		patch.extend_from_slice(&varint((0 << 2) | 1));
+		patch.push(9);
+		patch.extend_from_slice(&varint((1 << 2) | 2));
+		patch.extend_from_slice(&varint(2 << 1));
+		patch.extend_from_slice(&varint((2 << 2) | 3));
+		patch.extend_from_slice(&varint(4 << 1));
+		let mut patch = with_footer(patch, &source, &target);
+		assert_eq!(Ok(target.to_vec()), apply_patch(&source, &patch));
+		patch[5] ^= 1;
+		match apply_patch(&source, &patch) {
+			Err(PatchError::PatchMismatch { .. }) => {},
+			result => panic!("{:?}", result)
+		};
+
+		// TargetCopy making more than the target size
+		let mut patch = b"BPS1".to_vec();
+		patch.extend_from_slice(&varint(4));
+		patch.extend_from_slice(&varint(2));
+		patch.extend_from_slice(&varint(0));
+		patch.extend_from_slice(&varint((0 << 2) | 0));
+		patch.extend_from_slice(&varint((0x100000 << 2) | 3));
+		patch.extend_from_slice(&varint(0));
+		let patch = with_footer(patch, &source, &target);
+		assert_eq!(Err(PatchError::OutOfRange), apply_patch(&source, &patch));
+	}
+}
@@ -1,7 +1,7 @@
 use register::Register;
 use memory::Memory;
 use rom::Rom;
-use rom::Mirrorings;
+use mapper::{NameTableSource, PpuPhase};
 use display::Display;
 use save_state::PpuState;
 
@@ -60,6 +60,8 @@ pub struct Ppu {
 	sprite_ids: [u8; 256],
 	sprite_palette_addresses: [u16; 256],
 	sprite_priorities: [u8; 256],
+	// Primary OAM index of each sprite in secondary OAM
+	sprite_slot_ids: [u8; 8],
 
 	// Primary OAM, holds 64 sprites for the frame
 	primary_oam: SpritesManager,
@@ -120,6 +122,11 @@ pub struct Ppu {
 	// @TODO: Support decay. Decaying after a frame or so.
 	data_bus: u8,
 
+	// Line A12 of PPU address bus, and how many PPU cycles it has been low.
+	// Some mappers count the rising edges of A12 to detect scanlines.
+	address_bus_a12: bool,
+	a12_low_cycles: u32,
+
 	// -- 
 
 	display: Box<dyn Display>,
@@ -217,6 +224,7 @@ impl Ppu {
 			sprite_ids: [0; 256],
 			sprite_palette_addresses: [0; 256],
 			sprite_priorities: [0; 256],
+			sprite_slot_ids: [0; 8],
 			oamaddr: Register::<u8>::new(),
 			oamdata: Register::<u8>::new(),
 			oamdma: Register::<u8>::new(),
@@ -234,6 +242,8 @@ impl Ppu {
 			attribute_table_high: Register::<u16>::new(),
 			pattern_table_low: Register::<u16>::new(),
 			pattern_table_high: Register::<u16>::new(),
+			address_bus_a12: false,
+			a12_low_cycles: 0,
 			display: display,
 			nmi_interrupted: false,
 			irq_interrupted: false
@@ -263,12 +273,16 @@ impl Ppu {
 		self.shift_registers();
 		self.fetch(rom);
 		self.evaluate_sprites(rom);
+		self.fetch_sprites(rom);
 		self.update_flags(rom);
 		self.countup_scroll_counters();
 		self.countup_cycle();
+		if !self.address_bus_a12 {
+			self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+		}
 	}
 
-	pub fn load_register(&mut self, address: u16, rom: &Rom) -> u8 {
+	pub fn load_register(&mut self, address: u16, rom: &mut Rom) -> u8 {
 		match address {
 			// ppustatus load
 			0x2002 => {
@@ -323,6 +337,8 @@ impl Ppu {
 
 				// @TODO: Support greyscale if needed
 
+				self.drive_address_bus_from_cpu(self.current_vram_address, rom);
+
 				// Accessing ppudata increments vram_address
 				self.increment_vram_address();
 				self.data_bus = return_value;
@@ -414,6 +430,7 @@ impl Ppu {
 					self.temporal_vram_address &= 0xFF00;
 					self.temporal_vram_address |= value as u16;
 					self.current_vram_address = self.temporal_vram_address;
+					self.drive_address_bus_from_cpu(self.current_vram_address, rom);
 				}
 
 				self.register_first_store = !self.register_first_store;
@@ -422,6 +439,7 @@ impl Ppu {
 			0x2007 => {
 				self.ppudata.store(value);
 				self.store(self.current_vram_address, value, rom);
+				self.drive_address_bus_from_cpu(self.current_vram_address, rom);
 				// Accessing ppudata increments vram_address
 				self.increment_vram_address();
 			},
@@ -438,28 +456,50 @@ impl Ppu {
 	fn load(&self, mut address: u16, rom: &Rom) -> u8 {
 		address = address & 0x3FFF;  // just in case
 
-		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM if exists.
+		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM or CHR-RAM.
+		// 0x2000 - 0x3EFF is nametables the cartridge maps to.
 		// Otherwise load from VRAM.
 
-		match address < 0x2000 && rom.has_chr_rom() {
-			true => rom.load(address as u32),
-			false => self.vram.load(self.convert_vram_address(address, rom) as u32)
+		match address {
+			0..=0x1FFF => rom.load(address as u32),
+			0x2000..=0x3EFF => self.load_name_table(address, rom),
+			_ => self.vram.load(self.convert_vram_address(address) as u32)
 		}
 	}
 
 	fn store(&mut self, mut address: u16, value: u8, rom: &mut Rom) {
 		address = address & 0x3FFF;  // just in case
 
-		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM if exists.
+		// 0x0000 - 0x1FFF is mapped with cartridge's CHR-ROM or CHR-RAM.
+		// 0x2000 - 0x3EFF is nametables the cartridge maps to.
 		// Otherwise store to VRAM.
 
-		match address < 0x2000 && rom.has_chr_rom() {
-			true => rom.store(address as u32, value),
-			false => self.vram.store(self.convert_vram_address(address, rom) as u32, value)
+		match address {
+			0..=0x1FFF => rom.store(address as u32, value),
+			0x2000..=0x3EFF => self.store_name_table(address, value, rom),
+			_ => self.vram.store(self.convert_vram_address(address) as u32, value)
 		};
 	}
 
-	fn convert_vram_address(&self, address: u16, rom: &Rom) -> u16 {
+	fn load_name_table(&self, address: u16, rom: &Rom) -> u8 {
+		match rom.name_table_source(address as u32) {
+			NameTableSource::Vram(page) => self.vram.load(self.get_name_table_address(address, page) as u32),
+			NameTableSource::Mapper => rom.load_name_table((address & 0x2FFF) as u32),
+			NameTableSource::ChrRom(base) => rom.load_chr_name_table(base + (address & 0x3FF) as u32),
+			NameTableSource::CartridgeVram(base) => rom.load_cartridge_vram(base + (address & 0x3FF) as u32)
+		}
+	}
+
+	fn store_name_table(&mut self, address: u16, value: u8, rom: &mut Rom) {
+		match rom.name_table_source(address as u32) {
+			NameTableSource::Vram(page) => self.vram.store(self.get_name_table_address(address, page) as u32, value),
+			NameTableSource::Mapper => rom.store_name_table((address & 0x2FFF) as u32, value),
+			NameTableSource::ChrRom(base) => rom.store_chr_name_table(base + (address & 0x3FF) as u32, value),
+			NameTableSource::CartridgeVram(base) => rom.store_cartridge_vram(base + (address & 0x3FF) as u32, value)
+		};
+	}
+
+	fn convert_vram_address(&self, address: u16) -> u16 {
 		// 0x0000 - 0x0FFF: pattern table 0
 		// 0x1000 - 0x1FFF: pattern table 1
 		// 0x2000 - 0x23FF: nametable 0
@@ -470,9 +510,10 @@ impl Ppu {
 		// 0x3F00 - 0x3F1F: Palette RAM indices
 		// 0x3F20 - 0x3FFF: Mirrors of 0x3F00 - 0x3F1F
 
+		// Nametables are resolved in load/store_name_table()
+
 		match address {
-			0..=0x1FFF => address,
-			0x2000..=0x3EFF => self.get_name_table_address_with_mirroring(address & 0x2FFF, rom),
+			0..=0x3EFF => address,
 			_ /* 0x3F00..=0x3FFF */ => {
 				// Addresses for palette
 				// 0x3F10/0x3F14/0x3F18/0x3F1C are mirrors of
@@ -581,7 +622,7 @@ impl Ppu {
 	}
 
 	#[inline]
-	fn fetch(&mut self, rom: &Rom) {
+	fn fetch(&mut self, rom: &mut Rom) {
 		// No fetch during post-rendering scanline 240 and vblank interval 241-260
 		if self.scanline >= 240 && self.scanline <= 260 {
 			return;
@@ -606,7 +647,25 @@ impl Ppu {
 			return;
 		}
 
+		// Tells mapper that the background tiles of the next scanline
+		// are going to be fetched
+		if self.cycle == 321 {
+			let next_scanline = match self.scanline {
+				261 => 0,
+				_ => self.scanline + 1
+			};
+			rom.notify_ppu_phase(match self.is_rendering_enabled() && next_scanline < 240 {
+				true => PpuPhase::Background(next_scanline),
+				false => PpuPhase::Idle
+			});
+		}
+
 		if (self.cycle >= 257 && self.cycle <= 320) || self.cycle >= 337 {
+			// Unused nametable fetches at cycle 337 and 339
+			if self.cycle == 337 || self.cycle == 339 {
+				let address = 0x2000 | (self.current_vram_address & 0x0FFF);
+				self.drive_address_bus_for_rendering(address, rom);
+			}
 			return;
 		}
 
@@ -642,7 +701,7 @@ impl Ppu {
 		};
 	}
 
-	fn fetch_name_table(&mut self, rom: &Rom) {
+	fn fetch_name_table(&mut self, rom: &mut Rom) {
 		// A nametable is a 1024 byte area of memory used by the PPU to lay out backgrounds.
 		// Each byte in the nametable controls one 8x8 pixel character cell, and each nametable
 		// has 30 rows of 32 tiles each, for 960 (0x3C0) bytes; the rest is used by each nametable's
@@ -660,10 +719,16 @@ impl Ppu {
 		// Here fetches a tile of a nametable.
 
 		// address is from http://wiki.nesdev.com/w/index.php/PPU_scrolling
-		self.name_table_latch = self.load(0x2000 | (self.current_vram_address & 0x0FFF), rom);
+		// Mapper may replace the data.
+		let address = 0x2000 | (self.current_vram_address & 0x0FFF);
+		self.drive_address_bus_for_rendering(address, rom);
+		self.name_table_latch = match rom.fetch_name_table(address as u32) {
+			Some(value) => value,
+			None => self.load(address, rom)
+		};
 	}
 
-	fn fetch_attribute_table(&mut self, rom: &Rom) {
+	fn fetch_attribute_table(&mut self, rom: &mut Rom) {
 		// @TODO: Implement properly
 
 		// The attribute table is a 64-byte array at the end of each nametable
@@ -686,7 +751,13 @@ impl Ppu {
 		// 5-3: high 3bits of coarse y
 		// 2-0: high 3bits of coarse x
 		// From http://wiki.nesdev.com/w/index.php/PPU_scrolling
-		let byte = self.load(0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07), rom);
+		// Mapper may replace the data.
+		let address = 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+		self.drive_address_bus_for_rendering(address, rom);
+		let byte = match rom.fetch_attribute_table(address as u32) {
+			Some(value) => value,
+			None => self.load(address, rom)
+		};
 
 		// byte includes four two bits
 		// 7-6: bottom right
@@ -725,18 +796,20 @@ impl Ppu {
 		};
 	}
 
-	fn fetch_pattern_table_low(&mut self, rom: &Rom) {
+	fn fetch_pattern_table_low(&mut self, rom: &mut Rom) {
 		let fine_scroll_y = (self.current_vram_address >> 12) & 0x7;
 		let index = self.ppuctrl.background_pattern_table_base_address() +
 			((self.name_table.load() as u16) << 4) + fine_scroll_y;
 		self.pattern_table_low_latch = self.load(index, rom);
+		self.notify_pattern_table_fetch(index, rom);
 	}
 
-	fn fetch_pattern_table_high(&mut self, rom: &Rom) {
+	fn fetch_pattern_table_high(&mut self, rom: &mut Rom) {
 		let fine_scroll_y = (self.current_vram_address >> 12) & 0x7;
 		let index = self.ppuctrl.background_pattern_table_base_address() +
 			((self.name_table.load() as u16) << 4) + fine_scroll_y;
 		self.pattern_table_high_latch = self.load(index + 0x8, rom);
+		self.notify_pattern_table_fetch(index + 0x8, rom);
 	}
 
 	#[inline]
@@ -794,8 +867,9 @@ impl Ppu {
 			}
 		}
 
-		// @TODO: check this driving IRQ counter for MMC3Mapper timing is correct
-		// @TODO: This is MMC3Mapper specific. Should this be here?
+		// Mappers counting scanlines without watching PPU address bus.
+		// MMC3 counts A12 rising edges instead, see drive_address_bus().
+		// @TODO: check this timing is correct
 
 		if self.cycle == 340 && self.scanline <= 240 &&
 			self.ppumask.is_background_visible() &&
@@ -900,7 +974,7 @@ impl Ppu {
 	}
 
 	#[inline]
-	fn evaluate_sprites(&mut self, rom: &Rom) {
+	fn evaluate_sprites(&mut self, rom: &mut Rom) {
 		// oamaddr is set to 0 during cycle 257-320 of the pre-render and visible scanlines.
 		if (self.scanline < 240 || self.scanline == 261) &&
 			self.cycle >= 257 && self.cycle <= 320 {
@@ -925,14 +999,17 @@ impl Ppu {
 			// and simplicity so far
 			self.secondary_oam.reset();
 		} else if self.cycle == 257 {
+			if self.is_rendering_enabled() {
+				rom.notify_ppu_phase(PpuPhase::Sprites(self.ppuctrl.sprite_height() == 16));
+			}
 			// Evaluate at a time at cycle 257 due to performance
 			// and simplicity so far
-			self.process_sprite_pixels(rom);
+			self.select_sprites();
 		}
 	}
 
 	#[inline]
-	fn process_sprite_pixels(&mut self, rom: &Rom) {
+	fn select_sprites(&mut self) {
 		// Clear sprite availability using fast memset
 		self.sprite_availables = [false; 256];
 
@@ -941,9 +1018,8 @@ impl Ppu {
 		let mut n = 0;
 
 		// Find up to eight sprite on this scan line from primary OAM and
-		// copy them to secondary OAM.
-		// And process all bits of a scanline for sprites here now
-		// for the performance and simplicity.
+		// copy them to secondary OAM. Their pixels are processed at their
+		// pattern table fetches, see fetch_sprites().
 		for i in 0..64 {
 			let s = self.primary_oam.get(i);
 			if s.on(y, height) {
@@ -953,68 +1029,171 @@ impl Ppu {
 					self.ppustatus.set_overflow();
 					break;
 				}
-				let base_x = s.get_x();
-				let y_in_sprite = s.get_y_in_sprite(y, height);
-				let msb = s.get_palette_num() as u16;
-				for j in 0..8 {
-					//
-					if base_x as u16 + j as u16 >= 256 {
-						break;
-					}
-					let x = base_x + j;
-					// No override with later sprites
-					if self.sprite_availables[x as usize] {
-						continue;
-					}
-					let x_in_sprite = match s.horizontal_flip() {
-						true => 7 - j,
-						false => j
-					};
-					// pattern table holds the lowest two bits of palette memory address
-					let lsb = self.get_pattern_table_element_for_sprite(&s, x_in_sprite, y_in_sprite, height, rom) as u16;
-					// the lowest two 0 bits means transparent (=no sprite pixel)
-					if lsb != 0 {
-						self.sprite_availables[x as usize] = true;
-						// Sprite palette indices are in 0x3F10-0x3F1F
-						self.sprite_palette_addresses[x as usize] = 0x3F10 | (msb << 2) | lsb;
-						self.sprite_ids[x as usize] = i;
-						self.sprite_priorities[x as usize] = s.get_priority();
-					}
-				}
 				self.secondary_oam.copy(n, s);
+				self.sprite_slot_ids[n as usize] = i;
 				n += 1;
 			}
 		}
 	}
 
-	fn get_name_table_address_with_mirroring(&self, address: u16, rom: &Rom) -> u16 {
-		let name_table_address = address & 0x2C00;
-		(address & 0x3FF) | match rom.mirroring_type() {
-			Mirrorings::SingleScreen => 0x2000,
-            Mirrorings::OneScreenLow => 0x2000,
-            Mirrorings::OneScreenHigh => 0x2400,
-			Mirrorings::Horizontal => match name_table_address {
-				0x2000 => 0x2000,
-				0x2400 => 0x2000,
-				0x2800 => 0x2800,
-				_ /* 0x2C00 */ => 0x2800
+	#[inline]
+	fn process_sprite_pixels(&mut self, slot: u8, rom: &mut Rom) {
+		// Process all bits of a scanline for the sprite here now
+		// for the performance and simplicity.
+
+		let s = self.secondary_oam.get(slot);
+		// Empty slot
+		if s.get_y() == 0xFF {
+			return;
+		}
+
+		let i = self.sprite_slot_ids[slot as usize];
+		let y = self.scanline as u8;
+		let height = self.ppuctrl.sprite_height();
+		let base_x = s.get_x();
+		let y_in_sprite = s.get_y_in_sprite(y, height);
+		let msb = s.get_palette_num() as u16;
+		for j in 0..8 {
+			//
+			if base_x as u16 + j as u16 >= 256 {
+				break;
+			}
+			let x = base_x + j;
+			// No override with later sprites
+			if self.sprite_availables[x as usize] {
+				continue;
+			}
+			let x_in_sprite = match s.horizontal_flip() {
+				true => 7 - j,
+				false => j
+			};
+			// pattern table holds the lowest two bits of palette memory address
+			let lsb = self.get_pattern_table_element_for_sprite(&s, x_in_sprite, y_in_sprite, height, rom) as u16;
+			// the lowest two 0 bits means transparent (=no sprite pixel)
+			if lsb != 0 {
+				self.sprite_availables[x as usize] = true;
+				// Sprite palette indices are in 0x3F10-0x3F1F
+				self.sprite_palette_addresses[x as usize] = 0x3F10 | (msb << 2) | lsb;
+				self.sprite_ids[x as usize] = i;
+				self.sprite_priorities[x as usize] = s.get_priority();
+			}
+		}
+	}
+
+	#[inline]
+	fn fetch_sprites(&mut self, rom: &mut Rom) {
+		// Cycle 257-320 of the visible and pre-render scanlines:
+		//   The pattern table data of the eight sprites in secondary OAM
+		//   are fetched, 8 cycles per sprite
+		//     - Garbage nametable byte
+		//     - Garbage nametable byte
+		//     - Pattern table tile low
+		//     - Pattern table tile high
+		//   Empty slots fetch tile 0xFF.
+		// The sprite pixels are processed at the tile low fetch, after the
+		// fetches of the earlier sprites MMC2/MMC4 may switch banks on.
+
+		if (self.scanline >= 240 && self.scanline != 261) ||
+			self.cycle < 257 || self.cycle > 320 {
+			return;
+		}
+
+		let slot = ((self.cycle - 257) / 8) as u8;
+		match (self.cycle - 257) % 8 {
+			0 | 2 => {
+				let address = 0x2000 | (self.current_vram_address & 0x0FFF);
+				self.drive_address_bus_for_rendering(address, rom);
+			},
+			4 => {
+				if self.scanline < 240 {
+					self.process_sprite_pixels(slot, rom);
+				}
+				let address = self.get_pattern_table_address_for_slot(slot);
+				self.notify_pattern_table_fetch(address, rom);
 			},
-			Mirrorings::Vertical => match name_table_address {
-				0x2000 => 0x2000,
-				0x2400 => 0x2400,
-				0x2800 => 0x2000,
-				_ /* 0x2C00 */ => 0x2400
+			6 => {
+				let address = self.get_pattern_table_address_for_slot(slot);
+				self.notify_pattern_table_fetch(address + 8, rom);
 			},
-			Mirrorings::FourScreen => name_table_address
+			_ => {}
+		};
+	}
+
+	fn get_pattern_table_address_for_slot(&self, slot: u8) -> u16 {
+		let y = self.scanline as u8;
+		let height = self.ppuctrl.sprite_height();
+		let s = self.secondary_oam.get(slot);
+		// Y 0xFF is never in the visible scanlines, an empty slot.
+		// Secondary OAM isn't evaluated for the pre-render scanline.
+		match self.scanline < 240 && s.get_y() != 0xFF {
+			true => self.get_pattern_table_address_for_sprite(&s, s.get_y_in_sprite(y, height), height),
+			false => {
+				let empty = Sprite { byte0: 0xFF, byte1: 0xFF, byte2: 0xFF, byte3: 0xFF };
+				self.get_pattern_table_address_for_sprite(&empty, 0, height)
+			}
 		}
 	}
 
+	// Pattern table fetch for rendering. Mappers may switch banks on it.
+	fn notify_pattern_table_fetch(&mut self, address: u16, rom: &mut Rom) {
+		if self.is_rendering_enabled() {
+			self.drive_address_bus(address, rom);
+			rom.notify_ppu_read(address as u32);
+		}
+	}
+
+	fn drive_address_bus_for_rendering(&mut self, address: u16, rom: &mut Rom) {
+		if self.is_rendering_enabled() {
+			self.drive_address_bus(address, rom);
+		}
+	}
+
+	// 0x2006 and 0x2007 accesses put vram_address on the bus
+	// unless rendering fetches own it
+	fn drive_address_bus_from_cpu(&mut self, address: u16, rom: &mut Rom) {
+		if !self.is_rendering_enabled() || (self.scanline >= 240 && self.scanline <= 260) {
+			self.drive_address_bus(address, rom);
+		}
+	}
+
+	fn drive_address_bus(&mut self, address: u16, rom: &mut Rom) {
+		let a12 = (address & 0x1000) != 0;
+		if a12 && !self.address_bus_a12 {
+			rom.notify_ppu_a12_rise(self.a12_low_cycles);
+		} else if !a12 && self.address_bus_a12 {
+			self.a12_low_cycles = 0;
+		}
+		self.address_bus_a12 = a12;
+	}
+
+	// VRAM address of the nametable page. Four-screen boards have
+	// the nametables 2 and 3 on the cartridge, see Rom::name_table_source().
+	fn get_name_table_address(&self, address: u16, page: u8) -> u16 {
+		0x2000 | ((page as u16) << 10) | (address & 0x3FF)
+	}
+
+	fn is_rendering_enabled(&self) -> bool {
+		self.ppumask.is_background_visible() || self.ppumask.is_sprites_visible()
+	}
+
 	fn get_pattern_table_element_for_sprite(&self, s: &Sprite, x_in_sprite: u8, y_in_sprite: u8, height: u8, rom: &Rom) -> u8 {
 		// Get an element from pattern table consisting of the lowest two bits
 		// of palette memory address for sprites
 
+		let address = self.get_pattern_table_address_for_sprite(s, y_in_sprite, height);
+
+		// Each tile has 16bytes (8x2 rows)
+		// The first 8bytes in a tile are for 0-bit,
+		// while the second 8bytes are for 1-bit of palette memory address
+		let lower_bits = self.load(address, rom);
+		let higher_bits = self.load(address + 8, rom);
+		let pos = 7 - x_in_sprite; // xxx_bits[7:0] corresponds to x_in_sprite[0:7] 
+		(((higher_bits >> pos) & 1) << 1) | ((lower_bits >> pos) & 1)
+	}
+
+	fn get_pattern_table_address_for_sprite(&self, s: &Sprite, y_in_sprite: u8, height: u8) -> u16 {
 		// 8x8 sprite and 8x16 sprite calculates tile address differently
-		let address = match height == 8 {
+		match height == 8 {
 			true => {
 				// 8x8 sprite
 				// ppuctrl selects base address 0x0000 or 0x1000
@@ -1037,15 +1216,7 @@ impl Ppu {
 				let row = ((y_in_sprite % 8) + ((y_in_sprite & 0x8) << 1)) as u16;
 				base_address + byte_offset + row
 			}
-		};
-
-		// Each tile has 16bytes (8x2 rows)
-		// The first 8bytes in a tile are for 0-bit,
-		// while the second 8bytes are for 1-bit of palette memory address
-		let lower_bits = self.load(address, rom);
-		let higher_bits = self.load(address + 8, rom);
-		let pos = 7 - x_in_sprite; // xxx_bits[7:0] corresponds to x_in_sprite[0:7] 
-		(((higher_bits >> pos) & 1) << 1) | ((lower_bits >> pos) & 1)
+		}
 	}
 
 	#[inline(always)]
@@ -1108,6 +1279,8 @@ impl Ppu {
 			ppumask: self.ppumask.register.get_data(),
 			ppustatus: self.ppustatus.register.get_data(),
 			data_bus: self.data_bus,
+			address_bus_a12: self.address_bus_a12,
+			a12_low_cycles: self.a12_low_cycles,
 			nmi_interrupted: self.nmi_interrupted,
 			irq_interrupted: self.irq_interrupted,
 		}
@@ -1142,6 +1315,8 @@ impl Ppu {
 		self.ppumask.register.set_data(state.ppumask);
 		self.ppustatus.register.set_data(state.ppustatus);
 		self.data_bus = state.data_bus;
+		self.address_bus_a12 = state.address_bus_a12;
+		self.a12_low_cycles = state.a12_low_cycles;
 		self.nmi_interrupted = state.nmi_interrupted;
 		self.irq_interrupted = state.irq_interrupted;
 	}
@@ -1457,3 +1632,135 @@ impl Sprite {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests_ppu {
+	use super::*;
+	use default_display::DefaultDisplay;
+
+	// Mapper 4 with the IRQ counter reload value 10
+	fn mmc3_rom() -> Rom {
+		let mut v = vec![0; 16];
+		v[0] = 0x4e; // N
+		v[1] = 0x45; // E
+		v[2] = 0x53; // S
+		v[3] = 0x1a; // magic number
+		v[4] = 2;
+		v[5] = 1;
+		v[6] = 0x40;
+		v.resize(16 + 2 * 0x4000 + 0x2000, 0);
+		let mut rom = Rom::new(v).unwrap();
+		rom.store(0xC000, 10);
+		rom.store(0xC001, 0);
+		rom.store(0xE001, 0);
+		rom
+	}
+
+	fn ppu(ppuctrl: u8, ppumask: u8, rom: &mut Rom) -> Ppu {
+		let mut ppu = Ppu::new(Box::new(DefaultDisplay::new()));
+		ppu.store_register(0x2000, ppuctrl, rom);
+		ppu.store_register(0x2001, ppumask, rom);
+		// Sprites out of the screen
+		for _i in 0..256 {
+			ppu.store_register(0x2004, 0xFF, rom);
+		}
+		ppu
+	}
+
+	fn run_to(ppu: &mut Ppu, rom: &mut Rom, scanline: u16, cycle: u16) {
+		while ppu.scanline != scanline || ppu.cycle != cycle {
+			ppu.step(rom);
+		}
+	}
+
+	#[test]
+	fn a12_rises_at_sprite_fetches() {
+		// Background 0x0000, sprites 0x1000. The eleventh rise fires IRQ.
+		let mut rom = mmc3_rom();
+		let mut ppu = ppu(0x08, 0x18, &mut rom);
+		run_to(&mut ppu, &mut rom, 10, 257);
+		assert!(!rom.irq_pending());
+		run_to(&mut ppu, &mut rom, 10, 265);
+		assert!(rom.irq_pending());
+	}
+
+	#[test]
+	fn a12_rises_at_background_fetches() {
+		// Background 0x1000, sprites 0x0000
+		let mut rom = mmc3_rom();
+		let mut ppu = ppu(0x10, 0x18, &mut rom);
+		run_to(&mut ppu, &mut rom, 10, 321);
+		assert!(!rom.irq_pending());
+		run_to(&mut ppu, &mut rom, 10, 329);
+		assert!(rom.irq_pending());
+	}
+
+	#[test]
+	fn a12_rises_with_empty_8x16_sprites() {
+		// Empty slots fetch tile 0xFF at 0x1000 in 8x16 mode
+		let mut rom = mmc3_rom();
+		let mut ppu = ppu(0x20, 0x18, &mut rom);
+		run_to(&mut ppu, &mut rom, 10, 257);
+		assert!(!rom.irq_pending());
+		run_to(&mut ppu, &mut rom, 10, 265);
+		assert!(rom.irq_pending());
+	}
+
+	#[test]
+	fn sprite_fetches_switch_mmc2_banks() {
+		// Mapper 9, FD bank 1 and FE bank 0 for 0x0000 - 0x0FFF.
+		// Tile 1 is opaque only in bank 1.
+		let mut v = vec![0; 16];
+		v[0] = 0x4e; // N
+		v[1] = 0x45; // E
+		v[2] = 0x53; // S
+		v[3] = 0x1a; // magic number
+		v[4] = 2;
+		v[5] = 1;
+		v[6] = 0x90;
+		v.resize(16 + 2 * 0x4000 + 0x2000, 0);
+		for row in 0..8 {
+			v[16 + 2 * 0x4000 + 0x1010 + row] = 0xFF;
+		}
+		let mut rom = Rom::new(v).unwrap();
+		rom.store(0xB000, 1);
+		rom.store(0xC000, 0);
+
+		// Sprites 0x0000 and background 0x1000. Sprite 0 with tile 0xFD
+		// switches to FD bank, sprite 1 with tile 1 next to it uses it.
+		let mut ppu = ppu(0x10, 0x18, &mut rom);
+		ppu.store_register(0x2003, 0, &mut rom);
+		for value in [9, 0xFD, 0, 0, 9, 0x01, 0, 16].iter() {
+			ppu.store_register(0x2004, *value, &mut rom);
+		}
+		run_to(&mut ppu, &mut rom, 9, 321);
+		assert!(!ppu.sprite_availables[0]);
+		assert!(ppu.sprite_availables[16]);
+		assert_eq!(1, ppu.sprite_ids[16]);
+	}
+
+	#[test]
+	fn no_a12_rises_without_rendering() {
+		let mut rom = mmc3_rom();
+		let mut ppu = ppu(0x08, 0x00, &mut rom);
+		run_to(&mut ppu, &mut rom, 240, 0);
+		assert!(!rom.irq_pending());
+	}
+
+	#[test]
+	fn a12_rises_by_ppuaddr() {
+		// Toggling 0x1000 with 0x2006 writes out of rendering
+		let mut rom = mmc3_rom();
+		let mut ppu = ppu(0x00, 0x00, &mut rom);
+		for _i in 0..11 {
+			for address in [0x0000, 0x1000].iter() {
+				ppu.store_register(0x2006, (address >> 8) as u8, &mut rom);
+				ppu.store_register(0x2006, *address as u8, &mut rom);
+				for _j in 0..12 {
+					ppu.step(&mut rom);
+				}
+			}
+		}
+		assert!(rom.irq_pending());
+	}
+}
@@ -1,14 +1,53 @@
+use std::fmt;
+use std::error::Error;
+
 use memory::Memory;
-use mapper::{Mapper, MapperFactory};
-use save_state::MapperState;
+use mapper::{Mapper, MapperFactory, FdsMapper, NsfMapper, CpuMapping, NameTableSource, PpuPhase};
+use fds::{FdsDisk, FDS_BIOS_SIZE, is_fds_image};
+use nsf::{Nsf, NsfMetadata, is_nsf};
+use unif::{Unif, is_unif};
+use database::{RomDatabase, DatabaseEntry, board_name, sha1};
+use patch::{crc32, crc32_update};
+use save_state::CartridgeState;
 
 pub struct Rom {
 	header: RomHeader,
-	memory: Memory,
-	mapper: Box<dyn Mapper>
+	prg_rom: Memory,
+	chr_rom: Memory,
+	// Used in place of CHR-ROM if the cart has no CHR-ROM
+	chr_ram: Memory,
+	// Work RAM or battery backed save RAM (SRAM), 0x6000 - 0x7FFF in general
+	prg_ram: Memory,
+	// Extra 2KB VRAM of four-screen boards for the nametables 2 and 3.
+	// Empty unless the header says four-screen.
+	four_screen_vram: Memory,
+	// Whether battery backed RAM has been written since the last flush
+	sram_dirty: bool,
+	// Whether battery backed RAM has been imported, which the trainer
+	// must not overwrite at bootup
+	sram_imported: bool,
+	// 512-byte trainer loaded into 0x7000 - 0x71FF at bootup. Empty if none.
+	trainer: Vec<u8>,
+	mapper: Box<dyn Mapper>,
+	info: RomInfo
 }
 
-pub static HEADER_SIZE: usize = 16;
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+
+// Header standing for the FDS RAM adapter. NES 2.0, mapper 20, 8KB PRG-ROM
+// (the BIOS) in the exponent notation, 32KB PRG-RAM and 8KB CHR-RAM.
+const FDS_HEADER: [u8; HEADER_SIZE] = [
+	0x4E, 0x45, 0x53, 0x1A, 0x34, 0x00, 0x40, 0x18,
+	0x00, 0x0F, 0x09, 0x07, 0x00, 0x00, 0x00, 0x00
+];
+
+// Header standing for the NSF player. NES 2.0, mapper 0 and 8KB CHR-RAM.
+// The NSF mapper holds the music data and RAM itself.
+const NSF_HEADER: [u8; HEADER_SIZE] = [
+	0x4E, 0x45, 0x53, 0x1A, 0x00, 0x00, 0x00, 0x08,
+	0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x00
+];
 
 pub enum Mirrorings {
 	SingleScreen,
@@ -19,55 +58,495 @@ pub enum Mirrorings {
     OneScreenHigh,
 }
 
+/// Reasons a ROM image is rejected by `Rom::new()`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RomError {
+	// The image is shorter than the 16-byte header
+	TruncatedHeader { size: usize },
+	// The image doesn't start with "NES" 0x1A
+	BadMagic,
+	// The header declares a 512-byte trainer the image doesn't hold
+	TruncatedTrainer { expected: usize, actual: usize },
+	// The header declares no PRG-ROM
+	EmptyPrgRom,
+	TruncatedPrgRom { expected: usize, actual: usize },
+	TruncatedChrRom { expected: usize, actual: usize },
+	UnsupportedMapper(u16),
+	// FDS disk image, which needs the BIOS. Load with `Rom::new_fds()`
+	FdsImage,
+	// `Rom::new_fds()` got data not an FDS disk image
+	BadFdsImage,
+	// FDS BIOS must be 8KB
+	BadFdsBios { size: usize },
+	// NSF or NSFe data is broken, for the reason
+	BadNsf(&'static str),
+	// UNIF data is broken, for the reason
+	BadUnif(&'static str),
+	// UNIF board name no mapper is known for
+	UnsupportedUnifBoard(String)
+}
+
+impl fmt::Display for RomError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			RomError::TruncatedHeader { size } =>
+				write!(f, "File is too small to be a NES ROM ({} bytes)", size),
+			RomError::BadMagic =>
+				write!(f, "Not an iNES or NES 2.0 ROM (bad magic number)"),
+			RomError::TruncatedTrainer { expected, actual } =>
+				write!(f, "Trainer is truncated ({} of {} bytes)", actual, expected),
+			RomError::EmptyPrgRom =>
+				write!(f, "Header declares no PRG-ROM"),
+			RomError::TruncatedPrgRom { expected, actual } =>
+				write!(f, "PRG-ROM is truncated ({} of {} bytes)", actual, expected),
+			RomError::TruncatedChrRom { expected, actual } =>
+				write!(f, "CHR-ROM is truncated ({} of {} bytes)", actual, expected),
+			RomError::UnsupportedMapper(num) =>
+				write!(f, "Unsupported mapper {}", num),
+			RomError::FdsImage =>
+				write!(f, "FDS disk image needs the FDS BIOS"),
+			RomError::BadFdsImage =>
+				write!(f, "Not an FDS disk image"),
+			RomError::BadFdsBios { size } =>
+				write!(f, "FDS BIOS must be {} bytes ({} bytes)", FDS_BIOS_SIZE, size),
+			RomError::BadNsf(reason) =>
+				write!(f, "Broken NSF ({})", reason),
+			RomError::BadUnif(reason) =>
+				write!(f, "Broken UNIF ({})", reason),
+			RomError::UnsupportedUnifBoard(board) =>
+				write!(f, "Unsupported UNIF board {}", board)
+		}
+	}
+}
+
+impl Error for RomError {}
+
 impl Rom {
-	pub fn new(data: Vec<u8>) -> Option<Self> {
-        if data.len() < HEADER_SIZE {
-            return None;
-        }
-		let header = RomHeader::new(data[0..HEADER_SIZE].to_vec());
+	/**
+	 * Loads iNES, NES 2.0, UNIF or NSF data. The header is corrected
+	 * with the embedded game database, which only has the ROMs bundled
+	 * in roms/. Correcting other games is opt-in, with the full
+	 * nes20db.xml given to new_with_database().
+	 */
+	pub fn new(data: Vec<u8>) -> Result<Self, RomError> {
+		Rom::new_with_database(data, RomDatabase::embedded())
+	}
+
+	/**
+	 * Same as new() but corrects the header with the given game
+	 * database instead of the embedded one, e.g. the full nes20db.xml
+	 * parsed with RomDatabase::parse().
+	 */
+	pub fn new_with_database(data: Vec<u8>, database: &RomDatabase) -> Result<Self, RomError> {
+		if is_nsf(&data) {
+			return Rom::new_nsf(&data);
+		}
+
+		if is_unif(&data) {
+			return Rom::new_unif(&data, database);
+		}
+
+		if data.len() < HEADER_SIZE {
+			return Err(RomError::TruncatedHeader { size: data.len() });
+		}
+
+		let header = RomHeader::new(&data);
+
+		if !header.is_nes() {
+			return Err(match is_fds_image(&data) {
+				true => RomError::FdsImage,
+				false => RomError::BadMagic
+			});
+		}
+
+		if header.prg_rom_size() == 0 {
+			return Err(RomError::EmptyPrgRom);
+		}
+
+		let mut offset = HEADER_SIZE;
+
+		// The trainer precedes PRG-ROM data
+		let trainer = match header.has_trainer() {
+			true => slice_rom(&data, offset, TRAINER_SIZE)
+				.map_err(|actual| RomError::TruncatedTrainer {
+					expected: TRAINER_SIZE,
+					actual: actual
+				})?,
+			false => vec![]
+		};
+		offset += trainer.len();
+
+		let prg_rom = slice_rom(&data, offset, header.prg_rom_size())
+			.map_err(|actual| RomError::TruncatedPrgRom {
+				expected: header.prg_rom_size(),
+				actual: actual
+			})?;
+		offset += header.prg_rom_size();
+
+		let chr_rom = slice_rom(&data, offset, header.chr_rom_size())
+			.map_err(|actual| RomError::TruncatedChrRom {
+				expected: header.chr_rom_size(),
+				actual: actual
+			})?;
+
+		Rom::from_parts(header, trainer, prg_rom, chr_rom, database)
+	}
+
+	/**
+	 * UNIF image, loaded as iNES with a NES 2.0 header standing for
+	 * the board.
+	 */
+	fn new_unif(data: &[u8], database: &RomDatabase) -> Result<Self, RomError> {
+		let unif = Unif::new(data).map_err(RomError::BadUnif)?;
+		let header = match unif.nes20_header() {
+			Some(bytes) => RomHeader::new(&bytes),
+			None => return Err(RomError::UnsupportedUnifBoard(unif.board))
+		};
+		let mut rom = Rom::from_parts(header, vec![], unif.prg_rom, unif.chr_rom, database)?;
+		rom.info.board = unif.board;
+		Ok(rom)
+	}
+
+	// Headers of the dumps in the game database are corrected with it
+	fn from_parts(mut header: RomHeader, trainer: Vec<u8>, prg_rom: Vec<u8>, chr_rom: Vec<u8>,
+		database: &RomDatabase) -> Result<Self, RomError> {
+		// SHA-1 is only for telling apart the games of the same CRC32
+		let data_crc32 = crc32_update(crc32(&prg_rom), &chr_rom);
+		let data_sha1 = match database.contains_crc32(data_crc32) {
+			true => Some(sha1(&[&prg_rom, &chr_rom])),
+			false => None
+		};
+		let entry = data_sha1.as_ref().and_then(|data_sha1| database.find(data_crc32, data_sha1));
+		if let Some(entry) = entry {
+			header.apply_database_entry(entry);
+		}
+		let info = RomInfo {
+			title: entry.map(|entry| entry.title.clone()),
+			sha1: data_sha1,
+			in_database: entry.is_some(),
+			..RomInfo::new(&header, data_crc32)
+		};
+
 		let mapper = match MapperFactory::create(&header) {
-            Some(m) => m,
-            None => return None,
-        };
-		Some(Rom {
+			Some(m) => m,
+			None => return Err(RomError::UnsupportedMapper(header.mapper_num()))
+		};
+
+		let chr_ram_size = header.chr_ram_size() + header.chr_nvram_size();
+		let mut prg_ram_size = header.prg_ram_size() + header.prg_nvram_size();
+
+		// Trainers need RAM at 0x7000 even if the header says nothing about it
+		if !trainer.is_empty() && prg_ram_size < 0x2000 {
+			prg_ram_size = 0x2000;
+		}
+
+		let four_screen_vram_size = match header.mirroring_type() {
+			Mirrorings::FourScreen => 0x800,
+			_ => 0
+		};
+
+		Ok(Rom {
+			header: header,
+			prg_rom: Memory::new(prg_rom),
+			chr_rom: Memory::new(chr_rom),
+			chr_ram: Memory::new(vec![0; chr_ram_size]),
+			prg_ram: Memory::new(vec![0; prg_ram_size]),
+			four_screen_vram: Memory::new(vec![0; four_screen_vram_size]),
+			sram_dirty: false,
+			sram_imported: false,
+			trainer: trainer,
+			mapper: mapper,
+			info: info
+		})
+	}
+
+	/**
+	 * FDS disk image (.fds, with or without the fwNES header) played
+	 * with the 8KB BIOS (disksys.rom). The first side is inserted.
+	 */
+	pub fn new_fds(image: Vec<u8>, bios: Vec<u8>) -> Result<Self, RomError> {
+		if bios.len() != FDS_BIOS_SIZE {
+			return Err(RomError::BadFdsBios { size: bios.len() });
+		}
+
+		let disk = match FdsDisk::new(&image) {
+			Some(disk) => disk,
+			None => return Err(RomError::BadFdsImage)
+		};
+
+		let header = RomHeader::new(&FDS_HEADER);
+		let chr_ram_size = header.chr_ram_size();
+		let prg_ram_size = header.prg_ram_size();
+		let info = RomInfo::new(&header, crc32(&image));
+
+		Ok(Rom {
 			header: header,
-			memory: Memory::new(data[HEADER_SIZE..].to_vec()),
-			mapper: mapper
+			prg_rom: Memory::new(bios),
+			chr_rom: Memory::new(vec![]),
+			chr_ram: Memory::new(vec![0; chr_ram_size]),
+			prg_ram: Memory::new(vec![0; prg_ram_size]),
+			four_screen_vram: Memory::new(vec![]),
+			sram_dirty: false,
+			sram_imported: false,
+			trainer: vec![],
+			mapper: Box::new(FdsMapper::new(disk)),
+			info: info
 		})
 	}
 
+	/**
+	 * NSF or NSFe music played with the NSF player mapper
+	 * starting from the starting track.
+	 */
+	fn new_nsf(data: &[u8]) -> Result<Self, RomError> {
+		let nsf = Nsf::new(data).map_err(RomError::BadNsf)?;
+		let header = RomHeader::new(&NSF_HEADER);
+		let chr_ram_size = header.chr_ram_size();
+		let info = RomInfo {
+			title: Some(nsf.metadata.title.clone()),
+			board: "NSF".to_string(),
+			..RomInfo::new(&header, crc32(data))
+		};
+		let mapper = NsfMapper::new(nsf, &header);
+
+		Ok(Rom {
+			header: header,
+			prg_rom: Memory::new(vec![]),
+			chr_rom: Memory::new(vec![]),
+			chr_ram: Memory::new(vec![0; chr_ram_size]),
+			prg_ram: Memory::new(vec![]),
+			four_screen_vram: Memory::new(vec![]),
+			sram_dirty: false,
+			sram_imported: false,
+			trainer: vec![],
+			mapper: Box::new(mapper),
+			info: info
+		})
+	}
+
+	/**
+	 * Placeholder cartridge (NROM without any data) used until a real Rom is set.
+	 * Every load returns 0.
+	 */
+	pub fn empty() -> Self {
+		let mut data = vec![0; HEADER_SIZE];
+		data[0..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+		data[4] = 1;
+		let header = RomHeader::new(&data);
+		let mapper = MapperFactory::create(&header).unwrap();
+		let info = RomInfo::new(&header, crc32(&[]));
+		Rom {
+			header: header,
+			prg_rom: Memory::new(vec![]),
+			chr_rom: Memory::new(vec![]),
+			chr_ram: Memory::new(vec![]),
+			prg_ram: Memory::new(vec![]),
+			four_screen_vram: Memory::new(vec![]),
+			sram_dirty: false,
+			sram_imported: false,
+			trainer: vec![],
+			mapper: mapper,
+			info: info
+		}
+	}
+
+	pub fn bootup(&mut self) {
+		// Trainer is mapped into 0x7000 - 0x71FF unless saved
+		// battery backed RAM has been imported there
+		if self.sram_imported {
+			return;
+		}
+		for (i, value) in self.trainer.iter().enumerate() {
+			self.prg_ram.store(0x1000 + i as u32, *value);
+		}
+	}
+
 	/**
 	 * CPU memory address:
-	 * 0x0000 - 0x1FFF: Character ROM access
-	 * 0x8000 - 0xFFFF: Program ROM access
+	 * 0x0000 - 0x1FFF: Character ROM (or RAM) access
+	 * 0x6000 - 0xFFFF: PRG-RAM and Program ROM access
 	 *
 	 * To access wide range ROM data with limited CPU memory address space
 	 * Mapper maps CPU memory address to ROM's.
 	 * In general writing control registers in Mapper via .store() switches bank.
+	 *
+	 * Mapped addresses beyond the ROM size wrap around as incomplete
+	 * address decoding does on real cartridges.
 	 */
 	pub fn load(&self, address: u32) -> u8 {
-		let mut address_in_rom = 0 as u32;
 		if address < 0x2000 {
-			// load from character rom
-			address_in_rom += self.header.prg_rom_bank_num() as u32 * 0x4000;
-			address_in_rom += self.mapper.map_for_chr_rom(address);
-		} else {
-			address_in_rom += self.mapper.map(address);
+			let memory = match self.mapper.is_chr_ram(address) {
+				true => &self.chr_ram,
+				false => self.chr_memory()
+			};
+			return load_wrapped(memory, self.mapper.map_for_chr_rom(address));
+		}
+		match self.mapper.map_for_cpu(address) {
+			CpuMapping::ProgramRom(address_in_rom) => load_wrapped(&self.prg_rom, address_in_rom),
+			CpuMapping::ProgramRam(address_in_ram) => load_wrapped(&self.prg_ram, address_in_ram),
+			CpuMapping::Value(value) => value,
+			CpuMapping::Unmapped => 0
+		}
+	}
+
+	fn chr_memory(&self) -> &Memory {
+		match self.has_chr_rom() {
+			true => &self.chr_rom,
+			false => &self.chr_ram
 		}
-		self.memory.load(address_in_rom)
 	}
 
 	pub fn load_without_mapping(&self, address: u32) -> u8 {
-		self.memory.load(address)
+		load_wrapped(&self.prg_rom, address)
 	}
 
 	/**
-	 * In general writing with ROM address space updates control registers in Mapper.
+	 * 0x0000 - 0x1FFF: Writes CHR-RAM. Writes to CHR-ROM are ignored.
+	 *                  Boards with both map them with Mapper.is_chr_ram().
+	 * 0x6000 - 0xFFFF: Writes PRG-RAM if the mapper maps it there.
+	 * 0x6000 - 0x7FFF: Also reaches the mapper registers, if any.
+	 * 0x8000 - 0xFFFF: In general writing with ROM address space
+	 *                  updates control registers in Mapper.
+	 *                  On boards with bus conflicts the ROM drives
+	 *                  the data bus too, the value is ANDed with it.
 	 */
 	pub fn store(&mut self, address: u32, value: u8) {
+		if address < 0x2000 {
+			let capacity = self.chr_ram.capacity();
+			if (!self.has_chr_rom() || self.mapper.is_chr_ram(address)) && capacity > 0 {
+				let address_in_ram = self.mapper.map_for_chr_rom(address) % capacity;
+				self.chr_ram.store(address_in_ram, value);
+			}
+			return;
+		}
+		if let CpuMapping::ProgramRam(address_in_ram) = self.mapper.map_for_cpu(address) {
+			if self.mapper.prg_ram_writable(address) {
+				self.store_prg_ram_internal(address_in_ram, value);
+			}
+		}
+		if address < 0x8000 {
+			self.mapper.store_register(address, value);
+			return;
+		}
+		let value = match self.mapper.has_bus_conflicts() {
+			true => value & self.load(address),
+			false => value
+		};
 		self.mapper.store(address, value);
 	}
 
+	/**
+	 * 0x6000 - 0x7FFF: PRG-RAM access without mapping.
+	 * Carts without PRG-RAM return 0.
+	 */
+	pub fn load_prg_ram(&self, address: u32) -> u8 {
+		load_wrapped(&self.prg_ram, address - 0x6000)
+	}
+
+	pub fn store_prg_ram(&mut self, address: u32, value: u8) {
+		self.store_prg_ram_internal(address - 0x6000, value);
+	}
+
+	fn store_prg_ram_internal(&mut self, address: u32, value: u8) {
+		let capacity = self.prg_ram.capacity();
+		if capacity == 0 {
+			return;
+		}
+		let address_in_ram = address % capacity;
+		if self.prg_ram.load(address_in_ram) != value {
+			self.prg_ram.store(address_in_ram, value);
+			if self.has_battery_backed_prg_ram() {
+				self.sram_dirty = true;
+			}
+		}
+	}
+
+	fn has_battery_backed_prg_ram(&self) -> bool {
+		self.header.has_battery_backed_ram() && self.prg_ram.capacity() > 0
+	}
+
+	/// Whether the cart has battery backed RAM. FDS disks count
+	/// as it to persist disk writes in the same way.
+	pub fn has_battery_backed_ram(&self) -> bool {
+		self.has_battery_backed_prg_ram() || self.mapper.disk_side_count() > 0
+	}
+
+	/// Copy of battery backed RAM for persisting. Empty if the cart has no battery.
+	/// For FDS the disk image with the writes.
+	pub fn export_sram(&self) -> Vec<u8> {
+		if let Some(image) = self.mapper.export_disk() {
+			return image;
+		}
+		match self.has_battery_backed_prg_ram() {
+			true => self.prg_ram.get_data(),
+			false => vec![]
+		}
+	}
+
+	/// Restores battery backed RAM exported with export_sram().
+	/// Data longer than the RAM is ignored.
+	pub fn import_sram(&mut self, data: &[u8]) {
+		if self.mapper.disk_side_count() > 0 {
+			self.mapper.import_disk(data);
+			return;
+		}
+		if self.has_battery_backed_prg_ram() {
+			self.prg_ram.set_data(data);
+			self.sram_dirty = false;
+			self.sram_imported = true;
+		}
+	}
+
+	pub fn is_sram_dirty(&self) -> bool {
+		self.sram_dirty || self.mapper.is_disk_dirty()
+	}
+
+	pub fn mark_sram_flushed(&mut self) {
+		self.sram_dirty = false;
+		self.mapper.mark_disk_flushed();
+	}
+
+	/// Disk side count of FDS. 0 for cartridges.
+	pub fn disk_side_count(&self) -> usize {
+		self.mapper.disk_side_count()
+	}
+
+	/// Inserted FDS disk side. None while ejected and for cartridges.
+	pub fn disk_side(&self) -> Option<usize> {
+		self.mapper.disk_side()
+	}
+
+	/// Inserts the FDS disk side, or ejects the disk with None.
+	/// A side out of range ejects too.
+	pub fn insert_disk(&mut self, side: Option<usize>) {
+		self.mapper.insert_disk(side);
+	}
+
+	/// NSF metadata. None for cartridges.
+	pub fn nsf_metadata(&self) -> Option<&NsfMetadata> {
+		self.mapper.nsf_metadata()
+	}
+
+	/// Playing NSF track, 0-based
+	pub fn track(&self) -> u8 {
+		self.mapper.track()
+	}
+
+	/// Selects the NSF track. Tracks out of range are ignored.
+	/// Needs a CPU reset to start playing it.
+	pub fn select_track(&mut self, track: u8) {
+		self.mapper.select_track(track);
+	}
+
+	pub fn header(&self) -> &RomHeader {
+		&self.header
+	}
+
+	/// Title, region, board and hashes of the ROM, for frontends
+	pub fn info(&self) -> &RomInfo {
+		&self.info
+	}
+
 	pub fn valid(&self) -> bool {
 		self.header.is_nes()
 	}
@@ -83,130 +562,541 @@ impl Rom {
 		}
 	}
 
-	// @TODO: MMC3Mapper specific. Should this method be here?
+	/**
+	 * Tells mapper that PPU fetched pattern table data at the address
+	 * (0x0000 - 0x1FFF) for rendering. Some mappers switch banks on it.
+	 */
+	pub fn notify_ppu_read(&mut self, address: u32) {
+		self.mapper.notify_ppu_read(address);
+	}
+
+	/**
+	 * Tells mapper that line A12 of PPU address bus rose after
+	 * being low for the PPU cycles.
+	 */
+	pub fn notify_ppu_a12_rise(&mut self, low_cycles: u32) {
+		self.mapper.notify_ppu_a12_rise(low_cycles);
+	}
+
+	// @TODO: Only Sunsoft uses it. Should this method be here?
 	pub fn irq_interrupted(&mut self) -> bool {
 		self.mapper.drive_irq_counter()
 	}
 
+	/**
+	 * 0x4020 - 0x5FFF: Mapper registers. None if the mapper
+	 * has no register at the address.
+	 */
+	pub fn load_register(&mut self, address: u32) -> Option<u8> {
+		self.mapper.load_register(address)
+	}
+
+	pub fn store_register(&mut self, address: u32, value: u8) -> bool {
+		self.mapper.store_register(address, value)
+	}
+
+	pub fn set_solder_pad(&mut self, value: u8) {
+		self.mapper.set_solder_pad(value);
+	}
+
+	// Expects being called at CPU clock rate
+	pub fn step(&mut self) {
+		self.mapper.step();
+	}
+
+	pub fn irq_pending(&self) -> bool {
+		self.mapper.irq_pending()
+	}
+
+	pub fn audio_output(&self) -> f32 {
+		self.mapper.audio_output()
+	}
+
+	/**
+	 * Resolves the nametable slot the address (0x2000 - 0x2FFF) belongs to.
+	 * Unless the mapper maps the slot, four-screen boards map the slots 2 and 3
+	 * to the cartridge VRAM ignoring the mapper mirroring control, and the others
+	 * fall back to the mirroring type.
+	 */
+	pub fn name_table_source(&self, address: u32) -> NameTableSource {
+		let slot = ((address >> 10) & 3) as u8;
+		if let Some(source) = self.mapper.name_table_source(slot) {
+			return source;
+		}
+		if self.four_screen_vram.capacity() > 0 {
+			return match slot {
+				0 | 1 => NameTableSource::Vram(slot),
+				_ => NameTableSource::CartridgeVram((slot as u32 - 2) * 0x400)
+			};
+		}
+		NameTableSource::Vram(match self.mirroring_type() {
+			Mirrorings::SingleScreen | Mirrorings::OneScreenLow => 0,
+			Mirrorings::OneScreenHigh => 1,
+			Mirrorings::Horizontal => slot >> 1,
+			Mirrorings::Vertical => slot & 1,
+			Mirrorings::FourScreen => slot
+		})
+	}
+
+	pub fn load_name_table(&self, address: u32) -> u8 {
+		self.mapper.load_name_table(address)
+	}
+
+	pub fn store_name_table(&mut self, address: u32, value: u8) {
+		self.mapper.store_name_table(address, value);
+	}
+
+	/**
+	 * Accesses NameTableSource::ChrRom slots with the address in CHR memory.
+	 * Writes are ignored unless the cartridge has CHR-RAM.
+	 */
+	pub fn load_chr_name_table(&self, address: u32) -> u8 {
+		load_wrapped(self.chr_memory(), address)
+	}
+
+	pub fn load_cartridge_vram(&self, address: u32) -> u8 {
+		load_wrapped(&self.four_screen_vram, address)
+	}
+
+	pub fn store_cartridge_vram(&mut self, address: u32, value: u8) {
+		let capacity = self.four_screen_vram.capacity();
+		if capacity > 0 {
+			self.four_screen_vram.store(address % capacity, value);
+		}
+	}
+
+	pub fn store_chr_name_table(&mut self, address: u32, value: u8) {
+		let capacity = self.chr_ram.capacity();
+		if !self.has_chr_rom() && capacity > 0 {
+			self.chr_ram.store(address % capacity, value);
+		}
+	}
+
+	/**
+	 * Lets mapper replace nametable and attribute table data
+	 * PPU fetches for rendering.
+	 */
+	pub fn fetch_name_table(&mut self, address: u32) -> Option<u8> {
+		self.mapper.fetch_name_table(address)
+	}
+
+	pub fn fetch_attribute_table(&mut self, address: u32) -> Option<u8> {
+		self.mapper.fetch_attribute_table(address)
+	}
+
+	pub fn notify_ppu_phase(&mut self, phase: PpuPhase) {
+		self.mapper.notify_ppu_phase(phase);
+	}
+
 	/// Save mapper state
-	pub fn save_mapper_state(&self) -> MapperState {
+	pub fn save_mapper_state(&self) -> Vec<u8> {
 		self.mapper.save_state()
 	}
 
 	/// Load mapper state
-	pub fn load_mapper_state(&mut self, state: &MapperState) {
-		self.mapper.load_state(state);
+	pub fn load_mapper_state(&mut self, data: &[u8]) {
+		self.mapper.load_state(data);
+	}
+
+	/// Save cartridge RAM state
+	pub fn save_cartridge_state(&self) -> CartridgeState {
+		CartridgeState {
+			prg_ram: self.prg_ram.get_data(),
+			chr_ram: self.chr_ram.get_data(),
+			four_screen_vram: self.four_screen_vram.get_data()
+		}
+	}
+
+	/// Load cartridge RAM state
+	pub fn load_cartridge_state(&mut self, state: &CartridgeState) {
+		self.prg_ram.set_data(&state.prg_ram);
+		self.chr_ram.set_data(&state.chr_ram);
+		self.four_screen_vram.set_data(&state.four_screen_vram);
+	}
+}
+
+/**
+ * What is known about the loaded ROM, for frontends. Header fields
+ * are the ones corrected with the game database if it has the ROM.
+ */
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomInfo {
+	// Title in the game database, or of the NSF. None if unknown.
+	pub title: Option<String>,
+	pub region: TimingMode,
+	// UNIF board name for UNIF, or the board the mapper number stands for.
+	// Empty if unknown.
+	pub board: String,
+	pub mapper_num: u16,
+	pub submapper_num: u8,
+	// Of PRG-ROM + CHR-ROM, or of the whole image for FDS and NSF
+	pub crc32: u32,
+	// Of PRG-ROM + CHR-ROM in uppercase hex. Computed only if the game
+	// database has the CRC32.
+	pub sha1: Option<String>,
+	// Whether the game database has the ROM
+	pub in_database: bool
+}
+
+impl RomInfo {
+	fn new(header: &RomHeader, crc32: u32) -> Self {
+		RomInfo {
+			title: None,
+			region: header.timing_mode(),
+			board: board_name(header.mapper_num(), header.submapper_num()).to_string(),
+			mapper_num: header.mapper_num(),
+			submapper_num: header.submapper_num(),
+			crc32: crc32,
+			sha1: None,
+			in_database: false
+		}
 	}
 }
 
-// @TODO: Cache
+/// Header flavor a ROM image was dumped with.
+/// Refer to https://wiki.nesdev.com/w/index.php/NES_2.0#Identification
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RomFormat {
+	// Old iNES dumps whose bytes 7-15 can hold garbage like "DiskDude!"
+	ArchaicINes,
+	INes,
+	Nes20
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimingMode {
+	Ntsc,
+	Pal,
+	MultiRegion,
+	Dendy
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConsoleType {
+	Nes,
+	VsSystem,
+	Playchoice10,
+	// NES 2.0 byte 13 extended console type
+	Extended(u8)
+}
+
+/**
+ * iNES / NES 2.0 header parsed at load time.
+ * Refer to https://wiki.nesdev.com/w/index.php/INES and
+ * https://wiki.nesdev.com/w/index.php/NES_2.0
+ */
+#[derive(Clone)]
 pub struct RomHeader {
-	data: Vec<u8>
+	signature: [u8; 4],
+	format: RomFormat,
+	prg_rom_size: usize,
+	chr_rom_size: usize,
+	mapper_num: u16,
+	submapper_num: u8,
+	vertical_mirroring: bool,
+	battery_backed_ram: bool,
+	trainer: bool,
+	four_screen_mirroring: bool,
+	prg_ram_size: usize,
+	prg_nvram_size: usize,
+	chr_ram_size: usize,
+	chr_nvram_size: usize,
+	timing_mode: TimingMode,
+	console_type: ConsoleType,
+	misc_rom_num: u8,
+	default_expansion_device: u8
 }
 
 impl RomHeader {
-	fn new(vec: Vec<u8>) -> Self {
+	fn new(data: &[u8]) -> Self {
+		let mut bytes = [0; HEADER_SIZE];
+		bytes.copy_from_slice(&data[0..HEADER_SIZE]);
+
+		let format = match bytes[7] & 0x0C {
+			0x08 => RomFormat::Nes20,
+			0x00 if bytes[12..16].iter().all(|&b| b == 0) => RomFormat::INes,
+			_ => RomFormat::ArchaicINes
+		};
+
 		let mut header = RomHeader {
-			data: Vec::new()
+			signature: [bytes[0], bytes[1], bytes[2], bytes[3]],
+			format: format,
+			prg_rom_size: bytes[4] as usize * 0x4000,
+			chr_rom_size: bytes[5] as usize * 0x2000,
+			mapper_num: (bytes[6] >> 4) as u16,
+			submapper_num: 0,
+			vertical_mirroring: (bytes[6] & 0x01) != 0,
+			battery_backed_ram: (bytes[6] & 0x02) != 0,
+			trainer: (bytes[6] & 0x04) != 0,
+			four_screen_mirroring: (bytes[6] & 0x08) != 0,
+			prg_ram_size: 0,
+			prg_nvram_size: 0,
+			chr_ram_size: 0,
+			chr_nvram_size: 0,
+			timing_mode: TimingMode::Ntsc,
+			console_type: ConsoleType::Nes,
+			misc_rom_num: 0,
+			default_expansion_device: 0
 		};
-		for i in 0..HEADER_SIZE {
-			header.data.push(vec[i]);
-		}
+
+		match format {
+			RomFormat::Nes20 => header.parse_nes20(&bytes),
+			RomFormat::INes => header.parse_ines(&bytes),
+			RomFormat::ArchaicINes => {
+				// Only the lower mapper nibble is trustworthy in archaic dumps.
+				// PRG-RAM size is guessed as for iNES.
+				header.parse_ram_sizes_without_header();
+			}
+		};
+
 		header
 	}
 
-	fn load(&self, address: u32) -> u8 {
-		self.data[address as usize]
+	fn parse_ines(&mut self, bytes: &[u8; HEADER_SIZE]) {
+		self.mapper_num |= (bytes[7] & 0xF0) as u16;
+		self.console_type = console_type(bytes[7] & 0x03, 0);
+		// Byte 9 bit 0 is the TV system though almost no dump sets it
+		self.timing_mode = match bytes[9] & 0x01 {
+			0 => TimingMode::Ntsc,
+			_ => TimingMode::Pal
+		};
+		self.parse_ram_sizes_without_header();
+		// Byte 8 is PRG-RAM size in 8KB units. 0 infers 8KB for compatibility.
+		if bytes[8] > 0 {
+			self.prg_ram_size = bytes[8] as usize * 0x2000;
+		}
 	}
 
-	fn is_nes(&self) -> bool {
-		if self.signature() == "NES" && self.magic_number() == 0x1a {
-			return true;
-		}
-		false
+	fn parse_nes20(&mut self, bytes: &[u8; HEADER_SIZE]) {
+		self.mapper_num |= (bytes[7] & 0xF0) as u16 | ((bytes[8] & 0x0F) as u16) << 8;
+		self.submapper_num = bytes[8] >> 4;
+		self.prg_rom_size = nes20_rom_size(bytes[4], bytes[9] & 0x0F, 0x4000);
+		self.chr_rom_size = nes20_rom_size(bytes[5], bytes[9] >> 4, 0x2000);
+		self.prg_ram_size = nes20_ram_size(bytes[10] & 0x0F);
+		self.prg_nvram_size = nes20_ram_size(bytes[10] >> 4);
+		self.chr_ram_size = nes20_ram_size(bytes[11] & 0x0F);
+		self.chr_nvram_size = nes20_ram_size(bytes[11] >> 4);
+		self.timing_mode = match bytes[12] & 0x03 {
+			0 => TimingMode::Ntsc,
+			1 => TimingMode::Pal,
+			2 => TimingMode::MultiRegion,
+			_ /* 3 */ => TimingMode::Dendy
+		};
+		self.console_type = console_type(bytes[7] & 0x03, bytes[13] & 0x0F);
+		self.misc_rom_num = bytes[14] & 0x03;
+		self.default_expansion_device = bytes[15] & 0x3F;
+	}
+
+	// Overwrites the fields the game database knows better.
+	// The sizes of PRG-ROM and CHR-ROM are the data.
+	fn apply_database_entry(&mut self, entry: &DatabaseEntry) {
+		self.mapper_num = entry.mapper_num;
+		self.submapper_num = entry.submapper_num;
+		match entry.mirroring.as_str() {
+			"H" => {
+				self.vertical_mirroring = false;
+				self.four_screen_mirroring = false;
+			},
+			"V" => {
+				self.vertical_mirroring = true;
+				self.four_screen_mirroring = false;
+			},
+			"4" => self.four_screen_mirroring = true,
+			_ => {}
+		};
+		self.battery_backed_ram = entry.battery;
+		self.prg_ram_size = entry.prg_ram_size;
+		self.prg_nvram_size = entry.prg_nvram_size;
+		self.chr_ram_size = entry.chr_ram_size;
+		self.chr_nvram_size = entry.chr_nvram_size;
+		self.timing_mode = match entry.region {
+			0 => TimingMode::Ntsc,
+			1 => TimingMode::Pal,
+			2 => TimingMode::MultiRegion,
+			_ => TimingMode::Dendy
+		};
+		self.console_type = match entry.console_type {
+			value @ 0..=2 => console_type(value, 0),
+			extended => console_type(3, extended)
+		};
 	}
 
-	fn signature(&self) -> String {
-		let mut vec = Vec::new();
-		for i in 0..3 as u32 {
-			vec.push(self.load(i));
+	// iNES has no reliable RAM size fields so guessing from the other fields
+	fn parse_ram_sizes_without_header(&mut self) {
+		match self.battery_backed_ram {
+			true => self.prg_nvram_size = 0x2000,
+			false => self.prg_ram_size = 0x2000
+		};
+		if self.chr_rom_size == 0 {
+			self.chr_ram_size = match self.mapper_num {
+				13 => 0x4000, // CPROM
+				30 => 0x8000, // UNROM 512
+				_ => 0x2000
+			};
 		}
-		String::from_utf8(vec).unwrap()
+		// TQROM has CHR-RAM along with CHR-ROM
+		if self.mapper_num == 119 {
+			self.chr_ram_size = 0x2000;
+		}
+	}
+
+	pub fn is_nes(&self) -> bool {
+		self.signature == [0x4E, 0x45, 0x53, 0x1A] // "NES" + magic number 0x1A
 	}
 
-	fn magic_number(&self) -> u8 {
-		self.load(3)
+	pub fn format(&self) -> RomFormat {
+		self.format
 	}
 
+	// Number of 16KB PRG-ROM banks. Saturates at 0xFF for huge NES 2.0 images,
+	// use prg_rom_size() for the exact size.
 	pub fn prg_rom_bank_num(&self) -> u8 {
-		self.load(4)
+		std::cmp::min(self.prg_rom_size / 0x4000, 0xFF) as u8
 	}
 
+	// Number of 8KB CHR-ROM banks. Saturates at 0xFF like prg_rom_bank_num().
 	pub fn chr_rom_bank_num(&self) -> u8 {
-		self.load(5)
+		std::cmp::min(self.chr_rom_size / 0x2000, 0xFF) as u8
 	}
 
-	fn has_chr_rom(&self) -> bool {
-		self.chr_rom_bank_num() > 0
+	pub fn prg_rom_size(&self) -> usize {
+		self.prg_rom_size
 	}
 
-	fn control_byte1(&self) -> u8 {
-		self.load(6)
+	pub fn chr_rom_size(&self) -> usize {
+		self.chr_rom_size
 	}
 
-	fn control_byte2(&self) -> u8 {
-		self.load(7)
+	pub fn has_chr_rom(&self) -> bool {
+		self.chr_rom_size > 0
 	}
 
-	fn _ram_bank_num(&self) -> u8 {
-		self.load(8)
+	// Number of 8KB CHR banks, CHR-ROM or CHR-RAM if the cart has no CHR-ROM.
+	// Saturates at 0xFF like prg_rom_bank_num().
+	pub fn chr_bank_num(&self) -> u8 {
+		let size = match self.has_chr_rom() {
+			true => self.chr_rom_size,
+			false => self.chr_ram_size + self.chr_nvram_size
+		};
+		std::cmp::min(size / 0x2000, 0xFF) as u8
 	}
 
-	fn _unused_field(&self) -> u64 {
-		let mut value = 0 as u64;
-		for i in 0..7 as u32 {
-			value = (value << 8) | self.load(9 + i) as u64;
-		}
-		value
+	pub fn mapper_num(&self) -> u16 {
+		self.mapper_num
 	}
 
-	fn extract_bits(&self, value: u8, offset: u8, size: u8) -> u8 {
-		(value >> offset) & ((1 << size) - 1)
+	// Always 0 unless NES 2.0
+	pub fn submapper_num(&self) -> u8 {
+		self.submapper_num
 	}
 
-	fn mirroring_type(&self) -> Mirrorings {
-		match self.four_screen_mirroring() {
+	pub fn mirroring_type(&self) -> Mirrorings {
+		match self.four_screen_mirroring {
 			true => Mirrorings::FourScreen,
-			false => match self.extract_bits(self.control_byte1(), 0, 1) {
-				0 => Mirrorings::Horizontal,
-				_ /* 1 */ => Mirrorings::Vertical
+			false => match self.vertical_mirroring {
+				false => Mirrorings::Horizontal,
+				true => Mirrorings::Vertical
 			}
 		}
 	}
 
-	fn _is_horizontal_mirroring(&self) -> bool {
-		match self.mirroring_type() {
-			Mirrorings::Horizontal => true,
-			_ => false
-		}
+	// Byte 6 bit 0. Some boards (e.g. UNROM-512) give it a meaning
+	// even if the four-screen bit is set.
+	pub fn vertical_mirroring_flag(&self) -> bool {
+		self.vertical_mirroring
+	}
+
+	pub fn has_battery_backed_ram(&self) -> bool {
+		self.battery_backed_ram
+	}
+
+	pub fn has_trainer(&self) -> bool {
+		self.trainer
+	}
+
+	// Volatile PRG-RAM (work RAM) size in bytes
+	pub fn prg_ram_size(&self) -> usize {
+		self.prg_ram_size
+	}
+
+	// Battery backed PRG-RAM size in bytes
+	pub fn prg_nvram_size(&self) -> usize {
+		self.prg_nvram_size
 	}
 
-	fn _battery_backed_ram(&self) -> u8 {
-		self.extract_bits(self.control_byte1(), 1, 1)
+	pub fn chr_ram_size(&self) -> usize {
+		self.chr_ram_size
 	}
 
-	fn _trainer_512_bytes(&self) -> u8 {
-		self.extract_bits(self.control_byte1(), 2, 1)
+	pub fn chr_nvram_size(&self) -> usize {
+		self.chr_nvram_size
 	}
 
-	fn four_screen_mirroring(&self) -> bool {
-		self.extract_bits(self.control_byte1(), 3, 1) == 1
+	pub fn timing_mode(&self) -> TimingMode {
+		self.timing_mode
 	}
 
-	pub fn mapper_num(&self) -> u8 {
-		let lower_bits = self.extract_bits(self.control_byte1(), 4, 4);
-		let higher_bits = self.extract_bits(self.control_byte2(), 4, 4);
-		(higher_bits << 4) | lower_bits
+	pub fn console_type(&self) -> ConsoleType {
+		self.console_type
+	}
+
+	pub fn misc_rom_num(&self) -> u8 {
+		self.misc_rom_num
+	}
+
+	pub fn default_expansion_device(&self) -> u8 {
+		self.default_expansion_device
+	}
+}
+
+fn console_type(value: u8, extended: u8) -> ConsoleType {
+	match value {
+		0 => ConsoleType::Nes,
+		1 => ConsoleType::VsSystem,
+		2 => ConsoleType::Playchoice10,
+		_ /* 3 */ => ConsoleType::Extended(extended)
+	}
+}
+
+// NES 2.0 ROM size is either (MSB nibble << 8 | LSB) * unit or,
+// if MSB nibble is 0xF, the exponent-multiplier notation 2^E * (MM * 2 + 1)
+// where LSB is EEEEEEMM.
+fn nes20_rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+	match msb {
+		0x0F => {
+			let exponent = (lsb >> 2) as u32;
+			let multiplier = (lsb & 0x03) as usize * 2 + 1;
+			match 1usize.checked_shl(exponent) {
+				Some(size) => size.saturating_mul(multiplier),
+				None => usize::MAX
+			}
+		},
+		_ => (((msb as usize) << 8) | lsb as usize) * unit
+	}
+}
+
+// NES 2.0 RAM size is 64 << shift count bytes. 0 means no RAM.
+fn nes20_ram_size(shift_count: u8) -> usize {
+	match shift_count {
+		0 => 0,
+		_ => 64 << shift_count
+	}
+}
+
+// Returns data[offset..offset + size] or the available byte count if it's short
+fn slice_rom(data: &[u8], offset: usize, size: usize) -> Result<Vec<u8>, usize> {
+	let available = data.len().saturating_sub(offset);
+	match available >= size {
+		true => Ok(data[offset..offset + size].to_vec()),
+		false => Err(available)
+	}
+}
+
+#[inline(always)]
+fn load_wrapped(memory: &Memory, address: u32) -> u8 {
+	match memory.capacity() {
+		0 => 0,
+		capacity => memory.load(address % capacity)
 	}
 }
 
@@ -214,33 +1104,671 @@ impl RomHeader {
 mod tests_rom {
 	use super::*;
 
+	fn image(prg_rom_bank_num: u8, chr_rom_bank_num: u8, control_byte1: u8) -> Vec<u8> {
+		let mut v = vec![0; HEADER_SIZE];
+		v[0] = 0x4e; // N
+		v[1] = 0x45; // E
+		v[2] = 0x53; // S
+		v[3] = 0x1a; // magic number
+		v[4] = prg_rom_bank_num;
+		v[5] = chr_rom_bank_num;
+		v[6] = control_byte1;
+		v.resize(HEADER_SIZE + prg_rom_bank_num as usize * 0x4000 + chr_rom_bank_num as usize * 0x2000, 0);
+		v
+	}
+
+	fn fds_image() -> Vec<u8> {
+		let mut side = vec![0x01];
+		side.extend_from_slice(b"*NINTENDO-HVC*");
+		side.resize(56, 0);
+		side.resize(::fds::FDS_SIDE_SIZE, 0);
+		side
+	}
+
+	#[test]
+	fn fds() {
+		assert_eq!(Some(RomError::FdsImage), Rom::new(fds_image()).err());
+		assert_eq!(Some(RomError::BadFdsBios { size: 0x1000 }), Rom::new_fds(fds_image(), vec![0; 0x1000]).err());
+		assert_eq!(Some(RomError::BadFdsImage), Rom::new_fds(image(1, 1, 0), vec![0; 0x2000]).err());
+
+		let mut bios = vec![0; 0x2000];
+		bios[0x1FFC] = 0x24;
+		let mut r = Rom::new_fds(fds_image(), bios).unwrap();
+		assert_eq!(20, r.header().mapper_num());
+		assert_eq!(0x24, r.load(0xFFFC));
+		// PRG-RAM in 0x6000 - 0xDFFF, CHR-RAM
+		r.store(0xDFFF, 1);
+		assert_eq!(1, r.load(0xDFFF));
+		r.store(0x1FFF, 2);
+		assert_eq!(2, r.load(0x1FFF));
+		// Disk writes persist as SRAM, PRG-RAM doesn't
+		assert_eq!(true, r.has_battery_backed_ram());
+		assert_eq!(false, r.is_sram_dirty());
+		assert_eq!(fds_image(), r.export_sram());
+		assert_eq!(1, r.disk_side_count());
+		r.insert_disk(None);
+		assert_eq!(None, r.disk_side());
+	}
+
+	fn nsf_image(expansion: u8, banks: [u8; 8]) -> Vec<u8> {
+		let mut v = b"NESM\x1A\x01\x03\x02".to_vec();
+		v.extend_from_slice(&[0x00, 0x80, 0x00, 0x80, 0x03, 0x80]);
+		v.resize(0x6E, 0);
+		v.extend_from_slice(&[0x1A, 0x41]);
+		v.extend_from_slice(&banks);
+		v.resize(0x7B, 0);
+		v.push(expansion);
+		v.resize(0x80, 0);
+		for bank in 0..3 {
+			v.extend_from_slice(&[bank + 1; 0x1000]);
+		}
+		v
+	}
+
+	#[test]
+	fn nsf() {
+		assert_eq!(Some(RomError::BadNsf("header is truncated")), Rom::new(b"NESM\x1A".to_vec()).err());
+
+		let mut r = Rom::new(nsf_image(0, [0; 8])).unwrap();
+		assert_eq!(3, r.nsf_metadata().unwrap().track_count);
+		assert_eq!(1, r.track());
+		// Vectors to the player code reading the track and INIT/PLAY addresses
+		assert_eq!(0x00, r.load(0xFFFC));
+		assert_eq!(0x41, r.load(0xFFFD));
+		assert_eq!(0x78, r.load_register(0x4100).unwrap());
+		assert_eq!(Some(1), r.load_register(0x41F0));
+		assert_eq!(Some(0x80), r.load_register(0x41F5));
+		assert_eq!(Some(0x03), r.load_register(0x41F6));
+		// Sequential banks from 0x8000, RAM in 0x6000 - 0x7FFF
+		assert_eq!(1, r.load(0x8000));
+		assert_eq!(3, r.load(0xA000));
+		assert_eq!(0, r.load(0xB000));
+		r.store(0x6000, 0x55);
+		assert_eq!(0x55, r.load(0x6000));
+
+		// Play timer IRQ every 29828 CPU cycles for 16666 microseconds
+		r.store_register(0x41F2, 1);
+		for _ in 0..29828 - 1 {
+			r.step();
+		}
+		assert_eq!(false, r.irq_pending());
+		r.step();
+		assert_eq!(true, r.irq_pending());
+		assert_eq!(Some(1), r.load_register(0x41F3));
+		assert_eq!(false, r.irq_pending());
+
+		let state = r.save_mapper_state();
+		r.select_track(0);
+		assert_eq!(0, r.track());
+		assert_eq!(0, r.load(0x6000));
+		r.select_track(3);
+		assert_eq!(0, r.track());
+		r.load_mapper_state(&state);
+		assert_eq!(1, r.track());
+		assert_eq!(0x55, r.load(0x6000));
+	}
+
+	#[test]
+	fn bankswitched_fds_nsf() {
+		let mut r = Rom::new(nsf_image(::nsf::NSF_FDS, [0, 1, 2, 0, 0, 0, 0, 0])).unwrap();
+		// Banks copied into RAM
+		assert_eq!(2, r.load(0x9000));
+		r.store_register(0x5FF9, 2);
+		assert_eq!(3, r.load(0x9000));
+		r.store(0x9000, 0x55);
+		assert_eq!(0x55, r.load(0x9000));
+		r.store_register(0x5FF6, 1);
+		assert_eq!(2, r.load(0x6000));
+		// FDS audio
+		r.store_register(0x4080, 0x9F);
+		assert_eq!(Some(0x5F), r.load_register(0x4090));
+	}
+
+	fn unif_image(board: &[u8]) -> Vec<u8> {
+		let mut v = b"UNIF".to_vec();
+		v.resize(32, 0);
+		for &(id, ref body) in [(b"MAPR", board.to_vec()), (b"PRG0", vec![1; 0x4000]),
+			(b"CHR0", vec![2; 0x2000]), (b"MIRR", vec![4])].iter() {
+			v.extend_from_slice(id);
+			v.extend_from_slice(&(body.len() as u32).to_le_bytes());
+			v.extend_from_slice(body);
+		}
+		v
+	}
+
+	#[test]
+	fn unif() {
+		assert_eq!(Some(RomError::BadUnif("header is truncated")), Rom::new(b"UNIF".to_vec()).err());
+		assert_eq!(Some(RomError::UnsupportedUnifBoard("UNL-FOO".to_string())), Rom::new(unif_image(b"UNL-FOO\0")).err());
+
+		let mut r = Rom::new(unif_image(b"NES-CNROM\0")).unwrap();
+		assert_eq!(3, r.header().mapper_num());
+		assert_eq!(1, r.header().prg_rom_bank_num());
+		assert_eq!(0x2000, r.header().chr_rom_size());
+		assert_eq!(1, r.load(0xC000));
+		assert_eq!(2, r.load(0x0000));
+		// Four-screen from MIRR
+		match r.name_table_source(0x2C00) {
+			NameTableSource::CartridgeVram(_) => {},
+			_ => panic!("not four-screen")
+		};
+		r.store(0x8000, 0);
+		assert_eq!(2, r.load(0x1FFF));
+		assert_eq!("NES-CNROM", r.info().board);
+		assert_eq!(None, r.info().title);
+	}
+
+	#[test]
+	fn unif_chr_ram() {
+		// Board, CHR-RAM size, bank select shift and the switched address
+		for &(board, size, shift, address) in [
+			(&b"NES-CPROM\0"[..], 0x4000, 0, 0x1000),
+			(&b"UNL-UNROM-512-32\0"[..], 0x8000, 5, 0x0000)
+		].iter() {
+			let mut v = b"UNIF".to_vec();
+			v.resize(32, 0);
+			for &(id, ref body) in [(b"MAPR", board.to_vec()), (b"PRG0", vec![0xFF; 0x8000])].iter() {
+				v.extend_from_slice(id);
+				v.extend_from_slice(&(body.len() as u32).to_le_bytes());
+				v.extend_from_slice(body);
+			}
+			let mut r = Rom::new(v).unwrap();
+			assert_eq!(size, r.header().chr_ram_size());
+			for bank in 0..4 {
+				r.store(0x8000, bank << shift);
+				r.store(address, 0x10 + bank);
+			}
+			for bank in 0..4 {
+				r.store(0x8000, bank << shift);
+				assert_eq!(0x10 + bank, r.load(address));
+			}
+		}
+	}
+
+	#[test]
+	fn database() {
+		// Wrong mapper, mirroring and battery bits
+		let mut contents = include_bytes!("../roms/nestest.nes").to_vec();
+		contents[6] |= 0x13;
+		let r = Rom::new(contents).unwrap();
+		assert_eq!(0, r.header().mapper_num());
+		assert_eq!(false, r.header().vertical_mirroring_flag());
+		assert_eq!(false, r.header().has_battery_backed_ram());
+		assert_eq!(RomInfo {
+			title: Some("nestest".to_string()),
+			region: TimingMode::Ntsc,
+			board: "NROM".to_string(),
+			mapper_num: 0,
+			submapper_num: 0,
+			crc32: 0x158B0388,
+			sha1: Some("4131307F0F69F2A5C54B7D438328C5B2A5ED0820".to_string()),
+			in_database: true
+		}, *r.info());
+
+		// Not in the database, the header is kept
+		let mut contents = include_bytes!("../roms/nestest.nes").to_vec();
+		contents[6] |= 0x13;
+		contents[16] ^= 0xFF;
+		let r = Rom::new(contents).unwrap();
+		assert_eq!(1, r.header().mapper_num());
+		assert_eq!(true, r.header().has_battery_backed_ram());
+		assert_eq!(None, r.info().title);
+		assert_eq!(None, r.info().sha1);
+		assert_eq!("MMC1 (SxROM)", r.info().board);
+		assert_eq!(false, r.info().in_database);
+	}
+
+	#[test]
+	fn new_with_database() {
+		let database = RomDatabase::parse(r#"<nes20db>
+			<game>
+				<!-- NES\Test (Europe).nes -->
+				<rom size="24576" crc32="158B0388" sha1="4131307F0F69F2A5C54B7D438328C5B2A5ED0820"/>
+				<prgnvram size="8192"/>
+				<pcb mapper="1" submapper="0" mirroring="V" battery="1"/>
+				<console type="0" region="1"/>
+			</game>
+		</nes20db>"#);
+		// The header says mapper 0, horizontal mirroring and no battery
+		let contents = include_bytes!("../roms/nestest.nes").to_vec();
+		assert_eq!(0, contents[6] & 0xF3);
+		let r = Rom::new_with_database(contents, &database).unwrap();
+		assert_eq!(1, r.header().mapper_num());
+		assert_eq!(true, r.header().vertical_mirroring_flag());
+		assert_eq!(true, r.header().has_battery_backed_ram());
+		assert_eq!(0x2000, r.header().prg_nvram_size());
+		assert_eq!(TimingMode::Pal, r.header().timing_mode());
+		assert_eq!(Some("Test (Europe)".to_string()), r.info().title);
+		assert_eq!("MMC1 (SxROM)", r.info().board);
+
+		// Same CRC32 but different SHA-1
+		let database = RomDatabase::parse(r#"<nes20db><game>
+			<rom size="24576" crc32="158B0388" sha1="0000000000000000000000000000000000000000"/>
+			<pcb mapper="1" submapper="0" mirroring="V" battery="1"/>
+		</game></nes20db>"#);
+		let r = Rom::new_with_database(include_bytes!("../roms/nestest.nes").to_vec(), &database).unwrap();
+		assert_eq!(0, r.header().mapper_num());
+		assert_eq!(false, r.info().in_database);
+	}
+
+	struct TestMapper {
+		bank: u8
+	}
+
+	impl Mapper for TestMapper {
+		fn map(&self, address: u32) -> u32 {
+			self.bank as u32 * 0x4000 + (address & 0x3FFF)
+		}
+
+		fn map_for_chr_rom(&self, address: u32) -> u32 {
+			address
+		}
+
+		fn store(&mut self, _address: u32, value: u8) {
+			self.bank = value;
+		}
+
+		fn has_mirroring_type(&self) -> bool {
+			false
+		}
+
+		fn mirroring_type(&self) -> Mirrorings {
+			Mirrorings::Vertical
+		}
+
+		fn drive_irq_counter(&mut self) -> bool {
+			false
+		}
+
+		fn save_state(&self) -> Vec<u8> {
+			vec![self.bank]
+		}
+
+		fn load_state(&mut self, data: &[u8]) {
+			self.bank = data[0];
+		}
+	}
+
+	#[test]
+	fn registered_mapper() {
+		// NES 2.0, mapper 0x300
+		let mut v = image(2, 1, 0);
+		v[7] = 0x08;
+		v[8] = 0x03;
+		v[HEADER_SIZE + 0x4000] = 1;
+		assert_eq!(Some(RomError::UnsupportedMapper(0x300)), Rom::new(v.clone()).err());
+
+		MapperFactory::register(0x300, None, |_header: &RomHeader| -> Box<dyn Mapper> {
+			Box::new(TestMapper { bank: 0 })
+		});
+		let mut r = Rom::new(v.clone()).unwrap();
+		assert_eq!(0, r.load(0x8000));
+		r.store(0x8000, 1);
+		assert_eq!(1, r.load(0x8000));
+		let state = r.save_mapper_state();
+		r.store(0x8000, 0);
+		r.load_mapper_state(&state);
+		assert_eq!(1, r.load(0x8000));
+
+		// Exact submapper takes precedence
+		MapperFactory::register(0x300, Some(1), |_header: &RomHeader| -> Box<dyn Mapper> {
+			Box::new(TestMapper { bank: 1 })
+		});
+		assert_eq!(0, Rom::new(v.clone()).unwrap().load(0x8000));
+		v[8] = 0x13;
+		assert_eq!(1, Rom::new(v.clone()).unwrap().load(0x8000));
+
+		assert!(MapperFactory::unregister(0x300, Some(1)));
+		assert!(MapperFactory::unregister(0x300, None));
+		assert!(!MapperFactory::unregister(0x300, None));
+		assert_eq!(Some(RomError::UnsupportedMapper(0x300)), Rom::new(v).err());
+	}
+
 	#[test]
 	fn initialize() {
-		let r = Rom::new(vec![0; 17]);
+		Rom::new(image(1, 1, 0)).unwrap();
 	}
 
 	#[test]
 	fn load() {
-		let r = Rom::new(vec![0; 17]);
-		assert_eq!(0, r.load(0));
+		let mut v = image(1, 1, 0);
+		v[HEADER_SIZE] = 1;
+		v[HEADER_SIZE + 0x4000] = 2;
+		let r = Rom::new(v).unwrap();
+		assert_eq!(1, r.load(0x8000));
+		assert_eq!(1, r.load(0xC000));
+		assert_eq!(2, r.load(0));
 	}
 
 	#[test]
 	fn store() {
-		let mut r = Rom::new(vec![0; 17]);
+		let mut r = Rom::new(image(1, 1, 0)).unwrap();
 		r.store(0, 0);
 	}
 
 	#[test]
 	fn valid() {
-		let r = Rom::new(vec![0; 64]);
-		assert_eq!(false, r.valid());
-		let mut v = vec![0; 64];
-		v[0] = 0x4e; // N
-		v[1] = 0x45; // E
-		v[2] = 0x53; // S
-		v[3] = 0x1a; // magic number
-		let r2 = Rom::new(v);
+		assert_eq!(Some(RomError::BadMagic), Rom::new(vec![0; 64]).err());
+		let r2 = Rom::new(image(1, 0, 0)).unwrap();
 		assert_eq!(true, r2.valid());
 	}
+
+	#[test]
+	fn truncated_header() {
+		assert_eq!(Some(RomError::TruncatedHeader { size: 15 }), Rom::new(vec![0; 15]).err());
+	}
+
+	#[test]
+	fn empty_prg_rom() {
+		assert_eq!(Some(RomError::EmptyPrgRom), Rom::new(image(0, 1, 0)).err());
+	}
+
+	#[test]
+	fn truncated_prg_rom() {
+		let mut v = image(2, 0, 0);
+		v.truncate(HEADER_SIZE + 0x5000);
+		assert_eq!(Some(RomError::TruncatedPrgRom { expected: 0x8000, actual: 0x5000 }), Rom::new(v).err());
+	}
+
+	#[test]
+	fn truncated_chr_rom() {
+		let mut v = image(1, 1, 0);
+		v.truncate(HEADER_SIZE + 0x4000 + 0x100);
+		assert_eq!(Some(RomError::TruncatedChrRom { expected: 0x2000, actual: 0x100 }), Rom::new(v).err());
+	}
+
+	#[test]
+	fn truncated_trainer() {
+		let mut v = image(1, 0, 0x04);
+		v.truncate(HEADER_SIZE + 0x100);
+		assert_eq!(Some(RomError::TruncatedTrainer { expected: TRAINER_SIZE, actual: 0x100 }), Rom::new(v).err());
+	}
+
+	#[test]
+	fn unsupported_mapper() {
+		let mut v = image(1, 0, 0);
+		v[7] = 0xF0;
+		assert_eq!(Some(RomError::UnsupportedMapper(0xF0)), Rom::new(v).err());
+	}
+
+	#[test]
+	fn battery_backed_ram() {
+		let mut r = Rom::new(image(1, 1, 0x02)).unwrap();
+		assert_eq!(true, r.has_battery_backed_ram());
+		assert_eq!(false, r.is_sram_dirty());
+		r.store_prg_ram(0x6001, 5);
+		assert_eq!(5, r.load_prg_ram(0x6001));
+		assert_eq!(true, r.is_sram_dirty());
+		let sram = r.export_sram();
+		assert_eq!(0x2000, sram.len());
+		r.mark_sram_flushed();
+		assert_eq!(false, r.is_sram_dirty());
+
+		let mut r2 = Rom::new(image(1, 1, 0x02)).unwrap();
+		r2.import_sram(&sram);
+		assert_eq!(5, r2.load_prg_ram(0x6001));
+		assert_eq!(false, r2.is_sram_dirty());
+	}
+
+	#[test]
+	fn work_ram_is_not_exported() {
+		let mut r = Rom::new(image(1, 1, 0)).unwrap();
+		r.store_prg_ram(0x7FFF, 1);
+		assert_eq!(1, r.load_prg_ram(0x7FFF));
+		assert_eq!(false, r.is_sram_dirty());
+		assert_eq!(0, r.export_sram().len());
+	}
+
+	#[test]
+	fn trainer() {
+		let mut v = image(1, 1, 0x04);
+		v.resize(v.len() + TRAINER_SIZE, 0);
+		v[HEADER_SIZE] = 0x11;
+		v[HEADER_SIZE + TRAINER_SIZE - 1] = 0x22;
+		v[HEADER_SIZE + TRAINER_SIZE] = 0x33; // PRG-ROM
+		v[HEADER_SIZE + TRAINER_SIZE + 0x4000] = 0x44; // CHR-ROM
+		let mut r = Rom::new(v).unwrap();
+		assert_eq!(0x33, r.load(0x8000));
+		assert_eq!(0x44, r.load(0x0000));
+		r.bootup();
+		assert_eq!(0x11, r.load_prg_ram(0x7000));
+		assert_eq!(0x22, r.load_prg_ram(0x71FF));
+		assert_eq!(0, r.load_prg_ram(0x7200));
+
+		// Imported battery backed RAM is kept
+		let mut v = image(1, 1, 0x06);
+		v.resize(v.len() + TRAINER_SIZE, 0);
+		v[HEADER_SIZE] = 0x11;
+		let mut r = Rom::new(v).unwrap();
+		let mut sram = r.export_sram();
+		sram[0x1000] = 0x55;
+		r.import_sram(&sram);
+		r.bootup();
+		assert_eq!(0x55, r.load_prg_ram(0x7000));
+	}
+
+	#[test]
+	fn unrom512_chr_ram() {
+		// iNES mapper 30 without CHR-ROM
+		let mut v = image(2, 0, 0xE0);
+		v[7] = 0x10;
+		let mut r = Rom::new(v).unwrap();
+		assert_eq!(0x8000, r.header().chr_ram_size());
+		for bank in 0..4 {
+			r.store(0x8000, bank << 5);
+			r.store(0x0000, 0x10 + bank);
+		}
+		for bank in 0..4 {
+			r.store(0x8000, bank << 5);
+			assert_eq!(0x10 + bank, r.load(0x0000));
+		}
+	}
+
+	#[test]
+	fn chr_ram() {
+		let mut r = Rom::new(image(1, 0, 0)).unwrap();
+		r.store(0x1FFF, 3);
+		assert_eq!(3, r.load(0x1FFF));
+		assert_eq!(3, r.save_cartridge_state().chr_ram[0x1FFF]);
+	}
+
+	#[test]
+	fn bus_conflicts() {
+		// GxROM with 0x11 at 0x8000
+		let mut v = image(4, 4, 0x20);
+		v[7] = 0x40;
+		v[HEADER_SIZE] = 0x11;
+		v[HEADER_SIZE + 0x8000] = 0x22;
+		v[HEADER_SIZE + 0x10000 + 0x2000] = 0x33;
+		let mut r = Rom::new(v).unwrap();
+		// 0x13 & 0x11 selects PRG bank 1 and CHR bank 1
+		r.store(0x8000, 0x13);
+		assert_eq!(0x22, r.load(0x8000));
+		assert_eq!(0x33, r.load(0x0000));
+	}
+
+	#[test]
+	fn registers_in_prg_ram() {
+		// NINA-001 registers are in PRG-RAM
+		let mut v = image(4, 2, 0x20);
+		v[7] = 0x20;
+		v[HEADER_SIZE + 0x8000] = 0x33;
+		let mut r = Rom::new(v).unwrap();
+		r.store(0x7FFD, 1);
+		assert_eq!(0x33, r.load(0x8000));
+		assert_eq!(1, r.load(0x7FFD));
+	}
+
+	#[test]
+	fn four_screen_vram() {
+		// MMC3 with four-screen bit
+		let mut r = Rom::new(image(2, 1, 0x48)).unwrap();
+		// MMC3 mirroring control is ignored
+		r.store(0xA000, 0x01);
+		let pages: Vec<u8> = [0x2000, 0x2400].iter().map(|address| match r.name_table_source(*address) {
+			NameTableSource::Vram(page) => page,
+			_ => panic!("expected VRAM")
+		}).collect();
+		assert_eq!(vec![0, 1], pages);
+		let offsets: Vec<u32> = [0x2800, 0x2C00].iter().map(|address| match r.name_table_source(*address) {
+			NameTableSource::CartridgeVram(offset) => offset,
+			_ => panic!("expected cartridge VRAM")
+		}).collect();
+		assert_eq!(vec![0x000, 0x400], offsets);
+		r.store_cartridge_vram(0x401, 0x11);
+		assert_eq!(0x11, r.load_cartridge_vram(0x401));
+		assert_eq!(0x800, r.save_cartridge_state().four_screen_vram.len());
+
+		// No cartridge VRAM without four-screen bit
+		let r = Rom::new(image(2, 1, 0x40)).unwrap();
+		assert_eq!(0, r.save_cartridge_state().four_screen_vram.len());
+		match r.name_table_source(0x2C00) {
+			NameTableSource::Vram(1) => {},
+			_ => panic!("expected vertical mirroring")
+		};
+	}
+
+	#[test]
+	fn mmc1_prg_ram() {
+		// SOROM, mapper 1 with 16KB PRG-RAM
+		let mut v = image(8, 0, 0x10);
+		v[8] = 2;
+		let mut r = Rom::new(v).unwrap();
+		let store_serially = |r: &mut Rom, address: u32, value: u8| {
+			for i in 0..5 {
+				r.store(address, (value >> i) & 1);
+			}
+		};
+		r.store(0x6000, 0x11);
+		// CHR bank 0 register bit 3 selects the second 8KB
+		store_serially(&mut r, 0xA000, 0x08);
+		assert_eq!(0x00, r.load(0x6000));
+		r.store(0x6000, 0x22);
+		store_serially(&mut r, 0xA000, 0x00);
+		assert_eq!(0x11, r.load(0x6000));
+		// PRG bank register bit 4 disables PRG-RAM
+		store_serially(&mut r, 0xE000, 0x10);
+		r.store(0x6000, 0x33);
+		assert_eq!(0x00, r.load(0x6000));
+		store_serially(&mut r, 0xE000, 0x00);
+		assert_eq!(0x11, r.load(0x6000));
+	}
+
+	#[test]
+	fn mmc3_prg_ram_protect() {
+		// NES 2.0 mapper 4 with 8KB PRG-RAM
+		let mut v = image(2, 1, 0x40);
+		v[7] = 0x08;
+		v[10] = 0x07;
+		let mut r = Rom::new(v).unwrap();
+		assert_eq!(RomFormat::Nes20, r.header().format());
+		r.store(0x6000, 0x11);
+		assert_eq!(0x00, r.load(0x6000));
+		r.store(0xA001, 0x80);
+		r.store(0x6000, 0x11);
+		assert_eq!(0x11, r.load(0x6000));
+		r.store(0xA001, 0xC0);
+		r.store(0x6000, 0x22);
+		assert_eq!(0x11, r.load(0x6000));
+
+		// iNES images ignore 0xA001
+		let mut r = Rom::new(image(2, 1, 0x40)).unwrap();
+		r.store(0x6000, 0x11);
+		assert_eq!(0x11, r.load(0x6000));
+	}
+
+	#[test]
+	fn tqrom_chr_ram() {
+		// Mapper 119 with 64KB CHR-ROM
+		let mut v = image(2, 8, 0x70);
+		v[7] = 0x70;
+		v[HEADER_SIZE + 0x8000] = 0x11;
+		let mut r = Rom::new(v).unwrap();
+		r.store(0x0000, 0x22);
+		assert_eq!(0x11, r.load(0x0000));
+		// R0 selects CHR-RAM
+		r.store(0x8000, 0x00);
+		r.store(0x8001, 0x40);
+		r.store(0x0000, 0x22);
+		assert_eq!(0x22, r.load(0x0000));
+		assert_eq!(0x2000, r.save_cartridge_state().chr_ram.len());
+	}
+
+	#[test]
+	fn cprom_chr_ram() {
+		let r = Rom::new(image(2, 0, 0xD0)).unwrap();
+		assert_eq!(0x4000, r.save_cartridge_state().chr_ram.len());
+	}
+
+	#[test]
+	fn chr_rom_is_read_only() {
+		let mut r = Rom::new(image(1, 1, 0)).unwrap();
+		r.store(0x0000, 3);
+		assert_eq!(0, r.load(0x0000));
+	}
+
+	#[test]
+	fn nes20_chr_ram_size() {
+		let mut v = image(1, 0, 0);
+		v[7] = 0x08; // NES 2.0
+		v[11] = 0x09; // 64 << 9 = 32KB
+		let r = Rom::new(v).unwrap();
+		assert_eq!(4, r.header().chr_bank_num());
+		assert_eq!(0x8000, r.save_cartridge_state().chr_ram.len());
+	}
+
+	#[test]
+	fn empty_rom_loads_zero() {
+		let r = Rom::empty();
+		assert_eq!(0, r.load(0x8000));
+		assert_eq!(0, r.load(0x0000));
+	}
+
+	fn header(bytes: [u8; 16]) -> RomHeader {
+		RomHeader::new(&bytes)
+	}
+
+	#[test]
+	fn ines_header() {
+		let h = header([0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x43, 0x10, 0, 0, 0, 0, 0, 0, 0, 0]);
+		assert_eq!(RomFormat::INes, h.format());
+		assert_eq!(0x8000, h.prg_rom_size());
+		assert_eq!(0x2000, h.chr_rom_size());
+		assert_eq!(0x14, h.mapper_num());
+		assert_eq!(true, h.has_battery_backed_ram());
+		assert_eq!(false, h.has_trainer());
+		assert_eq!(0x2000, h.prg_nvram_size());
+		assert_eq!(0, h.chr_ram_size());
+	}
+
+	#[test]
+	fn archaic_ines_header_ignores_upper_mapper_bits() {
+		// "DiskDude!" garbage in bytes 7-15
+		let h = header([0x4E, 0x45, 0x53, 0x1A, 2, 0, 0x40, 0x44, 0x69, 0x73, 0x6B, 0x44, 0x75, 0x64, 0x65, 0x21]);
+		assert_eq!(RomFormat::ArchaicINes, h.format());
+		assert_eq!(4, h.mapper_num());
+		assert_eq!(0x2000, h.chr_ram_size());
+	}
+
+	#[test]
+	fn nes20_header() {
+		let h = header([0x4E, 0x45, 0x53, 0x1A, 0x20, 0x00, 0x52, 0x58, 0x21, 0x01, 0x70, 0x09, 0x01, 0x00, 0x00, 0x00]);
+		assert_eq!(RomFormat::Nes20, h.format());
+		assert_eq!(0x120 * 0x4000, h.prg_rom_size());
+		assert_eq!(0, h.chr_rom_size());
+		assert_eq!(0x155, h.mapper_num());
+		assert_eq!(2, h.submapper_num());
+		assert_eq!(0, h.prg_ram_size());
+		assert_eq!(0x2000, h.prg_nvram_size());
+		assert_eq!(0x8000, h.chr_ram_size());
+		assert_eq!(TimingMode::Pal, h.timing_mode());
+		assert_eq!(ConsoleType::Nes, h.console_type());
+	}
+
+	#[test]
+	fn nes20_exponent_multiplier_rom_size() {
+		// 2^10 * (1 * 2 + 1)
+		assert_eq!(3072, nes20_rom_size(0x29, 0x0F, 0x4000));
+		assert_eq!(0x4000 * 0x102, nes20_rom_size(0x02, 0x01, 0x4000));
+	}
 }
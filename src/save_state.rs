@@ -1,7 +1,8 @@
 use serde::{Serialize, Deserialize};
+use mapper::VrcWiring;
 
 /// Save state version for compatibility checking
-pub const SAVE_STATE_VERSION: u32 = 1;
+pub const SAVE_STATE_VERSION: u32 = 19;
 
 /// Complete NES save state
 #[derive(Serialize, Deserialize)]
@@ -12,7 +13,9 @@ pub struct SaveState {
     pub apu: ApuState,
     pub joypad1: JoypadState,
     pub joypad2: JoypadState,
-    pub mapper: MapperState,
+    /// Opaque blob made by Mapper::save_state()
+    pub mapper: Vec<u8>,
+    pub cartridge: CartridgeState,
 }
 
 impl SaveState {
@@ -24,7 +27,8 @@ impl SaveState {
             apu: ApuState::new(),
             joypad1: JoypadState::new(),
             joypad2: JoypadState::new(),
-            mapper: MapperState::None,
+            mapper: Vec::new(),
+            cartridge: CartridgeState::new(),
         }
     }
 }
@@ -51,7 +55,7 @@ impl CpuState {
             x: 0,
             y: 0,
             p: 0,
-            ram: vec![0; 64 * 1024],
+            ram: vec![0; 2 * 1024],
             stall_cycles: 0,
         }
     }
@@ -87,6 +91,8 @@ pub struct PpuState {
     pub ppumask: u8,
     pub ppustatus: u8,
     pub data_bus: u8,
+    pub address_bus_a12: bool,
+    pub a12_low_cycles: u32,
     pub nmi_interrupted: bool,
     pub irq_interrupted: bool,
 }
@@ -121,6 +127,8 @@ impl PpuState {
             ppumask: 0,
             ppustatus: 0,
             data_bus: 0,
+            address_bus_a12: false,
+            a12_low_cycles: 0,
             nmi_interrupted: false,
             irq_interrupted: false,
         }
@@ -323,6 +331,24 @@ impl JoypadState {
     }
 }
 
+/// Cartridge RAM state
+#[derive(Serialize, Deserialize)]
+pub struct CartridgeState {
+    pub prg_ram: Vec<u8>,
+    pub chr_ram: Vec<u8>,
+    pub four_screen_vram: Vec<u8>,
+}
+
+impl CartridgeState {
+    pub fn new() -> Self {
+        CartridgeState {
+            prg_ram: vec![],
+            chr_ram: vec![],
+            four_screen_vram: vec![],
+        }
+    }
+}
+
 /// Mapper state - each mapper type has different state
 #[derive(Serialize, Deserialize)]
 pub enum MapperState {
@@ -346,6 +372,92 @@ pub enum MapperState {
     CNRom {
         register: u8,
     },
+    AxRom {
+        register: u8,
+    },
+    GxRom {
+        register: u8,
+    },
+    ColorDreams {
+        register: u8,
+    },
+    BNRom {
+        register: u8,
+    },
+    Nina001 {
+        program_register: u8,
+        character_registers: [u8; 2],
+    },
+    Camerica {
+        register: u8,
+        mirroring: Option<u8>,
+    },
+    CPRom {
+        register: u8,
+    },
+    D1038 {
+        latch: u16,
+    },
+    Multicart225 {
+        latch: u16,
+        ram: [u8; 4],
+    },
+    Multicart226 {
+        registers: [u8; 2],
+    },
+    Multicart227 {
+        latch: u16,
+    },
+    Action52 {
+        latch: u16,
+        data: u8,
+        ram: [u8; 4],
+    },
+    Fds {
+        disk_sides: Vec<Vec<u8>>,
+        disk_side: Option<usize>,
+        disk_registers_enabled: bool,
+        sound_registers_enabled: bool,
+        timer_reload: u16,
+        timer_counter: u16,
+        timer_repeat: bool,
+        timer_enabled: bool,
+        timer_irq: bool,
+        write_data: u8,
+        read_data: u8,
+        control: u8,
+        motor_on: bool,
+        disk_position: usize,
+        drive_delay: u32,
+        end_of_head: bool,
+        scanning: bool,
+        gap_ended: bool,
+        transfer_complete: bool,
+        disk_irq: bool,
+        previous_crc_control: bool,
+        audio: FdsAudioState,
+    },
+    Nsf {
+        track: u8,
+        banks: [u8; 10],
+        ram: Vec<u8>,
+        play_counter: u32,
+        playing: bool,
+        play_irq: bool,
+        fds_audio: Option<FdsAudioState>,
+        expansions: Vec<Vec<u8>>,
+    },
+    UNRom512 {
+        program_bank_num: u8,
+        register: u8,
+    },
+    MMC2 {
+        program_register: u8,
+        character_registers: [u8; 4],
+        latch0: bool,
+        latch1: bool,
+        mirroring_register: u8,
+    },
     MMC3 {
         program_bank_num: u8,
         character_bank_num: u8,
@@ -368,6 +480,62 @@ pub enum MapperState {
         irq_counter: u8,
         irq_counter_reload: bool,
         irq_enabled: bool,
+        irq_pending: bool,
+    },
+    MMC5 {
+        program_mode: u8,
+        character_mode: u8,
+        prg_ram_protect1: u8,
+        prg_ram_protect2: u8,
+        exram_mode: u8,
+        name_table_mapping: u8,
+        fill_tile: u8,
+        fill_attribute: u8,
+        program_registers: [u8; 5],
+        character_registers: [u16; 12],
+        character_upper_bits: u8,
+        character_set_b_written: bool,
+        split_control: u8,
+        split_scroll: u8,
+        split_bank: u8,
+        irq_target: u8,
+        irq_enabled: bool,
+        irq_pending: bool,
+        irq_counter: u8,
+        in_frame: bool,
+        multiplicand: u8,
+        multiplier: u8,
+        exram: Vec<u8>,
+        pulse1: ApuPulseState,
+        pulse2: ApuPulseState,
+        pcm: u8,
+        audio_cycle: u32,
+    },
+    VRC4 {
+        wiring: VrcWiring,
+        program_registers: [u8; 2],
+        mirroring: u8,
+        program_mode: u8,
+        character_registers: [u16; 8],
+        irq: VrcIrqState,
+    },
+    VRC6 {
+        program_registers: [u8; 2],
+        control: u8,
+        character_registers: [u8; 8],
+        frequency_control: u8,
+        irq: VrcIrqState,
+        pulse1: Vrc6PulseState,
+        pulse2: Vrc6PulseState,
+        sawtooth: Vrc6SawtoothState,
+    },
+    VRC7 {
+        program_registers: [u8; 3],
+        character_registers: [u8; 8],
+        control: u8,
+        irq: VrcIrqState,
+        audio: OpllState,
+        audio_cycle: u8,
     },
     Sunsoft {
         command_register: u8,
@@ -378,7 +546,135 @@ pub enum MapperState {
         irq_counter_enabled: bool,
         irq_counter: u16,
         mirroring: u8,
+        audio: Sunsoft5BState,
     },
+    Namco163 {
+        character_registers: [u8; 8],
+        name_table_registers: [u8; 4],
+        program_registers: [u8; 3],
+        sound_address: u8,
+        sound_ram: Vec<u8>,
+        irq_counter: u16,
+        irq_enabled: bool,
+        irq_pending: bool,
+        audio_cycle: u8,
+        audio_channel: u8,
+        audio_outputs: [i16; 8],
+    },
+}
+
+/// Konami VRC IRQ counter state
+#[derive(Serialize, Deserialize)]
+pub struct VrcIrqState {
+    pub latch: u8,
+    pub enabled: bool,
+    pub enabled_after_acknowledge: bool,
+    pub cycle_mode: bool,
+    pub counter: u8,
+    pub prescaler: i16,
+    pub pending: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Vrc6PulseState {
+    pub register: u8,
+    pub period: u16,
+    pub enabled: bool,
+    pub timer_counter: u16,
+    pub step: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Vrc6SawtoothState {
+    pub rate: u8,
+    pub period: u16,
+    pub enabled: bool,
+    pub timer_counter: u16,
+    pub step: u8,
+    pub accumulator: u8,
+}
+
+/// FDS audio state
+#[derive(Serialize, Deserialize)]
+pub struct FdsAudioState {
+    pub wave_table: Vec<u8>,
+    pub wave_frequency: u16,
+    pub wave_halted: bool,
+    pub envelopes_halted: bool,
+    pub wave_accumulator: u32,
+    pub volume: FdsEnvelopeState,
+    pub modulation: FdsEnvelopeState,
+    pub mod_counter: i8,
+    pub mod_frequency: u16,
+    pub mod_halted: bool,
+    pub mod_table: Vec<u8>,
+    pub mod_position: u8,
+    pub mod_accumulator: u32,
+    pub wave_write_enabled: bool,
+    pub master_volume: u8,
+    pub master_speed: u8,
+    pub output: u8,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FdsEnvelopeState {
+    pub speed: u8,
+    pub gain: u8,
+    pub disabled: bool,
+    pub increase: bool,
+    pub timer: u32,
+}
+
+/// Sunsoft 5B audio state
+#[derive(Serialize, Deserialize)]
+pub struct Sunsoft5BState {
+    pub address: u8,
+    pub registers: [u8; 16],
+    pub cycle: u8,
+    pub tone_counters: [u16; 3],
+    pub tone_outputs: [bool; 3],
+    pub noise_counter: u8,
+    pub noise_shift: u32,
+    pub envelope_counter: u16,
+    pub envelope_step: u8,
+    pub envelope_attack: bool,
+    pub envelope_holding: bool,
+}
+
+/// VRC7 FM synthesizer state
+#[derive(Serialize, Deserialize)]
+pub struct OpllState {
+    pub address: u8,
+    pub custom_instrument: [u8; 8],
+    pub frequencies: [u8; 6],
+    pub controls: [u8; 6],
+    pub instruments: [u8; 6],
+    pub slots: Vec<OpllSlotState>,
+    pub counter: u32,
+    pub tremolo_position: u32,
+    pub vibrato_position: u32,
+    pub output: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OpllSlotState {
+    pub phase: u32,
+    pub envelope: u8,
+    pub state: u8,
+    pub outputs: [i32; 2],
+}
+
+impl MapperState {
+    /// Serialize into the blob Mapper::save_state() returns
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).unwrap_or_default()
+    }
+
+    /// Deserialize a blob given to Mapper::load_state().
+    /// None if the blob isn't of a built-in mapper.
+    pub fn from_bytes(data: &[u8]) -> Self {
+        bincode::deserialize(data).unwrap_or(MapperState::None)
+    }
 }
 
 /// Serialize a save state to bytes
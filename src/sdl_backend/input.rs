@@ -24,6 +24,9 @@ fn keycode_to_button(key: Keycode) -> Option<button::Button> {
 		Keycode::Num2 => Some(button::Button::Joypad2Down),
 		Keycode::Num4 => Some(button::Button::Joypad2Left),
 		Keycode::Num6 => Some(button::Button::Joypad2Right),
+		// NSF player
+		Keycode::N => Some(button::Button::NextTrack),
+		Keycode::P => Some(button::Button::PreviousTrack),
 		_ => None
 	}
 }
@@ -0,0 +1,331 @@
+use rom::HEADER_SIZE;
+
+const UNIF_HEADER_SIZE: usize = 32;
+const UNIF_SIGNATURE: &[u8] = b"UNIF";
+
+// Board name prefixes telling the maker, not the board
+const BOARD_PREFIXES: [&str; 6] = ["NES-", "HVC-", "UNL-", "BTL-", "BMC-", "MLT-"];
+
+// Board name (without the prefix), mapper number, submapper number,
+// PRG-RAM size and CHR-RAM size used without CHR-ROM.
+// UNIF has no RAM sizes so the board tells them.
+const BOARDS: [(&str, u16, u8, usize, usize); 61] = [
+	("NROM", 0, 0, 0, 0x2000),
+	("NROM-128", 0, 0, 0, 0x2000),
+	("NROM-256", 0, 0, 0, 0x2000),
+	("RROM", 0, 0, 0, 0x2000),
+	("SAROM", 1, 0, 0x2000, 0x2000),
+	("SBROM", 1, 0, 0, 0x2000),
+	("SCROM", 1, 0, 0, 0x2000),
+	("SEROM", 1, 0, 0, 0x2000),
+	("SGROM", 1, 0, 0, 0x2000),
+	("SKROM", 1, 0, 0x2000, 0x2000),
+	("SLROM", 1, 0, 0, 0x2000),
+	("SL1ROM", 1, 0, 0, 0x2000),
+	("SNROM", 1, 0, 0x2000, 0x2000),
+	("SOROM", 1, 0, 0x4000, 0x2000),
+	("SUROM", 1, 0, 0x2000, 0x2000),
+	("SXROM", 1, 0, 0x8000, 0x2000),
+	("UNROM", 2, 0, 0, 0x2000),
+	("UOROM", 2, 0, 0, 0x2000),
+	("CNROM", 3, 0, 0, 0x2000),
+	("TBROM", 4, 0, 0, 0x2000),
+	("TEROM", 4, 0, 0, 0x2000),
+	("TFROM", 4, 0, 0, 0x2000),
+	("TGROM", 4, 0, 0, 0x2000),
+	("TKROM", 4, 0, 0x2000, 0x2000),
+	("TLROM", 4, 0, 0, 0x2000),
+	("TL1ROM", 4, 0, 0, 0x2000),
+	("TNROM", 4, 0, 0x2000, 0x2000),
+	("TR1ROM", 4, 0, 0, 0x2000),
+	("TSROM", 4, 0, 0x2000, 0x2000),
+	("TVROM", 4, 0, 0, 0x2000),
+	("HKROM", 4, 1, 0x400, 0x2000),
+	("ELROM", 5, 0, 0, 0x2000),
+	("EKROM", 5, 0, 0x2000, 0x2000),
+	("ETROM", 5, 0, 0x4000, 0x2000),
+	("EWROM", 5, 0, 0x8000, 0x2000),
+	("AMROM", 7, 0, 0, 0x2000),
+	("ANROM", 7, 0, 0, 0x2000),
+	("AN1ROM", 7, 0, 0, 0x2000),
+	("AOROM", 7, 0, 0, 0x2000),
+	("PNROM", 9, 0, 0, 0x2000),
+	("PEEOROM", 9, 0, 0, 0x2000),
+	("FJROM", 10, 0, 0x2000, 0x2000),
+	("FKROM", 10, 0, 0x2000, 0x2000),
+	("CPROM", 13, 0, 0, 0x4000),
+	("UNROM-512-8", 30, 0, 0, 0x2000),
+	("UNROM-512-16", 30, 0, 0, 0x4000),
+	("UNROM-512-32", 30, 0, 0, 0x8000),
+	("NINA-001", 34, 1, 0x2000, 0x2000),
+	("BNROM", 34, 2, 0, 0x2000),
+	("D1038", 59, 0, 0, 0x2000),
+	("GNROM", 66, 0, 0, 0x2000),
+	("MHROM", 66, 0, 0, 0x2000),
+	("JLROM", 69, 0, 0, 0x2000),
+	("JSROM", 69, 0, 0x2000, 0x2000),
+	("BTR", 69, 0, 0x2000, 0x2000),
+	("BF9093", 71, 0, 0, 0x2000),
+	("BF9097", 71, 0, 0, 0x2000),
+	("TLSROM", 118, 0, 0, 0x2000),
+	("TKSROM", 118, 0, 0x2000, 0x2000),
+	("TQROM", 119, 0, 0, 0x2000),
+	("ACTION52", 228, 0, 0, 0x2000)
+];
+
+/// Whether the data is UNIF
+pub fn is_unif(data: &[u8]) -> bool {
+	data.starts_with(UNIF_SIGNATURE)
+}
+
+/// Mapper number, submapper number, PRG-RAM size and CHR-RAM size
+/// of the UNIF board name. None if the board is unknown.
+pub fn board_to_mapper(board: &str) -> Option<(u16, u8, usize, usize)> {
+	let name = BOARD_PREFIXES.iter()
+		.find(|prefix| board.starts_with(*prefix))
+		.map_or(board, |prefix| &board[prefix.len()..]);
+	BOARDS.iter()
+		.find(|&&(board_name, _, _, _, _)| board_name.eq_ignore_ascii_case(name))
+		.map(|&(_, mapper_num, submapper_num, prg_ram_size, chr_ram_size)|
+			(mapper_num, submapper_num, prg_ram_size, chr_ram_size))
+}
+
+/**
+ * UNIF cartridge image. Chunks (4-byte ID, 4-byte length, data) follow
+ * the 32-byte header. PRG0 - PRGF and CHR0 - CHRF chunks are joined
+ * in the order into PRG-ROM and CHR-ROM.
+ * Refer to https://wiki.nesdev.com/w/index.php/UNIF
+ */
+pub struct Unif {
+	pub board: String,
+	pub name: String,
+	pub prg_rom: Vec<u8>,
+	pub chr_rom: Vec<u8>,
+	// MIRR chunk. 0: Horizontal, 1: Vertical, 2, 3: Single screen,
+	// 4: Four-screen, 5: Mapper controlled. None without the chunk.
+	pub mirroring: Option<u8>,
+	pub battery: bool
+}
+
+impl Unif {
+	/// Parses UNIF. Err tells why if it's broken.
+	pub fn new(data: &[u8]) -> Result<Self, &'static str> {
+		if data.len() < UNIF_HEADER_SIZE || !is_unif(data) {
+			return Err("header is truncated");
+		}
+
+		let mut unif = Unif {
+			board: String::new(),
+			name: String::new(),
+			prg_rom: vec![],
+			chr_rom: vec![],
+			mirroring: None,
+			battery: false
+		};
+		let mut prg_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+		let mut chr_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+
+		let mut position = UNIF_HEADER_SIZE;
+		while position < data.len() {
+			if position + 8 > data.len() {
+				return Err("chunk is truncated");
+			}
+			let id = &data[position..position + 4];
+			let length = (0..4).fold(0, |value, i| value | (data[position + 4 + i] as usize) << (i * 8));
+			let start = position + 8;
+			if length > data.len() - start {
+				return Err("chunk is truncated");
+			}
+			let chunk = &data[start..start + length];
+			position = start + length;
+
+			match id {
+				b"MAPR" => unif.board = parse_string(chunk),
+				b"NAME" => unif.name = parse_string(chunk),
+				b"MIRR" => unif.mirroring = chunk.first().cloned(),
+				b"BATR" => unif.battery = chunk.first() != Some(&0),
+				_ => {
+					// PRG0 - PRGF, CHR0 - CHRF
+					let index = (id[3] as char).to_digit(16);
+					match (&id[..3], index) {
+						(b"PRG", Some(index)) => prg_chunks[index as usize] = Some(chunk),
+						(b"CHR", Some(index)) => chr_chunks[index as usize] = Some(chunk),
+						_ => {}
+					};
+				}
+			};
+		}
+
+		for chunk in prg_chunks.iter().filter_map(|chunk| *chunk) {
+			unif.prg_rom.extend_from_slice(chunk);
+		}
+		for chunk in chr_chunks.iter().filter_map(|chunk| *chunk) {
+			unif.chr_rom.extend_from_slice(chunk);
+		}
+
+		if unif.board.is_empty() {
+			return Err("MAPR chunk is missing");
+		}
+		if unif.prg_rom.is_empty() {
+			return Err("PRG chunks are missing");
+		}
+
+		pad_rom(&mut unif.prg_rom, 0x4000);
+		pad_rom(&mut unif.chr_rom, 0x2000);
+		Ok(unif)
+	}
+
+	/**
+	 * NES 2.0 header standing for the image so that it's loaded
+	 * in the same way as iNES. The board tells CHR-RAM size if the image
+	 * has no CHR-ROM.
+	 * None if the board is unknown.
+	 */
+	pub fn nes20_header(&self) -> Option<[u8; HEADER_SIZE]> {
+		let (mapper_num, submapper_num, prg_ram_size, chr_ram_size) = board_to_mapper(&self.board)?;
+		let (prg_lsb, prg_msb) = encode_rom_size(self.prg_rom.len(), 0x4000);
+		let (chr_lsb, chr_msb) = encode_rom_size(self.chr_rom.len(), 0x2000);
+
+		let mut header = [0; HEADER_SIZE];
+		header[0..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+		header[4] = prg_lsb;
+		header[5] = chr_lsb;
+		header[6] = ((mapper_num as u8 & 0x0F) << 4) | ((self.battery as u8) << 1) |
+			match self.mirroring {
+				Some(1) => 0x01,
+				Some(4) => 0x08,
+				_ => 0x00
+			};
+		header[7] = (mapper_num as u8 & 0xF0) | 0x08;
+		header[8] = (submapper_num << 4) | (mapper_num >> 8) as u8;
+		header[9] = (chr_msb << 4) | prg_msb;
+		header[10] = match self.battery {
+			true => encode_ram_size(prg_ram_size) << 4,
+			false => encode_ram_size(prg_ram_size)
+		};
+		header[11] = match self.chr_rom.is_empty() {
+			true => encode_ram_size(chr_ram_size),
+			false => 0
+		};
+		Some(header)
+	}
+}
+
+// NUL terminated or padded string
+fn parse_string(data: &[u8]) -> String {
+	let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+	String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+// NES 2.0 ROM size LSB and MSB nibble. Sizes not in units fit in
+// the exponent notation, 2^E * (M * 2 + 1), after pad_rom().
+fn encode_rom_size(size: usize, unit: usize) -> (u8, u8) {
+	if size % unit == 0 {
+		let units = size / unit;
+		return (units as u8, (units >> 8) as u8);
+	}
+	let exponent = size.trailing_zeros() as usize;
+	let multiplier = (size >> exponent) / 2;
+	(((exponent << 2) | multiplier) as u8, 0xF)
+}
+
+// Pads ROM data to the unit unless the exponent notation can tell the size
+fn pad_rom(rom: &mut Vec<u8>, unit: usize) {
+	let size = rom.len();
+	if size == 0 || size % unit == 0 || (size >> size.trailing_zeros()) <= 7 {
+		return;
+	}
+	let padded_size = (size / unit + 1) * unit;
+	rom.resize(padded_size, 0);
+}
+
+// NES 2.0 RAM size shift count, 64 << shift
+fn encode_ram_size(size: usize) -> u8 {
+	match size {
+		0 => 0,
+		_ => (size / 64).trailing_zeros() as u8
+	}
+}
+
+#[cfg(test)]
+mod tests_unif {
+	use super::*;
+
+	fn chunk(id: &[u8], body: &[u8]) -> Vec<u8> {
+		let mut data = id.to_vec();
+		data.extend_from_slice(&(body.len() as u32).to_le_bytes());
+		data.extend_from_slice(body);
+		data
+	}
+
+	fn unif_data(board: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+		let mut data = UNIF_SIGNATURE.to_vec();
+		data.resize(UNIF_HEADER_SIZE, 0);
+		data.extend_from_slice(&chunk(b"MAPR", board));
+		for chunk in chunks {
+			data.extend_from_slice(chunk);
+		}
+		data
+	}
+
+	#[test]
+	fn board_to_mapper_() {
+		assert_eq!(Some((0, 0, 0, 0x2000)), board_to_mapper("NES-NROM-256"));
+		assert_eq!(Some((1, 0, 0x8000, 0x2000)), board_to_mapper("NES-SXROM"));
+		assert_eq!(Some((4, 1, 0x400, 0x2000)), board_to_mapper("HKROM"));
+		assert_eq!(Some((228, 0, 0, 0x2000)), board_to_mapper("MLT-ACTION52"));
+		assert_eq!(Some((13, 0, 0, 0x4000)), board_to_mapper("NES-CPROM"));
+		assert_eq!(Some((30, 0, 0, 0x8000)), board_to_mapper("UNL-UNROM-512-32"));
+		assert_eq!(None, board_to_mapper("UNL-UNKNOWN"));
+		assert_eq!(None, board_to_mapper(""));
+	}
+
+	#[test]
+	fn parse() {
+		let data = unif_data(b"NES-SNROM\0", &[
+			chunk(b"NAME", b"Game\0"),
+			chunk(b"PRG1", &[2; 0x4000]),
+			chunk(b"PRG0", &[1; 0x4000]),
+			chunk(b"MIRR", &[1]),
+			chunk(b"BATR", &[1]),
+			chunk(b"DINF", &[0; 4])
+		]);
+		assert!(is_unif(&data));
+		let unif = Unif::new(&data).unwrap();
+		assert_eq!("NES-SNROM", unif.board);
+		assert_eq!("Game", unif.name);
+		assert_eq!(Some(1), unif.mirroring);
+		assert!(unif.battery);
+		assert_eq!(0x8000, unif.prg_rom.len());
+		assert_eq!(1, unif.prg_rom[0]);
+		assert_eq!(2, unif.prg_rom[0x4000]);
+		assert!(unif.chr_rom.is_empty());
+		assert_eq!([
+			0x4E, 0x45, 0x53, 0x1A, 0x02, 0x00, 0x13, 0x08,
+			0x00, 0x00, 0x70, 0x07, 0x00, 0x00, 0x00, 0x00
+		], unif.nes20_header().unwrap());
+	}
+
+	#[test]
+	fn rom_sizes() {
+		// 8KB PRG-ROM in the exponent notation, 4KB CHR-ROM too
+		let unif = Unif::new(&unif_data(b"NROM", &[
+			chunk(b"PRG0", &[0; 0x2000]),
+			chunk(b"CHR0", &[0; 0x1000])
+		])).unwrap();
+		let header = unif.nes20_header().unwrap();
+		assert_eq!((0x34, 0x30, 0xFF), (header[4], header[5], header[9]));
+		// 24KB + 1 byte doesn't fit, padded to 32KB
+		let unif = Unif::new(&unif_data(b"NROM", &[chunk(b"PRG0", &[0; 0x6001])])).unwrap();
+		assert_eq!(0x8000, unif.prg_rom.len());
+	}
+
+	#[test]
+	fn broken() {
+		assert_eq!(Err("header is truncated"), Unif::new(UNIF_SIGNATURE).map(|_| ()));
+		assert_eq!(Err("PRG chunks are missing"), Unif::new(&unif_data(b"NROM", &[])).map(|_| ()));
+		let mut data = unif_data(b"NROM", &[chunk(b"PRG0", &[0; 0x10])]);
+		data.truncate(data.len() - 1);
+		assert_eq!(Err("chunk is truncated"), Unif::new(&data).map(|_| ()));
+	}
+}
@@ -4,7 +4,8 @@ extern crate nes_rust;
 use wasm_bindgen::prelude::*;
 
 use nes_rust::Nes;
-use nes_rust::rom::Rom;
+use nes_rust::rom::{Rom, TimingMode};
+use nes_rust::patch::apply_patch;
 use nes_rust::button;
 use nes_rust::default_input::DefaultInput;
 use nes_rust::default_audio::DefaultAudio;
@@ -115,12 +116,89 @@ impl WasmNes {
 		}
 	}
 
-	/// Sets up NES rom
+	/// Sets up NES rom. Throws an error describing why
+	/// the image is rejected if it isn't a valid ROM.
 	///
 	/// # Arguments
 	/// * `rom` Rom image binary `Uint8Array`
-	pub fn set_rom(&mut self, contents: Vec<u8>) {
-		self.nes.set_rom(Rom::new(contents));
+	pub fn set_rom(&mut self, contents: Vec<u8>) -> Result<(), JsValue> {
+		let rom = Rom::new(contents).map_err(|e| JsValue::from_str(&e.to_string()))?;
+		self.nes.set_rom(rom);
+		Ok(())
+	}
+
+	/// Sets up NES rom patched with an IPS, UPS or BPS patch. Throws
+	/// an error describing why if the patch or the ROM is rejected.
+	///
+	/// # Arguments
+	/// * `contents` Rom image binary `Uint8Array`
+	/// * `patch` Patch binary `Uint8Array`
+	pub fn set_rom_with_patch(&mut self, contents: Vec<u8>, patch: Vec<u8>) -> Result<(), JsValue> {
+		let contents = apply_patch(&contents, &patch).map_err(|e| JsValue::from_str(&e.to_string()))?;
+		self.set_rom(contents)
+	}
+
+	/// Sets up FDS disk image. Throws an error describing why
+	/// the image or the BIOS is rejected.
+	///
+	/// # Arguments
+	/// * `contents` Disk image binary `Uint8Array`
+	/// * `bios` 8KB FDS BIOS binary `Uint8Array`
+	pub fn set_fds(&mut self, contents: Vec<u8>, bios: Vec<u8>) -> Result<(), JsValue> {
+		let rom = Rom::new_fds(contents, bios).map_err(|e| JsValue::from_str(&e.to_string()))?;
+		self.nes.set_rom(rom);
+		Ok(())
+	}
+
+	/// Title of the ROM in the game database, or of the NSF.
+	/// `undefined` if unknown.
+	pub fn rom_title(&self) -> Option<String> {
+		self.nes.rom_info().title.clone()
+	}
+
+	/// Board name of the ROM. Empty if unknown.
+	pub fn rom_board(&self) -> String {
+		self.nes.rom_info().board.clone()
+	}
+
+	/// "NTSC", "PAL", "Multiple" or "Dendy"
+	pub fn rom_region(&self) -> String {
+		match self.nes.rom_info().region {
+			TimingMode::Ntsc => "NTSC",
+			TimingMode::Pal => "PAL",
+			TimingMode::MultiRegion => "Multiple",
+			TimingMode::Dendy => "Dendy"
+		}.to_string()
+	}
+
+	/// CRC32 of the ROM
+	pub fn rom_crc32(&self) -> u32 {
+		self.nes.rom_info().crc32
+	}
+
+	/// Inserts the FDS disk side
+	pub fn insert_disk(&mut self, side: usize) {
+		self.nes.insert_disk(side);
+	}
+
+	/// Ejects the FDS disk
+	pub fn eject_disk(&mut self) {
+		self.nes.eject_disk();
+	}
+
+	/// Starts playing the NSF track, 0-based
+	pub fn select_track(&mut self, track: u8) {
+		self.nes.select_track(track);
+	}
+
+	/// Starts playing the next NSF track
+	pub fn next_track(&mut self) {
+		self.nes.next_track();
+	}
+
+	/// Starts playing the previous NSF track
+	pub fn previous_track(&mut self) {
+		self.nes.previous_track();
 	}
 
 	/// Boots up
@@ -133,6 +211,11 @@ impl WasmNes {
 		self.nes.reset();
 	}
 
+	/// Sets the solder pad setting of multicart boards
+	pub fn set_solder_pad(&mut self, pad: u8) {
+		self.nes.set_solder_pad(pad);
+	}
+
 	/// Executes a CPU cycle
 	pub fn step(&mut self) {
 		self.nes.step();